@@ -0,0 +1,21 @@
+//! Records the version of rust-htslib resolved in Cargo.lock, for `--version`
+
+use std::fs;
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock: String = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let version: &str = lock
+        .split("[[package]]")
+        .find_map(|package| {
+            let mut lines = package.lines().map(str::trim);
+            match lines.find(|line| line.starts_with("name = ")) {
+                Some("name = \"rust-htslib\"") => lines
+                    .find_map(|line| line.strip_prefix("version = "))
+                    .map(|version| version.trim_matches('"')),
+                _ => None,
+            }
+        })
+        .unwrap_or("unknown");
+    println!("cargo:rustc-env=RUST_HTSLIB_VERSION={}", version);
+}
@@ -0,0 +1,94 @@
+#ifndef FILTER_CLIPPED_H
+#define FILTER_CLIPPED_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of [fc_evaluate_cigar]
+typedef enum FcDecision {
+  FC_PASS = 0,
+  FC_FAIL_TOTAL_CLIP = 1,
+  FC_FAIL_LEFT_CLIP = 2,
+  FC_FAIL_RIGHT_CLIP = 3,
+  FC_FAIL_OTHER = 4,
+  FC_ERROR = -1,
+} FcDecision;
+
+// Opaque handle holding the settings of a run
+typedef struct FcConfig FcConfig;
+
+// Summary of a run, see [RunStats]
+typedef struct FcRunStats {
+  uint64_t read;
+  uint64_t written;
+  uint64_t unaligned;
+  uint64_t bypassed;
+  uint64_t dropped;
+  uint64_t failed;
+} FcRunStats;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a config handle with the default settings, to be freed with [fc_config_free]
+struct FcConfig *fc_config_new(void);
+
+// Free a config handle
+//
+// # Safety
+// `config` must be null or a handle from [fc_config_new], not used afterwards
+void fc_config_free(struct FcConfig *config);
+
+// Set the input and output alignment file paths ("-" for stdin/stdout)
+//
+// # Safety
+// `config` must be a handle from [fc_config_new], `in_bam` and `out_bam` NUL-terminated strings
+int fc_config_set_paths(struct FcConfig *config, const char *in_bam, const char *out_bam);
+
+// Set the maximum fractions of clipped bases: in total, on the 5' end and on the 3' end
+//
+// # Safety
+// `config` must be a handle from [fc_config_new]
+int fc_config_set_thresholds(struct FcConfig *config,
+                             double both_end,
+                             double left_side,
+                             double right_side);
+
+// Write out the failed alignments only (`inverse` non-zero), and/or make the failed
+// alignments unmapped instead of removing them (`unalign` non-zero)
+//
+// # Safety
+// `config` must be a handle from [fc_config_new]
+int fc_config_set_mode(struct FcConfig *config, int inverse, int unalign);
+
+// Filter the input file into the output file
+//
+// # Arguments
+// - `config`: settings of the run
+// - `stats`: filled with the summary of the run if not null
+//
+// # Safety
+// `config` must be a handle from [fc_config_new], `stats` null or a valid pointer
+int fc_run(const struct FcConfig *config, struct FcRunStats *stats);
+
+// Check a CIGAR string against the clipping thresholds of a config, without
+// going through an alignment file
+//
+// # Safety
+// `config` must be a handle from [fc_config_new], `cigar` a NUL-terminated string
+enum FcDecision fc_evaluate_cigar(const struct FcConfig *config, const char *cigar);
+
+// Message of the last error on this thread, or null if there was none. The string
+// is owned by the library and stays valid until the next failing call on this thread
+const char *fc_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FILTER_CLIPPED_H */
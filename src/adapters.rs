@@ -0,0 +1,289 @@
+use crate::error::FilterClippedError;
+use rust_htslib::bam::record::{Cigar, CigarString, CigarStringView, Record};
+use serde::{Deserialize, Serialize};
+
+/// Shortest clipped segment compared to the adapters, shorter clips can match by chance
+pub const MIN_ADAPTER_OVERLAP: usize = 5;
+
+/// A known adapter sequence
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Adapter {
+    /// name of the adapter, used in the summary
+    pub name: String,
+    /// sequence of the adapter, 5' to 3'
+    pub sequence: String,
+}
+
+/// What to do with an alignment whose clipped bases are an adapter
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdapterPolicy {
+    /// remove the clipped adapter bases from the alignment, and don't count them as clipped
+    Trim,
+    /// fail the alignment
+    Fail,
+}
+
+/// End of an alignment (in reference orientation) a clip is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipSide {
+    /// the leading clip in the CIGAR string
+    Left,
+    /// the trailing clip in the CIGAR string
+    Right,
+}
+
+/// An adapter found in the soft clipped bases of an alignment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdapterHit<'a> {
+    /// the adapter
+    pub adapter: &'a Adapter,
+    /// the clip holding the adapter
+    pub side: ClipSide,
+    /// number of soft clipped bases on that side
+    pub len: usize,
+}
+
+/// Parse adapter sequences from a FASTA file
+///
+/// # Arguments
+/// - `text`: content of the FASTA file
+///
+/// # Example
+/// ```
+/// use filter_clipped::adapters::parse_fasta;
+/// let adapters = parse_fasta(">TruSeq read 1\nAGATCGGAAGAGC\nACACGTCT\n>Nextera\nctgtctcttata\n").unwrap();
+/// assert_eq!(adapters.len(), 2);
+/// assert_eq!(adapters[0].name, "TruSeq");
+/// assert_eq!(adapters[0].sequence, "AGATCGGAAGAGCACACGTCT");
+/// assert_eq!(adapters[1].sequence, "CTGTCTCTTATA");
+/// ```
+pub fn parse_fasta(text: &str) -> Result<Vec<Adapter>, FilterClippedError> {
+    let mut adapters: Vec<Adapter> = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match (line.strip_prefix('>'), adapters.last_mut()) {
+            (Some(header), _) => adapters.push(Adapter {
+                name: header.split_whitespace().next().unwrap_or("").to_string(),
+                sequence: String::new(),
+            }),
+            (None, Some(adapter)) => adapter.sequence.push_str(&line.to_ascii_uppercase()),
+            (None, None) => {
+                return Err(FilterClippedError::InvalidConfig(String::from(
+                    "adapter FASTA file doesn't start with a >name line",
+                )))
+            }
+        }
+    }
+    if let Some(adapter) = adapters.iter().find(|adapter| adapter.sequence.is_empty()) {
+        return Err(FilterClippedError::InvalidConfig(format!(
+            "adapter {} has no sequence",
+            adapter.name
+        )));
+    }
+    Ok(adapters)
+}
+
+/// Reverse complement of a sequence, bases other than A, C, G and T become N
+///
+/// # Example
+/// ```
+/// use filter_clipped::adapters::reverse_complement;
+/// assert_eq!(reverse_complement(b"AACGTN"), b"NACGTT");
+/// ```
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|base| match base.to_ascii_uppercase() {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// Whether two sequences of the same length match with at most a fraction of
+/// mismatches, N matching any base
+fn matches_with_mismatches(seq: &[u8], adapter: &[u8], max_mismatch_rate: f64) -> bool {
+    let mismatches: usize = seq
+        .iter()
+        .zip(adapter.iter())
+        .filter(|(a, b)| {
+            let (a, b) = (a.to_ascii_uppercase(), b.to_ascii_uppercase());
+            a != b && a != b'N' && b != b'N'
+        })
+        .count();
+    mismatches as f64 <= max_mismatch_rate * seq.len() as f64
+}
+
+/// Look for an adapter in the soft clipped bases of an alignment. In the orientation of
+/// the read, a clip on its 3' end should start with the beginning of an adapter (read-through
+/// into the adapter), and a clip on its 5' end should end with the end of an adapter
+///
+/// # Arguments
+/// - `record`: the alignment
+/// - `adapters`: the known adapters
+/// - `max_mismatch_rate`: highest fraction of mismatching bases for a match
+///
+/// # Returns
+/// - the first adapter found, on the 3' clip of the read first
+pub fn find_adapter<'a>(
+    record: &Record,
+    adapters: &'a [Adapter],
+    max_mismatch_rate: f64,
+) -> Option<AdapterHit<'a>> {
+    let cigar: CigarStringView = record.cigar();
+    let seq: Vec<u8> = record.seq().as_bytes();
+    let leading: usize = cigar.leading_softclips() as usize;
+    let trailing: usize = cigar.trailing_softclips() as usize;
+    let reverse: bool = record.is_reverse();
+    // 3' end of the read first, it's where read-through adapters are
+    let sides = match reverse {
+        true => [ClipSide::Left, ClipSide::Right],
+        _ => [ClipSide::Right, ClipSide::Left],
+    };
+    for side in sides {
+        let clip: &[u8] = match side {
+            ClipSide::Left => &seq[..leading.min(seq.len())],
+            ClipSide::Right => &seq[seq.len().saturating_sub(trailing)..],
+        };
+        if clip.len() < MIN_ADAPTER_OVERLAP {
+            continue;
+        }
+        let clip: Vec<u8> = match reverse {
+            true => reverse_complement(clip),
+            _ => clip.to_vec(),
+        };
+        let three_prime: bool = (side == ClipSide::Right) != reverse;
+        let found = adapters.iter().find(|adapter| {
+            let adapter_seq: &[u8] = adapter.sequence.as_bytes();
+            let n: usize = clip.len().min(adapter_seq.len());
+            match three_prime {
+                true => matches_with_mismatches(&clip[..n], &adapter_seq[..n], max_mismatch_rate),
+                _ => matches_with_mismatches(
+                    &clip[clip.len() - n..],
+                    &adapter_seq[adapter_seq.len() - n..],
+                    max_mismatch_rate,
+                ),
+            }
+        });
+        if let Some(adapter) = found {
+            return Some(AdapterHit {
+                adapter,
+                side,
+                len: match side {
+                    ClipSide::Left => leading,
+                    ClipSide::Right => trailing,
+                },
+            });
+        }
+    }
+    None
+}
+
+/// Remove the soft clipped bases holding an adapter from an alignment, as if the
+/// adapter had been trimmed off the read before aligning it
+///
+/// # Arguments
+/// - `record`: the alignment
+/// - `hit`: the adapter found on the alignment, see [find_adapter]
+pub fn trim(record: &mut Record, hit: &AdapterHit) -> Result<(), FilterClippedError> {
+    let mut cigar: Vec<Cigar> = record.cigar().take().0;
+    let seq: Vec<u8> = record.seq().as_bytes();
+    let qual: Vec<u8> = record.qual().to_vec();
+    let soft_clip = |op: &Cigar| matches!(op, Cigar::SoftClip(_));
+    let (index, kept) = match hit.side {
+        ClipSide::Left => (cigar.iter().position(soft_clip), hit.len..seq.len()),
+        ClipSide::Right => (cigar.iter().rposition(soft_clip), 0..seq.len() - hit.len),
+    };
+    match index {
+        Some(index) => cigar.remove(index),
+        None => {
+            return Err(FilterClippedError::InvalidRecord(format!(
+                "{} has no soft clip to trim",
+                String::from_utf8_lossy(record.qname())
+            )))
+        }
+    };
+    let qname: Vec<u8> = record.qname().to_vec();
+    record.set(
+        &qname,
+        Some(&CigarString(cigar)),
+        &seq[kept.clone()],
+        &qual[kept],
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipping::record_from_cigar;
+    use rstest::rstest;
+
+    fn truseq() -> Vec<Adapter> {
+        vec![Adapter {
+            name: String::from("TruSeq"),
+            sequence: String::from("AGATCGGAAGAGC"),
+        }]
+    }
+
+    #[rstest]
+    // read-through into the adapter on the 3' end
+    #[case("20M12S", 0, "AGATCGGAAGAG", Some(ClipSide::Right))]
+    // one mismatch within 10%
+    #[case("20M12S", 0, "AGATCGGTAGAG", Some(ClipSide::Right))]
+    #[case("20M12S", 0, "AGTTCGGTAGAG", None)]
+    #[case("20M12S", 0, "TTTTTTTTTTTT", None)]
+    // too short to tell
+    #[case("20M4S", 0, "AGAT", None)]
+    // reverse strand: the 3' end of the read is on the left
+    #[case("12S20M", 0x10, "CTCTTCCGATCT", Some(ClipSide::Left))]
+    #[case("12S20M", 0, "CTCTTCCGATCT", None)]
+    // adapter end on the 5' end of the read
+    #[case("8S20M", 0, "GGAAGAGC", Some(ClipSide::Left))]
+    fn test_find_adapter(
+        #[case] cigar: &str,
+        #[case] flag: u16,
+        #[case] clip: &str,
+        #[case] expected: Option<ClipSide>,
+    ) {
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let aligned: String = "ACGTTGCAAC".repeat(2);
+        let seq: String = match cigar.0[0] {
+            Cigar::SoftClip(_) => format!("{}{}", clip, aligned),
+            _ => format!("{}{}", aligned, clip),
+        };
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), seq.as_bytes(), &vec![30; seq.len()]);
+        record.set_flags(flag);
+        let adapters = truseq();
+        let hit = find_adapter(&record, &adapters, 0.1);
+        assert_eq!(hit.map(|hit| hit.side), expected);
+    }
+
+    #[rstest]
+    #[case("5H3S20M12S", ClipSide::Right, 12, "5H3S20M", 23)]
+    #[case("12S20M2H", ClipSide::Left, 12, "20M2H", 20)]
+    fn test_trim(
+        #[case] cigar: &str,
+        #[case] side: ClipSide,
+        #[case] len: usize,
+        #[case] expected_cigar: &str,
+        #[case] expected_len: usize,
+    ) {
+        let mut record: Record = record_from_cigar(cigar);
+        let adapters = truseq();
+        let hit = AdapterHit {
+            adapter: &adapters[0],
+            side,
+            len,
+        };
+        trim(&mut record, &hit).unwrap();
+        assert_eq!(record.cigar().to_string(), expected_cigar);
+        assert_eq!(record.seq_len(), expected_len);
+        assert_eq!(record.qual().len(), expected_len);
+    }
+}
@@ -0,0 +1,622 @@
+use crate::adapters::reverse_complement;
+use crate::clipping::ClipStat;
+use crate::error::FilterClippedError;
+use crate::tags;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString, Record};
+use rust_htslib::bam::HeaderView;
+
+/// One segment of a chimeric alignment as listed in an SA tag
+/// (`rname,pos,strand,CIGAR,mapQ,NM;`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaSegment {
+    /// reference sequence name
+    pub rname: String,
+    /// 1-based leftmost position
+    pub pos: i64,
+    /// whether the segment is aligned to the reverse strand
+    pub reverse: bool,
+    /// CIGAR string of the segment
+    pub cigar: String,
+    /// mapping quality of the segment
+    pub mapq: u8,
+    /// edit distance of the segment
+    pub nm: i64,
+}
+
+impl SaSegment {
+    /// Describe an alignment as an SA tag entry
+    ///
+    /// # Arguments
+    /// * `record`: a mapped alignment
+    /// * `header`: header of the alignment, to look up its reference name
+    pub fn from_record(record: &Record, header: &HeaderView) -> Self {
+        Self {
+            rname: String::from_utf8_lossy(header.tid2name(record.tid() as u32)).to_string(),
+            pos: record.pos() + 1,
+            reverse: record.is_reverse(),
+            cigar: record.cigar().to_string(),
+            mapq: record.mapq(),
+            nm: tags::integer_tag(record, b"NM").unwrap_or(0),
+        }
+    }
+
+    /// Format the segment as an SA tag entry (without the trailing `;`)
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::chimeric::parse_sa_tag;
+    /// let segments = parse_sa_tag("chr1,100,-,10S40M,60,2;").unwrap();
+    /// assert_eq!(segments[0].to_sa_entry(), "chr1,100,-,10S40M,60,2");
+    /// ```
+    pub fn to_sa_entry(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.rname,
+            self.pos,
+            if self.reverse { '-' } else { '+' },
+            self.cigar,
+            self.mapq,
+            self.nm
+        )
+    }
+}
+
+/// Parse the value of an SA tag into its segments
+///
+/// # Arguments
+/// * `sa`: value of the SA tag, e.g. `chr1,100,+,60S40M,60,0;chr2,500,-,40M60S,60,1;`
+///
+/// # Return
+/// * a list of [SaSegment]
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::parse_sa_tag;
+/// let segments = parse_sa_tag("chr1,100,+,60S40M,60,0;chr2,500,-,40M60S,30,1;").unwrap();
+/// assert_eq!(segments.len(), 2);
+/// assert_eq!(segments[1].rname, "chr2");
+/// assert!(segments[1].reverse);
+/// assert_eq!(segments[1].mapq, 30);
+/// ```
+pub fn parse_sa_tag(sa: &str) -> Result<Vec<SaSegment>, FilterClippedError> {
+    sa.split(';')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let malformed =
+                || FilterClippedError::InvalidRecord(format!("Malformed SA tag entry: {}", entry));
+            let fields: Vec<&str> = entry.split(',').collect();
+            if fields.len() != 6 {
+                return Err(malformed());
+            }
+            Ok(SaSegment {
+                rname: fields[0].to_string(),
+                pos: fields[1].parse::<i64>().map_err(|_| malformed())?,
+                reverse: match fields[2] {
+                    "+" => false,
+                    "-" => true,
+                    _ => return Err(malformed()),
+                },
+                cigar: fields[3].to_string(),
+                mapq: fields[4].parse::<u8>().map_err(|_| malformed())?,
+                nm: fields[5].parse::<i64>().map_err(|_| malformed())?,
+            })
+        })
+        .collect()
+}
+
+/// Format a list of segments as the value of an SA tag
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::{format_sa_tag, parse_sa_tag};
+/// let sa = "chr1,100,+,60S40M,60,0;chr2,500,-,40M60S,30,1;";
+/// assert_eq!(format_sa_tag(&parse_sa_tag(sa).unwrap()), sa);
+/// ```
+pub fn format_sa_tag(segments: &[SaSegment]) -> String {
+    segments
+        .iter()
+        .map(|segment| format!("{};", segment.to_sa_entry()))
+        .collect()
+}
+
+/// Locate the aligned part of a segment on the original (sequenced) read
+///
+/// # Arguments
+/// * `cigar`: CIGAR string of the segment
+/// * `reverse`: whether the segment is aligned to the reverse strand
+///
+/// # Return
+/// * (start, end, read length) with a 0-based, half-open interval of the aligned part
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::query_interval;
+/// assert_eq!(query_interval("10H50M40S", false).unwrap(), (10, 60, 100));
+/// assert_eq!(query_interval("10H50M40S", true).unwrap(), (40, 90, 100));
+/// ```
+pub fn query_interval(cigar: &str, reverse: bool) -> Result<(i64, i64, i64), FilterClippedError> {
+    let malformed =
+        || FilterClippedError::InvalidRecord(format!("Malformed CIGAR string: {}", cigar));
+    let mut leading: i64 = 0;
+    let mut aligned: i64 = 0;
+    let mut trailing: i64 = 0;
+    let mut n_base = String::new();
+    for c in cigar.chars() {
+        if c.is_ascii_digit() {
+            n_base.push(c);
+            continue;
+        }
+        let n: i64 = n_base.parse::<i64>().map_err(|_| malformed())?;
+        n_base.clear();
+        match c {
+            'S' | 'H' if aligned == 0 => leading += n,
+            'S' | 'H' => trailing += n,
+            'M' | 'I' | '=' | 'X' => aligned += n,
+            'D' | 'N' | 'P' => (),
+            _ => return Err(malformed()),
+        }
+    }
+    let read_len = leading + aligned + trailing;
+    match reverse {
+        true => Ok((trailing, trailing + aligned, read_len)),
+        _ => Ok((leading, leading + aligned, read_len)),
+    }
+}
+
+/// Number of reference bases covered by a CIGAR string
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::reference_length;
+/// assert_eq!(reference_length("10S40M2D5M100N5M20H").unwrap(), 152);
+/// ```
+pub fn reference_length(cigar: &str) -> Result<i64, FilterClippedError> {
+    let malformed =
+        || FilterClippedError::InvalidRecord(format!("Malformed CIGAR string: {}", cigar));
+    let mut length: i64 = 0;
+    let mut n_base = String::new();
+    for c in cigar.chars() {
+        if c.is_ascii_digit() {
+            n_base.push(c);
+            continue;
+        }
+        let n: i64 = n_base.parse::<i64>().map_err(|_| malformed())?;
+        n_base.clear();
+        match c {
+            'M' | 'D' | 'N' | '=' | 'X' => length += n,
+            'S' | 'H' | 'I' | 'P' => (),
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(length)
+}
+
+/// Breakpoint of a chimeric read on one side of a junction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    /// reference sequence name
+    pub rname: String,
+    /// 0-based reference position of the last aligned base before the junction
+    pub pos: i64,
+    /// whether the segment is aligned to the reverse strand
+    pub reverse: bool,
+}
+
+/// Junctions between an alignment and each of the segments in its SA tag, from the order
+/// of the segments along the read: the alignment side of the junction is its aligned end
+/// facing the segment on the read, and the other way round
+///
+/// # Arguments
+/// * `record`: an alignment with an SA tag
+/// * `rname`: name of the reference sequence of the alignment
+///
+/// # Return
+/// * a (alignment breakpoint, segment breakpoint, segment mapping quality) per segment,
+///   none if the alignment has no SA tag
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::junctions;
+/// use rust_htslib::bam::record::{Aux, CigarString, Record};
+/// let mut record = Record::new();
+/// let cigar = CigarString::try_from("60M40S").unwrap();
+/// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+/// record.set_flags(0);
+/// record.set_pos(999);
+/// record.push_aux(b"SA", Aux::String("chr2,5001,+,60H40M,60,0;")).unwrap();
+/// let junction = &junctions(&record, "chr1").unwrap()[0];
+/// // the read runs from chr1:1000-1059 into chr2:5001-5040
+/// assert_eq!((junction.0.pos, junction.1.pos), (1058, 5000));
+/// ```
+pub fn junctions(
+    record: &Record,
+    rname: &str,
+) -> Result<Vec<(Breakpoint, Breakpoint, u8)>, FilterClippedError> {
+    let segments: Vec<SaSegment> = match record.aux(b"SA") {
+        Ok(Aux::String(sa)) => parse_sa_tag(sa)?,
+        _ => return Ok(Vec::new()),
+    };
+    let (start, _, _) = query_interval(&record.cigar().to_string(), record.is_reverse())?;
+    let (ref_start, ref_end) = (record.pos(), record.cigar().end_pos() - 1);
+    let mut junctions = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let (seg_start, _, _) = query_interval(&segment.cigar, segment.reverse)?;
+        let seg_ref_start: i64 = segment.pos - 1;
+        let seg_ref_end: i64 = seg_ref_start + reference_length(&segment.cigar)? - 1;
+        // the alignment leaves the read at its 3' end if the segment comes later on the read
+        let segment_after: bool = seg_start > start;
+        let pos: i64 = match segment_after != record.is_reverse() {
+            true => ref_end,
+            _ => ref_start,
+        };
+        let seg_pos: i64 = match segment_after != segment.reverse {
+            true => seg_ref_start,
+            _ => seg_ref_end,
+        };
+        junctions.push((
+            Breakpoint {
+                rname: rname.to_string(),
+                pos,
+                reverse: record.is_reverse(),
+            },
+            Breakpoint {
+                rname: segment.rname,
+                pos: seg_pos,
+                reverse: segment.reverse,
+            },
+            segment.mapq,
+        ));
+    }
+    Ok(junctions)
+}
+
+/// Clipping statistics of a chimeric read, where a base only counts as clipped if it is
+/// not covered by any of the segments listed in the SA tag either
+///
+/// # Arguments
+/// * `record`: an alignment with an SA tag
+///
+/// # Return
+/// * None if the alignment has no SA tag, otherwise the [ClipStat] computed from the union
+///   of the segments (in the orientation of the alignment) and the full read length
+///   (including hard clipped bases)
+pub fn union_clip_stat(record: &Record) -> Result<Option<(ClipStat, f64)>, FilterClippedError> {
+    let union: UnionCoverage = match union_coverage(record)? {
+        Some(union) => union,
+        None => return Ok(None),
+    };
+    let (left, right) = match record.is_reverse() {
+        true => (union.read_len - union.last_covered, union.first_covered),
+        _ => (union.first_covered, union.read_len - union.last_covered),
+    };
+    Ok(Some((
+        ClipStat::from_parts(left, right, union.read_len - union.covered),
+        union.read_len as f64,
+    )))
+}
+
+/// Fraction of a split read covered by the union of the alignment and the segments listed
+/// in its SA tag, so that chimeric reads aren't judged on the clips of each segment
+///
+/// # Arguments
+/// * `record`: an alignment with an SA tag
+///
+/// # Return
+/// * None if the alignment has no SA tag, otherwise the covered fraction of the full read
+///   length (including hard clipped bases)
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::split_coverage;
+/// use rust_htslib::bam::record::{Aux, CigarString, Record};
+/// let mut record = Record::new();
+/// let cigar = CigarString::try_from("60M40S").unwrap();
+/// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+/// assert_eq!(split_coverage(&record).unwrap(), None);
+/// record.push_aux(b"SA", Aux::String("chr2,500,+,65H30M5H,60,0;")).unwrap();
+/// assert_eq!(split_coverage(&record).unwrap(), Some(0.9));
+/// ```
+pub fn split_coverage(record: &Record) -> Result<Option<f64>, FilterClippedError> {
+    Ok(union_coverage(record)?.map(|union| union.covered as f64 / union.read_len.max(1) as f64))
+}
+
+/// Query bases of a read covered by the union of an alignment and its SA segments
+struct UnionCoverage {
+    /// number of covered bases
+    covered: i64,
+    /// first covered base, in the orientation of the read
+    first_covered: i64,
+    /// position after the last covered base, in the orientation of the read
+    last_covered: i64,
+    /// full read length, including hard clipped bases
+    read_len: i64,
+}
+
+/// Union of the query intervals of an alignment and the segments listed in its SA tag,
+/// None if it has no SA tag
+fn union_coverage(record: &Record) -> Result<Option<UnionCoverage>, FilterClippedError> {
+    let segments: Vec<SaSegment> = match record.aux(b"SA") {
+        Ok(Aux::String(sa)) => parse_sa_tag(sa)?,
+        _ => return Ok(None),
+    };
+    let (start, end, read_len) = query_interval(&record.cigar().to_string(), record.is_reverse())?;
+    let mut intervals: Vec<(i64, i64)> = vec![(start, end)];
+    for segment in segments.iter() {
+        let (seg_start, seg_end, _) = query_interval(&segment.cigar, segment.reverse)?;
+        intervals.push((seg_start.min(read_len), seg_end.min(read_len)));
+    }
+    intervals.sort_unstable();
+
+    let mut covered: i64 = 0;
+    let mut merged: (i64, i64) = intervals[0];
+    for interval in intervals.iter().skip(1) {
+        if interval.0 <= merged.1 {
+            merged.1 = merged.1.max(interval.1);
+        } else {
+            covered += merged.1 - merged.0;
+            merged = *interval;
+        }
+    }
+    covered += merged.1 - merged.0;
+
+    Ok(Some(UnionCoverage {
+        covered,
+        first_covered: intervals[0].0,
+        last_covered: merged.1,
+        read_len,
+    }))
+}
+
+/// Replace the SA tag of an alignment, removing it if there are no segments left
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::{parse_sa_tag, set_sa_tag};
+/// use rust_htslib::bam::record::{Aux, Record};
+/// let mut record = Record::new();
+/// record.push_aux(b"SA", Aux::String("chr1,100,+,60S40M,60,0;")).unwrap();
+/// set_sa_tag(&mut record, &parse_sa_tag("chr2,500,-,40M60S,30,1;").unwrap()).unwrap();
+/// assert_eq!(record.aux(b"SA").unwrap(), Aux::String("chr2,500,-,40M60S,30,1;"));
+/// set_sa_tag(&mut record, &[]).unwrap();
+/// assert!(record.aux(b"SA").is_err());
+/// ```
+pub fn set_sa_tag(record: &mut Record, segments: &[SaSegment]) -> Result<(), FilterClippedError> {
+    if record.aux(b"SA").is_ok() {
+        record.remove_aux(b"SA")?;
+    }
+    if !segments.is_empty() {
+        record.push_aux(b"SA", Aux::String(&format_sa_tag(segments)))?;
+    }
+    Ok(())
+}
+
+/// Make a supplementary alignment the primary alignment of its read, in place of a failing
+/// primary alignment: clear its supplementary flag, and turn its hard clips into soft clips
+/// with the bases of the old primary alignment so that it holds the whole read like a
+/// primary alignment should. The hard clips are left as they are if the old primary
+/// alignment doesn't hold the whole read either
+///
+/// # Arguments
+/// * `primary`: the failing primary alignment of the read (or mate)
+/// * `supplementary`: the supplementary alignment to promote
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::promote_supplementary;
+/// use rust_htslib::bam::record::{CigarString, Record};
+/// let mut primary = Record::new();
+/// let cigar = CigarString::try_from("4M4S").unwrap();
+/// primary.set(b"read", Some(&cigar), b"AACCGGTT", &[30; 8]);
+/// let mut supplementary = Record::new();
+/// let cigar = CigarString::try_from("4H4M").unwrap();
+/// supplementary.set(b"read", Some(&cigar), b"GGTT", &[30; 4]);
+/// supplementary.set_supplementary();
+/// promote_supplementary(&primary, &mut supplementary).unwrap();
+/// assert!(!supplementary.is_supplementary());
+/// assert_eq!(supplementary.cigar().to_string(), "4S4M");
+/// assert_eq!(supplementary.seq().as_bytes(), b"AACCGGTT");
+/// ```
+pub fn promote_supplementary(
+    primary: &Record,
+    supplementary: &mut Record,
+) -> Result<(), FilterClippedError> {
+    supplementary.unset_supplementary();
+    let hardclipped: i64 = supplementary
+        .cigar()
+        .iter()
+        .filter(|op| matches!(op, Cigar::HardClip(_)))
+        .map(|op| op.len() as i64)
+        .sum();
+    let primary_hardclipped: bool = primary
+        .cigar()
+        .iter()
+        .any(|op| matches!(op, Cigar::HardClip(_)));
+    if hardclipped == 0
+        || primary_hardclipped
+        || supplementary.seq_len() as i64 + hardclipped != primary.seq_len() as i64
+    {
+        return Ok(());
+    }
+
+    // the whole read, in the orientation of the supplementary alignment
+    let (mut seq, mut qual): (Vec<u8>, Vec<u8>) =
+        (primary.seq().as_bytes(), primary.qual().to_vec());
+    if primary.is_reverse() != supplementary.is_reverse() {
+        seq = reverse_complement(&seq);
+        qual.reverse();
+    }
+    let mut ops: Vec<Cigar> = Vec::new();
+    for op in supplementary.cigar().iter() {
+        let op: Cigar = match op {
+            Cigar::HardClip(len) => Cigar::SoftClip(*len),
+            op => *op,
+        };
+        match (ops.last_mut(), op) {
+            (Some(Cigar::SoftClip(last)), Cigar::SoftClip(len)) => *last += len,
+            _ => ops.push(op),
+        }
+    }
+    let qname: Vec<u8> = supplementary.qname().to_vec();
+    supplementary.set(&qname, Some(&CigarString(ops)), &seq, &qual);
+    Ok(())
+}
+
+/// Only keep the SA tag entries for which `keep` returns true, and remove the
+/// SA tag entirely if no entry is left
+///
+/// # Arguments
+/// * `record`: the alignment to update
+/// * `keep`: predicate deciding whether a segment stays in the SA tag
+///
+/// # Example
+/// ```
+/// use filter_clipped::chimeric::retain_sa_segments;
+/// use rust_htslib::bam::record::{Aux, Record};
+/// let mut record = Record::new();
+/// record.push_aux(b"SA", Aux::String("chr1,100,+,60S40M,60,0;chr2,500,-,40M60S,30,1;")).unwrap();
+/// retain_sa_segments(&mut record, |segment| segment.rname == "chr2").unwrap();
+/// assert_eq!(record.aux(b"SA").unwrap(), Aux::String("chr2,500,-,40M60S,30,1;"));
+/// retain_sa_segments(&mut record, |_| false).unwrap();
+/// assert!(record.aux(b"SA").is_err());
+/// ```
+pub fn retain_sa_segments<F>(record: &mut Record, keep: F) -> Result<(), FilterClippedError>
+where
+    F: Fn(&SaSegment) -> bool,
+{
+    let segments: Vec<SaSegment> = match record.aux(b"SA") {
+        Ok(Aux::String(sa)) => parse_sa_tag(sa)?,
+        _ => return Ok(()),
+    };
+    let kept: Vec<SaSegment> = segments.iter().filter(|s| keep(s)).cloned().collect();
+    if kept.len() == segments.len() {
+        return Ok(());
+    }
+    record.remove_aux(b"SA")?;
+    if !kept.is_empty() {
+        record.push_aux(b"SA", Aux::String(&format_sa_tag(&kept)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
+
+    #[rstest]
+    #[case("50M", false, (0, 50, 50))]
+    #[case("10S40M", false, (10, 50, 50))]
+    #[case("10S40M", true, (0, 40, 50))]
+    #[case("5H10S20M2I3D18M5S", false, (15, 55, 60))]
+    fn test_query_interval(
+        #[case] cigar: &str,
+        #[case] reverse: bool,
+        #[case] expected: (i64, i64, i64),
+    ) {
+        assert_eq!(query_interval(cigar, reverse).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("chr1,100,+,60S40M,60")]
+    #[case("chr1,100,*,60S40M,60,0")]
+    #[case("chr1,abc,+,60S40M,60,0")]
+    #[should_panic]
+    fn test_parse_sa_tag_panic(#[case] sa: &str) {
+        parse_sa_tag(sa).unwrap();
+    }
+
+    #[rstest]
+    #[case("60M40S", false, "chr2,5001,+,60H40M,60,0;", (1058, 5000))]
+    #[case("60M40S", false, "chr2,5001,-,40M60H,60,0;", (1058, 5039))]
+    #[case("40S60M", false, "chr2,5001,+,40M60S,60,0;", (999, 5039))]
+    #[case("40S60M", true, "chr2,5001,+,60H40M,60,0;", (999, 5000))]
+    #[case("10S80M10S", false, "chr2,5001,+,90S10M,60,0;chr3,101,+,10M90S,60,0;", (1078, 5000))]
+    fn test_junctions(
+        #[case] cigar: &str,
+        #[case] reverse: bool,
+        #[case] sa: &str,
+        #[case] expected: (i64, i64),
+    ) {
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(if reverse { 0x10 } else { 0 });
+        record.set_pos(999);
+        record.push_aux(b"SA", Aux::String(sa)).unwrap();
+        let junctions = junctions(&record, "chr1").unwrap();
+        assert_eq!(junctions.len(), sa.matches(';').count());
+        assert_eq!((junctions[0].0.pos, junctions[0].1.pos), expected);
+    }
+
+    #[rstest]
+    #[case("60M40S", false, "60H40M", false, "60S40M")]
+    #[case("60M40S", false, "40M60H", true, "40M60S")]
+    #[case("60M40S", true, "5H10S20M65H", false, "15S20M65S")]
+    #[case("10H50M40S", false, "60H40M", false, "60H40M")]
+    #[case("60M40S", false, "60H30M", false, "60H30M")]
+    fn test_promote_supplementary(
+        #[case] primary_cigar: &str,
+        #[case] primary_reverse: bool,
+        #[case] cigar: &str,
+        #[case] reverse: bool,
+        #[case] expected_cigar: &str,
+    ) {
+        let seq: Vec<u8> = (0..100).map(|i| b"AACGT"[i % 5]).collect();
+        let set = |record: &mut Record, cigar: &str, reverse: bool| {
+            let cigar = CigarString::try_from(cigar).unwrap();
+            let len: usize = cigar
+                .iter()
+                .filter(|op| op.char() != 'H')
+                .map(|op| op.len() as usize)
+                .sum();
+            record.set(b"read", Some(&cigar), &seq[..len], &vec![30; len]);
+            if reverse {
+                record.set_reverse();
+            }
+        };
+        let mut primary = Record::new();
+        set(&mut primary, primary_cigar, primary_reverse);
+        let mut supplementary = Record::new();
+        set(&mut supplementary, cigar, reverse);
+        supplementary.set_supplementary();
+        let hardclipped: bool = expected_cigar.contains('H');
+
+        promote_supplementary(&primary, &mut supplementary).unwrap();
+        assert!(!supplementary.is_supplementary());
+        assert_eq!(supplementary.cigar().to_string(), expected_cigar);
+        let expected_seq: Vec<u8> = match (hardclipped, primary_reverse != reverse) {
+            (true, _) => seq[..supplementary.seq_len()].to_vec(),
+            (false, true) => reverse_complement(&seq),
+            (false, false) => seq.clone(),
+        };
+        assert_eq!(supplementary.seq().as_bytes(), expected_seq);
+    }
+
+    #[rstest]
+    #[case("60M40S", false, "chr1,5000,+,60S40M,60,0;", 0, 0, 0)]
+    #[case("50M50S", false, "chr1,8000,+,50S20M30S,60,0;", 0, 30, 30)]
+    #[case("30S70M", true, "chr1,9000,+,70S30M,60,0;", 0, 0, 0)]
+    #[case("10S30M60S", false, "chr1,9000,+,60S30M10S,60,0;", 10, 10, 40)]
+    fn test_union_clip_stat(
+        #[case] cigar: &str,
+        #[case] reverse: bool,
+        #[case] sa: &str,
+        #[case] expected_left: i64,
+        #[case] expected_right: i64,
+        #[case] expected_total: i64,
+    ) {
+        let cigar = rust_htslib::bam::record::CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        if reverse {
+            record.set_reverse();
+        }
+        record.push_aux(b"SA", Aux::String(sa)).unwrap();
+        let (clip_stat, read_len) = union_clip_stat(&record).unwrap().unwrap();
+        assert_eq!(read_len, 100.0);
+        assert_eq!(clip_stat.left(), expected_left);
+        assert_eq!(clip_stat.right(), expected_right);
+        assert_eq!(clip_stat.total_clipped(), expected_total);
+    }
+}
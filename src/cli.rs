@@ -1,4 +1,13 @@
-pub use clap::Parser;
+use crate::adapters::{self, AdapterPolicy};
+use crate::config::{
+    parse_contig_thresholds, parse_length_thresholds, AlreadyFilteredPolicy, FilterConfig,
+    FlagPolicy, OutputFormat, PairPolicy, Preset, ScoreWeights, SeqlessPolicy, SingletonPolicy,
+    Strand, Thresholds, UnmappedPolicy, UNALIGN_STRIP_TAGS,
+};
+use crate::error::FilterClippedError;
+use crate::logging::LogFormat;
+use crate::primers;
+pub use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, ValueSource};
 use std::string::String;
 
 /// Remove alignments with high number of clipped base. Sometimes aligner has very loose scoring methods and write alignments with
@@ -7,6 +16,8 @@ use std::string::String;
 /// in relative to the read sequence length
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(subcommand_negates_reqs = true)]
+#[clap(group(ArgGroup::new("pairing").args(&["paired", "two-pass"])))]
 pub struct Command {
     /// maximum fraction of bases on the sequence being clipped
     /// from the left side (5' end)
@@ -22,9 +33,46 @@ pub struct Command {
     #[clap(short, long, value_parser=check_fraction, default_value_t = 0.1)]
     pub both_end: f64,
 
-    /// input bam file path  ("-" for stdin)
-    #[clap(short, long, value_parser)]
-    pub in_bam: String,
+    /// --left-side for the first mates (read 1) only, e.g. when adapter read-through affects
+    /// the mates differently
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub left_side_r1: Option<f64>,
+
+    /// --right-side for the first mates (read 1) only
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub right_side_r1: Option<f64>,
+
+    /// --both-end for the first mates (read 1) only
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub both_end_r1: Option<f64>,
+
+    /// --left-side for the second mates (read 2) only
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub left_side_r2: Option<f64>,
+
+    /// --right-side for the second mates (read 2) only
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub right_side_r2: Option<f64>,
+
+    /// --both-end for the second mates (read 2) only
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub both_end_r2: Option<f64>,
+
+    /// input bam file path  ("-" for stdin, or an http/https/ftp/s3 URL); can be repeated to
+    /// filter several files into one output, with their headers merged
+    #[clap(
+        short,
+        long,
+        value_parser,
+        multiple_occurrences = true,
+        required_unless_present_any = &["config", "dump-config", "in-bam-list"]
+    )]
+    pub in_bam: Vec<String>,
+
+    /// file listing input bam files, one path per line (e.g. per-lane files), filtered after
+    /// any --in-bam into the same output with their headers merged
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub in_bam_list: Option<String>,
 
     /// output bam file path ("-" for stdout)
     #[clap(short, long, value_parser, default_value = "-")]
@@ -37,6 +85,1002 @@ pub struct Command {
     /// make the record to unmapped instead of removing it, ignore --inverse flag
     #[clap(short, long, action)]
     pub unalign: bool,
+
+    /// keep the failed alignments in place with MAPQ set to 0 instead of removing them
+    /// ("present but untrusted" for variant callers), ignore --inverse flag
+    #[clap(long, action, conflicts_with = "unalign")]
+    pub zero_mapq: bool,
+
+    /// with --zero-mapq, write why an alignment failed (e.g. total_clip) into this aux tag
+    #[clap(long, value_parser, value_name = "TAG", requires = "zero-mapq")]
+    pub reason_tag: Option<String>,
+
+    /// comma-separated aux tags removed from the alignments made unmapped with --unalign
+    /// (which also lose their CIGAR and MAPQ), none if given without a value
+    #[clap(
+        long,
+        value_parser,
+        value_name = "TAGS",
+        value_delimiter = ',',
+        min_values = 0,
+        default_values = &UNALIGN_STRIP_TAGS
+    )]
+    pub unalign_strip_tags: Vec<String>,
+
+    /// reverse complement SEQ and reverse QUAL of the reverse strand alignments made unmapped
+    /// with --unalign, so that they match the reads of the original FASTQ
+    #[clap(long, action, requires = "unalign")]
+    pub restore_orientation: bool,
+
+    /// write the alignments made unmapped with --unalign into this unaligned BAM (SAM for a
+    /// .sam path, header with only the @HD, @RG and @PG lines) instead of the output, which
+    /// then only holds the passing alignments, e.g. to realign the failed reads
+    #[clap(long, value_parser, value_name = "PATH", requires = "unalign")]
+    pub unaligned_out: Option<String>,
+
+    /// write the alignments made unmapped with --unalign after all the mapped ones, so that a
+    /// coordinate sorted output stays sorted and can be indexed (--write-index)
+    #[clap(long, action, requires = "unalign", conflicts_with = "unaligned-out")]
+    pub unaligned_to_end: bool,
+
+    /// how to handle secondary alignments: keep them without checking the clipping,
+    /// drop them, or filter them like primary alignments
+    #[clap(long, value_enum, default_value_t = FlagPolicy::Filter)]
+    pub secondary: FlagPolicy,
+
+    /// how to handle the alignments already unmapped in the input: keep them unless the rest
+    /// of their read fails, drop them, or pass them through whatever happens to their read
+    #[clap(long, value_enum, default_value_t = UnmappedPolicy::Keep)]
+    pub unmapped: UnmappedPolicy,
+
+    /// how to handle the secondary alignments without SEQ (hard clips only), whose clip
+    /// fractions can't be worked out: follow the rest of their read name in --paired,
+    /// --two-pass or --cascade mode (kept otherwise), keep them, or drop them
+    #[clap(long, value_enum, default_value_t = SeqlessPolicy::Inherit)]
+    pub seqless_secondary: SeqlessPolicy,
+
+    /// how to handle supplementary alignments: keep them without checking the clipping,
+    /// drop them, or filter them like primary alignments
+    #[clap(long, value_enum, default_value_t = FlagPolicy::Filter)]
+    pub supplementary: FlagPolicy,
+
+    /// skip checking PCR/optical duplicates (flag 0x400): drop them entirely (default when no value is given),
+    /// or keep them without checking the clipping
+    #[clap(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        min_values = 0,
+        default_missing_value = "drop"
+    )]
+    pub skip_duplicates: Option<FlagPolicy>,
+
+    /// remove alignments already flagged as QC-fail (flag 0x200) before checking the clipping
+    #[clap(long, action, conflicts_with = "filter-qcfail")]
+    pub ignore_qcfail: bool,
+
+    /// check alignments already flagged as QC-fail (flag 0x200) like any other alignment (default)
+    #[clap(long, action)]
+    pub filter_qcfail: bool,
+
+    /// remove alignments without the proper-pair flag (0x2) before checking the clipping, like
+    /// samtools view -f 2, counted apart in the summary (made unmapped with --unalign)
+    #[clap(long, action)]
+    pub require_proper_pair: bool,
+
+    /// only check the clipping of the alignments on this strand, the others are written out
+    /// untouched, e.g. for strand-specific protocols with asymmetric clipping artifacts
+    #[clap(long, value_enum, value_name = "STRAND")]
+    pub only_strand: Option<Strand>,
+
+    /// evaluate the mates of a read pair together, so that no orphaned mates are written out.
+    /// Input must be queryname sorted or collated
+    #[clap(long, action)]
+    pub paired: bool,
+
+    /// in --paired mode, group the alignments by read name internally, for input that isn't
+    /// queryname sorted or collated (e.g. coordinate sorted); the output is collated
+    #[clap(long, action, requires = "paired")]
+    pub collate: bool,
+
+    /// number of alignments of incomplete reads held in memory by --collate before they are
+    /// spilled into temporary files next to the output
+    #[clap(long, value_parser, default_value_t = 1_000_000, value_name = "N")]
+    pub collate_max_pending: usize,
+
+    /// read the input file twice to evaluate the mates of a read pair together, for coordinate sorted
+    /// input where mates are far apart. Failing read names are held in memory between the two passes.
+    /// Doesn't work with stdin
+    #[clap(long, action)]
+    pub two_pass: bool,
+
+    /// in --paired or --two-pass mode, keep a read pair if both mates pass or if any of the mates passes
+    #[clap(long, value_enum, default_value_t = PairPolicy::Both, requires = "pairing")]
+    pub pair_policy: PairPolicy,
+
+    /// in --paired or --two-pass mode, decide on a read using its primary alignments only,
+    /// and remove (or unalign) its secondary/supplementary alignments together with a failing primary
+    /// (not with a flag policy of keep)
+    #[clap(long, action, requires = "pairing")]
+    pub cascade: bool,
+
+    /// for chimeric reads with an SA tag, only count bases not covered by any of the read's segments as clipped,
+    /// and remove SA tag entries pointing at removed or unaligned segments
+    /// (all segments of a read are only seen together in --paired mode)
+    #[clap(long, action)]
+    pub sa_aware: bool,
+
+    /// in --paired mode, when a primary alignment fails but a supplementary alignment of the same
+    /// read (or mate) passes, make the supplementary alignment primary (soft clipping the whole
+    /// read back in and rewriting the SA tags) and remove the failing one, rather than losing the read
+    #[clap(long, action, requires = "paired")]
+    pub promote_supplementary: bool,
+
+    /// in --paired or --two-pass mode, mark the alignments written without their mate as having an
+    /// unmapped mate, clearing the mate position, TLEN and the MC/MQ tags
+    #[clap(long, action, requires = "pairing")]
+    pub fix_orphans: bool,
+
+    /// in --paired or --two-pass mode, decide on the mates of a failing pair one by one, and keep
+    /// the passing mate, drop it, or write it into its own file with `separate-out PATH`
+    #[clap(
+        long,
+        value_parser,
+        min_values = 1,
+        max_values = 2,
+        value_name = "POLICY",
+        requires = "pairing"
+    )]
+    pub singletons: Option<Vec<String>>,
+
+    /// record the thresholds, mode flags, input path and a timestamp as a JSON @CO comment
+    /// in the output header
+    #[clap(long, action)]
+    pub header_comment: bool,
+
+    /// what to do when the input was already filtered by filter-clipped with the same
+    /// settings (going by the @PG records of its header): warn and filter it again, skip the
+    /// run without writing any output, or proceed without a warning
+    #[clap(long, value_enum, default_value_t = AlreadyFilteredPolicy::Warn)]
+    pub if_already_filtered: AlreadyFilteredPolicy,
+
+    /// output format, guessed from the extension of --out-bam if not given (BAM for stdout)
+    #[clap(long, value_enum)]
+    pub output_fmt: Option<OutputFormat>,
+
+    /// reference fasta file, needed for reading or writing CRAM files
+    #[clap(long, value_parser)]
+    pub reference: Option<String>,
+
+    /// index the output file (.bai/.csi for BAM, .crai for CRAM) after writing it,
+    /// the input needs to be coordinate sorted
+    #[clap(long, action)]
+    pub write_index: bool,
+
+    /// skip this many alignments at the start of the input
+    #[clap(long, value_parser, default_value_t = 0, value_name = "N")]
+    pub skip_records: u64,
+
+    /// stop after filtering this many alignments (after --skip-records), writing a valid
+    /// truncated output and the usual summary, e.g. to try out thresholds on a large file
+    #[clap(long, visible_alias = "head", value_parser, value_name = "N")]
+    pub max_records: Option<u64>,
+
+    /// filter windows of the reference over this many threads and merge them in coordinate
+    /// order, for an indexed input file (a single stream without an index)
+    #[clap(long, value_parser, default_value_t = 1)]
+    pub region_threads: usize,
+
+    /// size in bases of the reference windows filtered in parallel with --region-threads
+    #[clap(long, value_parser, default_value_t = 10_000_000)]
+    pub region_size: u64,
+
+    /// with --region-threads, write the windows out in the order of the input (default)
+    #[clap(long, action, conflicts_with = "unordered")]
+    pub ordered: bool,
+
+    /// with --region-threads, write the windows out as soon as they are done, faster when the
+    /// output is sorted afterwards anyway; the output is unsorted
+    #[clap(long, action)]
+    pub unordered: bool,
+
+    /// also write the output alignments into this directory, split into one BAM file per
+    /// reference sequence (<name>.bam) plus unmapped.bam, e.g. for per-chromosome jobs
+    #[clap(long, value_parser, value_name = "OUTDIR")]
+    pub shard_by_chrom: Option<String>,
+
+    /// write the failing alignments, as they were in the input, into this directory with one
+    /// BAM file per reason they failed for (left_clip.bam, right_clip.bam, total_clip.bam, ...)
+    #[clap(long, value_parser, value_name = "DIR")]
+    pub split_by_reason: Option<String>,
+
+    /// keep rewriting this file with the counters of the run (alignments read/written/failed,
+    /// bytes, throughput) in the Prometheus text format, e.g. for a node exporter's textfile
+    /// collector
+    #[clap(long, value_parser, value_name = "PATH")]
+    pub metrics_out: Option<String>,
+
+    /// seconds between two rewrites of --metrics-out
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 10)]
+    pub metrics_interval: u64,
+
+    /// abort instead of warning when the sort order in the input header doesn't fit
+    /// the other options (e.g. --paired needs queryname sorted or collated input)
+    #[clap(long, action)]
+    pub require_sorted: bool,
+
+    /// exit with code 3 (after writing all the outputs) if no alignment passed the filter
+    #[clap(long, action)]
+    pub fail_if_empty: bool,
+
+    /// exit with code 3 (after writing all the outputs) if more than this fraction of the
+    /// alignments read failed the filter
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub fail_if_removed_frac: Option<f64>,
+
+    /// skip and count the records that can't be read (up to --max-malformed of them),
+    /// with a warning at the end, instead of aborting at the first one
+    #[clap(long, action, conflicts_with = "strict")]
+    pub tolerant: bool,
+
+    /// abort at the first record that can't be read (the default, overrides a settings file)
+    #[clap(long, action)]
+    pub strict: bool,
+
+    /// most unreadable records skipped with --tolerant before aborting anyway
+    #[clap(long, value_parser, default_value_t = 1000, value_name = "N")]
+    pub max_malformed: u64,
+
+    /// show a progress bar with the records processed and the throughput on stderr,
+    /// and an ETA if the input is indexed
+    #[clap(long, action)]
+    pub progress: bool,
+
+    /// print a table like `samtools flagstat` on stderr at the end of the run: the numbers of
+    /// alignments read, written, unaligned and removed in total, and for the primary, secondary,
+    /// supplementary, duplicate, paired, read 1, read 2 and properly paired alignments
+    #[clap(long, action)]
+    pub flagstat: bool,
+
+    /// write binned counts of the left/right/total clip fractions and clip lengths of the
+    /// evaluated alignments into this TSV file (columns: metric, bin_start, bin_end, count)
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub hist_out: Option<String>,
+
+    /// write the run summary, clip fraction and length histograms and per-reference-sequence
+    /// counts into this single static HTML page, e.g. to share with collaborators
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub html_report: Option<String>,
+
+    /// write the numbers of alignments beginning or ending a soft clip at each reference
+    /// position (or window, see --profile-window) into this TSV file, e.g. to screen for
+    /// breakpoints or adapter pile-ups
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub clip_profile: Option<String>,
+
+    /// write a BED file of the reference positions (or windows, see --profile-window) where
+    /// soft clip boundaries cluster, with the number of supporting alignments as score,
+    /// e.g. to screen for structural variant candidates
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub breakpoint_bed: Option<String>,
+
+    /// fewest alignments with a soft clip boundary at a position to report it in --breakpoint-bed
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 3)]
+    pub min_breakpoint_support: u64,
+
+    /// size in bases of the reference windows of --clip-profile and --breakpoint-bed
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1)]
+    pub profile_window: u64,
+
+    /// write the consensus of the soft clipped sequences at each clip boundary shared by
+    /// enough alignments (see --min-consensus-support) into this FASTA file, e.g. to identify
+    /// inserted sequences or viral integration sites
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub clip_consensus: Option<String>,
+
+    /// fewest alignments clipped at a boundary to report it in --clip-consensus; the consensus
+    /// sequence also stops where fewer clipped sequences are left
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 3)]
+    pub min_consensus_support: u64,
+
+    /// write binned counts of the GC content of the soft clipped and the aligned sequence of
+    /// the evaluated alignments into this TSV file (columns: segment, bin_start, bin_end,
+    /// count), e.g. to tell adapter clips (a sharp peak) from genomic clips
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub gc_out: Option<String>,
+
+    /// write the k-mers most enriched in the soft clipped sequence of the evaluated alignments
+    /// relative to their aligned sequence into this TSV file (columns: kmer, clipped, aligned,
+    /// enrichment), e.g. to discover unknown adapters or contaminants driving the clipping
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub kmer_out: Option<String>,
+
+    /// length of the k-mers of --kmer-out; the counts of the aligned sequence take up to 4^k
+    /// entries in memory
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..=32), default_value_t = 8)]
+    pub kmer_size: u64,
+
+    /// most k-mers written into --kmer-out
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 50)]
+    pub top_kmers: u64,
+
+    /// write pass/fail counts and mean clip fractions by cell barcode (single-cell data)
+    /// into this TSV file
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub cell_stats: Option<String>,
+
+    /// file of cell barcodes (plain or gzipped, one per line): alignments with another (or no)
+    /// cell barcode are removed; barcodes are matched with or without their "-1" suffix
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub cb_whitelist: Option<String>,
+
+    /// file of read names (plain or gzipped, one per line) whose alignments are kept
+    /// without checking their flags and clipping
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub keep_names: Option<String>,
+
+    /// file of read names (plain or gzipped, one per line) whose alignments are removed,
+    /// even if also listed in --keep-names
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub drop_names: Option<String>,
+
+    /// write the read names of the removed alignments into this file, one alignment per line
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub removed_names: Option<String>,
+
+    /// with --removed-names, also write the flag and the reason of the removal
+    /// (tab-separated) after each read name
+    #[clap(long, action, requires = "removed-names")]
+    pub removed_details: bool,
+
+    /// write the name, flag, position, clip fractions and decision of every alignment into
+    /// this Parquet file (needs filter-clipped built with the parquet feature)
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub metrics_parquet: Option<String>,
+
+    /// write the junctions between the primary alignments and the segments in their SA tags
+    /// into this BEDPE file, as candidate fusions or structural variants
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub junction_bedpe: Option<String>,
+
+    /// fewest clipped bases on one side of a primary alignment to report its junctions
+    /// in --junction-bedpe
+    #[clap(long, value_parser, default_value_t = 20)]
+    pub min_junction_clip: u64,
+
+    /// aux tag holding the cell barcode, for --cell-stats and --cb-whitelist
+    #[clap(long, value_parser, default_value = "CB")]
+    pub cell_tag: String,
+
+    /// minimum mapping quality, alignments below it fail the filter like highly clipped ones
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub min_mapq: u8,
+
+    /// highest fraction of inserted and deleted bases (relative to the read length), gappy
+    /// alignments passing the clipping thresholds fail the filter above it
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub max_indel_fraction: Option<f64>,
+
+    /// highest fraction of mismatched bases (NM tag minus the inserted and deleted bases,
+    /// relative to the aligned bases), alignments passing the clipping thresholds fail the
+    /// filter above it; alignments without an NM tag aren't checked
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub max_mismatch_fraction: Option<f64>,
+
+    /// lowest fraction of the read (hard clipped bases included) aligned as M/=/X bases,
+    /// alignments passing the clipping thresholds fail the filter below it; with thresholds of
+    /// 1 the alignments are only gated on it
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub min_query_coverage: Option<f64>,
+
+    /// shortest contiguous run of aligned (M/=/X) bases, alignments passing the clipping
+    /// thresholds without one fail the filter, e.g. to keep only anchored noisy long reads
+    #[clap(long, value_parser, value_name = "N")]
+    pub min_aligned_block: Option<u64>,
+
+    /// longest insertion or deletion not breaking a run of aligned bases for
+    /// --min-aligned-block
+    #[clap(long, value_parser, default_value_t = 5, value_name = "N")]
+    pub aligned_block_max_indel: u32,
+
+    /// lowest fraction of a split read covered by the union of the primary and the
+    /// supplementary segments of its SA tag; split reads are gated on it instead of the
+    /// clipping thresholds, so that chimeric reads aren't failed for the clips of each segment
+    #[clap(long, value_parser = check_fraction, value_name = "FRACTION")]
+    pub min_split_coverage: Option<f64>,
+
+    /// smallest insert size (absolute TLEN) of paired alignments, alignments passing the
+    /// clipping thresholds with a smaller one fail the filter; TLEN 0 isn't checked
+    #[clap(long, value_parser, value_name = "N")]
+    pub min_insert_size: Option<u64>,
+
+    /// largest insert size (absolute TLEN) of paired alignments, e.g. to also remove wildly
+    /// discordant pairs, often heavily clipped too; TLEN 0 isn't checked
+    #[clap(long, value_parser, value_name = "N")]
+    pub max_insert_size: Option<u64>,
+
+    /// filter expression like samtools view -e, e.g. 'mapq >= 20 && [NM] <= 5': alignments
+    /// passing the clipping thresholds fail the filter if it isn't true on them. Fields: qname,
+    /// flag, rname, pos, endpos, mapq, mrname, mpos, tlen, qlen, rlen, sclen, hclen, ncigar,
+    /// flag.<name> (e.g. flag.reverse); aux tags as [NM]; no regular expressions
+    #[clap(short = 'e', long, value_parser, value_name = "EXPR")]
+    pub expression: Option<String>,
+
+    /// don't count low-complexity soft clips (e.g. homopolymer or dinucleotide repeat tails)
+    /// as clipped
+    #[clap(long, action)]
+    pub exempt_low_complexity: bool,
+
+    /// don't count soft clips made of repeats of these motifs as clipped (comma separated,
+    /// either strand), e.g. TTAGGG for the telomeric reads clipped at chromosome ends
+    #[clap(
+        long = "exempt-motif",
+        value_parser,
+        value_name = "MOTIF",
+        value_delimiter = ','
+    )]
+    pub exempt_motifs: Vec<String>,
+
+    /// lowest fraction of the bases of a soft clip matching the repeat of an --exempt-motif
+    /// for the clip not to count
+    #[clap(long, value_parser = check_fraction, default_value_t = 0.9, requires = "exempt-motifs")]
+    pub motif_purity: f64,
+
+    /// only count the soft clipped bases with at least this base quality as clipped,
+    /// as low quality tails are expected to be clipped
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub min_clip_bq: u8,
+
+    /// log more details (-v for debug, -vv for trace), overrides RUST_LOG
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// only log errors, overrides RUST_LOG
+    #[clap(short, long, action, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// write the logs to this file instead of stderr
+    #[clap(long, value_parser)]
+    pub log_file: Option<String>,
+
+    /// format of the logs: text lines, or one JSON object per event (with the counters of the
+    /// run in the summary event) for log aggregators
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// set the thresholds for a data type (on top of --config), explicitly given thresholds
+    /// take precedence: short-read (0.1/0.1/0.1), long-read (0.3/0.2/0.2, hard clips of
+    /// supplementary alignments not counted), amplicon (0.1/0.05/0.05), rna (0.2/0.1/0.1)
+    #[clap(long, value_enum)]
+    pub preset: Option<Preset>,
+
+    /// don't count the hard clipped bases of supplementary alignments, which aligners use
+    /// for the parts of a chimeric read aligned elsewhere
+    #[clap(long, action)]
+    pub ignore_supplementary_hardclips: bool,
+
+    /// splice-aware mode for RNA alignments: spliced alignments (N in their CIGAR string)
+    /// aren't filtered on clipping, or get --spliced-thresholds
+    #[clap(long, action)]
+    pub rna: bool,
+
+    /// thresholds for spliced alignments with --rna, as <fraction> or
+    /// <both_end>/<left_side>/<right_side>
+    #[clap(long, value_parser, value_name = "THRESHOLDS", requires = "rna")]
+    pub spliced_thresholds: Option<String>,
+
+    /// thresholds by read length (hard clipped bases included), e.g. "0-200:0.1,200-5000:0.2,5000-:0.3",
+    /// ranges include their start and exclude their end; a fraction can also be given
+    /// as <both_end>/<left_side>/<right_side>
+    #[clap(long, value_parser, value_name = "SCHEDULE")]
+    pub length_thresholds: Option<String>,
+
+    /// combine the clip fractions into one weighted score checked against --max-score, instead
+    /// of the separate thresholds, e.g. "left=1,right=1,total=0.5,nm=1" (nm: NM tag relative to
+    /// the read length; weights left out are 0)
+    #[clap(long, value_parser, value_name = "WEIGHTS")]
+    pub score_weights: Option<String>,
+
+    /// highest weighted score an alignment can have to pass, with --score-weights
+    #[clap(long, value_parser, default_value_t = 0.1)]
+    pub max_score: f64,
+
+    /// FASTA file of adapter sequences: alignments whose soft clipped bases are one of these
+    /// adapters are handled with --adapter-policy, and counted in the summary
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub adapters: Option<String>,
+
+    /// with --adapters, trim the clipped adapter bases off and don't count them as clipped,
+    /// or fail the alignment
+    #[clap(long, value_enum, default_value_t = AdapterPolicy::Trim)]
+    pub adapter_policy: AdapterPolicy,
+
+    /// highest fraction of mismatching bases between a clip and an adapter
+    #[clap(long, value_parser=check_fraction, default_value_t = 0.1)]
+    pub adapter_mismatch_rate: f64,
+
+    /// FASTA file of contaminant sequences (e.g. vectors, primers, spike-ins): soft clips
+    /// sharing a k-mer with one of them are counted by contaminant in the summary
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub screen_fasta: Option<String>,
+
+    /// length of the k-mers shared by a soft clip and a contaminant of --screen-fasta
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..=32),
+        default_value_t = 16,
+        requires = "screen-fasta"
+    )]
+    pub screen_kmer_size: u64,
+
+    /// fail the alignments with a contaminant of --screen-fasta in their soft clipped bases
+    #[clap(long, action, requires = "screen-fasta")]
+    pub screen_fail: bool,
+
+    /// BED file of primer intervals (e.g. of an amplicon panel): clips whose reference
+    /// span lies within a primer are not counted as clipped
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub primer_bed: Option<String>,
+
+    /// derive the total, left and right thresholds from a first pass over the input, keeping
+    /// this percentile (0-100) of the alignments on each of them; doesn't work with stdin
+    #[clap(
+        long,
+        value_parser,
+        value_name = "PERCENTILE",
+        conflicts_with_all = &["left-side", "right-side", "both-end"]
+    )]
+    pub auto_threshold: Option<f64>,
+
+    /// pick --preset from the aligner in the @PG records of the input: bwa and bowtie2
+    /// short-read, minimap2 long-read, STAR rna; doesn't work with stdin
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = &["preset", "left-side", "right-side", "both-end"]
+    )]
+    pub auto_preset: bool,
+
+    /// keep this fraction (0-1) of the passing reads, picked by a hash of the read name so
+    /// that mates stay together (like filtering, then `samtools view -s`)
+    #[clap(long, value_parser=check_fraction, value_name = "FRACTION")]
+    pub subsample: Option<f64>,
+
+    /// seed of --subsample, the same seed keeping the same reads
+    #[clap(long, value_parser, default_value_t = 0, requires = "subsample")]
+    pub seed: u64,
+
+    /// tab-separated table of thresholds for specific reference sequences (e.g. chrM or spike-ins),
+    /// one line per reference sequence: <name> <both_end> <left_side> <right_side>
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub contig_thresholds: Option<String>,
+
+    /// read the thresholds, paths and mode flags from a TOML (or .yaml/.json) settings file,
+    /// options given on the command line take precedence
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// write the effective settings (--config file and command line options) to a TOML
+    /// (or .yaml/.json) settings file ("-" for stdout) and exit without filtering
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub dump_config: Option<String>,
+
+    /// scan the input and print (to stdout) the --left-side/--right-side/--both-end thresholds
+    /// keeping these percentages of the alignments (comma-separated, 90,95,99 if none are
+    /// given), then exit without filtering; doesn't work with stdin
+    #[clap(
+        long,
+        value_parser = check_percent,
+        value_name = "PERCENTS",
+        value_delimiter = ',',
+        min_values = 0
+    )]
+    pub suggest_thresholds: Option<Vec<f64>>,
+
+    #[clap(subcommand)]
+    pub utility: Option<Utility>,
+}
+
+/// Utilities run instead of the filtering
+#[derive(clap::Subcommand, Debug, PartialEq, Eq)]
+pub enum Utility {
+    /// print a man page (roff) of the command line options to stdout, e.g.
+    /// `filter-clipped man > /usr/local/share/man/man1/filter-clipped.1`
+    Man,
+    /// compare the alignments of two files (e.g. the input and output of a run, or the outputs
+    /// of two runs with different thresholds) and write the ones found in only one of them,
+    /// with their clip fractions, as TSV; unmapped records count as absent. The clip fractions
+    /// follow the options given before the subcommand (e.g. --min-clip-bq), as does the
+    /// --reference of CRAM files
+    Compare {
+        /// first alignment file (SAM/BAM/CRAM, "-" for stdin)
+        #[clap(value_parser)]
+        first: String,
+        /// second alignment file (SAM/BAM/CRAM), read twice
+        #[clap(value_parser)]
+        second: String,
+        /// TSV file of the alignments found in only one of the files ("-" for stdout)
+        #[clap(short, long, value_parser, default_value = "-", value_name = "FILE")]
+        out: String,
+    },
+}
+
+/// Prefix of the environment variables setting the options, e.g. FILTER_CLIPPED_LEFT_SIDE
+/// for --left-side
+pub const ENV_PREFIX: &str = "FILTER_CLIPPED_";
+
+/// Environment variable setting an option
+///
+/// # Example
+/// ```
+/// use filter_clipped::cli::env_name;
+/// assert_eq!(env_name("left-side"), "FILTER_CLIPPED_LEFT_SIDE");
+/// ```
+pub fn env_name(id: &str) -> String {
+    format!("{}{}", ENV_PREFIX, id.to_uppercase().replace('-', "_"))
+}
+
+/// The command line definitions with every option also read from its environment variable
+/// (see [env_name]), so containerized steps can be set up without touching the command line;
+/// options given on the command line take precedence over the environment
+pub fn command_with_env() -> clap::Command<'static> {
+    let command = Command::command();
+    let ids: Vec<&'static str> = command
+        .get_arguments()
+        .map(|arg| arg.get_id())
+        .filter(|id| !matches!(*id, "help" | "version"))
+        .collect();
+    ids.into_iter().fold(command, |command, id| {
+        // built once per run, clap needs the names for the lifetime of the command
+        let name: &'static str = Box::leak(env_name(id).into_boxed_str());
+        command.mut_arg(id, |arg| arg.env(name))
+    })
+}
+
+impl Command {
+    /// Build the settings of the run: the --config file (or the defaults) as a base,
+    /// with the options given on the command line (or through their environment variables)
+    /// on top of it
+    ///
+    /// # Arguments
+    /// - `matches`: the parsed command line, telling which options were given
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::cli::{Command, CommandFactory, FromArgMatches};
+    /// let matches = Command::command().get_matches_from(["filter-clipped", "-i", "in.bam", "-b", "0.3"]);
+    /// let config = Command::from_arg_matches(&matches).unwrap().to_config(&matches).unwrap();
+    /// assert_eq!(config.in_bam, "in.bam");
+    /// assert_eq!(config.both_end, 0.3);
+    /// assert_eq!(config.left_side, 0.1);
+    /// ```
+    pub fn to_config(&self, matches: &ArgMatches) -> Result<FilterConfig, FilterClippedError> {
+        let mut config: FilterConfig = match self.config.as_deref() {
+            Some(path) => FilterConfig::from_file(path)?,
+            None => FilterConfig::default(),
+        };
+        if let Some(preset) = self.preset {
+            config = config.preset(preset);
+        }
+        let given = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        let mut inputs: Vec<String> = self.in_bam.clone();
+        if let Some(path) = self.in_bam_list.as_deref() {
+            let text: String = std::fs::read_to_string(path)?;
+            inputs.extend(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        if let Some((in_bam, extra_inputs)) = inputs.split_first() {
+            config.in_bam = in_bam.clone();
+            config.extra_inputs = extra_inputs.to_vec();
+        }
+        if given("out-bam") {
+            config.out_bam = self.out_bam.clone();
+        }
+        if given("left-side") {
+            config.left_side = self.left_side;
+        }
+        if given("right-side") {
+            config.right_side = self.right_side;
+        }
+        if given("both-end") {
+            config.both_end = self.both_end;
+        }
+        // the thresholds of a mate default to the ones of all the alignments
+        for (mate, (left_side, right_side, both_end)) in [
+            (1, (self.left_side_r1, self.right_side_r1, self.both_end_r1)),
+            (2, (self.left_side_r2, self.right_side_r2, self.both_end_r2)),
+        ] {
+            if left_side.is_none() && right_side.is_none() && both_end.is_none() {
+                continue;
+            }
+            let current: Thresholds = match mate {
+                1 => config.read1_thresholds,
+                _ => config.read2_thresholds,
+            }
+            .unwrap_or_else(|| config.thresholds());
+            let thresholds = Thresholds {
+                both_end: both_end.unwrap_or(current.both_end),
+                left_side: left_side.unwrap_or(current.left_side),
+                right_side: right_side.unwrap_or(current.right_side),
+            };
+            match mate {
+                1 => config.read1_thresholds = Some(thresholds),
+                _ => config.read2_thresholds = Some(thresholds),
+            }
+        }
+        if given("secondary") {
+            config.secondary = self.secondary;
+        }
+        if given("unalign-strip-tags") {
+            config.unalign_strip_tags = self.unalign_strip_tags.clone();
+        }
+        if given("unmapped") {
+            config.unmapped = self.unmapped;
+        }
+        if given("seqless-secondary") {
+            config.seqless_secondary = self.seqless_secondary;
+        }
+        if given("supplementary") {
+            config.supplementary = self.supplementary;
+        }
+        if let Some(policy) = self.skip_duplicates {
+            config.duplicates = policy;
+        }
+        if self.ignore_qcfail {
+            config.qcfail = FlagPolicy::Drop;
+        }
+        if self.filter_qcfail {
+            config.qcfail = FlagPolicy::Filter;
+        }
+        if given("pair-policy") {
+            config.pair_policy = self.pair_policy;
+        }
+        if self.output_fmt.is_some() {
+            config.output_fmt = self.output_fmt;
+        }
+        if self.reference.is_some() {
+            config.reference = self.reference.clone();
+        }
+        if let Some(spec) = self.score_weights.as_deref() {
+            config.score_weights = Some(ScoreWeights::parse(spec)?);
+        }
+        if given("max-score") {
+            config.max_score = self.max_score;
+        }
+        if let Some(path) = self.adapters.as_deref() {
+            let text: String = std::fs::read_to_string(path)?;
+            config.adapters = adapters::parse_fasta(&text)?;
+        }
+        if given("adapter-policy") {
+            config.adapter_policy = self.adapter_policy;
+        }
+        if given("adapter-mismatch-rate") {
+            config.adapter_mismatch_rate = self.adapter_mismatch_rate;
+        }
+        if let Some(path) = self.screen_fasta.as_deref() {
+            let text: String = std::fs::read_to_string(path)?;
+            config.contaminants = adapters::parse_fasta(&text)?;
+        }
+        if given("screen-kmer-size") {
+            config.screen_kmer_size = self.screen_kmer_size;
+        }
+        config.screen_fail |= self.screen_fail;
+        if let Some(path) = self.primer_bed.as_deref() {
+            let text: String = std::fs::read_to_string(path)?;
+            config.primers = primers::parse_bed(&text)?;
+        }
+        if let Some(spec) = self.length_thresholds.as_deref() {
+            config.length_thresholds = parse_length_thresholds(spec)?;
+        }
+        config.auto_preset |= self.auto_preset;
+        if self.auto_threshold.is_some() {
+            config.auto_threshold = self.auto_threshold;
+        }
+        if self.subsample.is_some() {
+            config.subsample = self.subsample;
+        }
+        if given("seed") {
+            config.seed = self.seed;
+        }
+        if given("min-mapq") {
+            config.min_mapq = self.min_mapq;
+        }
+        if self.max_indel_fraction.is_some() {
+            config.max_indel_fraction = self.max_indel_fraction;
+        }
+        if self.max_mismatch_fraction.is_some() {
+            config.max_mismatch_fraction = self.max_mismatch_fraction;
+        }
+        if self.min_query_coverage.is_some() {
+            config.min_query_coverage = self.min_query_coverage;
+        }
+        if self.min_aligned_block.is_some() {
+            config.min_aligned_block = self.min_aligned_block;
+        }
+        if self.min_insert_size.is_some() {
+            config.min_insert_size = self.min_insert_size;
+        }
+        if self.max_insert_size.is_some() {
+            config.max_insert_size = self.max_insert_size;
+        }
+        if given("aligned-block-max-indel") {
+            config.aligned_block_max_indel = self.aligned_block_max_indel;
+        }
+        if self.min_split_coverage.is_some() {
+            config.min_split_coverage = self.min_split_coverage;
+        }
+        if self.expression.is_some() {
+            config.expression = self.expression.clone();
+        }
+        if self.hist_out.is_some() {
+            config.hist_out = self.hist_out.clone();
+        }
+        if self.html_report.is_some() {
+            config.html_report = self.html_report.clone();
+        }
+        if self.clip_profile.is_some() {
+            config.clip_profile = self.clip_profile.clone();
+        }
+        if given("profile-window") {
+            config.profile_window = self.profile_window;
+        }
+        if self.breakpoint_bed.is_some() {
+            config.breakpoint_bed = self.breakpoint_bed.clone();
+        }
+        if given("min-breakpoint-support") {
+            config.min_breakpoint_support = self.min_breakpoint_support;
+        }
+        if self.clip_consensus.is_some() {
+            config.clip_consensus = self.clip_consensus.clone();
+        }
+        if given("min-consensus-support") {
+            config.min_consensus_support = self.min_consensus_support;
+        }
+        if self.gc_out.is_some() {
+            config.gc_out = self.gc_out.clone();
+        }
+        if self.kmer_out.is_some() {
+            config.kmer_out = self.kmer_out.clone();
+        }
+        if given("kmer-size") {
+            config.kmer_size = self.kmer_size;
+        }
+        if given("top-kmers") {
+            config.top_kmers = self.top_kmers;
+        }
+        if self.cell_stats.is_some() {
+            config.cell_stats = self.cell_stats.clone();
+        }
+        if self.cb_whitelist.is_some() {
+            config.cb_whitelist = self.cb_whitelist.clone();
+        }
+        if self.keep_names.is_some() {
+            config.keep_names = self.keep_names.clone();
+        }
+        if self.drop_names.is_some() {
+            config.drop_names = self.drop_names.clone();
+        }
+        if self.removed_names.is_some() {
+            config.removed_names = self.removed_names.clone();
+        }
+        config.removed_details |= self.removed_details;
+        if given("max-malformed") {
+            config.max_malformed = self.max_malformed;
+        }
+        if given("skip-records") {
+            config.skip_records = self.skip_records;
+        }
+        if self.max_records.is_some() {
+            config.max_records = self.max_records;
+        }
+        if given("region-threads") {
+            config.region_threads = self.region_threads;
+        }
+        if given("region-size") {
+            config.region_size = self.region_size;
+        }
+        if self.ordered {
+            config.unordered = false;
+        }
+        config.unordered |= self.unordered;
+        if self.unaligned_out.is_some() {
+            config.unaligned_out = self.unaligned_out.clone();
+        }
+        config.unaligned_to_end |= self.unaligned_to_end;
+        if self.split_by_reason.is_some() {
+            config.split_by_reason = self.split_by_reason.clone();
+        }
+        if self.metrics_out.is_some() {
+            config.metrics_out = self.metrics_out.clone();
+        }
+        if given("metrics-interval") {
+            config.metrics_interval = self.metrics_interval;
+        }
+        if self.shard_by_chrom.is_some() {
+            config.shard_by_chrom = self.shard_by_chrom.clone();
+        }
+        if self.metrics_parquet.is_some() {
+            config.metrics_parquet = self.metrics_parquet.clone();
+        }
+        if self.junction_bedpe.is_some() {
+            config.junction_bedpe = self.junction_bedpe.clone();
+        }
+        if given("min-junction-clip") {
+            config.min_junction_clip = self.min_junction_clip;
+        }
+        if given("cell-tag") {
+            config.cell_tag = self.cell_tag.clone();
+        }
+        if given("min-clip-bq") {
+            config.min_clip_bq = self.min_clip_bq;
+        }
+        // switches can only be turned on from the command line
+        config.inverse |= self.inverse;
+        config.unalign |= self.unalign;
+        config.zero_mapq |= self.zero_mapq;
+        if self.reason_tag.is_some() {
+            config.reason_tag = self.reason_tag.clone();
+        }
+        config.require_proper_pair |= self.require_proper_pair;
+        if self.only_strand.is_some() {
+            config.only_strand = self.only_strand;
+        }
+        config.paired |= self.paired;
+        config.collate |= self.collate;
+        if given("collate-max-pending") {
+            config.collate_max_pending = self.collate_max_pending;
+        }
+        config.two_pass |= self.two_pass;
+        config.cascade |= self.cascade;
+        config.sa_aware |= self.sa_aware;
+        config.promote_supplementary |= self.promote_supplementary;
+        config.fix_orphans |= self.fix_orphans;
+        if let Some(values) = self.singletons.as_deref() {
+            config.singletons = Some(SingletonPolicy::parse(values)?);
+        }
+        config.header_comment |= self.header_comment;
+        if given("if-already-filtered") {
+            config.if_already_filtered = self.if_already_filtered;
+        }
+        config.write_index |= self.write_index;
+        config.require_sorted |= self.require_sorted;
+        config.fail_if_empty |= self.fail_if_empty;
+        if self.fail_if_removed_frac.is_some() {
+            config.fail_if_removed_frac = self.fail_if_removed_frac;
+        }
+        config.restore_orientation |= self.restore_orientation;
+        config.tolerant = (config.tolerant || self.tolerant) && !self.strict;
+        config.progress |= self.progress;
+        config.flagstat |= self.flagstat;
+        config.ignore_supplementary_hardclips |= self.ignore_supplementary_hardclips;
+        config.rna |= self.rna;
+        config.exempt_low_complexity |= self.exempt_low_complexity;
+        if !self.exempt_motifs.is_empty() {
+            config.exempt_motifs = self.exempt_motifs.clone();
+        }
+        if given("motif-purity") {
+            config.motif_purity = self.motif_purity;
+        }
+        if let Some(spec) = self.spliced_thresholds.as_deref() {
+            config.spliced_thresholds = Some(Thresholds::parse(spec)?);
+        }
+        if let Some(path) = self.contig_thresholds.as_deref() {
+            let text: String = std::fs::read_to_string(path)?;
+            config
+                .contig_thresholds
+                .extend(parse_contig_thresholds(&text)?);
+        }
+        Ok(config)
+    }
 }
 
 /// check if a give value is between 0 and 1
@@ -61,6 +1105,23 @@ pub fn check_fraction(val: &str) -> Result<f64, String> {
     }
 }
 
+/// Check that a percentage is within 0 and 100
+///
+/// # Example
+/// ```
+/// use filter_clipped::cli::check_percent;
+/// assert_eq!(check_percent("95").unwrap(), 95.0);
+/// assert!(check_percent("101").is_err());
+/// ```
+pub fn check_percent(val: &str) -> Result<f64, String> {
+    let f_val: f64 = val.parse::<f64>().map_err(|e| e.to_string())?;
+    if (0.0..=100.0).contains(&f_val) {
+        Ok(f_val)
+    } else {
+        Err(format!("{} is not within 0 and 100", val))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -81,4 +1142,192 @@ mod tests {
     fn test_check_fraction_panic(#[case] val: &str) {
         check_fraction(val).unwrap();
     }
+
+    #[rstest]
+    #[case(vec![], 0.1, 0.1, 0.1, FlagPolicy::Filter, false)]
+    #[case(vec!["--config", "test/data/filter.toml"], 0.2, 0.05, 0.1, FlagPolicy::Drop, true)]
+    #[case(vec!["--config", "test/data/filter.yaml"], 0.2, 0.05, 0.1, FlagPolicy::Drop, true)]
+    #[case(vec!["--config", "test/data/filter.toml", "-b", "0.4", "--secondary", "keep"], 0.4, 0.05, 0.1, FlagPolicy::Keep, true)]
+    #[case(vec!["--config", "test/data/filter.toml", "--inverse"], 0.2, 0.05, 0.1, FlagPolicy::Drop, true)]
+    #[case(vec!["--preset", "amplicon"], 0.1, 0.05, 0.05, FlagPolicy::Filter, false)]
+    #[case(vec!["--preset", "long-read", "-l", "0.1"], 0.3, 0.1, 0.2, FlagPolicy::Filter, false)]
+    #[case(vec!["--config", "test/data/filter.toml", "--preset", "rna"], 0.2, 0.1, 0.1, FlagPolicy::Drop, true)]
+    fn test_to_config(
+        #[case] options: Vec<&str>,
+        #[case] both_end: f64,
+        #[case] left_side: f64,
+        #[case] right_side: f64,
+        #[case] secondary: FlagPolicy,
+        #[case] inverse: bool,
+    ) {
+        let mut args: Vec<&str> = vec!["filter-clipped", "-i", "test/data/test.sam"];
+        args.extend(options);
+        let matches = Command::command().get_matches_from(args);
+        let config = Command::from_arg_matches(&matches)
+            .unwrap()
+            .to_config(&matches)
+            .unwrap();
+        assert_eq!(config.in_bam, "test/data/test.sam");
+        assert_eq!(config.both_end, both_end);
+        assert_eq!(config.left_side, left_side);
+        assert_eq!(config.right_side, right_side);
+        assert_eq!(config.secondary, secondary);
+        assert_eq!(config.inverse, inverse);
+    }
+
+    #[rstest]
+    #[case(vec!["--paired", "--singletons", "keep"], Some(SingletonPolicy::Keep))]
+    #[case(vec!["--two-pass", "--singletons", "drop"], Some(SingletonPolicy::Drop))]
+    #[case(
+        vec!["--paired", "--singletons", "separate-out", "singletons.bam"],
+        Some(SingletonPolicy::SeparateOut(String::from("singletons.bam")))
+    )]
+    #[case(vec!["--paired", "--singletons", "separate-out"], None)]
+    #[case(vec!["--paired", "--singletons", "keep", "singletons.bam"], None)]
+    fn test_singletons(#[case] options: Vec<&str>, #[case] expected: Option<SingletonPolicy>) {
+        let mut args: Vec<&str> = vec!["filter-clipped", "-i", "test/data/test.sam"];
+        args.extend(options);
+        let matches = Command::command().get_matches_from(args);
+        let config = Command::from_arg_matches(&matches)
+            .unwrap()
+            .to_config(&matches);
+        assert_eq!(config.ok().and_then(|config| config.singletons), expected);
+    }
+
+    #[rstest]
+    #[case(vec![], None, None)]
+    #[case(vec!["--right-side-r2", "0.3"], None, Some([0.1, 0.1, 0.3]))]
+    #[case(
+        vec!["-b", "0.2", "--both-end-r1", "0.15", "--left-side-r2", "0.05"],
+        Some([0.15, 0.1, 0.1]),
+        Some([0.2, 0.05, 0.1])
+    )]
+    #[case(vec!["--preset", "long-read", "--both-end-r2", "0.4"], None, Some([0.4, 0.2, 0.2]))]
+    fn test_mate_thresholds(
+        #[case] options: Vec<&str>,
+        #[case] read1: Option<[f64; 3]>,
+        #[case] read2: Option<[f64; 3]>,
+    ) {
+        let mut args: Vec<&str> = vec!["filter-clipped", "-i", "test/data/test.sam"];
+        args.extend(options);
+        let matches = Command::command().get_matches_from(args);
+        let config = Command::from_arg_matches(&matches)
+            .unwrap()
+            .to_config(&matches)
+            .unwrap();
+        let as_array = |thresholds: Thresholds| {
+            [
+                thresholds.both_end,
+                thresholds.left_side,
+                thresholds.right_side,
+            ]
+        };
+        assert_eq!(config.read1_thresholds.map(as_array), read1);
+        assert_eq!(config.read2_thresholds.map(as_array), read2);
+    }
+
+    #[rstest]
+    #[case(vec![], vec![], 0.9)]
+    #[case(vec!["--exempt-motif", "TTAGGG"], vec!["TTAGGG"], 0.9)]
+    #[case(vec!["--exempt-motif", "TTAGGG,GGAAT", "--motif-purity", "0.8"], vec!["TTAGGG", "GGAAT"], 0.8)]
+    fn test_exempt_motifs(
+        #[case] options: Vec<&str>,
+        #[case] motifs: Vec<&str>,
+        #[case] purity: f64,
+    ) {
+        let mut args: Vec<&str> = vec!["filter-clipped", "-i", "test/data/test.sam"];
+        args.extend(options);
+        let matches = Command::command().get_matches_from(args);
+        let config = Command::from_arg_matches(&matches)
+            .unwrap()
+            .to_config(&matches)
+            .unwrap();
+        assert_eq!(config.exempt_motifs, motifs);
+        assert_eq!(config.motif_purity, purity);
+    }
+
+    #[rstest]
+    #[case(vec!["-i", "a.bam"], "a.bam", vec![])]
+    #[case(vec!["-i", "a.bam", "-i", "b.bam", "--in-bam", "c.bam"], "a.bam", vec!["b.bam", "c.bam"])]
+    #[case(vec!["--in-bam-list", "test/data/inputs.txt"], "test/data/adapters.sam", vec!["test/data/lane2.sam"])]
+    #[case(vec!["-i", "a.bam", "--in-bam-list", "test/data/inputs.txt"], "a.bam", vec!["test/data/adapters.sam", "test/data/lane2.sam"])]
+    fn test_inputs(
+        #[case] options: Vec<&str>,
+        #[case] expected_in_bam: &str,
+        #[case] expected_extra: Vec<&str>,
+    ) {
+        let mut args: Vec<&str> = vec!["filter-clipped"];
+        args.extend(options);
+        let matches = Command::command().get_matches_from(args);
+        let config = Command::from_arg_matches(&matches)
+            .unwrap()
+            .to_config(&matches)
+            .unwrap();
+        assert_eq!(config.in_bam, expected_in_bam);
+        assert_eq!(config.extra_inputs, expected_extra);
+    }
+
+    #[rstest]
+    #[case(vec![], None)]
+    #[case(vec!["--suggest-thresholds"], Some(vec![]))]
+    #[case(vec!["--suggest-thresholds", "90,99.5"], Some(vec![90.0, 99.5]))]
+    fn test_suggest_thresholds(#[case] options: Vec<&str>, #[case] expected: Option<Vec<f64>>) {
+        let mut args: Vec<&str> = vec!["filter-clipped", "-i", "test/data/test.sam"];
+        args.extend(options);
+        let command = Command::try_parse_from(args).unwrap();
+        assert_eq!(command.suggest_thresholds, expected);
+    }
+
+    #[rstest]
+    #[case(vec![], 0.3, true, "test/data/test.sam")]
+    #[case(vec!["-b", "0.4"], 0.4, true, "test/data/test.sam")]
+    #[case(vec!["-i", "other.sam"], 0.3, true, "other.sam")]
+    fn test_env(
+        #[case] options: Vec<&str>,
+        #[case] expected_both_end: f64,
+        #[case] expected_unalign: bool,
+        #[case] expected_in_bam: &str,
+    ) {
+        // only read through command_with_env, so no other test sees them
+        std::env::set_var("FILTER_CLIPPED_IN_BAM", "test/data/test.sam");
+        std::env::set_var("FILTER_CLIPPED_BOTH_END", "0.3");
+        std::env::set_var("FILTER_CLIPPED_UNALIGN", "true");
+        let mut args: Vec<&str> = vec!["filter-clipped"];
+        args.extend(options);
+        let matches = command_with_env().get_matches_from(args);
+        let config = Command::from_arg_matches(&matches)
+            .unwrap()
+            .to_config(&matches)
+            .unwrap();
+        assert_eq!(config.both_end, expected_both_end);
+        assert_eq!(config.unalign, expected_unalign);
+        assert_eq!(config.in_bam, expected_in_bam);
+        assert_eq!(config.left_side, 0.1);
+    }
+
+    #[rstest]
+    #[case(vec!["filter-clipped", "-i", "test/data/test.sam"], None)]
+    #[case(vec!["filter-clipped", "man"], Some(Utility::Man))]
+    #[case(
+        vec!["filter-clipped", "compare", "in.bam", "out.bam"],
+        Some(Utility::Compare { first: "in.bam".into(), second: "out.bam".into(), out: "-".into() })
+    )]
+    #[case(
+        vec!["filter-clipped", "--min-clip-bq", "20", "compare", "a.bam", "b.bam", "-o", "diff.tsv"],
+        Some(Utility::Compare { first: "a.bam".into(), second: "b.bam".into(), out: "diff.tsv".into() })
+    )]
+    fn test_utility(#[case] args: Vec<&str>, #[case] expected: Option<Utility>) {
+        let command = Command::try_parse_from(args).unwrap();
+        assert_eq!(command.utility, expected);
+    }
+
+    #[test]
+    fn test_man_page() {
+        let page: String = crate::man::render(&Command::command());
+        assert!(page.starts_with(".TH filter-clipped 1"));
+        for option in ["\\-\\-left\\-side", "\\-\\-in\\-bam", "\\-\\-unmapped"] {
+            assert!(page.contains(option), "{} missing", option);
+        }
+        assert!(page.contains(".SH SUBCOMMANDS\n.TP\n\\fBman\\fR"));
+    }
 }
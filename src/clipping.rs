@@ -1,3 +1,24 @@
+use crate::error::FilterClippedError;
+use rust_htslib::bam::record::{Aux, Cigar, CigarStringView, Record};
+use std::borrow::Cow;
+
+/// BAM code of the alignment match CIGAR operation
+const BAM_CMATCH: u32 = 0;
+/// BAM code of the sequence match CIGAR operation
+const BAM_CEQUAL: u32 = 7;
+/// BAM code of the sequence mismatch CIGAR operation
+const BAM_CDIFF: u32 = 8;
+/// BAM code of the insertion CIGAR operation
+const BAM_CINS: u32 = 1;
+/// BAM code of the deletion CIGAR operation
+const BAM_CDEL: u32 = 2;
+/// BAM code of the soft clip CIGAR operation
+const BAM_CSOFT_CLIP: u32 = 4;
+/// BAM code of the hard clip CIGAR operation
+const BAM_CHARD_CLIP: u32 = 5;
+/// BAM code of the reference skip CIGAR operation
+const BAM_CREF_SKIP: u32 = 3;
+
 #[derive(Debug)]
 /// An object to store statistics for base clipping on
 /// an alignment
@@ -9,6 +30,12 @@ pub struct ClipStat {
     /// total number of clipped bases on the alignment,
     /// this should be the sum of left and right
     total_clipped: i64,
+    /// number of inserted bases over the whole alignment
+    insertions: i64,
+    /// number of deleted (reference) bases over the whole alignment
+    deletions: i64,
+    /// number of aligned (M, = and X) bases over the whole alignment
+    aligned: i64,
 }
 
 /// Helper function to find the maximum value in a list
@@ -47,14 +74,281 @@ pub fn vec_to_max(clip_vec: Vec<i64>) -> i64 {
 /// use filter_clipped::clipping::nbase_to_frac;
 /// assert_eq!(nbase_to_frac(10, 10.0).unwrap(), 1.0)
 /// ```
-pub fn nbase_to_frac(n_base: i64, seq_len: f64) -> Result<f64, String> {
+pub fn nbase_to_frac(n_base: i64, seq_len: f64) -> Result<f64, FilterClippedError> {
     if seq_len < 1.0 {
-        Err(String::from("seq_len must be greater than 0"))
+        Err(FilterClippedError::InvalidRecord(String::from(
+            "seq_len must be greater than 0",
+        )))
     } else {
         Ok(n_base as f64 / seq_len)
     }
 }
 
+/// Helper function to count the clipped bases with a base quality of at least `min_bq`,
+/// bases without base qualities (255) are all counted
+///
+/// # Arguments
+/// * `qual`: base qualities of the clipped bases
+/// * `min_bq`: lowest base quality of a counted base
+///
+/// # Return
+/// * number of counted bases
+///
+/// # Examples
+/// ```
+/// use filter_clipped::clipping::count_clipped_bq;
+/// assert_eq!(count_clipped_bq(&[2, 2, 30, 40], 20), 2);
+/// assert_eq!(count_clipped_bq(&[255, 255], 20), 2);
+/// ```
+pub fn count_clipped_bq(qual: &[u8], min_bq: u8) -> i64 {
+    qual.iter()
+        .filter(|bq| **bq >= min_bq || **bq == 255)
+        .count() as i64
+}
+
+/// Inserted and deleted bases of BAM-encoded CIGAR operations (`length << 4 | operation`)
+///
+/// # Arguments
+/// * `raw`: the CIGAR operations, e.g. [Record::raw_cigar]
+///
+/// # Return
+/// * the numbers of inserted and deleted bases
+///
+/// # Examples
+/// ```
+/// use filter_clipped::clipping::count_indels;
+/// // 5M2I5M3D5M1I
+/// assert_eq!(count_indels(&[5 << 4, 2 << 4 | 1, 5 << 4, 3 << 4 | 2, 5 << 4, 1 << 4 | 1]), (3, 3));
+/// ```
+pub fn count_indels(raw: &[u32]) -> (i64, i64) {
+    raw.iter()
+        .fold((0, 0), |(insertions, deletions), op| match op & 0xf {
+            BAM_CINS => (insertions + (op >> 4) as i64, deletions),
+            BAM_CDEL => (insertions, deletions + (op >> 4) as i64),
+            _ => (insertions, deletions),
+        })
+}
+
+/// Aligned (M, = and X) bases of BAM-encoded CIGAR operations (`length << 4 | operation`)
+///
+/// # Arguments
+/// * `raw`: the CIGAR operations, e.g. [Record::raw_cigar]
+///
+/// # Examples
+/// ```
+/// use filter_clipped::clipping::count_aligned;
+/// // 5S10M2I5=1X3D4M
+/// assert_eq!(count_aligned(&[5 << 4 | 4, 10 << 4, 2 << 4 | 1, 5 << 4 | 7, 1 << 4 | 8, 3 << 4 | 2, 4 << 4]), 20);
+/// ```
+pub fn count_aligned(raw: &[u32]) -> i64 {
+    raw.iter()
+        .filter(|op| matches!(*op & 0xf, BAM_CMATCH | BAM_CEQUAL | BAM_CDIFF))
+        .map(|op| (op >> 4) as i64)
+        .sum()
+}
+
+/// Length of the read of BAM-encoded CIGAR operations (`length << 4 | operation`): the
+/// bases of the operations consuming the query (M, I, S, = and X) plus the hard clipped bases
+///
+/// # Arguments
+/// * `raw`: the CIGAR operations, e.g. [Record::raw_cigar]
+///
+/// # Examples
+/// ```
+/// use filter_clipped::clipping::count_read_len;
+/// // 2H5S10M2I3D4M
+/// assert_eq!(count_read_len(&[2 << 4 | 5, 5 << 4 | 4, 10 << 4, 2 << 4 | 1, 3 << 4 | 2, 4 << 4]), 23);
+/// ```
+pub fn count_read_len(raw: &[u32]) -> i64 {
+    raw.iter()
+        .filter(|op| {
+            matches!(
+                *op & 0xf,
+                BAM_CMATCH | BAM_CINS | BAM_CSOFT_CLIP | BAM_CHARD_CLIP | BAM_CEQUAL | BAM_CDIFF
+            )
+        })
+        .map(|op| (op >> 4) as i64)
+        .sum()
+}
+
+/// Length of the query sequence of CIGAR operations, i.e. of the SEQ of an alignment with
+/// them: the bases of the operations consuming the query (M, I, S, = and X)
+///
+/// # Arguments
+/// * `cigar`: the CIGAR operations
+///
+/// # Examples
+/// ```
+/// use filter_clipped::clipping::cigar_query_len;
+/// use rust_htslib::bam::record::CigarString;
+/// let cigar = CigarString::try_from("2H5S10M2I3D1000N4=1X").unwrap();
+/// assert_eq!(cigar_query_len(&cigar), 22);
+/// ```
+pub fn cigar_query_len(cigar: &[Cigar]) -> usize {
+    cigar
+        .iter()
+        .filter(|op| {
+            matches!(
+                op,
+                Cigar::Match(_)
+                    | Cigar::Ins(_)
+                    | Cigar::SoftClip(_)
+                    | Cigar::Equal(_)
+                    | Cigar::Diff(_)
+            )
+        })
+        .map(|op| op.len() as usize)
+        .sum()
+}
+
+/// Longest contiguous run of aligned (M, = and X) bases of BAM-encoded CIGAR operations
+/// (`length << 4 | operation`), insertions and deletions of up to `max_indel` bases don't
+/// break a run (nor count in it), all other operations do
+///
+/// # Arguments
+/// * `raw`: the CIGAR operations, e.g. [Record::raw_cigar]
+/// * `max_indel`: longest insertion or deletion within a run
+///
+/// # Examples
+/// ```
+/// use filter_clipped::clipping::longest_aligned_block;
+/// // 10M2I20M8D15M
+/// let raw = [10 << 4, 2 << 4 | 1, 20 << 4, 8 << 4 | 2, 15 << 4];
+/// assert_eq!(longest_aligned_block(&raw, 5), 30);
+/// assert_eq!(longest_aligned_block(&raw, 1), 20);
+/// assert_eq!(longest_aligned_block(&raw, 10), 45);
+/// ```
+pub fn longest_aligned_block(raw: &[u32], max_indel: u32) -> i64 {
+    let (mut longest, mut block): (i64, i64) = (0, 0);
+    for op in raw {
+        match op & 0xf {
+            BAM_CMATCH | BAM_CEQUAL | BAM_CDIFF => {
+                block += (op >> 4) as i64;
+                longest = longest.max(block);
+            }
+            BAM_CINS | BAM_CDEL if op >> 4 <= max_indel => (),
+            _ => block = 0,
+        }
+    }
+    longest
+}
+
+/// BAM-encoded CIGAR operations of an alignment. Alignments with more CIGAR operations
+/// than BAM can hold store a `<read length>S<reference length>N` placeholder and their
+/// real CIGAR in a CG tag, which is read instead when htslib hasn't already put it back
+///
+/// # Arguments
+/// * `record`: the alignment
+pub fn full_raw_cigar(record: &Record) -> Cow<'_, [u32]> {
+    let raw: &[u32] = record.raw_cigar();
+    let placeholder: bool = raw.len() == 2
+        && raw[0] & 0xf == BAM_CSOFT_CLIP
+        && (raw[0] >> 4) as usize == record.seq_len()
+        && raw[1] & 0xf == BAM_CREF_SKIP;
+    if placeholder {
+        if let Ok(Aux::ArrayU32(ops)) = record.aux(b"CG") {
+            return Cow::Owned(ops.iter().collect());
+        }
+    }
+    Cow::Borrowed(raw)
+}
+
+/// Inserted and deleted bases of an alignment, over its whole CIGAR string
+///
+/// # Arguments
+/// * `record`: the alignment
+pub fn record_indels(record: &Record) -> (i64, i64) {
+    count_indels(&full_raw_cigar(record))
+}
+
+/// Aligned (M, = and X) bases of an alignment, over its whole CIGAR string
+///
+/// # Arguments
+/// * `record`: the alignment
+pub fn record_aligned(record: &Record) -> i64 {
+    count_aligned(&full_raw_cigar(record))
+}
+
+/// Length of the read of an alignment, hard clipped bases included (see [count_read_len])
+///
+/// # Arguments
+/// * `record`: the alignment
+pub fn record_read_len(record: &Record) -> i64 {
+    count_read_len(&full_raw_cigar(record))
+}
+
+/// Soft and hard clipped bases at both ends of an alignment, read straight from its
+/// BAM-encoded CIGAR operations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CigarClips {
+    /// number of soft clipped bases at the start of the alignment
+    pub leading_softclips: i64,
+    /// number of hard clipped bases at the start of the alignment
+    pub leading_hardclips: i64,
+    /// number of soft clipped bases at the end of the alignment
+    pub trailing_softclips: i64,
+    /// number of hard clipped bases at the end of the alignment
+    pub trailing_hardclips: i64,
+}
+
+/// Length of a clip operation of a BAM-encoded CIGAR operation, 0 for the other operations
+fn clip_len(op: Option<&u32>, code: u32) -> i64 {
+    match op {
+        Some(op) if op & 0xf == code => (op >> 4) as i64,
+        _ => 0,
+    }
+}
+
+impl CigarClips {
+    /// Clipped bases of BAM-encoded CIGAR operations (`length << 4 | operation`), only the
+    /// first and last two operations are looked at: a hard clip, then a soft clip
+    ///
+    /// # Arguments
+    /// * `raw`: the CIGAR operations, e.g. [Record::raw_cigar]
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::CigarClips;
+    /// // 2H3S10M4S
+    /// let clips = CigarClips::from_raw_cigar(&[2 << 4 | 5, 3 << 4 | 4, 10 << 4, 4 << 4 | 4]);
+    /// assert_eq!((clips.leading_hardclips, clips.leading_softclips), (2, 3));
+    /// assert_eq!((clips.trailing_hardclips, clips.trailing_softclips), (0, 4));
+    /// ```
+    pub fn from_raw_cigar(raw: &[u32]) -> Self {
+        let ends = |first: Option<&u32>, second: Option<&u32>| -> (i64, i64) {
+            let hardclips: i64 = clip_len(first, BAM_CHARD_CLIP);
+            let softclips: i64 = match hardclips {
+                0 => clip_len(first, BAM_CSOFT_CLIP),
+                _ => clip_len(second, BAM_CSOFT_CLIP),
+            };
+            (softclips, hardclips)
+        };
+        let (leading_softclips, leading_hardclips) = ends(raw.first(), raw.get(1));
+        let (trailing_softclips, trailing_hardclips) = match raw.len() {
+            // a single operation is both the first and the last
+            0 | 1 => (leading_softclips, leading_hardclips),
+            n => ends(raw.last(), raw.get(n - 2)),
+        };
+        Self {
+            leading_softclips,
+            leading_hardclips,
+            trailing_softclips,
+            trailing_hardclips,
+        }
+    }
+
+    /// Clipped bases of an alignment, without parsing its whole CIGAR string. Alignments
+    /// with more CIGAR operations than BAM can hold store a `<read length>S<reference
+    /// length>N` placeholder and their real CIGAR in a CG tag, which is read instead when
+    /// htslib hasn't already put it back
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    pub fn from_record(record: &Record) -> Self {
+        Self::from_raw_cigar(&full_raw_cigar(record))
+    }
+}
+
 impl ClipStat {
     /// Creat a new ClipStat object for an alignment
     ///
@@ -84,9 +378,148 @@ impl ClipStat {
             left: vec_to_max(leading_clipped),
             right: vec_to_max(trailing_clipped),
             total_clipped: all_clipped,
+            insertions: 0,
+            deletions: 0,
+            aligned: 0,
+        }
+    }
+
+    /// Creat a ClipStat object from already computed numbers of clipped bases,
+    /// e.g. when the total is not simply the sum of both sides
+    ///
+    /// # Arguments
+    /// * `left`: number of 5' clipped bases
+    /// * `right`: number of 3' clipped bases
+    /// * `total_clipped`: total number of clipped bases
+    ///
+    /// # Return:
+    /// A ClipStat object
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// let clip_stat = ClipStat::from_parts(1, 2, 5);
+    /// assert_eq!(clip_stat.left(), 1);
+    /// assert_eq!(clip_stat.right(), 2);
+    /// assert_eq!(clip_stat.total_clipped(), 5);
+    /// ```
+    pub fn from_parts(left: i64, right: i64, total_clipped: i64) -> Self {
+        Self {
+            left,
+            right,
+            total_clipped,
+            insertions: 0,
+            deletions: 0,
+            aligned: 0,
         }
     }
 
+    /// Set the inserted and deleted bases of the alignment
+    ///
+    /// # Arguments
+    /// * `insertions`: number of inserted bases
+    /// * `deletions`: number of deleted bases
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// let clip_stat = ClipStat::from_parts(1, 2, 3).with_indels(4, 6);
+    /// assert_eq!((clip_stat.insertions(), clip_stat.deletions()), (4, 6));
+    /// assert_eq!(clip_stat.indel_fraction(100.0).unwrap(), 0.1);
+    /// ```
+    pub fn with_indels(mut self, insertions: i64, deletions: i64) -> Self {
+        self.insertions = insertions;
+        self.deletions = deletions;
+        self
+    }
+
+    /// Set the aligned (M, = and X) bases of the alignment
+    ///
+    /// # Arguments
+    /// * `aligned`: number of aligned bases
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// let clip_stat = ClipStat::from_parts(0, 0, 0).with_indels(2, 1).with_aligned(100);
+    /// assert_eq!(clip_stat.aligned(), 100);
+    /// // NM counts the 3 indel bases too
+    /// assert_eq!(clip_stat.mismatches(8), 5);
+    /// assert_eq!(clip_stat.mismatch_fraction(8).unwrap(), 0.05);
+    /// ```
+    pub fn with_aligned(mut self, aligned: i64) -> Self {
+        self.aligned = aligned;
+        self
+    }
+
+    /// Creat a ClipStat object from the clipped bases at both ends of an alignment,
+    /// counting soft and hard clips the way the filter does
+    ///
+    /// # Arguments
+    /// * `clips`: soft and hard clipped bases of the alignment
+    pub fn from_clips(clips: &CigarClips) -> Self {
+        Self::new(
+            vec![clips.leading_softclips, clips.leading_hardclips],
+            vec![clips.trailing_softclips, clips.trailing_hardclips],
+        )
+    }
+
+    /// Creat a ClipStat object from BAM-encoded CIGAR operations (`length << 4 | operation`)
+    ///
+    /// # Arguments
+    /// * `raw`: the CIGAR operations, e.g. [Record::raw_cigar]
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// // 2H3S10M4S
+    /// let clip_stat = ClipStat::from_raw_cigar(&[2 << 4 | 5, 3 << 4 | 4, 10 << 4, 4 << 4 | 4]);
+    /// assert_eq!(clip_stat.left(), 3);
+    /// assert_eq!(clip_stat.right(), 4);
+    /// assert_eq!(clip_stat.total_clipped(), 9);
+    /// ```
+    pub fn from_raw_cigar(raw: &[u32]) -> Self {
+        let (insertions, deletions) = count_indels(raw);
+        Self::from_clips(&CigarClips::from_raw_cigar(raw))
+            .with_indels(insertions, deletions)
+            .with_aligned(count_aligned(raw))
+    }
+
+    /// Creat a ClipStat object from the CIGAR string of an alignment
+    ///
+    /// # Arguments
+    /// * `cigar`: the CIGAR string, e.g. [Record::cigar]
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    /// let cigar = CigarString::try_from("5S90M5H").unwrap();
+    /// let mut record = Record::new();
+    /// record.set(b"read", Some(&cigar), &[b'A'; 95], &[30; 95]);
+    /// let clip_stat = ClipStat::from_cigar(&record.cigar());
+    /// assert_eq!(clip_stat.left(), 5);
+    /// assert_eq!(clip_stat.right(), 5);
+    /// assert_eq!(clip_stat.total_clipped(), 10);
+    /// ```
+    pub fn from_cigar(cigar: &CigarStringView) -> Self {
+        let raw: Vec<u32> = cigar
+            .iter()
+            .map(|op| match op {
+                Cigar::SoftClip(len) => len << 4 | BAM_CSOFT_CLIP,
+                Cigar::HardClip(len) => len << 4 | BAM_CHARD_CLIP,
+                Cigar::Ins(len) => len << 4 | BAM_CINS,
+                Cigar::Del(len) => len << 4 | BAM_CDEL,
+                Cigar::Match(len) => len << 4 | BAM_CMATCH,
+                Cigar::Equal(len) => len << 4 | BAM_CEQUAL,
+                Cigar::Diff(len) => len << 4 | BAM_CDIFF,
+                // only the clips, indels and aligned bases are looked at
+                op => op.len() << 4 | BAM_CREF_SKIP,
+            })
+            .collect();
+        Self::from_raw_cigar(&raw)
+    }
+
     /// Return the fraction of 3' clipped base relative to the sequence length
     ///
     /// # Argument
@@ -104,7 +537,7 @@ impl ClipStat {
     /// );
     /// assert_eq!(clip_stat.right_fraction(10.0).unwrap(), 0.2);
     /// ```
-    pub fn right_fraction(&self, seq_len: f64) -> Result<f64, String> {
+    pub fn right_fraction(&self, seq_len: f64) -> Result<f64, FilterClippedError> {
         nbase_to_frac(self.right, seq_len)
     }
 
@@ -125,7 +558,7 @@ impl ClipStat {
     /// );
     /// assert_eq!(clip_stat.left_fraction(10.0).unwrap(), 0.1);
     /// ```    
-    pub fn left_fraction(&self, seq_len: f64) -> Result<f64, String> {
+    pub fn left_fraction(&self, seq_len: f64) -> Result<f64, FilterClippedError> {
         nbase_to_frac(self.left, seq_len)
     }
     /// Return the fraction of total clipped base relative to the sequence length
@@ -145,7 +578,7 @@ impl ClipStat {
     /// );
     /// assert_eq!(clip_stat.total_fraction(10.0).unwrap(), 0.3);
     /// ```    
-    pub fn total_fraction(&self, seq_len: f64) -> Result<f64, String> {
+    pub fn total_fraction(&self, seq_len: f64) -> Result<f64, FilterClippedError> {
         nbase_to_frac(self.total_clipped, seq_len)
     }
 
@@ -190,12 +623,100 @@ impl ClipStat {
     pub fn total_clipped(&self) -> i64 {
         self.total_clipped
     }
+
+    /// Return the number of inserted bases
+    pub fn insertions(&self) -> i64 {
+        self.insertions
+    }
+
+    /// Return the number of deleted bases
+    pub fn deletions(&self) -> i64 {
+        self.deletions
+    }
+
+    /// Return the fraction of inserted bases relative to the sequence length
+    ///
+    /// # Argument
+    /// * `seq_len`: sequence length of the alignment
+    pub fn insertion_fraction(&self, seq_len: f64) -> Result<f64, FilterClippedError> {
+        nbase_to_frac(self.insertions, seq_len)
+    }
+
+    /// Return the fraction of deleted bases relative to the sequence length
+    ///
+    /// # Argument
+    /// * `seq_len`: sequence length of the alignment
+    pub fn deletion_fraction(&self, seq_len: f64) -> Result<f64, FilterClippedError> {
+        nbase_to_frac(self.deletions, seq_len)
+    }
+
+    /// Return the number of aligned (M, = and X) bases
+    pub fn aligned(&self) -> i64 {
+        self.aligned
+    }
+
+    /// Return the number of mismatched bases: the edit distance (NM tag) minus the
+    /// inserted and deleted bases
+    ///
+    /// # Argument
+    /// * `nm`: edit distance of the alignment to the reference
+    pub fn mismatches(&self, nm: i64) -> i64 {
+        (nm - self.insertions - self.deletions).max(0)
+    }
+
+    /// Return the fraction of mismatched bases relative to the aligned bases
+    ///
+    /// # Argument
+    /// * `nm`: edit distance of the alignment to the reference
+    pub fn mismatch_fraction(&self, nm: i64) -> Result<f64, FilterClippedError> {
+        nbase_to_frac(self.mismatches(nm), self.aligned as f64)
+    }
+
+    /// Return the fraction of the read that is aligned (M, = and X bases)
+    ///
+    /// # Argument
+    /// * `read_len`: length of the read, hard clipped bases included
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// let clip_stat = ClipStat::from_raw_cigar(&[20 << 4 | 5, 80 << 4]);
+    /// assert_eq!(clip_stat.query_coverage(100.0).unwrap(), 0.8);
+    /// ```
+    pub fn query_coverage(&self, read_len: f64) -> Result<f64, FilterClippedError> {
+        nbase_to_frac(self.aligned, read_len)
+    }
+
+    /// Return the fraction of inserted and deleted bases relative to the sequence length
+    ///
+    /// # Argument
+    /// * `seq_len`: sequence length of the alignment
+    pub fn indel_fraction(&self, seq_len: f64) -> Result<f64, FilterClippedError> {
+        nbase_to_frac(self.insertions + self.deletions, seq_len)
+    }
+}
+
+/// Alignment with a CIGAR string and an all-A sequence as long as its query (see
+/// [cigar_query_len]), for the tests
+#[cfg(test)]
+pub(crate) fn record_from_cigar(cigar: &str) -> Record {
+    let cigar = rust_htslib::bam::record::CigarString::try_from(cigar).unwrap();
+    let seq_len: usize = cigar_query_len(&cigar);
+    let mut record = Record::new();
+    record.set(
+        b"read",
+        Some(&cigar),
+        &vec![b'A'; seq_len],
+        &vec![30; seq_len],
+    );
+    record
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
 
     #[rstest]
     #[case(vec![2,0], vec![0,2], 0.2, 0.2, 0.4)]
@@ -217,6 +738,145 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case("10M")]
+    #[case("5S10M")]
+    #[case("10M5S")]
+    #[case("2H5S10M3S4H")]
+    #[case("2H10M")]
+    #[case("5S")]
+    #[case("3H")]
+    #[case("5S2H")]
+    #[case("2S3M4I5M6S")]
+    fn test_cigar_clips(#[case] cigar: &str) {
+        let record: Record = record_from_cigar(cigar);
+        let view = record.cigar();
+        let expected = CigarClips {
+            leading_softclips: view.leading_softclips(),
+            leading_hardclips: view.leading_hardclips(),
+            trailing_softclips: view.trailing_softclips(),
+            trailing_hardclips: view.trailing_hardclips(),
+        };
+        assert_eq!(CigarClips::from_record(&record), expected);
+    }
+
+    #[rstest]
+    #[case("10M", 0, 0)]
+    #[case("5M2I3M", 2, 0)]
+    #[case("5S5M3D2I5M1D", 2, 4)]
+    fn test_clip_stat_indels(
+        #[case] cigar: &str,
+        #[case] expected_insertions: i64,
+        #[case] expected_deletions: i64,
+    ) {
+        let record: Record = record_from_cigar(cigar);
+        let expected = (expected_insertions, expected_deletions);
+        assert_eq!(record_indels(&record), expected);
+        assert_eq!(record_aligned(&record), count_aligned(record.raw_cigar()));
+        for clip_stat in [
+            ClipStat::from_cigar(&record.cigar()),
+            ClipStat::from_raw_cigar(record.raw_cigar()),
+        ] {
+            assert_eq!((clip_stat.insertions(), clip_stat.deletions()), expected);
+        }
+    }
+
+    #[rstest]
+    #[case("10M", 10)]
+    #[case("5S10M2I5M", 15)]
+    #[case("3=1X4=2D3M5H", 11)]
+    #[case("5S", 0)]
+    fn test_clip_stat_aligned(#[case] cigar: &str, #[case] expected: i64) {
+        let record: Record = record_from_cigar(cigar);
+        assert_eq!(record_aligned(&record), expected);
+        assert_eq!(record_read_len(&record), count_read_len(record.raw_cigar()));
+        assert_eq!(ClipStat::from_cigar(&record.cigar()).aligned(), expected);
+        assert_eq!(
+            ClipStat::from_raw_cigar(record.raw_cigar()).aligned(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("100M", 0, 100)]
+    #[case("5S40M3I50M5S", 2, 50)]
+    #[case("5S40M3I50M5S", 3, 90)]
+    #[case("40M1000N30M", 5, 40)]
+    #[case("10=1X10=1D10M", 0, 21)]
+    #[case("10=1X10=1D10M", 1, 31)]
+    #[case("5H10S", 5, 0)]
+    fn test_longest_aligned_block(
+        #[case] cigar: &str,
+        #[case] max_indel: u32,
+        #[case] expected: i64,
+    ) {
+        let record: Record = record_from_cigar(cigar);
+        assert_eq!(
+            longest_aligned_block(&full_raw_cigar(&record), max_indel),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(10, 0, 0, 100, 0.1)]
+    #[case(10, 3, 2, 100, 0.05)]
+    #[case(2, 3, 2, 100, 0.0)]
+    fn test_mismatch_fraction(
+        #[case] nm: i64,
+        #[case] insertions: i64,
+        #[case] deletions: i64,
+        #[case] aligned: i64,
+        #[case] expected: f64,
+    ) {
+        let clip_stat = ClipStat::from_parts(0, 0, 0)
+            .with_indels(insertions, deletions)
+            .with_aligned(aligned);
+        assert_eq!(clip_stat.mismatch_fraction(nm).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("10M", 0, 0, 0)]
+    #[case("5S10M", 5, 0, 5)]
+    #[case("10M5H", 0, 5, 5)]
+    #[case("2H5S10M3S4H", 5, 4, 14)]
+    #[case("2S3M4I5M6S", 2, 6, 8)]
+    #[case("5S", 5, 5, 10)]
+    fn test_clip_stat_from_cigar(
+        #[case] cigar: &str,
+        #[case] expected_left: i64,
+        #[case] expected_right: i64,
+        #[case] expected_total: i64,
+    ) {
+        let record: Record = record_from_cigar(cigar);
+        for clip_stat in [
+            ClipStat::from_cigar(&record.cigar()),
+            ClipStat::from_raw_cigar(record.raw_cigar()),
+        ] {
+            assert_eq!(
+                (
+                    clip_stat.left(),
+                    clip_stat.right(),
+                    clip_stat.total_clipped()
+                ),
+                (expected_left, expected_right, expected_total)
+            );
+        }
+    }
+
+    #[test]
+    fn test_cigar_clips_long_cigar() {
+        // placeholder CIGAR, with the real one (3S5M2S) in the CG tag
+        let cigar = CigarString::try_from("10S5N").unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 10], &[30; 10]);
+        let real: Vec<u32> = vec![3 << 4 | 4, 5 << 4, 2 << 4 | 4];
+        record
+            .push_aux(b"CG", Aux::ArrayU32((&real).into()))
+            .unwrap();
+        let clips = CigarClips::from_record(&record);
+        assert_eq!((clips.leading_softclips, clips.trailing_softclips), (3, 2));
+    }
+
     #[rstest]
     #[case(vec![2,3,0], 3)]
     #[case(vec![1,2,3], 3)]
@@ -225,6 +885,15 @@ mod tests {
         assert_eq!(expected_out, vec_to_max(input_vec));
     }
 
+    #[rstest]
+    #[case(vec![2, 2, 2], 20, 0)]
+    #[case(vec![2, 20, 40], 20, 2)]
+    #[case(vec![2, 20, 40], 0, 3)]
+    #[case(vec![255, 2], 20, 1)]
+    fn test_count_clipped_bq(#[case] qual: Vec<u8>, #[case] min_bq: u8, #[case] expected_out: i64) {
+        assert_eq!(count_clipped_bq(&qual, min_bq), expected_out);
+    }
+
     #[rstest]
     #[case(10, 20.0, 0.5)]
     #[case(10, 40.0, 0.25)]
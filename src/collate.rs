@@ -0,0 +1,346 @@
+//! Internal collation of the alignments by read name (`--collate`), so that `--paired` and
+//! `--cascade` work on input that isn't grouped by name (e.g. coordinate sorted) without
+//! running `samtools collate` first. The alignments of a read are held in memory until all of
+//! them are seen; once too many are pending, they are spilled into temporary files by read
+//! name, which are read back one at a time once the input is done
+
+use crate::config::FilterConfig;
+use crate::error::FilterClippedError;
+use crate::subsample::name_fraction;
+use crate::tags;
+use rust_htslib::bam::{self, record::Aux, Header, HeaderView, Read, Record};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of temporary files the spilled alignments are split into by read name, each of
+/// them being read back into memory at once
+const SPILL_FILES: usize = 64;
+
+/// Which mate of a read an alignment belongs to: (first in template, last in template)
+fn mate_key(record: &Record) -> (bool, bool) {
+    (record.is_first_in_template(), record.is_last_in_template())
+}
+
+/// Number of alignments of a read (or mate) going by its primary alignment: itself, the
+/// supplementary alignments listed in its SA tag, and the secondary alignments counted by its
+/// NH tag if it has one
+fn expected_alignments(primary: &Record) -> usize {
+    let supplementary: usize = match primary.aux(b"SA") {
+        Ok(Aux::String(sa)) => sa.split(';').filter(|entry| !entry.is_empty()).count(),
+        _ => 0,
+    };
+    let secondary: usize = match tags::integer_tag(primary, b"NH") {
+        Some(hits) if hits > 1 => (hits - 1) as usize,
+        _ => 0,
+    };
+    1 + supplementary + secondary
+}
+
+/// Whether all the alignments of a read are among the ones seen so far: the primary alignment
+/// of each mate, and as many alignments of each mate as its primary alignment says it has
+/// (see [expected_alignments]). Secondary alignments without an NH tag can't be waited for
+///
+/// # Arguments
+/// * `records`: the alignments seen so far, all sharing the same read name
+///
+/// # Example
+/// ```
+/// use filter_clipped::collate::is_complete;
+/// use rust_htslib::bam::record::{Aux, Record};
+/// let mut first = Record::new();
+/// first.set_flags(0x1 | 0x40);
+/// let mut second = Record::new();
+/// second.set_flags(0x1 | 0x80);
+/// assert!(!is_complete(&[first.clone()]));
+/// assert!(is_complete(&[first.clone(), second.clone()]));
+/// first.push_aux(b"SA", Aux::String("chr1,100,+,60S40M,60,0;")).unwrap();
+/// assert!(!is_complete(&[first, second]));
+/// ```
+pub fn is_complete(records: &[Record]) -> bool {
+    let mates: &[(bool, bool)] = match records.first() {
+        Some(record) if record.is_paired() => &[(true, false), (false, true)],
+        Some(_) => &[(false, false)],
+        None => return false,
+    };
+    mates.iter().all(|mate| {
+        let alignments: Vec<&Record> = records
+            .iter()
+            .filter(|record| mate_key(record) == *mate)
+            .collect();
+        match alignments
+            .iter()
+            .find(|record| !record.is_secondary() && !record.is_supplementary())
+        {
+            Some(primary) => alignments.len() >= expected_alignments(primary),
+            None => false,
+        }
+    })
+}
+
+/// Temporary file of spilled alignments, next to the output file (or in the temporary
+/// directory when writing to stdout)
+fn spill_path(config: &FilterConfig, index: usize) -> PathBuf {
+    match config.out_bam.as_str() {
+        "-" => std::env::temp_dir().join(format!(
+            "filter-clipped.{}.collate.{}.tmp.bam",
+            std::process::id(),
+            index
+        )),
+        out_bam => PathBuf::from(format!("{}.collate.{}.tmp.bam", out_bam, index)),
+    }
+}
+
+/// The temporary files holding the spilled alignments, split by read name
+struct Spill {
+    paths: Vec<PathBuf>,
+    writers: Vec<bam::Writer>,
+}
+
+impl Spill {
+    /// Create the temporary files
+    fn create(config: &FilterConfig, header: &Header) -> Result<Self, FilterClippedError> {
+        let paths: Vec<PathBuf> = (0..SPILL_FILES).map(|i| spill_path(config, i)).collect();
+        let mut writers: Vec<bam::Writer> = Vec::with_capacity(SPILL_FILES);
+        for path in paths.iter() {
+            let mut writer = bam::Writer::from_path(path, header, bam::Format::Bam)?;
+            // the files are read back in the same run, compressing them would only cost time
+            writer.set_compression_level(bam::CompressionLevel::Uncompressed)?;
+            writers.push(writer);
+        }
+        Ok(Self { paths, writers })
+    }
+
+    /// Write an alignment into the file of its read name
+    fn write(&mut self, record: &Record) -> Result<(), FilterClippedError> {
+        let index: usize = (name_fraction(record.qname(), 0) * SPILL_FILES as f64) as usize;
+        Ok(self.writers[index.min(SPILL_FILES - 1)].write(record)?)
+    }
+}
+
+/// Iterator adapter grouping the alignments of each read name together (see the module docs),
+/// the reads coming out as soon as all their alignments are seen
+pub struct Collator<'a, I> {
+    records: I,
+    config: &'a FilterConfig,
+    header: Header,
+    /// alignments of the reads not complete yet, by read name
+    pending: HashMap<Vec<u8>, Vec<Record>>,
+    pending_records: usize,
+    /// names of the reads sent to the temporary files, the rest of their alignments following
+    spilled: HashSet<Vec<u8>>,
+    spill: Option<Spill>,
+    /// temporary files left to read back once the input is done
+    spilled_paths: Vec<PathBuf>,
+    /// alignments of complete reads, in order
+    ready: VecDeque<Record>,
+    input_done: bool,
+}
+
+impl<'a, I> Collator<'a, I>
+where
+    I: Iterator<Item = Result<Record, FilterClippedError>>,
+{
+    /// Group the alignments of a stream by read name
+    ///
+    /// # Arguments
+    /// - `records`: input alignments
+    /// - `config`: settings for the run, with the number of alignments held in memory
+    ///   (`collate_max_pending`) and the output path the temporary files go next to
+    /// - `header`: header of the alignments
+    pub fn new(records: I, config: &'a FilterConfig, header: &HeaderView) -> Self {
+        Self {
+            records,
+            config,
+            header: Header::from_template(header),
+            pending: HashMap::new(),
+            pending_records: 0,
+            spilled: HashSet::new(),
+            spill: None,
+            spilled_paths: Vec::new(),
+            ready: VecDeque::new(),
+            input_done: false,
+        }
+    }
+
+    /// Hold an alignment until all the alignments of its read are seen
+    fn add(&mut self, record: Record) -> Result<(), FilterClippedError> {
+        if self.spilled.contains(record.qname()) {
+            return match self.spill.as_mut() {
+                Some(spill) => spill.write(&record),
+                None => unreachable!("reads are only spilled into open files"),
+            };
+        }
+        let name: Vec<u8> = record.qname().to_vec();
+        let group: &mut Vec<Record> = self.pending.entry(name).or_default();
+        group.push(record);
+        self.pending_records += 1;
+        if is_complete(group) {
+            let records: Vec<Record> = std::mem::take(group);
+            self.pending.remove(records[0].qname());
+            self.pending_records -= records.len();
+            self.ready.extend(records);
+        } else if self.pending_records > self.config.collate_max_pending {
+            self.spill_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Move all the pending alignments into the temporary files
+    fn spill_pending(&mut self) -> Result<(), FilterClippedError> {
+        if self.spill.is_none() {
+            self.spill = Some(Spill::create(self.config, &self.header)?);
+            log::debug!(
+                "Spilling the alignments of incomplete reads to temporary files after {} pending alignments",
+                self.pending_records
+            );
+        }
+        let spill: &mut Spill = self.spill.as_mut().expect("spill files were just created");
+        for (name, records) in self.pending.drain() {
+            for record in records.iter() {
+                spill.write(record)?;
+            }
+            self.spilled.insert(name);
+        }
+        self.pending_records = 0;
+        Ok(())
+    }
+
+    /// Once the input is done, hand out the reads still pending (the ones missing some of
+    /// their alignments), or spill them to be read back with the rest
+    fn finish_input(&mut self) -> Result<(), FilterClippedError> {
+        self.input_done = true;
+        if self.spill.is_none() {
+            let mut names: Vec<Vec<u8>> = self.pending.keys().cloned().collect();
+            names.sort();
+            for name in names {
+                let records: Vec<Record> = self.pending.remove(&name).unwrap_or_default();
+                self.ready.extend(records);
+            }
+            self.pending_records = 0;
+            return Ok(());
+        }
+        self.spill_pending()?;
+        if let Some(spill) = self.spill.take() {
+            // closing the writers flushes the files
+            drop(spill.writers);
+            self.spilled_paths = spill.paths;
+            self.spilled_paths.reverse();
+        }
+        Ok(())
+    }
+
+    /// Read a temporary file back, grouping its alignments by read name
+    fn read_back(&mut self, path: &PathBuf) -> Result<(), FilterClippedError> {
+        let mut names: Vec<Vec<u8>> = Vec::new();
+        let mut groups: HashMap<Vec<u8>, Vec<Record>> = HashMap::new();
+        let mut reader = bam::Reader::from_path(path)?;
+        for r in reader.records() {
+            let record: Record = r?;
+            if !groups.contains_key(record.qname()) {
+                names.push(record.qname().to_vec());
+            }
+            groups
+                .entry(record.qname().to_vec())
+                .or_default()
+                .push(record);
+        }
+        drop(reader);
+        fs::remove_file(path)?;
+        for name in names {
+            self.ready.extend(groups.remove(&name).unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    /// Move on until there are alignments ready to hand out, false once all are handed out
+    fn step(&mut self) -> Result<bool, FilterClippedError> {
+        if !self.input_done {
+            match self.records.next() {
+                Some(record) => self.add(record?)?,
+                None => self.finish_input()?,
+            }
+            return Ok(true);
+        }
+        match self.spilled_paths.pop() {
+            Some(path) => {
+                self.read_back(&path)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<'a, I> Iterator for Collator<'a, I>
+where
+    I: Iterator<Item = Result<Record, FilterClippedError>>,
+{
+    type Item = Result<Record, FilterClippedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.ready.pop_front() {
+                return Some(Ok(record));
+            }
+            match self.step() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl<'a, I> Drop for Collator<'a, I> {
+    /// Remove the temporary files left, e.g. when the run fails
+    fn drop(&mut self) {
+        let paths: Vec<PathBuf> = match self.spill.take() {
+            Some(spill) => {
+                drop(spill.writers);
+                spill.paths
+            }
+            None => Vec::new(),
+        };
+        for path in paths.iter().chain(self.spilled_paths.iter()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::reader_from_sam_text;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(1_000_000)]
+    #[case(2)]
+    #[case(0)]
+    fn test_collator(#[case] max_pending: usize) {
+        let mut reader =
+            reader_from_sam_text(&std::fs::read_to_string("test/data/paired_sorted.sam").unwrap())
+                .unwrap();
+        let header: HeaderView = reader.header().clone();
+        let config = FilterConfig {
+            out_bam: format!("test/data/out_collate_{}.bam", max_pending),
+            collate_max_pending: max_pending,
+            ..FilterConfig::default()
+        };
+        let records = reader
+            .records()
+            .map(|r| r.map_err(FilterClippedError::from));
+        let names: Vec<Vec<u8>> = Collator::new(records, &config, &header)
+            .map(|r| r.unwrap().qname().to_vec())
+            .collect();
+        assert_eq!(names.len(), 7);
+        // all the alignments of a read name are next to each other
+        let mut groups: Vec<&Vec<u8>> = names.iter().collect();
+        groups.dedup();
+        assert_eq!(groups.len(), 4);
+        for i in 0..SPILL_FILES {
+            assert!(!spill_path(&config, i).exists());
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Comparison of the alignments of two files (the `compare` subcommand), e.g. the input and
+//! the output of a run, or the outputs of two runs with different thresholds: the alignments
+//! found in only one of them are written with their clip fractions, to check what the
+//! thresholds took out. Alignments are matched by read name, mate, strand, secondary or
+//! supplementary flag and position, so that alignments trimmed of an adapter still match.
+//! Unmapped records (e.g. made unmapped with `--unalign`) count as absent
+
+use crate::config::FilterConfig;
+use crate::error::FilterClippedError;
+use crate::filter::ClipFilter;
+use crate::io;
+use rust_htslib::bam::{HeaderView, Read, Record};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Flags telling apart the alignments of a read name: read 1/2, reverse strand, secondary
+/// and supplementary
+const KEY_FLAGS: u16 = 0x10 | 0x40 | 0x80 | 0x100 | 0x800;
+
+/// What identifies an alignment across files: read name, flags, tid and position
+type AlignmentKey = (Vec<u8>, u16, i32, i64);
+
+/// Key of an alignment
+fn alignment_key(record: &Record) -> AlignmentKey {
+    (
+        record.qname().to_vec(),
+        record.flags() & KEY_FLAGS,
+        record.tid(),
+        record.pos(),
+    )
+}
+
+/// Numbers of alignments found in both files or in only one of them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompareStats {
+    /// alignments in both files
+    pub shared: u64,
+    /// alignments only in the first file
+    pub only_first: u64,
+    /// alignments only in the second file
+    pub only_second: u64,
+}
+
+/// Go through the mapped alignments of a file
+fn for_each_alignment<F>(
+    config: &FilterConfig,
+    path: &str,
+    mut f: F,
+) -> Result<(), FilterClippedError>
+where
+    F: FnMut(&Record) -> Result<(), FilterClippedError>,
+{
+    let mut reader = io::open_input(config, path)?;
+    for r in reader.records() {
+        let record: Record = r?;
+        if !record.is_unmapped() {
+            f(&record)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write an alignment found in only one of the files as a TSV line
+fn write_row<W: Write>(
+    writer: &mut W,
+    clip_filter: &ClipFilter,
+    file: &str,
+    record: &Record,
+    contigs: &[String],
+) -> Result<(), FilterClippedError> {
+    let (clip_stat, seq_len) = clip_filter.clip_stat(record)?;
+    let contig: &str = contigs
+        .get(record.tid() as usize)
+        .map_or("*", String::as_str);
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+        file,
+        String::from_utf8_lossy(record.qname()),
+        record.flags(),
+        contig,
+        record.pos() + 1,
+        record.mapq(),
+        record.cigar(),
+        clip_stat.left_fraction(seq_len)?,
+        clip_stat.right_fraction(seq_len)?,
+        clip_stat.total_fraction(seq_len)?
+    )?;
+    Ok(())
+}
+
+/// Names of the reference sequences of a header, by tid
+fn contig_names(header: &HeaderView) -> Vec<String> {
+    header
+        .target_names()
+        .iter()
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .collect()
+}
+
+/// Compare the mapped alignments of two files, writing the ones found in only one of them as
+/// a TSV (columns: file (`first` or `second`), read name, flag, reference sequence, 1-based
+/// position, MAPQ, CIGAR and the left, right and total clip fractions, counted as the filter
+/// of the settings counts them). The second file is read twice, so it can't be stdin
+///
+/// # Arguments
+/// * `config`: settings of the clip fractions, and the reference of CRAM files
+/// * `first`: path of the first file
+/// * `second`: path of the second file
+/// * `writer`: where to write the TSV
+///
+/// # Returns
+/// * the numbers of alignments in both files or in only one of them
+pub fn compare<W: Write>(
+    config: &FilterConfig,
+    first: &str,
+    second: &str,
+    writer: &mut W,
+) -> Result<CompareStats, FilterClippedError> {
+    if second == "-" {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "the second file of compare is read twice and can't be stdin",
+        )));
+    }
+    let contigs: Vec<String> = contig_names(io::open_input(config, second)?.header());
+    let mut first_reader = io::open_input(config, first)?;
+    if contig_names(first_reader.header()) != contigs {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "the files compared don't have the same reference sequences",
+        )));
+    }
+    let clip_filter = ClipFilter::new(config);
+    let mut stats = CompareStats::default();
+    let mut second_keys: HashSet<AlignmentKey> = HashSet::new();
+    for_each_alignment(config, second, |record| {
+        second_keys.insert(alignment_key(record));
+        Ok(())
+    })?;
+
+    writeln!(
+        writer,
+        "file\tname\tflag\tcontig\tpos\tmapq\tcigar\tleft_fraction\tright_fraction\ttotal_fraction"
+    )?;
+    let mut first_keys: HashSet<AlignmentKey> = HashSet::new();
+    for r in first_reader.records() {
+        let record: Record = r?;
+        if record.is_unmapped() {
+            continue;
+        }
+        let key: AlignmentKey = alignment_key(&record);
+        if second_keys.contains(&key) {
+            stats.shared += 1;
+        } else {
+            stats.only_first += 1;
+            write_row(writer, &clip_filter, "first", &record, &contigs)?;
+        }
+        first_keys.insert(key);
+    }
+    drop(second_keys);
+
+    for_each_alignment(config, second, |record| {
+        if !first_keys.contains(&alignment_key(record)) {
+            stats.only_second += 1;
+            write_row(writer, &clip_filter, "second", record, &contigs)?;
+        }
+        Ok(())
+    })?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_with_config;
+
+    #[test]
+    fn test_compare() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_compare.bam");
+        let run_stats = run_with_config(&config).unwrap();
+        assert!(run_stats.failed() > 0);
+        let mut tsv: Vec<u8> = Vec::new();
+        let stats = compare(
+            &FilterConfig::new(),
+            "test/data/adapters.sam",
+            "test/data/out_compare.bam",
+            &mut tsv,
+        )
+        .unwrap();
+        assert_eq!(stats.shared, run_stats.written);
+        assert_eq!(stats.only_first, run_stats.failed());
+        assert_eq!(stats.only_second, 0);
+        let text: String = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = text.lines().skip(1).collect();
+        assert_eq!(lines.len() as u64, run_stats.failed());
+        assert!(lines.iter().all(|line| line.starts_with("first\t")));
+
+        // the other way around, the removed alignments are only in the second file
+        let mut tsv: Vec<u8> = Vec::new();
+        let stats = compare(
+            &FilterConfig::new(),
+            "test/data/out_compare.bam",
+            "test/data/adapters.sam",
+            &mut tsv,
+        )
+        .unwrap();
+        assert_eq!(stats.only_first, 0);
+        assert_eq!(stats.only_second, run_stats.failed());
+    }
+
+    #[test]
+    fn test_compare_errors() {
+        let mut tsv: Vec<u8> = Vec::new();
+        let config = FilterConfig::new();
+        assert!(compare(&config, "test/data/test.sam", "-", &mut tsv).is_err());
+        assert!(compare(
+            &config,
+            "test/data/test.sam",
+            "test/data/adapters.sam",
+            &mut tsv
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::adapters::reverse_complement;
+use std::collections::HashMap;
+
+/// Lowest DUST score of a low-complexity sequence, see [dust_score]
+pub const DUST_THRESHOLD: f64 = 2.0;
+
+/// DUST score of a sequence: how much its triplets repeat, as the number of pairs of
+/// identical triplets over the number of triplets minus one. Homopolymers of length `n`
+/// score `(n - 2) / 2`, sequences without repeated triplets score 0
+///
+/// # Arguments
+/// * `seq`: the sequence
+///
+/// # Return
+/// * the score, 0 for sequences shorter than 4 bases
+///
+/// # Examples
+/// ```
+/// use filter_clipped::complexity::dust_score;
+/// assert_eq!(dust_score(b"AAAAAAAAAAAA"), 5.0);
+/// assert_eq!(dust_score(b"ACGTTGCA"), 0.0);
+/// ```
+pub fn dust_score(seq: &[u8]) -> f64 {
+    if seq.len() < 4 {
+        return 0.0;
+    }
+    let mut triplet_counts: HashMap<&[u8], u64> = HashMap::new();
+    for triplet in seq.windows(3) {
+        *triplet_counts.entry(triplet).or_insert(0) += 1;
+    }
+    let pairs: u64 = triplet_counts
+        .values()
+        .map(|count| count * (count - 1) / 2)
+        .sum();
+    pairs as f64 / (seq.len() - 3) as f64
+}
+
+/// Whether a sequence is low-complexity (e.g. a homopolymer or a dinucleotide repeat)
+///
+/// # Arguments
+/// * `seq`: the sequence
+///
+/// # Examples
+/// ```
+/// use filter_clipped::complexity::is_low_complexity;
+/// assert!(is_low_complexity(b"ACACACACACAC"));
+/// assert!(!is_low_complexity(b"AGATCGGAAGAGC"));
+/// ```
+pub fn is_low_complexity(seq: &[u8]) -> bool {
+    dust_score(seq) >= DUST_THRESHOLD
+}
+
+/// Purity of a sequence as a tandem repeat of a motif: the largest fraction of its bases
+/// matching the repeated motif, over all the phases of the repeat and both strands (e.g.
+/// telomeric TTAGGG repeats also read as CCCTAA)
+///
+/// # Arguments
+/// * `seq`: the sequence
+/// * `motif`: the repeat unit
+///
+/// # Return
+/// * the purity, 0 for empty sequences or motifs
+///
+/// # Examples
+/// ```
+/// use filter_clipped::complexity::motif_purity;
+/// assert_eq!(motif_purity(b"TTAGGGTTAGGG", b"TTAGGG"), 1.0);
+/// assert_eq!(motif_purity(b"GGGTTAGGGTTA", b"TTAGGG"), 1.0);
+/// assert_eq!(motif_purity(b"CCCTAACCCTAA", b"TTAGGG"), 1.0);
+/// assert_eq!(motif_purity(b"TTAGGGTTACGG", b"TTAGGG"), 11.0 / 12.0);
+/// ```
+pub fn motif_purity(seq: &[u8], motif: &[u8]) -> f64 {
+    if seq.is_empty() || motif.is_empty() {
+        return 0.0;
+    }
+    let motif: Vec<u8> = motif.to_ascii_uppercase();
+    let mut best: usize = 0;
+    for unit in [reverse_complement(&motif), motif] {
+        for phase in 0..unit.len() {
+            let matches: usize = seq
+                .iter()
+                .enumerate()
+                .filter(|(i, base)| base.to_ascii_uppercase() == unit[(i + phase) % unit.len()])
+                .count();
+            best = best.max(matches);
+        }
+    }
+    best as f64 / seq.len() as f64
+}
+
+/// Whether a sequence is a repeat of one of the motifs, with at least `min_purity` of its
+/// bases matching (see [motif_purity]); sequences shorter than a motif don't match it
+///
+/// # Arguments
+/// * `seq`: the sequence
+/// * `motifs`: the repeat units
+/// * `min_purity`: lowest purity of a matching sequence
+///
+/// # Examples
+/// ```
+/// use filter_clipped::complexity::is_motif_repeat;
+/// let motifs = vec![String::from("TTAGGG")];
+/// assert!(is_motif_repeat(b"TTAGGGTTAGGGTTACGG", &motifs, 0.9));
+/// assert!(!is_motif_repeat(b"TTAGG", &motifs, 0.9));
+/// assert!(!is_motif_repeat(b"AGATCGGAAGAGC", &motifs, 0.9));
+/// ```
+pub fn is_motif_repeat(seq: &[u8], motifs: &[String], min_purity: f64) -> bool {
+    motifs
+        .iter()
+        .any(|motif| seq.len() >= motif.len() && motif_purity(seq, motif.as_bytes()) >= min_purity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(b"AAA", 0.0)]
+    #[case(b"AAAAAA", 2.0)]
+    #[case(b"ACACACACACAC", 20.0 / 9.0)]
+    #[case(b"ACGTACGTACGT", 8.0 / 9.0)]
+    #[case(b"AGATCGGAAGAGC", 0.1)]
+    fn test_dust_score(#[case] seq: &[u8], #[case] expected: f64) {
+        assert_eq!(dust_score(seq), expected);
+    }
+
+    #[rstest]
+    #[case(b"", b"TTAGGG", 0.0)]
+    #[case(b"ttagggttaggg", b"TTAGGG", 1.0)]
+    #[case(b"AGGGTTAGGGTT", b"ttaggg", 1.0)]
+    #[case(b"TTAGGGTTNGGG", b"TTAGGG", 11.0 / 12.0)]
+    #[case(b"ACACACAC", b"CA", 1.0)]
+    #[case(b"ACGTACGT", b"AC", 0.5)]
+    fn test_motif_purity(#[case] seq: &[u8], #[case] motif: &[u8], #[case] expected: f64) {
+        assert_eq!(motif_purity(seq, motif), expected);
+    }
+}
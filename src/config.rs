@@ -0,0 +1,2592 @@
+use crate::adapters::{Adapter, AdapterPolicy};
+use crate::clipping::ClipStat;
+use crate::error::FilterClippedError;
+use crate::expression::Expression;
+use crate::primers::Primer;
+use rust_htslib::bam::Record;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+/// What to do with a class of alignments (e.g. secondary or supplementary alignments)
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagPolicy {
+    /// write the alignment out without checking its clipped bases
+    Keep,
+    /// always remove the alignment
+    Drop,
+    /// check the clipped bases like a primary alignment
+    Filter,
+}
+
+/// How the clipping results of the two mates decide whether a read pair is kept
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairPolicy {
+    /// keep the pair if either mate passes
+    Any,
+    /// keep the pair only if both mates pass
+    Both,
+}
+
+/// What to do with a mate passing the filter when the other mate of its pair fails, in
+/// paired or two-pass mode (see [FilterConfig::singletons])
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SingletonPolicy {
+    /// write it to the output without its mate
+    Keep,
+    /// remove it together with its mate
+    Drop,
+    /// write it into this file rather than the output
+    SeparateOut(String),
+}
+
+impl SingletonPolicy {
+    /// Parse the values given to `--singletons`: `keep`, `drop`, or `separate-out` followed
+    /// by the path of the file
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::SingletonPolicy;
+    /// let values = vec![String::from("separate-out"), String::from("singletons.bam")];
+    /// assert_eq!(
+    ///     SingletonPolicy::parse(&values).unwrap(),
+    ///     SingletonPolicy::SeparateOut(String::from("singletons.bam"))
+    /// );
+    /// assert_eq!(SingletonPolicy::parse(&[String::from("drop")]).unwrap(), SingletonPolicy::Drop);
+    /// assert!(SingletonPolicy::parse(&[String::from("separate-out")]).is_err());
+    /// ```
+    pub fn parse(values: &[String]) -> Result<Self, FilterClippedError> {
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        match values.as_slice() {
+            ["keep"] => Ok(SingletonPolicy::Keep),
+            ["drop"] => Ok(SingletonPolicy::Drop),
+            ["separate-out", path] => Ok(SingletonPolicy::SeparateOut(path.to_string())),
+            _ => Err(FilterClippedError::InvalidConfig(format!(
+                "singletons should be keep, drop or separate-out <PATH>: {}",
+                values.join(" ")
+            ))),
+        }
+    }
+}
+
+/// Aux tags describing the alignment that are removed from the alignments made unmapped
+/// with `unalign` by default: edit distance, mismatches, alignment scores, alternative and
+/// chimeric alignments, number of hits
+pub const UNALIGN_STRIP_TAGS: [&str; 8] = ["NM", "MD", "AS", "XS", "XA", "SA", "NH", "HI"];
+
+/// What to do with the alignments that are already unmapped in the input (flag 0x4 and
+/// CIGAR "*", see [is_unmapped_input]), which have no clipping to check
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnmappedPolicy {
+    /// follow the decision made for the other alignments of the read name (e.g. the mapped
+    /// mate in paired mode), kept if there are none
+    Keep,
+    /// always remove the alignment
+    Drop,
+    /// always write the alignment out as it is, whatever is decided for its read name
+    Passthrough,
+}
+
+/// What to do with the secondary alignments without a sequence (SEQ "*", usually with hard
+/// clips only, see [is_seqless_secondary]), whose clip fractions can't be worked out
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeqlessPolicy {
+    /// follow the decision made for the other alignments of the read name (the primary
+    /// alignment in paired, two-pass or cascade mode), kept otherwise
+    Inherit,
+    /// always write the alignment out
+    Keep,
+    /// always remove the alignment
+    Drop,
+}
+
+/// What to do when the input was already filtered by this program with the same settings,
+/// going by the @PG records of its header (see [crate::header::settings_fingerprint])
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlreadyFilteredPolicy {
+    /// log a warning and filter it again
+    Warn,
+    /// stop without writing any output
+    Skip,
+    /// filter it again without a warning, e.g. when re-filtering on purpose
+    Proceed,
+}
+
+/// Strand of the reference an alignment is on
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strand {
+    /// forward strand
+    Fwd,
+    /// reverse strand (flag 0x10)
+    Rev,
+}
+
+impl Strand {
+    /// Strand of an alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::Strand;
+    /// use rust_htslib::bam::Record;
+    /// let mut record = Record::new();
+    /// assert_eq!(Strand::of(&record), Strand::Fwd);
+    /// record.set_reverse();
+    /// assert_eq!(Strand::of(&record), Strand::Rev);
+    /// ```
+    pub fn of(record: &Record) -> Self {
+        match record.is_reverse() {
+            true => Strand::Rev,
+            false => Strand::Fwd,
+        }
+    }
+}
+
+/// Format of the output alignment file
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// plain text SAM
+    Sam,
+    /// compressed BAM
+    Bam,
+    /// reference-compressed CRAM
+    Cram,
+}
+
+impl OutputFormat {
+    /// Guess the output format from the extension of a file path,
+    /// falling back to BAM (also for stdout)
+    ///
+    /// # Arguments
+    /// * `path`: output file path
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::OutputFormat;
+    /// assert_eq!(OutputFormat::from_path("out.sam"), OutputFormat::Sam);
+    /// assert_eq!(OutputFormat::from_path("out.CRAM"), OutputFormat::Cram);
+    /// assert_eq!(OutputFormat::from_path("-"), OutputFormat::Bam);
+    /// ```
+    pub fn from_path(path: &str) -> Self {
+        let extension: Option<String> = std::path::Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("sam") => OutputFormat::Sam,
+            Some("cram") => OutputFormat::Cram,
+            _ => OutputFormat::Bam,
+        }
+    }
+}
+
+impl From<OutputFormat> for rust_htslib::bam::Format {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Sam => rust_htslib::bam::Format::Sam,
+            OutputFormat::Bam => rust_htslib::bam::Format::Bam,
+            OutputFormat::Cram => rust_htslib::bam::Format::Cram,
+        }
+    }
+}
+
+/// Format of a settings file, see [FilterConfig::from_file]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Guess the format of a settings file from the extension of its path,
+    /// falling back to TOML (also for stdout)
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::ConfigFormat;
+    /// assert_eq!(ConfigFormat::from_path("filter.yml"), ConfigFormat::Yaml);
+    /// assert_eq!(ConfigFormat::from_path("filter.json"), ConfigFormat::Json);
+    /// assert_eq!(ConfigFormat::from_path("-"), ConfigFormat::Toml);
+    /// ```
+    pub fn from_path(path: &str) -> Self {
+        let extension: Option<String> = std::path::Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+/// Maximum fractions of clipped bases relative to the read sequence length for an
+/// alignment to pass
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Thresholds {
+    /// maximum fraction of total clipped bases
+    pub both_end: f64,
+    /// maximum fraction of 5' clipped bases
+    pub left_side: f64,
+    /// maximum fraction of 3' clipped bases
+    pub right_side: f64,
+}
+
+impl Thresholds {
+    /// Parse thresholds given as a single fraction for the total, 5' and 3' clipping alike,
+    /// or as `<both_end>/<left_side>/<right_side>`
+    ///
+    /// # Arguments
+    /// * `spec`: the fractions
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::Thresholds;
+    /// let thresholds = Thresholds::parse("0.3/0.2/0.1").unwrap();
+    /// assert_eq!(thresholds.left_side, 0.2);
+    /// assert_eq!(Thresholds::parse("0.2").unwrap().right_side, 0.2);
+    /// assert!(Thresholds::parse("0.3/0.2").is_err());
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, FilterClippedError> {
+        let malformed = || {
+            FilterClippedError::InvalidConfig(format!(
+                "thresholds should look like <fraction> or <both_end>/<left_side>/<right_side>: {}",
+                spec
+            ))
+        };
+        let fractions: Vec<f64> = spec
+            .split('/')
+            .map(|fraction| fraction.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| malformed())?;
+        match fractions[..] {
+            [fraction] => Ok(Thresholds {
+                both_end: fraction,
+                left_side: fraction,
+                right_side: fraction,
+            }),
+            [both_end, left_side, right_side] => Ok(Thresholds {
+                both_end,
+                left_side,
+                right_side,
+            }),
+            _ => Err(malformed()),
+        }
+    }
+
+    /// Check that the thresholds are fractions within 0 and 1
+    ///
+    /// # Arguments
+    /// * `name`: what the thresholds are for, used in the error message
+    fn validate(&self, name: &str) -> Result<(), FilterClippedError> {
+        let thresholds = [
+            ("both_end", self.both_end),
+            ("left_side", self.left_side),
+            ("right_side", self.right_side),
+        ];
+        for (field, value) in thresholds {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "{}{} is {}, which is not within 0 and 1",
+                    name, field, value
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Weights combining the clip fractions (and the edit distance) of an alignment into
+/// a single score, see [ScoreWeights::score]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoreWeights {
+    /// weight of the fraction of 5' clipped bases
+    pub left: f64,
+    /// weight of the fraction of 3' clipped bases
+    pub right: f64,
+    /// weight of the fraction of total clipped bases
+    pub total: f64,
+    /// weight of the edit distance (NM tag) relative to the read sequence length
+    pub nm: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            left: 0.0,
+            right: 0.0,
+            total: 0.0,
+            nm: 0.0,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// Parse weights given as comma-separated `<name>=<weight>` entries, with the names
+    /// left, right, total and nm; weights left out are 0
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::ScoreWeights;
+    /// let weights = ScoreWeights::parse("left=1,right=1,total=0.5").unwrap();
+    /// assert_eq!(weights.total, 0.5);
+    /// assert_eq!(weights.nm, 0.0);
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, FilterClippedError> {
+        let malformed = |entry: &str| {
+            FilterClippedError::InvalidConfig(format!(
+                "score weights should look like left=<weight>,right=<weight>,total=<weight>,nm=<weight>: {}",
+                entry
+            ))
+        };
+        let mut weights = Self::default();
+        for entry in spec.split(',').map(str::trim) {
+            let (name, weight) = entry.split_once('=').ok_or_else(|| malformed(entry))?;
+            let weight: f64 = weight.trim().parse().map_err(|_| malformed(entry))?;
+            match name.trim() {
+                "left" => weights.left = weight,
+                "right" => weights.right = weight,
+                "total" => weights.total = weight,
+                "nm" => weights.nm = weight,
+                _ => return Err(malformed(entry)),
+            }
+        }
+        Ok(weights)
+    }
+
+    /// Combine the fractions into the weighted score
+    ///
+    /// # Arguments
+    /// * `left`: fraction of 5' clipped bases
+    /// * `right`: fraction of 3' clipped bases
+    /// * `total`: fraction of total clipped bases
+    /// * `nm`: edit distance relative to the read sequence length
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::ScoreWeights;
+    /// let weights = ScoreWeights::parse("left=1,right=1,nm=2").unwrap();
+    /// assert_eq!(weights.score(0.1, 0.05, 0.15, 0.01), 0.17);
+    /// ```
+    pub fn score(&self, left: f64, right: f64, total: f64, nm: f64) -> f64 {
+        self.left * left + self.right * right + self.total * total + self.nm * nm
+    }
+
+    /// Check that the weights are positive (or 0), with at least one of them above 0
+    fn validate(&self) -> Result<(), FilterClippedError> {
+        let weights = [self.left, self.right, self.total, self.nm];
+        let invalid = |weight: &f64| !weight.is_finite() || *weight < 0.0;
+        if weights.iter().any(invalid) || weights.iter().all(|weight| *weight == 0.0) {
+            return Err(FilterClippedError::InvalidConfig(format!(
+                "score weights must be 0 or more, with at least one above 0: {:?}",
+                self
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Thresholds for the reads with a length within a range, see [parse_length_thresholds]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LengthThresholds {
+    /// shortest read length the thresholds apply to
+    pub min_len: u64,
+    /// read length the thresholds stop applying at (excluded), no limit if None
+    pub max_len: Option<u64>,
+    /// the thresholds for the reads within the range
+    #[serde(flatten)]
+    pub thresholds: Thresholds,
+}
+
+impl LengthThresholds {
+    /// Whether a read length is within the range
+    pub fn contains(&self, read_len: u64) -> bool {
+        read_len >= self.min_len
+            && match self.max_len {
+                Some(max_len) => read_len < max_len,
+                None => true,
+            }
+    }
+}
+
+/// Parse a schedule of thresholds by read length: comma-separated `<min>-<max>:<fraction>`
+/// entries, with the range including `min` and excluding `max` (no upper limit if `max` is left out).
+/// A single fraction applies to the total, 5' and 3' clipping alike, otherwise they are given
+/// separately as `<both_end>/<left_side>/<right_side>`
+///
+/// # Arguments
+/// * `spec`: the schedule
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::parse_length_thresholds;
+/// let schedule = parse_length_thresholds("0-200:0.1,200-5000:0.2/0.1/0.1,5000-:0.3").unwrap();
+/// assert_eq!(schedule.len(), 3);
+/// assert!(schedule[1].contains(200));
+/// assert_eq!(schedule[1].thresholds.left_side, 0.1);
+/// assert_eq!(schedule[2].max_len, None);
+/// ```
+pub fn parse_length_thresholds(spec: &str) -> Result<Vec<LengthThresholds>, FilterClippedError> {
+    let malformed = |entry: &str| {
+        FilterClippedError::InvalidConfig(format!(
+            "length thresholds should look like <min>-<max>:<fraction> or <min>-<max>:<both_end>/<left_side>/<right_side>: {}",
+            entry
+        ))
+    };
+    let mut schedule: Vec<LengthThresholds> = Vec::new();
+    for entry in spec.split(',').map(str::trim) {
+        let (range, fractions) = entry.split_once(':').ok_or_else(|| malformed(entry))?;
+        let (min_len, max_len) = range.split_once('-').ok_or_else(|| malformed(entry))?;
+        let min_len: u64 = min_len.trim().parse().map_err(|_| malformed(entry))?;
+        let max_len: Option<u64> = match max_len.trim() {
+            "" => None,
+            max_len => Some(max_len.parse().map_err(|_| malformed(entry))?),
+        };
+        if matches!(max_len, Some(max_len) if max_len <= min_len) {
+            return Err(malformed(entry));
+        }
+        let thresholds: Thresholds = Thresholds::parse(fractions).map_err(|_| malformed(entry))?;
+        schedule.push(LengthThresholds {
+            min_len,
+            max_len,
+            thresholds,
+        });
+    }
+    Ok(schedule)
+}
+
+/// Parse a table of per-reference-sequence thresholds: one reference sequence per line,
+/// with its name, total, 5' and 3' thresholds separated by tabs. Empty lines and lines
+/// starting with `#` are skipped
+///
+/// # Arguments
+/// * `text`: content of the table
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::{parse_contig_thresholds, Thresholds};
+/// let table = parse_contig_thresholds("# name\tboth\tleft\tright\nchrM\t0.3\t0.2\t0.2\n").unwrap();
+/// assert_eq!(
+///     table["chrM"],
+///     Thresholds { both_end: 0.3, left_side: 0.2, right_side: 0.2 }
+/// );
+/// ```
+pub fn parse_contig_thresholds(
+    text: &str,
+) -> Result<BTreeMap<String, Thresholds>, FilterClippedError> {
+    let mut table: BTreeMap<String, Thresholds> = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let malformed = || {
+            FilterClippedError::InvalidConfig(format!(
+                "line {} of the contig thresholds should be <name>\\t<both_end>\\t<left_side>\\t<right_side>: {}",
+                i + 1,
+                line
+            ))
+        };
+        let fields: Vec<&str> = line.trim_end().split('\t').collect();
+        if fields.len() != 4 {
+            return Err(malformed());
+        }
+        let values: Vec<f64> = fields[1..]
+            .iter()
+            .map(|value| value.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| malformed())?;
+        table.insert(
+            fields[0].to_string(),
+            Thresholds {
+                both_end: values[0],
+                left_side: values[1],
+                right_side: values[2],
+            },
+        );
+    }
+    Ok(table)
+}
+
+/// Bundles of thresholds for common data types, see [FilterConfig::preset]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// short reads (e.g. Illumina): the default thresholds
+    ShortRead,
+    /// long reads (e.g. ONT, PacBio): looser thresholds, hard clips of supplementary
+    /// alignments are not counted
+    LongRead,
+    /// amplicon sequencing: stricter on clipping of either end
+    Amplicon,
+    /// RNA-seq: looser on the total clipping, as reads ending near exon junctions carry
+    /// small clips on both ends
+    Rna,
+}
+
+/// A user-supplied check run after the clip decision, see [FilterConfig::with_extra_filter]
+#[derive(Clone)]
+pub struct ExtraFilter(Arc<ExtraFilterFn>);
+
+/// Signature of the extra filter: (alignment, clipped bases, passed the built-in checks) -> keep
+type ExtraFilterFn = dyn Fn(&Record, &ClipStat, bool) -> bool + Send + Sync;
+
+impl ExtraFilter {
+    /// Run the check
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    /// - `clip_stat`: the clipped bases of the alignment
+    /// - `passed`: whether the alignment passed the built-in checks
+    ///
+    /// # Returns
+    /// - whether the alignment is kept
+    pub fn keep(&self, record: &Record, clip_stat: &ClipStat, passed: bool) -> bool {
+        (self.0)(record, clip_stat, passed)
+    }
+}
+
+impl fmt::Debug for ExtraFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExtraFilter")
+    }
+}
+
+/// All the settings needed for a filtering run
+///
+/// Settings files (see [FilterConfig::from_file]) use the same field names,
+/// fields left out take their default value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// input bam file path ("-" for stdin, or an http/https/ftp/s3 URL)
+    pub in_bam: String,
+    /// more input files, filtered after `in_bam` into the same output (with the headers merged)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_inputs: Vec<String>,
+    /// output bam file path ("-" for stdout)
+    pub out_bam: String,
+    /// write out the failed-filter alignments only
+    pub inverse: bool,
+    /// maximum fraction of total clipped bases relative to the read sequence length
+    pub both_end: f64,
+    /// maximum fraction of 5' clipped bases relative to the read sequence length
+    pub left_side: f64,
+    /// maximum fraction of 3' clipped bases relative to the read sequence length
+    pub right_side: f64,
+    /// make the failed alignments unmapped instead of removing them
+    pub unalign: bool,
+    /// keep the failed alignments where they are with MAPQ set to 0 instead of removing them,
+    /// for variant callers reading it as "present but untrusted"
+    pub zero_mapq: bool,
+    /// aux tag (type Z) holding why the alignments written with `zero_mapq` failed (see
+    /// [crate::stats::FailReason::as_str])
+    pub reason_tag: Option<String>,
+    /// aux tags removed from the alignments made unmapped with `unalign`, which also lose
+    /// their CIGAR and MAPQ
+    pub unalign_strip_tags: Vec<String>,
+    /// reverse complement SEQ (and reverse QUAL) of the reverse strand alignments made
+    /// unmapped with `unalign`, so that they read like the original read
+    pub restore_orientation: bool,
+    /// write the alignments made unmapped with `unalign` into this unaligned BAM (SAM for a
+    /// .sam path) instead of the output, with only the @HD, @RG and @PG lines in its header
+    pub unaligned_out: Option<String>,
+    /// write the alignments made unmapped with `unalign` after all the mapped ones (held in
+    /// a temporary file until then), so that a coordinate sorted output stays sorted and can
+    /// be indexed
+    pub unaligned_to_end: bool,
+    /// how to handle secondary alignments (flag 0x100)
+    pub secondary: FlagPolicy,
+    /// how to handle supplementary alignments (flag 0x800)
+    pub supplementary: FlagPolicy,
+    /// how to handle the alignments already unmapped in the input (flag 0x4 and CIGAR "*")
+    pub unmapped: UnmappedPolicy,
+    /// how to handle the secondary alignments without a sequence, when secondary alignments
+    /// are filtered
+    pub seqless_secondary: SeqlessPolicy,
+    /// how to handle PCR/optical duplicates (flag 0x400)
+    pub duplicates: FlagPolicy,
+    /// how to handle alignments already flagged as QC-fail (flag 0x200)
+    pub qcfail: FlagPolicy,
+    /// fail the alignments without the proper-pair flag (0x2) before checking their clipped
+    /// bases, like `samtools view -f 2`; they are made unmapped rather than removed with
+    /// `unalign`
+    pub require_proper_pair: bool,
+    /// only check the clipping of the alignments on this strand, the others are written out
+    /// without being checked, e.g. for strand-specific protocols with clipping artifacts on
+    /// one strand only
+    pub only_strand: Option<Strand>,
+    /// evaluate alignments sharing a read name together (input must be queryname sorted or collated)
+    pub paired: bool,
+    /// group the alignments of each read name together before filtering in paired mode, for
+    /// input that isn't queryname sorted or collated (see [crate::collate])
+    pub collate: bool,
+    /// number of alignments of incomplete reads held in memory by `collate` before they are
+    /// spilled into temporary files next to the output
+    pub collate_max_pending: usize,
+    /// how the mates decide whether a pair is kept in paired or two-pass mode
+    pub pair_policy: PairPolicy,
+    /// read the input twice, first collecting the failing read names, then removing all their alignments
+    /// (for coordinate sorted input, doesn't work with stdin)
+    pub two_pass: bool,
+    /// let secondary and supplementary alignments follow the decision made on the primary alignments
+    /// of the same read name (needs `paired` or `two_pass`)
+    pub cascade: bool,
+    /// compute the clipping of chimeric reads (with an SA tag) over the union of all of their segments,
+    /// and update the SA tags when segments are removed
+    pub sa_aware: bool,
+    /// when a primary alignment fails the filter but a supplementary alignment of the same read
+    /// (or mate) passes, make the supplementary alignment the primary one and remove the failing
+    /// one, rather than losing the read (needs `paired`)
+    pub promote_supplementary: bool,
+    /// clear the mate information (RNEXT/PNEXT/TLEN, MC and MQ) and set the mate unmapped flag
+    /// on the alignments written without the primary alignment of their mate (needs `paired` or
+    /// `two_pass`; in two-pass mode, only the mates removed by the run are noticed)
+    pub fix_orphans: bool,
+    /// what to do with the alignments of a mate passing the filter when the other mate fails
+    /// (needs `paired` or `two_pass`), rather than deciding on the whole pair with `pair_policy`
+    pub singletons: Option<SingletonPolicy>,
+    /// command line recorded in the @PG header record of the output
+    pub command_line: Option<String>,
+    /// record the settings of the run as a JSON @CO comment in the output header
+    pub header_comment: bool,
+    /// what to do when the input was already filtered with the same settings
+    pub if_already_filtered: AlreadyFilteredPolicy,
+    /// format of the output file, guessed from the output path if not given
+    pub output_fmt: Option<OutputFormat>,
+    /// reference fasta file, needed for CRAM input/output
+    pub reference: Option<String>,
+    /// index the output file after writing it (needs coordinate sorted input)
+    pub write_index: bool,
+    /// number of alignments skipped at the start of the input (over all the inputs)
+    pub skip_records: u64,
+    /// stop after filtering this many alignments (after the skipped ones), e.g. to try out
+    /// thresholds on the start of a large file
+    pub max_records: Option<u64>,
+    /// number of threads filtering windows of the reference in parallel (1 for a single
+    /// stream), for indexed input files; the filtered windows are merged in coordinate order
+    pub region_threads: usize,
+    /// size in bases of the reference windows filtered in parallel with `region_threads`
+    pub region_size: u64,
+    /// with `region_threads`, write the filtered windows out as soon as they are done rather
+    /// than in coordinate order, for consumers sorting the output anyway; the output is
+    /// unsorted
+    pub unordered: bool,
+    /// directory to also write the output alignments into, split into one BAM file per
+    /// reference sequence (`<name>.bam`) plus `unmapped.bam`
+    pub shard_by_chrom: Option<String>,
+    /// directory to write the failing alignments into, as they were in the input, one BAM
+    /// file per reason they failed for (`<reason>.bam`, e.g. `left_clip.bam`)
+    pub split_by_reason: Option<String>,
+    /// file to keep rewriting with the counters of the run in the Prometheus text format,
+    /// for a node exporter to scrape the progress of long runs
+    pub metrics_out: Option<String>,
+    /// seconds between two rewrites of `metrics_out`
+    pub metrics_interval: u64,
+    /// abort instead of warning when the input isn't sorted the way the other settings need it
+    pub require_sorted: bool,
+    /// fail the run (exit code 3) if no alignment passed the filter
+    pub fail_if_empty: bool,
+    /// fail the run (exit code 3) if more than this fraction of the alignments read failed
+    /// the filter
+    pub fail_if_removed_frac: Option<f64>,
+    /// skip and count the records that can't be read (up to `max_malformed` of them) instead
+    /// of aborting the run at the first one
+    pub tolerant: bool,
+    /// most unreadable records skipped with `tolerant` before the run is aborted anyway
+    pub max_malformed: u64,
+    /// show a progress bar on stderr
+    pub progress: bool,
+    /// print a table of what happened to the alignments by the categories of their flags
+    /// (like `samtools flagstat`) on stderr at the end of the run
+    pub flagstat: bool,
+    /// TSV file of the binned clip fractions and clip lengths of the evaluated alignments
+    pub hist_out: Option<String>,
+    /// self-contained HTML page of the run summary, clip fraction histograms and
+    /// per-reference-sequence counts
+    pub html_report: Option<String>,
+    /// TSV file of the numbers of alignments beginning or ending a soft clip, by reference window
+    pub clip_profile: Option<String>,
+    /// size in bases of the reference windows of `clip_profile` and `breakpoint_bed`
+    pub profile_window: u64,
+    /// BED file of the reference windows where soft clip boundaries cluster
+    pub breakpoint_bed: Option<String>,
+    /// fewest alignments with a soft clip boundary in a window to report it in `breakpoint_bed`
+    pub min_breakpoint_support: u64,
+    /// FASTA file of the consensus of the soft clipped sequences at each clip boundary
+    pub clip_consensus: Option<String>,
+    /// fewest alignments clipped at a boundary to report it in `clip_consensus`
+    pub min_consensus_support: u64,
+    /// TSV file of the binned GC content of the soft clipped and the aligned sequence of the
+    /// evaluated alignments
+    pub gc_out: Option<String>,
+    /// TSV file of the k-mers most enriched in the soft clipped sequence of the evaluated
+    /// alignments, relative to their aligned sequence
+    pub kmer_out: Option<String>,
+    /// length of the k-mers of `kmer_out`, up to 32
+    pub kmer_size: u64,
+    /// most k-mers written into `kmer_out`
+    pub top_kmers: u64,
+    /// TSV file of pass/fail counts and mean clip fractions by cell barcode
+    pub cell_stats: Option<String>,
+    /// aux tag holding the cell barcode, for `cell_stats` and `cb_whitelist`
+    pub cell_tag: String,
+    /// file of cell barcodes (plain or gzipped, one per line): alignments with another
+    /// (or no) cell barcode in `cell_tag` are removed
+    pub cb_whitelist: Option<String>,
+    /// file of read names (plain or gzipped, one per line) whose alignments are kept
+    /// without checking their flags and clipping
+    pub keep_names: Option<String>,
+    /// file of read names (plain or gzipped, one per line) whose alignments are removed,
+    /// even if also listed in `keep_names`
+    pub drop_names: Option<String>,
+    /// file of the read names of the removed alignments, one alignment per line
+    pub removed_names: Option<String>,
+    /// write the flag and the reason of the removal after the read names in `removed_names`
+    pub removed_details: bool,
+    /// Parquet file of the clip fractions and the decision of every alignment,
+    /// needs the `parquet` feature
+    pub metrics_parquet: Option<String>,
+    /// BEDPE file of the junctions between the primary alignments and the segments in
+    /// their SA tags, as candidate fusions or structural variants
+    pub junction_bedpe: Option<String>,
+    /// fewest clipped bases on one side of a primary alignment to report its junctions
+    /// in `junction_bedpe`
+    pub min_junction_clip: u64,
+    /// minimum mapping quality, alignments below it fail the filter
+    pub min_mapq: u8,
+    /// highest fraction of inserted and deleted bases (relative to the read length), the
+    /// alignments passing the clipping thresholds with more indels fail the filter
+    pub max_indel_fraction: Option<f64>,
+    /// highest fraction of mismatched bases (NM minus the indels, relative to the aligned
+    /// bases), the alignments passing the clipping thresholds with more mismatches fail the
+    /// filter; alignments without an NM tag aren't checked
+    pub max_mismatch_fraction: Option<f64>,
+    /// lowest fraction of the read (hard clipped bases included) that is aligned (M, = and
+    /// X bases), the alignments passing the clipping thresholds with less fail the filter
+    pub min_query_coverage: Option<f64>,
+    /// shortest contiguous run of aligned (M, = and X) bases an alignment needs to be anchored,
+    /// the alignments passing the clipping thresholds without one fail the filter
+    pub min_aligned_block: Option<u64>,
+    /// longest insertion or deletion not breaking a run of aligned bases for
+    /// `min_aligned_block`
+    pub aligned_block_max_indel: u32,
+    /// lowest fraction of a split read (an alignment with an SA tag) covered by the union of
+    /// the alignment and its supplementary segments; split reads are gated on it instead of
+    /// the clipping thresholds, the alignments without an SA tag aren't affected
+    pub min_split_coverage: Option<f64>,
+    /// smallest insert size (absolute TLEN) of a paired alignment, the alignments passing the
+    /// clipping thresholds with a smaller one fail the filter; alignments without an insert
+    /// size (TLEN 0, e.g. mates on different references) aren't checked
+    pub min_insert_size: Option<u64>,
+    /// largest insert size (absolute TLEN) of a paired alignment, see `min_insert_size`
+    pub max_insert_size: Option<u64>,
+    /// filter expression in the style of `samtools view -e` (see [crate::expression]), the
+    /// alignments passing the clipping thresholds fail the filter if it isn't true on them
+    pub expression: Option<String>,
+    /// don't count low-complexity soft clips (e.g. homopolymer or dinucleotide repeat tails)
+    /// as clipped, see [crate::complexity::is_low_complexity]
+    pub exempt_low_complexity: bool,
+    /// repeat units (e.g. TTAGGG for telomeres) whose repeats aren't counted as clipped when
+    /// they make up a soft clip, see [crate::complexity::is_motif_repeat]
+    pub exempt_motifs: Vec<String>,
+    /// lowest fraction of the bases of a soft clip matching the repeat of one of the
+    /// `exempt_motifs` for the clip not to count
+    pub motif_purity: f64,
+    /// lowest base quality of a soft clipped base counted as clipped, low quality tails
+    /// being expected to be clipped (0 counts all the clipped bases)
+    pub min_clip_bq: u8,
+    /// don't count the hard clipped bases of supplementary alignments, which aligners
+    /// use for the parts of a chimeric read aligned elsewhere
+    pub ignore_supplementary_hardclips: bool,
+    /// splice-aware mode for RNA alignments: spliced alignments (with N operations in their
+    /// CIGAR string) get `spliced_thresholds`, or aren't filtered on clipping without them
+    pub rna: bool,
+    /// thresholds for spliced alignments with `rna`, in place of all the other thresholds
+    pub spliced_thresholds: Option<Thresholds>,
+    /// thresholds for alignments on specific reference sequences (by name), in place of
+    /// `both_end`, `left_side` and `right_side`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub contig_thresholds: BTreeMap<String, Thresholds>,
+    /// thresholds by read length, in place of `both_end`, `left_side` and `right_side`
+    /// (the first matching range applies, thresholds of the reference sequence take precedence)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub length_thresholds: Vec<LengthThresholds>,
+    /// thresholds for the first mates (read 1) of paired reads, in place of `both_end`,
+    /// `left_side` and `right_side` (thresholds by reference sequence or read length take
+    /// precedence)
+    pub read1_thresholds: Option<Thresholds>,
+    /// thresholds for the second mates (read 2) of paired reads, see `read1_thresholds`
+    pub read2_thresholds: Option<Thresholds>,
+    /// check a single weighted score of the clip fractions against `max_score`, in place of
+    /// the total, 5' and 3' thresholds
+    pub score_weights: Option<ScoreWeights>,
+    /// highest weighted score an alignment can have to pass (when `score_weights` is set)
+    pub max_score: f64,
+    /// known adapter sequences, looked for in the soft clipped bases
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub adapters: Vec<Adapter>,
+    /// what to do with alignments whose soft clipped bases are an adapter
+    pub adapter_policy: AdapterPolicy,
+    /// highest fraction of mismatching bases between a clip and an adapter
+    pub adapter_mismatch_rate: f64,
+    /// contaminant sequences (e.g. vectors, primers, spike-ins) screened for in the soft
+    /// clipped bases by shared k-mers, the hits being counted in the summary
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contaminants: Vec<Adapter>,
+    /// length of the k-mers shared by a clip and a contaminant, up to 32
+    pub screen_kmer_size: u64,
+    /// fail the alignments with a contaminant in their soft clipped bases
+    pub screen_fail: bool,
+    /// primer intervals (e.g. of an amplicon panel): clips lying within a primer
+    /// don't count as clipped
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub primers: Vec<Primer>,
+    /// derive `both_end`, `left_side` and `right_side` from a first pass over the input,
+    /// keeping this percentile (0-100) of the alignments on each of them
+    pub auto_threshold: Option<f64>,
+    /// set the preset of the aligner found in the @PG records of the input (see
+    /// [crate::header::detect_aligner]), replacing `both_end`, `left_side` and `right_side`
+    pub auto_preset: bool,
+    /// keep this fraction (0-1) of the read names among the alignments written as they are,
+    /// picked by a hash of the read name so that mates stay together
+    pub subsample: Option<f64>,
+    /// seed of `subsample`, the same seed picking the same read names
+    pub seed: u64,
+    /// user-supplied check run after the clip decision, only available from the library
+    #[serde(skip)]
+    pub extra_filter: Option<ExtraFilter>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            in_bam: String::from("-"),
+            extra_inputs: Vec::new(),
+            out_bam: String::from("-"),
+            inverse: false,
+            zero_mapq: false,
+            reason_tag: None,
+            both_end: 0.1,
+            left_side: 0.1,
+            right_side: 0.1,
+            unalign: false,
+            unalign_strip_tags: UNALIGN_STRIP_TAGS
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect(),
+            restore_orientation: false,
+            unaligned_out: None,
+            unaligned_to_end: false,
+            secondary: FlagPolicy::Filter,
+            supplementary: FlagPolicy::Filter,
+            duplicates: FlagPolicy::Filter,
+            qcfail: FlagPolicy::Filter,
+            require_proper_pair: false,
+            only_strand: None,
+            unmapped: UnmappedPolicy::Keep,
+            seqless_secondary: SeqlessPolicy::Inherit,
+            paired: false,
+            collate: false,
+            collate_max_pending: 1_000_000,
+            pair_policy: PairPolicy::Both,
+            two_pass: false,
+            cascade: false,
+            sa_aware: false,
+            promote_supplementary: false,
+            fix_orphans: false,
+            singletons: None,
+            command_line: None,
+            header_comment: false,
+            if_already_filtered: AlreadyFilteredPolicy::Warn,
+            output_fmt: None,
+            reference: None,
+            write_index: false,
+            skip_records: 0,
+            max_records: None,
+            fail_if_empty: false,
+            fail_if_removed_frac: None,
+            tolerant: false,
+            max_malformed: 1000,
+            region_threads: 1,
+            unordered: false,
+            region_size: 10_000_000,
+            shard_by_chrom: None,
+            split_by_reason: None,
+            metrics_out: None,
+            metrics_interval: 10,
+            require_sorted: false,
+            progress: false,
+            flagstat: false,
+            hist_out: None,
+            html_report: None,
+            clip_profile: None,
+            profile_window: 1,
+            breakpoint_bed: None,
+            min_breakpoint_support: 3,
+            clip_consensus: None,
+            min_consensus_support: 3,
+            gc_out: None,
+            kmer_out: None,
+            kmer_size: 8,
+            top_kmers: 50,
+            cell_stats: None,
+            cell_tag: String::from("CB"),
+            cb_whitelist: None,
+            keep_names: None,
+            drop_names: None,
+            removed_names: None,
+            removed_details: false,
+            metrics_parquet: None,
+            junction_bedpe: None,
+            min_junction_clip: 20,
+            min_mapq: 0,
+            max_indel_fraction: None,
+            max_mismatch_fraction: None,
+            min_query_coverage: None,
+            min_aligned_block: None,
+            aligned_block_max_indel: 5,
+            min_split_coverage: None,
+            min_insert_size: None,
+            max_insert_size: None,
+            expression: None,
+            exempt_low_complexity: false,
+            exempt_motifs: Vec::new(),
+            motif_purity: 0.9,
+            min_clip_bq: 0,
+            ignore_supplementary_hardclips: false,
+            rna: false,
+            spliced_thresholds: None,
+            contig_thresholds: BTreeMap::new(),
+            length_thresholds: Vec::new(),
+            read1_thresholds: None,
+            read2_thresholds: None,
+            score_weights: None,
+            max_score: 0.1,
+            adapters: Vec::new(),
+            adapter_policy: AdapterPolicy::Trim,
+            adapter_mismatch_rate: 0.1,
+            contaminants: Vec::new(),
+            screen_kmer_size: 16,
+            screen_fail: false,
+            primers: Vec::new(),
+            auto_threshold: None,
+            auto_preset: false,
+            subsample: None,
+            seed: 0,
+            extra_filter: None,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Create a new set of settings with the default values, to be adjusted with the
+    /// builder methods
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, FlagPolicy};
+    /// let config = FilterConfig::new()
+    ///     .in_bam("in.bam")
+    ///     .out_bam("out.bam")
+    ///     .left(0.2)
+    ///     .right(0.3)
+    ///     .both(0.4)
+    ///     .inverse(true)
+    ///     .secondary(FlagPolicy::Drop);
+    /// assert_eq!(config.left_side, 0.2);
+    /// assert_eq!(config.right_side, 0.3);
+    /// assert_eq!(config.both_end, 0.4);
+    /// assert!(config.inverse);
+    /// assert!(config.validate().is_ok());
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the input alignment file path ("-" for stdin, or a URL)
+    pub fn in_bam(mut self, value: impl Into<String>) -> Self {
+        self.in_bam = value.into();
+        self
+    }
+
+    /// Filter more input files after `in_bam` into the same output, merging their headers
+    pub fn extra_inputs(mut self, paths: Vec<String>) -> Self {
+        self.extra_inputs = paths;
+        self
+    }
+
+    /// Set the output alignment file path ("-" for stdout)
+    pub fn out_bam(mut self, value: impl Into<String>) -> Self {
+        self.out_bam = value.into();
+        self
+    }
+
+    /// Write out the failed-filter alignments only
+    pub fn inverse(mut self, value: bool) -> Self {
+        self.inverse = value;
+        self
+    }
+
+    /// Set the maximum fraction of total clipped bases
+    pub fn both(mut self, value: f64) -> Self {
+        self.both_end = value;
+        self
+    }
+
+    /// Set the maximum fraction of 5' clipped bases
+    pub fn left(mut self, value: f64) -> Self {
+        self.left_side = value;
+        self
+    }
+
+    /// Set the maximum fraction of 3' clipped bases
+    pub fn right(mut self, value: f64) -> Self {
+        self.right_side = value;
+        self
+    }
+
+    /// Make the failed alignments unmapped instead of removing them
+    pub fn unalign(mut self, value: bool) -> Self {
+        self.unalign = value;
+        self
+    }
+
+    /// Set the aux tags removed from the alignments made unmapped
+    pub fn unalign_strip_tags(mut self, tags: Vec<String>) -> Self {
+        self.unalign_strip_tags = tags;
+        self
+    }
+
+    /// Put the reverse strand alignments made unmapped back in the orientation of the read
+    pub fn restore_orientation(mut self, value: bool) -> Self {
+        self.restore_orientation = value;
+        self
+    }
+
+    /// Write the alignments made unmapped into their own file instead of the output
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().unalign(true).unaligned_out("failed.bam");
+    /// assert_eq!(config.unaligned_out.as_deref(), Some("failed.bam"));
+    /// ```
+    pub fn unaligned_out(mut self, path: impl Into<String>) -> Self {
+        self.unaligned_out = Some(path.into());
+        self
+    }
+
+    /// Write the alignments made unmapped after all the mapped ones, keeping a coordinate
+    /// sorted output sorted
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// assert!(FilterConfig::new().unaligned_to_end(true).validate().is_err());
+    /// let config = FilterConfig::new().unalign(true).unaligned_to_end(true);
+    /// assert!(config.validate().is_ok());
+    /// assert!(config.unaligned_out("failed.bam").validate().is_err());
+    /// ```
+    pub fn unaligned_to_end(mut self, value: bool) -> Self {
+        self.unaligned_to_end = value;
+        self
+    }
+
+    /// Set how to handle secondary alignments
+    pub fn secondary(mut self, value: FlagPolicy) -> Self {
+        self.secondary = value;
+        self
+    }
+
+    /// Set how to handle supplementary alignments
+    pub fn supplementary(mut self, value: FlagPolicy) -> Self {
+        self.supplementary = value;
+        self
+    }
+
+    /// Set how to handle the alignments already unmapped in the input
+    pub fn unmapped(mut self, value: UnmappedPolicy) -> Self {
+        self.unmapped = value;
+        self
+    }
+
+    /// Set how to handle the secondary alignments without a sequence
+    pub fn seqless_secondary(mut self, value: SeqlessPolicy) -> Self {
+        self.seqless_secondary = value;
+        self
+    }
+
+    /// Set how to handle duplicates
+    pub fn duplicates(mut self, value: FlagPolicy) -> Self {
+        self.duplicates = value;
+        self
+    }
+
+    /// Set how to handle QC-fail alignments
+    pub fn qcfail(mut self, value: FlagPolicy) -> Self {
+        self.qcfail = value;
+        self
+    }
+
+    /// Fail the alignments without the proper-pair flag before checking their clipped bases
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().require_proper_pair(true);
+    /// assert!(config.require_proper_pair);
+    /// ```
+    pub fn require_proper_pair(mut self, value: bool) -> Self {
+        self.require_proper_pair = value;
+        self
+    }
+
+    /// Only check the clipping of the alignments on one strand
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, Strand};
+    /// let config = FilterConfig::new().only_strand(Strand::Rev);
+    /// assert_eq!(config.only_strand, Some(Strand::Rev));
+    /// ```
+    pub fn only_strand(mut self, strand: Strand) -> Self {
+        self.only_strand = Some(strand);
+        self
+    }
+
+    /// Evaluate alignments sharing a read name together
+    pub fn paired(mut self, value: bool) -> Self {
+        self.paired = value;
+        self
+    }
+
+    /// Group the alignments by read name before filtering in paired mode, for input that isn't
+    /// grouped by read name
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// assert!(FilterConfig::new().collate(true).validate().is_err());
+    /// assert!(FilterConfig::new().collate(true).paired(true).validate().is_ok());
+    /// ```
+    pub fn collate(mut self, value: bool) -> Self {
+        self.collate = value;
+        self
+    }
+
+    /// Set how the mates decide whether a pair is kept
+    pub fn pair_policy(mut self, value: PairPolicy) -> Self {
+        self.pair_policy = value;
+        self
+    }
+
+    /// Read the input twice, removing all alignments of failing read names
+    pub fn two_pass(mut self, value: bool) -> Self {
+        self.two_pass = value;
+        self
+    }
+
+    /// Let secondary/supplementary alignments follow their primary alignments
+    pub fn cascade(mut self, value: bool) -> Self {
+        self.cascade = value;
+        self
+    }
+
+    /// Compute the clipping of chimeric reads over all of their segments
+    pub fn sa_aware(mut self, value: bool) -> Self {
+        self.sa_aware = value;
+        self
+    }
+
+    /// Keep the failed alignments with MAPQ set to 0 instead of removing them, with why they
+    /// failed in an aux tag if there is one
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().zero_mapq(true, Some("XF"));
+    /// assert!(config.validate().is_ok());
+    /// assert!(config.unalign(true).validate().is_err());
+    /// assert!(FilterConfig::new().zero_mapq(false, Some("XF")).validate().is_err());
+    /// ```
+    pub fn zero_mapq(mut self, value: bool, reason_tag: Option<&str>) -> Self {
+        self.zero_mapq = value;
+        self.reason_tag = reason_tag.map(String::from);
+        self
+    }
+
+    /// Promote a passing supplementary alignment in place of a failing primary alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// assert!(FilterConfig::new().promote_supplementary(true).validate().is_err());
+    /// let config = FilterConfig::new().paired(true).promote_supplementary(true);
+    /// assert!(config.validate().is_ok());
+    /// assert!(config.inverse(true).validate().is_err());
+    /// ```
+    pub fn promote_supplementary(mut self, value: bool) -> Self {
+        self.promote_supplementary = value;
+        self
+    }
+
+    /// Mark the mates of the alignments written without their mate as unmapped
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// assert!(FilterConfig::new().fix_orphans(true).validate().is_err());
+    /// assert!(FilterConfig::new().fix_orphans(true).two_pass(true).validate().is_ok());
+    /// ```
+    pub fn fix_orphans(mut self, value: bool) -> Self {
+        self.fix_orphans = value;
+        self
+    }
+
+    /// Decide on the mates of a failing pair one by one, and set what happens to the passing one
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, PairPolicy, SingletonPolicy};
+    /// let config = FilterConfig::new().paired(true).singletons(SingletonPolicy::Keep);
+    /// assert!(config.validate().is_ok());
+    /// assert!(config.pair_policy(PairPolicy::Any).validate().is_err());
+    /// ```
+    pub fn singletons(mut self, policy: SingletonPolicy) -> Self {
+        self.singletons = Some(policy);
+        self
+    }
+
+    /// Set the command line recorded in the @PG header record
+    pub fn command_line(mut self, value: impl Into<String>) -> Self {
+        self.command_line = Some(value.into());
+        self
+    }
+
+    /// Record the settings as a JSON @CO header comment
+    pub fn header_comment(mut self, value: bool) -> Self {
+        self.header_comment = value;
+        self
+    }
+
+    /// Set what to do when the input was already filtered with the same settings
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{AlreadyFilteredPolicy, FilterConfig};
+    /// let config = FilterConfig::new().if_already_filtered(AlreadyFilteredPolicy::Skip);
+    /// assert_eq!(config.if_already_filtered, AlreadyFilteredPolicy::Skip);
+    /// ```
+    pub fn if_already_filtered(mut self, policy: AlreadyFilteredPolicy) -> Self {
+        self.if_already_filtered = policy;
+        self
+    }
+
+    /// Set the output format instead of guessing it from the output path
+    pub fn output_fmt(mut self, value: OutputFormat) -> Self {
+        self.output_fmt = Some(value);
+        self
+    }
+
+    /// Set the reference fasta file (for CRAM)
+    pub fn reference(mut self, value: impl Into<String>) -> Self {
+        self.reference = Some(value.into());
+        self
+    }
+
+    /// Index the output file after writing it
+    pub fn write_index(mut self, value: bool) -> Self {
+        self.write_index = value;
+        self
+    }
+
+    /// Fail the run if no alignment passed the filter
+    pub fn fail_if_empty(mut self, value: bool) -> Self {
+        self.fail_if_empty = value;
+        self
+    }
+
+    /// Fail the run if more than a fraction of the alignments failed the filter
+    pub fn fail_if_removed_frac(mut self, fraction: f64) -> Self {
+        self.fail_if_removed_frac = Some(fraction);
+        self
+    }
+
+    /// Skip up to `max_malformed` unreadable records instead of aborting at the first one
+    pub fn tolerant(mut self, max_malformed: u64) -> Self {
+        self.tolerant = true;
+        self.max_malformed = max_malformed;
+        self
+    }
+
+    /// Only filter `max_records` alignments (all the rest if None) after skipping
+    /// `skip_records` of them
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// use std::cmp::Ordering;
+    /// let config = FilterConfig::new().records(10, Some(5));
+    /// assert_eq!(config.record_position(9), Ordering::Less);
+    /// assert_eq!(config.record_position(14), Ordering::Equal);
+    /// assert_eq!(config.record_position(15), Ordering::Greater);
+    /// ```
+    pub fn records(mut self, skip: u64, max: Option<u64>) -> Self {
+        self.skip_records = skip;
+        self.max_records = max;
+        self
+    }
+
+    /// Where the alignment at `index` (0-based, over all the inputs) stands relative to
+    /// the range of alignments to filter: Less if it's skipped, Equal if it's filtered,
+    /// Greater if it comes after the last one to filter
+    pub fn record_position(&self, index: u64) -> Ordering {
+        if index < self.skip_records {
+            return Ordering::Less;
+        }
+        match self.max_records {
+            Some(max) if index - self.skip_records >= max => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+
+    /// Filter windows of `size` bases of the reference over `threads` threads, if the input
+    /// is indexed
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new()
+    ///     .in_bam("sorted.bam")
+    ///     .region_threads(8, 1_000_000);
+    /// assert_eq!(config.region_threads, 8);
+    /// assert!(config.validate().is_ok());
+    /// assert!(config.paired(true).validate().is_err());
+    /// ```
+    pub fn region_threads(mut self, threads: usize, size: u64) -> Self {
+        self.region_threads = threads;
+        self.region_size = size;
+        self
+    }
+
+    /// Write the windows filtered with `region_threads` out as soon as they are done, rather
+    /// than in coordinate order
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// assert!(FilterConfig::new().unordered(true).validate().is_err());
+    /// let config = FilterConfig::new()
+    ///     .in_bam("sorted.bam")
+    ///     .region_threads(8, 1_000_000)
+    ///     .unordered(true);
+    /// assert!(config.validate().is_ok());
+    /// ```
+    pub fn unordered(mut self, value: bool) -> Self {
+        self.unordered = value;
+        self
+    }
+
+    /// Also write the output alignments into a directory, one BAM file per reference sequence
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().shard_by_chrom("shards");
+    /// assert_eq!(config.shard_by_chrom.as_deref(), Some("shards"));
+    /// ```
+    pub fn shard_by_chrom(mut self, dir: impl Into<String>) -> Self {
+        self.shard_by_chrom = Some(dir.into());
+        self
+    }
+
+    /// Write the failing alignments into a directory, one BAM file per reason they failed for
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().split_by_reason("failed");
+    /// assert_eq!(config.split_by_reason.as_deref(), Some("failed"));
+    /// ```
+    pub fn split_by_reason(mut self, dir: impl Into<String>) -> Self {
+        self.split_by_reason = Some(dir.into());
+        self
+    }
+
+    /// Keep rewriting a file with the counters of the run in the Prometheus text format,
+    /// every `interval` seconds
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().metrics_out("filter_clipped.prom", 30);
+    /// assert_eq!(config.metrics_out.as_deref(), Some("filter_clipped.prom"));
+    /// assert_eq!(config.metrics_interval, 30);
+    /// ```
+    pub fn metrics_out(mut self, path: impl Into<String>, interval: u64) -> Self {
+        self.metrics_out = Some(path.into());
+        self.metrics_interval = interval;
+        self
+    }
+
+    /// Abort when the input sort order does not fit the settings
+    pub fn require_sorted(mut self, value: bool) -> Self {
+        self.require_sorted = value;
+        self
+    }
+
+    /// Show a progress bar on stderr
+    pub fn progress(mut self, value: bool) -> Self {
+        self.progress = value;
+        self
+    }
+
+    /// Print a flagstat-like table of the run on stderr at the end
+    pub fn flagstat(mut self, value: bool) -> Self {
+        self.flagstat = value;
+        self
+    }
+
+    /// Write the binned clip fractions and clip lengths into a TSV file
+    pub fn hist_out(mut self, path: impl Into<String>) -> Self {
+        self.hist_out = Some(path.into());
+        self
+    }
+
+    /// Write the numbers of alignments beginning or ending a soft clip by reference window
+    /// (of `window` bases) into a TSV file
+    pub fn clip_profile(mut self, path: impl Into<String>, window: u64) -> Self {
+        self.clip_profile = Some(path.into());
+        self.profile_window = window;
+        self
+    }
+
+    /// Write the reference windows (of `profile_window` bases) where soft clip boundaries of
+    /// at least `min_support` alignments cluster into a BED file
+    pub fn breakpoint_bed(mut self, path: impl Into<String>, min_support: u64) -> Self {
+        self.breakpoint_bed = Some(path.into());
+        self.min_breakpoint_support = min_support;
+        self
+    }
+
+    /// Write the run summary, clip fraction histograms and per-reference-sequence counts
+    /// into a self-contained HTML page
+    pub fn html_report(mut self, path: impl Into<String>) -> Self {
+        self.html_report = Some(path.into());
+        self
+    }
+
+    /// Whether the binned clip fractions and lengths are needed, for `hist_out` or `html_report`
+    pub fn needs_histogram(&self) -> bool {
+        self.hist_out.is_some() || self.html_report.is_some()
+    }
+
+    /// Whether the soft clip boundaries along the reference are needed, for `clip_profile`
+    /// or `breakpoint_bed`
+    pub fn needs_clip_profile(&self) -> bool {
+        self.clip_profile.is_some() || self.breakpoint_bed.is_some()
+    }
+
+    /// Write the consensus of the soft clipped sequences at the clip boundaries shared by
+    /// at least `min_support` alignments into a FASTA file
+    pub fn clip_consensus(mut self, path: impl Into<String>, min_support: u64) -> Self {
+        self.clip_consensus = Some(path.into());
+        self.min_consensus_support = min_support;
+        self
+    }
+
+    /// Write the binned GC content of the soft clipped and the aligned sequence into a TSV
+    /// file, e.g. to tell adapter clips (at the GC content of the adapter) from genomic clips
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().gc_out("gc.tsv");
+    /// assert_eq!(config.gc_out.as_deref(), Some("gc.tsv"));
+    /// ```
+    pub fn gc_out(mut self, path: impl Into<String>) -> Self {
+        self.gc_out = Some(path.into());
+        self
+    }
+
+    /// Write the `top` k-mers (of `kmer_size` bases) most enriched in the soft clipped
+    /// sequence relative to the aligned sequence into a TSV file, e.g. to discover unknown
+    /// adapters or contaminants
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().kmer_out("kmers.tsv", 12, 20);
+    /// assert_eq!(config.kmer_size, 12);
+    /// assert!(config.validate().is_ok());
+    /// assert!(FilterConfig::new().kmer_out("kmers.tsv", 33, 20).validate().is_err());
+    /// ```
+    pub fn kmer_out(mut self, path: impl Into<String>, kmer_size: u64, top: u64) -> Self {
+        self.kmer_out = Some(path.into());
+        self.kmer_size = kmer_size;
+        self.top_kmers = top;
+        self
+    }
+
+    /// Write pass/fail counts and mean clip fractions by cell barcode (from the `CB` tag
+    /// unless `cell_tag` is changed) into a TSV file
+    pub fn cell_stats(mut self, path: impl Into<String>) -> Self {
+        self.cell_stats = Some(path.into());
+        self
+    }
+
+    /// Remove the alignments whose cell barcode is not listed in a file
+    pub fn cb_whitelist(mut self, path: impl Into<String>) -> Self {
+        self.cb_whitelist = Some(path.into());
+        self
+    }
+
+    /// Keep the alignments of the reads listed in a file without checking them
+    pub fn keep_names(mut self, path: impl Into<String>) -> Self {
+        self.keep_names = Some(path.into());
+        self
+    }
+
+    /// Remove the alignments of the reads listed in a file
+    pub fn drop_names(mut self, path: impl Into<String>) -> Self {
+        self.drop_names = Some(path.into());
+        self
+    }
+
+    /// Write the read names of the removed alignments into a file, with their flags and
+    /// the reasons of the removal if `details` is set
+    pub fn removed_names(mut self, path: impl Into<String>, details: bool) -> Self {
+        self.removed_names = Some(path.into());
+        self.removed_details = details;
+        self
+    }
+
+    /// Write the clip fractions and the decision of every alignment into a Parquet file
+    /// (needs the `parquet` feature)
+    pub fn metrics_parquet(mut self, path: impl Into<String>) -> Self {
+        self.metrics_parquet = Some(path.into());
+        self
+    }
+
+    /// Write the junctions between the primary alignments clipped by at least `min_clip`
+    /// bases and the segments in their SA tags into a BEDPE file
+    pub fn junction_bedpe(mut self, path: impl Into<String>, min_clip: u64) -> Self {
+        self.junction_bedpe = Some(path.into());
+        self.min_junction_clip = min_clip;
+        self
+    }
+
+    /// Set the minimum mapping quality
+    pub fn min_mapq(mut self, value: u8) -> Self {
+        self.min_mapq = value;
+        self
+    }
+
+    /// Fail the alignments with more inserted and deleted bases than a fraction of their
+    /// read length
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().max_indel_fraction(0.05);
+    /// assert_eq!(config.max_indel_fraction, Some(0.05));
+    /// ```
+    pub fn max_indel_fraction(mut self, fraction: f64) -> Self {
+        self.max_indel_fraction = Some(fraction);
+        self
+    }
+
+    /// Fail the alignments with more mismatched bases than a fraction of their aligned bases
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().max_mismatch_fraction(0.05);
+    /// assert_eq!(config.max_mismatch_fraction, Some(0.05));
+    /// ```
+    pub fn max_mismatch_fraction(mut self, fraction: f64) -> Self {
+        self.max_mismatch_fraction = Some(fraction);
+        self
+    }
+
+    /// Fail the alignments with less than a fraction of their read aligned, a direct gate
+    /// on the aligned part of the read rather than on its clips
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().min_query_coverage(0.8);
+    /// assert_eq!(config.min_query_coverage, Some(0.8));
+    /// ```
+    pub fn min_query_coverage(mut self, fraction: f64) -> Self {
+        self.min_query_coverage = Some(fraction);
+        self
+    }
+
+    /// Fail the alignments without a contiguous run of at least `len` aligned bases,
+    /// insertions and deletions of up to `max_indel` bases not breaking a run
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().min_aligned_block(500, 10);
+    /// assert_eq!(config.min_aligned_block, Some(500));
+    /// assert_eq!(config.aligned_block_max_indel, 10);
+    /// ```
+    pub fn min_aligned_block(mut self, len: u64, max_indel: u32) -> Self {
+        self.min_aligned_block = Some(len);
+        self.aligned_block_max_indel = max_indel;
+        self
+    }
+
+    /// Fail the paired alignments with an insert size (absolute TLEN) outside of the bounds
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().insert_size(None, Some(1000));
+    /// assert_eq!(config.max_insert_size, Some(1000));
+    /// assert!(config.validate().is_ok());
+    /// assert!(FilterConfig::new().insert_size(Some(500), Some(100)).validate().is_err());
+    /// ```
+    pub fn insert_size(mut self, min: Option<u64>, max: Option<u64>) -> Self {
+        self.min_insert_size = min;
+        self.max_insert_size = max;
+        self
+    }
+
+    /// Gate the split reads on the fraction of the read covered by all their segments (see
+    /// [crate::chimeric::split_coverage]) rather than on the clips of each segment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().min_split_coverage(0.9);
+    /// assert_eq!(config.min_split_coverage, Some(0.9));
+    /// assert!(FilterConfig::new().min_split_coverage(1.5).validate().is_err());
+    /// ```
+    pub fn min_split_coverage(mut self, fraction: f64) -> Self {
+        self.min_split_coverage = Some(fraction);
+        self
+    }
+
+    /// Only keep the alignments on which a filter expression is true, on top of the
+    /// clipping thresholds
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().expression("mapq >= 20 && [NM] <= 5");
+    /// assert!(config.validate().is_ok());
+    /// assert!(FilterConfig::new().expression("mapq >=").validate().is_err());
+    /// ```
+    pub fn expression(mut self, text: impl Into<String>) -> Self {
+        self.expression = Some(text.into());
+        self
+    }
+
+    /// Don't count low-complexity soft clips as clipped
+    pub fn exempt_low_complexity(mut self, value: bool) -> Self {
+        self.exempt_low_complexity = value;
+        self
+    }
+
+    /// Don't count the soft clips made of repeats of one of the motifs (e.g. telomeric TTAGGG
+    /// repeats at chromosome ends), with at least `purity` of their bases matching the repeat
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().exempt_motifs(vec![String::from("TTAGGG")], 0.9);
+    /// assert!(config.validate().is_ok());
+    /// assert!(FilterConfig::new().exempt_motifs(vec![String::from("TTNGGG")], 0.9).validate().is_err());
+    /// ```
+    pub fn exempt_motifs(mut self, motifs: Vec<String>, purity: f64) -> Self {
+        self.exempt_motifs = motifs;
+        self.motif_purity = purity;
+        self
+    }
+
+    /// Set the lowest base quality of a soft clipped base counted as clipped
+    pub fn min_clip_bq(mut self, value: u8) -> Self {
+        self.min_clip_bq = value;
+        self
+    }
+
+    /// Don't count the hard clipped bases of supplementary alignments
+    pub fn ignore_supplementary_hardclips(mut self, value: bool) -> Self {
+        self.ignore_supplementary_hardclips = value;
+        self
+    }
+
+    /// Turn on the splice-aware mode, with the thresholds for spliced alignments
+    /// (spliced alignments aren't filtered on clipping if None)
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, Thresholds};
+    /// let config = FilterConfig::new().rna(Some(Thresholds::parse("0.3").unwrap()));
+    /// assert!(config.rna);
+    /// assert_eq!(config.spliced_thresholds.unwrap().both_end, 0.3);
+    /// ```
+    pub fn rna(mut self, spliced_thresholds: Option<Thresholds>) -> Self {
+        self.rna = true;
+        self.spliced_thresholds = spliced_thresholds;
+        self
+    }
+
+    /// Set the thresholds for the alignments on a reference sequence
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, Thresholds};
+    /// let spike_in = Thresholds { both_end: 0.5, left_side: 0.3, right_side: 0.3 };
+    /// let config = FilterConfig::new().contig_thresholds("chrM", spike_in);
+    /// assert_eq!(config.contig_thresholds["chrM"], spike_in);
+    /// assert_eq!(config.thresholds().both_end, 0.1);
+    /// ```
+    pub fn contig_thresholds(mut self, contig: impl Into<String>, thresholds: Thresholds) -> Self {
+        self.contig_thresholds.insert(contig.into(), thresholds);
+        self
+    }
+
+    /// Set the thresholds by read length, see [parse_length_thresholds]
+    pub fn length_thresholds(mut self, value: Vec<LengthThresholds>) -> Self {
+        self.length_thresholds = value;
+        self
+    }
+
+    /// Check a weighted score of the clip fractions instead of the separate thresholds
+    ///
+    /// # Arguments
+    /// * `weights`: weights of the clip fractions
+    /// * `max_score`: highest score an alignment can have to pass
+    pub fn score(mut self, weights: ScoreWeights, max_score: f64) -> Self {
+        self.score_weights = Some(weights);
+        self.max_score = max_score;
+        self
+    }
+
+    /// Set the known adapter sequences and what to do with alignments clipped at an adapter
+    pub fn adapters(mut self, adapters: Vec<Adapter>, policy: AdapterPolicy) -> Self {
+        self.adapters = adapters;
+        self.adapter_policy = policy;
+        self
+    }
+
+    /// Screen the soft clipped bases for contaminant sequences (read like the adapters, see
+    /// [crate::adapters::parse_fasta]) sharing a k-mer of `screen_kmer_size` bases with them,
+    /// and fail the alignments with a hit if `fail` is set
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::adapters::parse_fasta;
+    /// use filter_clipped::config::FilterConfig;
+    /// let contaminants = parse_fasta(">phiX\nGAGTTTTATCGCTTCCATGAC\n").unwrap();
+    /// let config = FilterConfig::new().screen(contaminants, true);
+    /// assert!(config.validate().is_ok());
+    /// assert!(FilterConfig::new().screen(Vec::new(), true).validate().is_err());
+    /// ```
+    pub fn screen(mut self, contaminants: Vec<Adapter>, fail: bool) -> Self {
+        self.contaminants = contaminants;
+        self.screen_fail = fail;
+        self
+    }
+
+    /// Set the length of the k-mers shared by a clip and a contaminant, see [FilterConfig::screen]
+    pub fn screen_kmer_size(mut self, kmer_size: u64) -> Self {
+        self.screen_kmer_size = kmer_size;
+        self
+    }
+
+    /// Set the primer intervals whose clips don't count as clipped
+    pub fn primers(mut self, primers: Vec<Primer>) -> Self {
+        self.primers = primers;
+        self
+    }
+
+    /// Derive the thresholds from the input, keeping a percentile (0-100) of the alignments
+    /// on each of the total, 5' and 3' clip fractions (see [crate::distribution::ClipDistribution::thresholds_at])
+    pub fn auto_threshold(mut self, percentile: f64) -> Self {
+        self.auto_threshold = Some(percentile);
+        self
+    }
+
+    /// Pick the preset from the aligner found in the @PG records of the input (bwa and
+    /// bowtie2: short-read, minimap2: long-read, STAR: rna)
+    pub fn auto_preset(mut self, value: bool) -> Self {
+        self.auto_preset = value;
+        self
+    }
+
+    /// Keep a deterministic fraction (0-1) of the read names among the alignments that are
+    /// written as they are, e.g. the passing ones
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// let config = FilterConfig::new().subsample(0.1, 42);
+    /// assert!(config.validate().is_ok());
+    /// assert!(config.subsample(1.5, 42).validate().is_err());
+    /// ```
+    pub fn subsample(mut self, fraction: f64, seed: u64) -> Self {
+        self.subsample = Some(fraction);
+        self.seed = seed;
+        self
+    }
+
+    /// Set the thresholds of the first mates (read 1) of paired reads
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, Thresholds};
+    /// let r2 = Thresholds { both_end: 0.3, left_side: 0.1, right_side: 0.3 };
+    /// let config = FilterConfig::new().read1_thresholds(FilterConfig::new().thresholds()).read2_thresholds(r2);
+    /// assert_eq!(config.read2_thresholds, Some(r2));
+    /// ```
+    pub fn read1_thresholds(mut self, thresholds: Thresholds) -> Self {
+        self.read1_thresholds = Some(thresholds);
+        self
+    }
+
+    /// Set the thresholds of the second mates (read 2) of paired reads
+    pub fn read2_thresholds(mut self, thresholds: Thresholds) -> Self {
+        self.read2_thresholds = Some(thresholds);
+        self
+    }
+
+    /// Set the total, 5' and 3' thresholds at once
+    pub fn thresholds_from(self, thresholds: Thresholds) -> Self {
+        self.both(thresholds.both_end)
+            .left(thresholds.left_side)
+            .right(thresholds.right_side)
+    }
+
+    /// The thresholds applied to alignments without specific thresholds for their reference sequence
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            both_end: self.both_end,
+            left_side: self.left_side,
+            right_side: self.right_side,
+        }
+    }
+
+    /// Set the thresholds of a preset, settings not part of the preset are left as they are
+    ///
+    /// | preset     | both_end | left_side | right_side | ignore_supplementary_hardclips |
+    /// |------------|----------|-----------|------------|--------------------------------|
+    /// | short-read | 0.1      | 0.1       | 0.1        | false                          |
+    /// | long-read  | 0.3      | 0.2       | 0.2        | true                           |
+    /// | amplicon   | 0.1      | 0.05      | 0.05       | false                          |
+    /// | rna        | 0.2      | 0.1       | 0.1        | false                          |
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, Preset};
+    /// let config = FilterConfig::new().preset(Preset::LongRead).left(0.25);
+    /// assert_eq!(config.both_end, 0.3);
+    /// assert_eq!(config.left_side, 0.25);
+    /// assert!(config.ignore_supplementary_hardclips);
+    /// ```
+    pub fn preset(self, preset: Preset) -> Self {
+        let (both_end, left_side, right_side, ignore_supplementary_hardclips) = match preset {
+            Preset::ShortRead => (0.1, 0.1, 0.1, false),
+            Preset::LongRead => (0.3, 0.2, 0.2, true),
+            Preset::Amplicon => (0.1, 0.05, 0.05, false),
+            Preset::Rna => (0.2, 0.1, 0.1, false),
+        };
+        self.both(both_end)
+            .left(left_side)
+            .right(right_side)
+            .ignore_supplementary_hardclips(ignore_supplementary_hardclips)
+    }
+
+    /// Add a check run after the clip decision (flag bypassed or dropped alignments are
+    /// not checked). It gets the alignment, its clipped bases and whether it passed the
+    /// built-in checks, and returns whether the alignment is kept: returning false on a
+    /// passing alignment vetoes it, returning true on a failing one rescues it
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// use filter_clipped::filter::{ClipFilter, FilterDecision};
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    ///
+    /// // keep all whitelisted reads, whatever their clipping
+    /// let whitelist = vec![b"control".to_vec()];
+    /// let config = FilterConfig::new().with_extra_filter(move |record, _clip_stat, passed| {
+    ///     passed || whitelist.contains(&record.qname().to_vec())
+    /// });
+    ///
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("50S50M").unwrap();
+    /// record.set(b"control", Some(&cigar), &[b'A'; 100], &[30; 100]);
+    /// assert_eq!(ClipFilter::new(&config).evaluate(&record).unwrap(), FilterDecision::Pass);
+    /// ```
+    pub fn with_extra_filter<F>(mut self, extra_filter: F) -> Self
+    where
+        F: Fn(&Record, &ClipStat, bool) -> bool + Send + Sync + 'static,
+    {
+        self.extra_filter = Some(ExtraFilter(Arc::new(extra_filter)));
+        self
+    }
+
+    /// Parse settings from the text of a settings file
+    ///
+    /// # Arguments
+    /// * `text`: content of the settings file
+    /// * `format`: format of the settings file
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{ConfigFormat, FilterConfig, FlagPolicy};
+    /// let config = FilterConfig::parse("both_end = 0.2\nsecondary = \"drop\"\n", ConfigFormat::Toml).unwrap();
+    /// assert_eq!(config.both_end, 0.2);
+    /// assert_eq!(config.secondary, FlagPolicy::Drop);
+    /// assert_eq!(config.left_side, 0.1);
+    /// ```
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self, FilterClippedError> {
+        let parsed: Result<Self, String> = match format {
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(FilterClippedError::InvalidConfig)
+    }
+
+    /// Write the settings out as the text of a settings file
+    ///
+    /// # Arguments
+    /// * `format`: format of the settings file
+    pub fn to_text(&self, format: ConfigFormat) -> Result<String, FilterClippedError> {
+        let text: Result<String, String> = match format {
+            // going through a toml::Value writes the tables after the plain values
+            ConfigFormat::Toml => toml::Value::try_from(self)
+                .and_then(|value| toml::to_string(&value))
+                .map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+        };
+        text.map_err(FilterClippedError::InvalidConfig)
+    }
+
+    /// Read settings from a TOML, YAML or JSON file, the format is guessed from
+    /// the extension of the path (see [ConfigFormat::from_path])
+    ///
+    /// # Arguments
+    /// * `path`: path of the settings file
+    pub fn from_file(path: &str) -> Result<Self, FilterClippedError> {
+        let text: String = fs::read_to_string(path)?;
+        Self::parse(&text, ConfigFormat::from_path(path)).map_err(|e| match e {
+            FilterClippedError::InvalidConfig(message) => {
+                FilterClippedError::InvalidConfig(format!("{}: {}", path, message))
+            }
+            e => e,
+        })
+    }
+
+    /// Write the settings to a TOML, YAML or JSON file, the format is guessed from
+    /// the extension of the path (see [ConfigFormat::from_path])
+    ///
+    /// # Arguments
+    /// * `path`: path of the settings file ("-" for stdout, as TOML)
+    pub fn write_file(&self, path: &str) -> Result<(), FilterClippedError> {
+        let text: String = self.to_text(ConfigFormat::from_path(path))?;
+        match path {
+            "-" => print!("{}", text),
+            _ => fs::write(path, text)?,
+        }
+        Ok(())
+    }
+
+    /// Check that the settings are usable: the thresholds are fractions within 0 and 1,
+    /// and the options depending on each other are set together
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// assert!(FilterConfig::new().left(1.5).validate().is_err());
+    /// assert!(FilterConfig::new().cascade(true).validate().is_err());
+    /// assert!(FilterConfig::new().cascade(true).paired(true).validate().is_ok());
+    /// ```
+    pub fn validate(&self) -> Result<(), FilterClippedError> {
+        self.thresholds().validate("")?;
+        for (contig, thresholds) in self.contig_thresholds.iter() {
+            thresholds.validate(&format!("{}: ", contig))?;
+        }
+        if self.profile_window == 0 {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "profile_window should be at least 1 base",
+            )));
+        }
+        for (name, kmer_size) in [
+            ("kmer_size", self.kmer_size),
+            ("screen_kmer_size", self.screen_kmer_size),
+        ] {
+            if kmer_size == 0 || kmer_size > crate::kmers::MAX_KMER_SIZE as u64 {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "{} should be between 1 and {} bases",
+                    name,
+                    crate::kmers::MAX_KMER_SIZE
+                )));
+            }
+        }
+        if self.screen_fail && self.contaminants.is_empty() {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "screen_fail needs contaminant sequences to screen for",
+            )));
+        }
+        if let Some(contaminant) = self
+            .contaminants
+            .iter()
+            .find(|contaminant| contaminant.sequence.len() < self.screen_kmer_size as usize)
+        {
+            return Err(FilterClippedError::InvalidConfig(format!(
+                "contaminant {} is shorter than screen_kmer_size ({} bases)",
+                contaminant.name, self.screen_kmer_size
+            )));
+        }
+        if !self.extra_inputs.is_empty()
+            && (self.in_bam == "-" || self.extra_inputs.iter().any(|path| path == "-"))
+        {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "stdin (\"-\") can only be read as the only input",
+            )));
+        }
+        if self.region_threads == 0 || self.region_size == 0 {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "region_threads and region_size should be at least 1",
+            )));
+        }
+        if self.unordered && self.region_threads < 2 {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "unordered only applies to the windows filtered in parallel with region_threads",
+            )));
+        }
+        if self.region_threads > 1 {
+            // the windows are read from a single indexed file, and filtered without
+            // the alignments of the same read name in other windows
+            let conflicts: Vec<&str> = [
+                ("stdin input", self.in_bam == "-"),
+                ("extra_inputs", !self.extra_inputs.is_empty()),
+                ("skip_records", self.skip_records > 0),
+                ("max_records", self.max_records.is_some()),
+                ("paired", self.paired),
+                ("two_pass", self.two_pass),
+                ("removed_names", self.removed_names.is_some()),
+                ("junction_bedpe", self.junction_bedpe.is_some()),
+                ("metrics_parquet", self.metrics_parquet.is_some()),
+                ("unaligned_out", self.unaligned_out.is_some()),
+                ("unaligned_to_end", self.unaligned_to_end),
+                ("split_by_reason", self.split_by_reason.is_some()),
+                ("metrics_out", self.metrics_out.is_some()),
+            ]
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .collect();
+            if !conflicts.is_empty() {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "region_threads doesn't work with: {}",
+                    conflicts.join(", ")
+                )));
+            }
+        }
+        if self.zero_mapq && self.unalign {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "zero_mapq and unalign are two ways of keeping the failed alignments, choose one",
+            )));
+        }
+        match self.reason_tag.as_deref() {
+            Some(_) if !self.zero_mapq => {
+                return Err(FilterClippedError::InvalidConfig(String::from(
+                    "reason_tag is only added with zero_mapq",
+                )));
+            }
+            Some(tag) if tag.len() != 2 => {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "reason_tag should be a two-letter tag name: {}",
+                    tag
+                )));
+            }
+            _ => (),
+        }
+        if self.unaligned_to_end && (!self.unalign || self.unaligned_out.is_some()) {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "unaligned_to_end moves the alignments made unmapped with unalign, without unaligned_out",
+            )));
+        }
+        if self.unaligned_out.is_some() && !self.unalign {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "unaligned_out only holds the alignments made unmapped with unalign",
+            )));
+        }
+        if self.metrics_parquet.is_some() && !cfg!(feature = "parquet") {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "metrics_parquet needs filter-clipped built with the parquet feature",
+            )));
+        }
+        if let Some(text) = self.expression.as_deref() {
+            Expression::parse(text)?;
+        }
+        if let (Some(min), Some(max)) = (self.min_insert_size, self.max_insert_size) {
+            if min > max {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "min_insert_size is above max_insert_size: {} > {}",
+                    min, max
+                )));
+            }
+        }
+        for (name, fraction) in [
+            ("fail_if_removed_frac", self.fail_if_removed_frac),
+            ("max_indel_fraction", self.max_indel_fraction),
+            ("max_mismatch_fraction", self.max_mismatch_fraction),
+            ("min_query_coverage", self.min_query_coverage),
+            ("min_split_coverage", self.min_split_coverage),
+            ("motif_purity", Some(self.motif_purity)),
+        ] {
+            match fraction {
+                Some(fraction) if !(0.0..=1.0).contains(&fraction) => {
+                    return Err(FilterClippedError::InvalidConfig(format!(
+                        "{} should be between 0 and 1: {}",
+                        name, fraction
+                    )));
+                }
+                _ => (),
+            }
+        }
+        if let Some(motif) = self.exempt_motifs.iter().find(|motif| {
+            motif.is_empty()
+                || !motif
+                    .bytes()
+                    .all(|base| b"ACGT".contains(&base.to_ascii_uppercase()))
+        }) {
+            return Err(FilterClippedError::InvalidConfig(format!(
+                "exempt_motifs should only have A, C, G and T bases: {:?}",
+                motif
+            )));
+        }
+        if self.cell_tag.len() != 2 {
+            return Err(FilterClippedError::InvalidConfig(format!(
+                "cell_tag should be a two-letter tag name: {}",
+                self.cell_tag
+            )));
+        }
+        if let Some(tag) = self.unalign_strip_tags.iter().find(|tag| tag.len() != 2) {
+            return Err(FilterClippedError::InvalidConfig(format!(
+                "unalign_strip_tags should be two-letter tag names: {}",
+                tag
+            )));
+        }
+        if let Some(thresholds) = self.spliced_thresholds.as_ref() {
+            thresholds.validate("spliced_thresholds: ")?;
+        }
+        if let Some(thresholds) = self.read1_thresholds.as_ref() {
+            thresholds.validate("read1_thresholds: ")?;
+        }
+        if let Some(thresholds) = self.read2_thresholds.as_ref() {
+            thresholds.validate("read2_thresholds: ")?;
+        }
+        for range in self.length_thresholds.iter() {
+            let max_len: String = range.max_len.map_or(String::new(), |len| len.to_string());
+            range
+                .thresholds
+                .validate(&format!("read length {}-{}: ", range.min_len, max_len))?;
+        }
+        if !(0.0..=1.0).contains(&self.adapter_mismatch_rate) {
+            return Err(FilterClippedError::InvalidConfig(format!(
+                "adapter_mismatch_rate is {}, which is not within 0 and 1",
+                self.adapter_mismatch_rate
+            )));
+        }
+        if let Some(weights) = self.score_weights.as_ref() {
+            weights.validate()?;
+            if self.auto_threshold.is_some() {
+                return Err(FilterClippedError::InvalidConfig(String::from(
+                    "auto_threshold derives the separate thresholds and can't be used with score_weights",
+                )));
+            }
+        }
+        if let Some(percentile) = self.auto_threshold {
+            if !(0.0..=100.0).contains(&percentile) {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "auto_threshold is {}, which is not a percentile within 0 and 100",
+                    percentile
+                )));
+            }
+        }
+        if let Some(fraction) = self.subsample {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(FilterClippedError::InvalidConfig(format!(
+                    "subsample is {}, which is not a fraction within 0 and 1",
+                    fraction
+                )));
+            }
+        }
+        if self.paired && self.two_pass {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "paired and two_pass can't be used together",
+            )));
+        }
+        if self.cascade && !(self.paired || self.two_pass) {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "cascade needs paired or two_pass",
+            )));
+        }
+        // the non-primary alignments follow their primary alignment in cascade mode
+        let kept_flags: bool = [
+            self.secondary,
+            self.supplementary,
+            self.duplicates,
+            self.qcfail,
+        ]
+        .contains(&FlagPolicy::Keep);
+        if self.cascade && kept_flags {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "cascade can't keep the secondary, supplementary, duplicate or qcfail alignments, they follow their primary alignment",
+            )));
+        }
+        if self.collate && !self.paired {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "collate needs paired",
+            )));
+        }
+        if self.fix_orphans && !(self.paired || self.two_pass) {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "fix_orphans needs paired or two_pass",
+            )));
+        }
+        if self.singletons.is_some() && !(self.paired || self.two_pass) {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "singletons needs paired or two_pass",
+            )));
+        }
+        if self.singletons.is_some() && self.pair_policy == PairPolicy::Any {
+            // a pair with a passing mate is kept whole
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "singletons can't be used with pair_policy any",
+            )));
+        }
+        if self.promote_supplementary && !self.paired {
+            // all the alignments of a read are only seen together in paired mode
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "promote_supplementary needs paired",
+            )));
+        }
+        if self.promote_supplementary && self.inverse {
+            return Err(FilterClippedError::InvalidConfig(String::from(
+                "promote_supplementary can't be used with inverse",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Find out how an alignment should be handled given its flag.
+    /// If more than one policy applies (e.g. a secondary alignment that is also a duplicate),
+    /// [FlagPolicy::Drop] wins over [FlagPolicy::Keep], which wins over [FlagPolicy::Filter]
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Return
+    /// * [FlagPolicy::Filter] for primary, non-duplicate, QC-passed alignments, otherwise the
+    ///   policy configured for the flags set on the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::{FilterConfig, FlagPolicy};
+    /// use rust_htslib::bam::Record;
+    /// let config = FilterConfig {
+    ///     secondary: FlagPolicy::Drop,
+    ///     duplicates: FlagPolicy::Keep,
+    ///     ..FilterConfig::default()
+    /// };
+    /// let mut record = Record::new();
+    /// assert_eq!(config.flag_policy(&record), FlagPolicy::Filter);
+    /// record.set_flags(0x400);
+    /// assert_eq!(config.flag_policy(&record), FlagPolicy::Keep);
+    /// record.set_flags(0x500);
+    /// assert_eq!(config.flag_policy(&record), FlagPolicy::Drop);
+    /// ```
+    pub fn flag_policy(&self, record: &Record) -> FlagPolicy {
+        let policies = [
+            (record.is_secondary(), self.secondary),
+            (record.is_supplementary(), self.supplementary),
+            (record.is_duplicate(), self.duplicates),
+            (record.is_quality_check_failed(), self.qcfail),
+        ];
+        let applied = policies
+            .iter()
+            .filter(|(flag_set, _)| *flag_set)
+            .map(|(_, policy)| *policy);
+
+        let mut policy = FlagPolicy::Filter;
+        for p in applied {
+            match p {
+                FlagPolicy::Drop => return FlagPolicy::Drop,
+                FlagPolicy::Keep => policy = FlagPolicy::Keep,
+                FlagPolicy::Filter => (),
+            }
+        }
+        if self.cascade && !is_primary(record) {
+            // follow whatever happens to the primary alignments
+            return FlagPolicy::Filter;
+        }
+        policy
+    }
+
+    /// Whether the clipping of an alignment counts towards the decision made for its read name.
+    /// In cascade mode only the primary alignments count, and the secondary/supplementary
+    /// alignments follow them. Unmapped alignments have no clipping and never count
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// use rust_htslib::bam::Record;
+    /// let config = FilterConfig {
+    ///     cascade: true,
+    ///     ..FilterConfig::default()
+    /// };
+    /// let mut record = Record::new();
+    /// assert!(!config.is_decisive(&record));
+    /// record.set_flags(0);
+    /// assert!(config.is_decisive(&record));
+    /// record.set_flags(0x800);
+    /// assert!(!config.is_decisive(&record));
+    /// ```
+    pub fn is_decisive(&self, record: &Record) -> bool {
+        !is_unmapped_input(record)
+            && !is_seqless_secondary(record)
+            && self.flag_policy(record) == FlagPolicy::Filter
+            && (!self.cascade || is_primary(record))
+    }
+}
+
+/// Whether an alignment is unmapped in the input: flagged unmapped and without a CIGAR
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::is_unmapped_input;
+/// use rust_htslib::bam::record::{Cigar, CigarString};
+/// use rust_htslib::bam::Record;
+/// let mut record = Record::new();
+/// record.set_flags(0x4);
+/// assert!(is_unmapped_input(&record));
+/// let cigar = CigarString(vec![Cigar::Match(4)]);
+/// record.set(b"read", Some(&cigar), b"ACGT", &[30; 4]);
+/// assert!(!is_unmapped_input(&record));
+/// ```
+pub fn is_unmapped_input(record: &Record) -> bool {
+    record.is_unmapped() && record.cigar_len() == 0
+}
+
+/// Whether an alignment is a secondary alignment without a sequence (SEQ "*"), as some
+/// aligners write them with hard clips only: there is no read length to work out the clip
+/// fractions from (see [SeqlessPolicy])
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::is_seqless_secondary;
+/// use rust_htslib::bam::record::{Cigar, CigarString};
+/// use rust_htslib::bam::Record;
+/// let mut record = Record::new();
+/// let cigar = CigarString(vec![Cigar::HardClip(20), Cigar::Match(30)]);
+/// record.set(b"read", Some(&cigar), b"", b"");
+/// record.set_flags(0x100);
+/// assert!(is_seqless_secondary(&record));
+/// record.set_flags(0x800);
+/// assert!(!is_seqless_secondary(&record));
+/// ```
+pub fn is_seqless_secondary(record: &Record) -> bool {
+    record.is_secondary() && record.seq_len() == 0 && record.cigar_len() > 0
+}
+
+/// An alignment is primary if it is neither secondary nor supplementary
+fn is_primary(record: &Record) -> bool {
+    !record.is_secondary() && !record.is_supplementary()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, FlagPolicy::Filter)]
+    #[case(0x100, FlagPolicy::Keep)]
+    #[case(0x800, FlagPolicy::Drop)]
+    #[case(0x400, FlagPolicy::Keep)]
+    #[case(0x500, FlagPolicy::Keep)]
+    #[case(0xc00, FlagPolicy::Drop)]
+    #[case(0x200, FlagPolicy::Drop)]
+    #[case(0x600, FlagPolicy::Drop)]
+    fn test_flag_policy(#[case] flag: u16, #[case] expected: FlagPolicy) {
+        let config = FilterConfig {
+            secondary: FlagPolicy::Keep,
+            supplementary: FlagPolicy::Drop,
+            duplicates: FlagPolicy::Keep,
+            qcfail: FlagPolicy::Drop,
+            ..FilterConfig::default()
+        };
+        let mut record = Record::new();
+        record.set_flags(flag);
+        assert_eq!(config.flag_policy(&record), expected);
+    }
+
+    #[rstest]
+    #[case(0, FlagPolicy::Filter, true)]
+    #[case(0x100, FlagPolicy::Drop, false)]
+    #[case(0x800, FlagPolicy::Filter, false)]
+    #[case(0xc00, FlagPolicy::Filter, false)]
+    #[case(0x400, FlagPolicy::Keep, false)]
+    fn test_cascade_policy(
+        #[case] flag: u16,
+        #[case] expected_policy: FlagPolicy,
+        #[case] expected_decisive: bool,
+    ) {
+        let config = FilterConfig {
+            secondary: FlagPolicy::Drop,
+            supplementary: FlagPolicy::Keep,
+            duplicates: FlagPolicy::Keep,
+            cascade: true,
+            ..FilterConfig::default()
+        };
+        let mut record = Record::new();
+        record.set_flags(flag);
+        assert_eq!(config.flag_policy(&record), expected_policy);
+        assert_eq!(config.is_decisive(&record), expected_decisive);
+    }
+
+    #[rstest]
+    #[case(FilterConfig::new(), true)]
+    #[case(FilterConfig::new().both(0.0).left(1.0).right(0.5), true)]
+    #[case(FilterConfig::new().both(-0.1), false)]
+    #[case(FilterConfig::new().right(f64::NAN), false)]
+    #[case(FilterConfig::new().paired(true).two_pass(true), false)]
+    #[case(FilterConfig::new().cascade(true), false)]
+    #[case(FilterConfig::new().cascade(true).two_pass(true), true)]
+    #[case(FilterConfig::new().cascade(true).paired(true).secondary(FlagPolicy::Keep), false)]
+    #[case(FilterConfig::new().cascade(true).paired(true).duplicates(FlagPolicy::Keep), false)]
+    #[case(FilterConfig::new().cascade(true).paired(true).supplementary(FlagPolicy::Drop), true)]
+    #[case(FilterConfig::new().auto_threshold(95.0), true)]
+    #[case(FilterConfig::new().score(ScoreWeights { left: 1.0, ..ScoreWeights::default() }, 0.1), true)]
+    #[case(FilterConfig::new().score(ScoreWeights::default(), 0.1), false)]
+    #[case(FilterConfig::new().score(ScoreWeights { nm: -1.0, total: 1.0, ..ScoreWeights::default() }, 0.1), false)]
+    #[case(FilterConfig::new().length_thresholds(parse_length_thresholds("0-:1.2").unwrap()), false)]
+    #[case(FilterConfig::new().auto_threshold(101.0), false)]
+    #[case(FilterConfig { cell_tag: String::from("CBX"), ..FilterConfig::default() }, false)]
+    #[case(FilterConfig::new().unalign_strip_tags(vec![String::from("XM")]), true)]
+    #[case(FilterConfig::new().unalign(true).unaligned_out("failed.bam"), true)]
+    #[case(FilterConfig::new().unaligned_out("failed.bam"), false)]
+    #[case(FilterConfig::new().unalign_strip_tags(vec![String::from("NM"), String::new()]), false)]
+    #[case(FilterConfig::new().clip_profile("profile.tsv", 0), false)]
+    #[case(FilterConfig::new().in_bam("in.bam").region_threads(4, 1000), true)]
+    #[case(FilterConfig::new().in_bam("in.bam").region_threads(0, 1000), false)]
+    #[case(FilterConfig::new().region_threads(4, 1000), false)]
+    #[case(FilterConfig::new().in_bam("in.bam").region_threads(4, 1000).two_pass(true), false)]
+    #[case(FilterConfig::new().rna(Thresholds::parse("1.5").ok()), false)]
+    #[case(FilterConfig::new().contig_thresholds("chrM", Thresholds { both_end: 1.5, left_side: 0.1, right_side: 0.1 }), false)]
+    #[case(FilterConfig::new().fail_if_removed_frac(0.5), true)]
+    #[case(FilterConfig::new().fail_if_removed_frac(1.5), false)]
+    #[case(FilterConfig::new().in_bam("in.bam").region_threads(4, 1000).metrics_out("run.prom", 10), false)]
+    #[case(FilterConfig::new().max_indel_fraction(0.1), true)]
+    #[case(FilterConfig::new().max_indel_fraction(-0.1), false)]
+    #[case(FilterConfig::new().exempt_motifs(vec![String::from("ttaggg")], 0.8), true)]
+    #[case(FilterConfig::new().exempt_motifs(vec![String::new()], 0.8), false)]
+    #[case(FilterConfig::new().exempt_motifs(vec![String::from("TTAGGG")], 1.2), false)]
+    fn test_validate(#[case] config: FilterConfig, #[case] expected_ok: bool) {
+        assert_eq!(config.validate().is_ok(), expected_ok);
+    }
+
+    #[rstest]
+    #[case("out.bam", OutputFormat::Bam)]
+    #[case("out.sam", OutputFormat::Sam)]
+    #[case("path/to/out.cram", OutputFormat::Cram)]
+    #[case("out.Sam", OutputFormat::Sam)]
+    #[case("out", OutputFormat::Bam)]
+    #[case("-", OutputFormat::Bam)]
+    fn test_output_format_from_path(#[case] path: &str, #[case] expected: OutputFormat) {
+        assert_eq!(OutputFormat::from_path(path), expected);
+    }
+
+    #[rstest]
+    #[case(ConfigFormat::Toml)]
+    #[case(ConfigFormat::Yaml)]
+    #[case(ConfigFormat::Json)]
+    fn test_config_round_trip(#[case] format: ConfigFormat) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .both(0.25)
+            .paired(true)
+            .cascade(true)
+            .supplementary(FlagPolicy::Drop)
+            .pair_policy(PairPolicy::Any)
+            .output_fmt(OutputFormat::Sam)
+            .min_mapq(20)
+            .contig_thresholds(
+                "chrM",
+                Thresholds {
+                    both_end: 0.5,
+                    left_side: 0.3,
+                    right_side: 0.3,
+                },
+            )
+            .length_thresholds(parse_length_thresholds("0-200:0.1,200-:0.3/0.2/0.2").unwrap());
+        let parsed = FilterConfig::parse(&config.to_text(format).unwrap(), format).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&config).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("both_ends = 0.2\n")]
+    #[case("secondary = \"remove\"\n")]
+    #[case("both_end = \"high\"\n")]
+    fn test_config_parse_error(#[case] text: &str) {
+        assert!(matches!(
+            FilterConfig::parse(text, ConfigFormat::Toml),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+
+    #[rstest]
+    #[case("chrM\t0.3\t0.2\n")]
+    #[case("chrM\t0.3\t0.2\thigh\n")]
+    #[case("chrM 0.3 0.2 0.2\n")]
+    fn test_parse_contig_thresholds_error(#[case] text: &str) {
+        assert!(matches!(
+            parse_contig_thresholds(text),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+
+    #[rstest]
+    #[case("0-200")]
+    #[case("0:0.1")]
+    #[case("200-100:0.1")]
+    #[case("0-200:0.1/0.2")]
+    #[case("0-200:high")]
+    #[case("0-200:0.1,")]
+    fn test_parse_length_thresholds_error(#[case] spec: &str) {
+        assert!(matches!(
+            parse_length_thresholds(spec),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::error::FilterClippedError;
+use rust_htslib::bam::{record::CigarStringView, HeaderView, Record};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Bases counted in the consensus columns, anything else counts as N
+const BASES: [u8; 5] = [b'A', b'C', b'G', b'T', b'N'];
+
+/// Index of a base in [BASES]
+fn base_index(base: u8) -> usize {
+    match base.to_ascii_uppercase() {
+        b'A' => 0,
+        b'C' => 1,
+        b'G' => 2,
+        b'T' => 3,
+        _ => 4,
+    }
+}
+
+/// Soft clipped sequences sharing a clip boundary, stacked from the boundary outwards
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ClippedPileup {
+    /// number of alignments clipped at the boundary
+    reads: u64,
+    /// base counts by distance from the boundary
+    columns: Vec<[u64; 5]>,
+}
+
+impl ClippedPileup {
+    /// Stack a clipped sequence, given from the boundary outwards
+    fn add<'a>(&mut self, bases: impl Iterator<Item = &'a u8>) {
+        self.reads += 1;
+        for (i, base) in bases.enumerate() {
+            if i == self.columns.len() {
+                self.columns.push([0; 5]);
+            }
+            self.columns[i][base_index(*base)] += 1;
+        }
+    }
+
+    /// Add the sequences stacked in another pileup of the same clip boundary
+    fn merge(&mut self, other: &ClippedPileup) {
+        self.reads += other.reads;
+        for (i, counts) in other.columns.iter().enumerate() {
+            if i == self.columns.len() {
+                self.columns.push([0; 5]);
+            }
+            for (count, other_count) in self.columns[i].iter_mut().zip(counts) {
+                *count += other_count;
+            }
+        }
+    }
+
+    /// Majority base of each column covered by at least `min_depth` sequences, N where no base
+    /// makes up more than half of the column, from the boundary outwards
+    fn consensus(&self, min_depth: u64) -> Vec<u8> {
+        self.columns
+            .iter()
+            .map(|counts| (counts, counts.iter().sum::<u64>()))
+            .take_while(|(_, depth)| *depth >= min_depth)
+            .map(|(counts, depth)| {
+                counts
+                    .iter()
+                    .zip(BASES)
+                    .find(|(count, _)| **count * 2 > depth)
+                    .map_or(b'N', |(_, base)| base)
+            })
+            .collect()
+    }
+}
+
+/// Consensus of the soft clipped sequences of the alignments sharing a clip boundary, e.g. to
+/// identify inserted sequences or viral integration sites where many reads are clipped
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClipConsensus {
+    /// clipped sequences by tid, aligned position next to the clip (0-based) and whether
+    /// the clip is on the right of the alignment
+    pileups: BTreeMap<(i32, i64, bool), ClippedPileup>,
+}
+
+impl ClipConsensus {
+    /// Stack the soft clipped sequences of an alignment, unmapped alignments are skipped
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::consensus::ClipConsensus;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    /// let mut consensus = ClipConsensus::default();
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("3S5M").unwrap();
+    /// record.set(b"read", Some(&cigar), b"TTGACGTA", &[30; 8]);
+    /// record.set_flags(0);
+    /// record.set_tid(0);
+    /// record.set_pos(100);
+    /// consensus.add(&record);
+    /// assert_eq!(consensus.len(), 1);
+    /// ```
+    pub fn add(&mut self, record: &Record) {
+        if record.is_unmapped() || record.tid() < 0 {
+            return;
+        }
+        let cigar: CigarStringView = record.cigar();
+        let leading: usize = cigar.leading_softclips() as usize;
+        let trailing: usize = cigar.trailing_softclips() as usize;
+        if leading == 0 && trailing == 0 {
+            return;
+        }
+        let seq: Vec<u8> = record.seq().as_bytes();
+        if leading > 0 && leading <= seq.len() {
+            self.pileups
+                .entry((record.tid(), record.pos(), false))
+                .or_default()
+                .add(seq[..leading].iter().rev());
+        }
+        if trailing > 0 && trailing <= seq.len() {
+            self.pileups
+                .entry((record.tid(), cigar.end_pos() - 1, true))
+                .or_default()
+                .add(seq[seq.len() - trailing..].iter());
+        }
+    }
+
+    /// Add the clipped sequences stacked from other alignments, e.g. of another part of
+    /// the input
+    pub fn merge(&mut self, other: &ClipConsensus) {
+        for (key, pileup) in other.pileups.iter() {
+            self.pileups.entry(*key).or_default().merge(pileup);
+        }
+    }
+
+    /// Number of clip boundaries
+    pub fn len(&self) -> usize {
+        self.pileups.len()
+    }
+
+    /// Whether no clipped alignments were stacked
+    pub fn is_empty(&self) -> bool {
+        self.pileups.is_empty()
+    }
+
+    /// Write the consensus sequences of the clip boundaries shared by at least `min_support`
+    /// alignments as FASTA, in the order of the reference sequences in the header. Records are
+    /// named `contig:position:side` (1-based position of the aligned base next to the clip,
+    /// `left_clip` or `right_clip`) with the number of supporting alignments in the description.
+    /// The sequences read in the reference orientation and stop where fewer than `min_support`
+    /// clipped sequences are left
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the FASTA
+    /// * `header`: header of the alignments, for the reference names
+    /// * `min_support`: fewest alignments clipped at a boundary to report it
+    pub fn write_fasta<W: Write>(
+        &self,
+        writer: &mut W,
+        header: &HeaderView,
+        min_support: u64,
+    ) -> Result<(), FilterClippedError> {
+        for ((tid, pos, right), pileup) in self.pileups.iter() {
+            if pileup.reads < min_support {
+                continue;
+            }
+            let mut seq: Vec<u8> = pileup.consensus(min_support);
+            if seq.is_empty() {
+                continue;
+            }
+            if !right {
+                seq.reverse();
+            }
+            let contig: String = String::from_utf8_lossy(header.tid2name(*tid as u32)).to_string();
+            let side: &str = if *right { "right_clip" } else { "left_clip" };
+            writeln!(
+                writer,
+                ">{}:{}:{} reads={}",
+                contig,
+                pos + 1,
+                side,
+                pileup.reads
+            )?;
+            writer.write_all(&seq)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
+
+    #[rstest]
+    #[case(vec!["ACGT"], 1, "ACGT")]
+    #[case(vec!["ACGT", "ACGA", "ACG"], 3, "ACG")]
+    #[case(vec!["ACGT", "ACGA", "ACG"], 1, "ACGN")]
+    #[case(vec!["ACGT", "ATGT", "AGGT"], 3, "ANGT")]
+    #[case(vec!["acgt", "ACGT"], 2, "ACGT")]
+    fn test_consensus(#[case] seqs: Vec<&str>, #[case] min_depth: u64, #[case] expected: &str) {
+        let mut pileup = ClippedPileup::default();
+        for seq in seqs {
+            pileup.add(seq.as_bytes().iter());
+        }
+        assert_eq!(pileup.consensus(min_depth), expected.as_bytes());
+    }
+
+    #[rstest]
+    #[case(
+        1,
+        ">chr1:101:left_clip reads=3\nGTTA\n>chr1:105:right_clip reads=1\nCC\n"
+    )]
+    #[case(2, ">chr1:101:left_clip reads=3\nTTA\n")]
+    #[case(4, "")]
+    fn test_write_fasta(#[case] min_support: u64, #[case] expected: &str) {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:250\n");
+        let mut consensus = ClipConsensus::default();
+        for (cigar, seq) in [
+            ("4S5M", "GTTAACGTA"),
+            ("3S5M", "TTAACGTA"),
+            ("3S5M2S", "TCAACGTACC"),
+            ("8M", "ACGTACGT"),
+        ] {
+            let cigar = CigarString::try_from(cigar).unwrap();
+            let mut record = Record::new();
+            record.set(b"read", Some(&cigar), seq.as_bytes(), &vec![30; seq.len()]);
+            record.set_flags(0);
+            record.set_tid(0);
+            record.set_pos(100);
+            consensus.add(&record);
+        }
+        let mut fasta: Vec<u8> = Vec::new();
+        consensus
+            .write_fasta(&mut fasta, &header, min_support)
+            .unwrap();
+        assert_eq!(String::from_utf8(fasta).unwrap(), expected);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::config::{is_seqless_secondary, FilterConfig, FlagPolicy, Thresholds};
+use crate::error::FilterClippedError;
+use crate::filter::ClipFilter;
+use crate::{io, progress};
+use rust_htslib::bam::{Read, Record};
+use std::cmp::Ordering;
+use std::io::Write;
+
+/// Percentages of alignments to keep that thresholds are suggested for by default
+pub const DEFAULT_RETENTION: [f64; 3] = [90.0, 95.0, 99.0];
+
+/// Clip fractions of the alignments of an input file, to derive thresholds from
+#[derive(Debug, Default, Clone)]
+pub struct ClipDistribution {
+    /// fractions of total clipped bases, sorted
+    total: Vec<f64>,
+    /// fractions of 5' clipped bases, sorted
+    left: Vec<f64>,
+    /// fractions of 3' clipped bases, sorted
+    right: Vec<f64>,
+    /// (total, 5', 3') clip fractions of each alignment, in input order
+    fractions: Vec<(f64, f64, f64)>,
+}
+
+/// Value at a percentile of sorted values (nearest-rank method)
+fn nearest_rank(sorted: &[f64], percentile: f64) -> Option<f64> {
+    let rank: usize = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+}
+
+impl ClipDistribution {
+    /// Build the distributions from the clip fractions of alignments
+    ///
+    /// # Arguments
+    /// - `fractions`: (total, 5', 3') clip fractions of each alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::distribution::ClipDistribution;
+    /// let distribution = ClipDistribution::from_fractions(vec![(0.0, 0.0, 0.0), (0.2, 0.1, 0.1)]);
+    /// assert_eq!(distribution.len(), 2);
+    /// ```
+    pub fn from_fractions(fractions: Vec<(f64, f64, f64)>) -> Self {
+        let mut distribution = Self::default();
+        for (total, left, right) in fractions.iter().copied() {
+            distribution.total.push(total);
+            distribution.left.push(left);
+            distribution.right.push(right);
+        }
+        distribution.fractions = fractions;
+        distribution.total.sort_by(f64::total_cmp);
+        distribution.left.sort_by(f64::total_cmp);
+        distribution.right.sort_by(f64::total_cmp);
+        distribution
+    }
+
+    /// Number of alignments in the distributions
+    pub fn len(&self) -> usize {
+        self.total.len()
+    }
+
+    /// Whether no alignment was seen
+    pub fn is_empty(&self) -> bool {
+        self.total.is_empty()
+    }
+
+    /// Thresholds letting through the given percentile of the alignments on each of the
+    /// total, 5' and 3' clip fractions. As the three are set independently, the fraction
+    /// of alignments passing all of them can be lower
+    ///
+    /// # Arguments
+    /// - `percentile`: percentage of alignments to keep on each of the clip fractions (0-100)
+    ///
+    /// # Returns
+    /// - the thresholds, or None if the distributions are empty
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::distribution::ClipDistribution;
+    /// let fractions = (0..10).map(|i| (i as f64 / 20.0, i as f64 / 40.0, 0.0)).collect();
+    /// let thresholds = ClipDistribution::from_fractions(fractions).thresholds_at(80.0).unwrap();
+    /// // alignments failing the total clip fraction have at least both_end
+    /// assert_eq!(thresholds.both_end, 0.4);
+    /// assert_eq!(thresholds.left_side, 0.175);
+    /// assert_eq!(thresholds.right_side, 0.0);
+    /// ```
+    pub fn thresholds_at(&self, percentile: f64) -> Option<Thresholds> {
+        let total: f64 = nearest_rank(&self.total, percentile)?;
+        // an alignment fails if its total clip fraction reaches both_end,
+        // so the threshold is the next higher fraction
+        let both_end: f64 = self
+            .total
+            .iter()
+            .copied()
+            .find(|fraction| *fraction > total)
+            .unwrap_or(1.0);
+        Some(Thresholds {
+            both_end,
+            left_side: nearest_rank(&self.left, percentile)?,
+            right_side: nearest_rank(&self.right, percentile)?,
+        })
+    }
+}
+
+impl ClipDistribution {
+    /// Fraction of the alignments passing all of the thresholds
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::Thresholds;
+    /// use filter_clipped::distribution::ClipDistribution;
+    /// let distribution = ClipDistribution::from_fractions(vec![(0.0, 0.0, 0.0), (0.2, 0.2, 0.0)]);
+    /// let thresholds = Thresholds { both_end: 0.3, left_side: 0.1, right_side: 0.1 };
+    /// assert_eq!(distribution.retention(&thresholds), 0.5);
+    /// ```
+    pub fn retention(&self, thresholds: &Thresholds) -> f64 {
+        let passed: usize = self
+            .fractions
+            .iter()
+            .filter(|(total, left, right)| {
+                *total < thresholds.both_end
+                    && *left <= thresholds.left_side
+                    && *right <= thresholds.right_side
+            })
+            .count();
+        passed as f64 / self.fractions.len().max(1) as f64
+    }
+
+    /// Thresholds keeping at least `target` percent of the alignments on all of the clip
+    /// fractions at once: the lowest percentile (see [ClipDistribution::thresholds_at]),
+    /// to within 0.01, whose thresholds reach the target
+    ///
+    /// # Arguments
+    /// - `target`: percentage of alignments to keep (0-100)
+    ///
+    /// # Returns
+    /// - the thresholds and the fraction of alignments they keep, or None if the
+    ///   distributions are empty
+    pub fn suggest(&self, target: f64) -> Option<(Thresholds, f64)> {
+        let reaches = |thresholds: &Thresholds| self.retention(thresholds) * 100.0 >= target;
+        let mut best: Thresholds = self.thresholds_at(100.0)?;
+        let (mut low, mut high): (f64, f64) = (target, 100.0);
+        let lowest: Thresholds = self.thresholds_at(low)?;
+        if reaches(&lowest) {
+            best = lowest;
+        } else {
+            while high - low > 0.01 {
+                let middle: f64 = (low + high) / 2.0;
+                let thresholds: Thresholds = self.thresholds_at(middle)?;
+                if reaches(&thresholds) {
+                    best = thresholds;
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+        }
+        let retention: f64 = self.retention(&best);
+        Some((best, retention))
+    }
+
+    /// Write the suggested thresholds for each target retention as a TSV: the target
+    /// percentage, the thresholds, the fraction of alignments kept and the matching
+    /// command line options
+    ///
+    /// # Arguments
+    /// - `writer`: where to write the TSV
+    /// - `targets`: percentages of alignments to keep (0-100)
+    pub fn write_suggestions<W: Write>(
+        &self,
+        writer: &mut W,
+        targets: &[f64],
+    ) -> Result<(), FilterClippedError> {
+        writeln!(
+            writer,
+            "target\tleft_side\tright_side\tboth_end\tretained\toptions"
+        )?;
+        for target in targets {
+            if let Some((thresholds, retention)) = self.suggest(*target) {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{:.4}\t--left-side {} --right-side {} --both-end {}",
+                    target,
+                    thresholds.left_side,
+                    thresholds.right_side,
+                    thresholds.both_end,
+                    retention,
+                    thresholds.left_side,
+                    thresholds.right_side,
+                    thresholds.both_end
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read through the input file and collect the clip fractions of the mapped alignments
+/// the filter applies to (see [FlagPolicy::Filter])
+///
+/// # Arguments
+/// - `config`: settings for the run, the input must be a file (not stdin)
+pub fn scan(config: &FilterConfig) -> Result<ClipDistribution, FilterClippedError> {
+    if config.in_bam.eq("-") {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "deriving thresholds needs to read the input twice and doesn't work with stdin",
+        )));
+    }
+    let clip_filter = ClipFilter::new(config);
+    let mut fractions: Vec<(f64, f64, f64)> = Vec::new();
+    let bar = progress::progress_bar(config, "Scanning");
+    let (mut index, mut malformed): (u64, u64) = (0, 0);
+    'inputs: for mut in_bam in io::open_inputs(config)? {
+        for r in in_bam.records() {
+            bar.inc(1);
+            index += 1;
+            match config.record_position(index - 1) {
+                Ordering::Less => continue,
+                Ordering::Greater => break 'inputs,
+                Ordering::Equal => (),
+            }
+            let record: Record = match r {
+                Ok(record) => record,
+                Err(e) => {
+                    io::skip_malformed(config, &mut malformed, e.into())?;
+                    continue;
+                }
+            };
+            if record.is_unmapped()
+                || is_seqless_secondary(&record)
+                || config.flag_policy(&record) != FlagPolicy::Filter
+            {
+                continue;
+            }
+            let (clip_stat, seq_len) = clip_filter.clip_stat(&record)?;
+            fractions.push((
+                clip_stat.total_fraction(seq_len)?,
+                clip_stat.left_fraction(seq_len)?,
+                clip_stat.right_fraction(seq_len)?,
+            ));
+        }
+    }
+    bar.finish_and_clear();
+    Ok(ClipDistribution::from_fractions(fractions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(vec![], 50.0, None)]
+    #[case(vec![0.3], 50.0, Some(0.3))]
+    #[case(vec![0.1, 0.2, 0.3, 0.4], 0.0, Some(0.1))]
+    #[case(vec![0.1, 0.2, 0.3, 0.4], 50.0, Some(0.2))]
+    #[case(vec![0.1, 0.2, 0.3, 0.4], 51.0, Some(0.3))]
+    #[case(vec![0.1, 0.2, 0.3, 0.4], 100.0, Some(0.4))]
+    fn test_nearest_rank(
+        #[case] sorted: Vec<f64>,
+        #[case] percentile: f64,
+        #[case] expected: Option<f64>,
+    ) {
+        assert_eq!(nearest_rank(&sorted, percentile), expected);
+    }
+
+    #[rstest]
+    #[case(50.0, (0.4, 0.2, 0.0), 0.6)]
+    #[case(80.0, (0.45, 0.3, 0.0), 0.8)]
+    #[case(90.0, (1.0, 0.4, 0.0), 1.0)]
+    fn test_suggest(
+        #[case] target: f64,
+        #[case] expected: (f64, f64, f64),
+        #[case] expected_retention: f64,
+    ) {
+        // the 5' clips make the joint retention lower than the percentile of the total clips
+        let fractions = (0..10)
+            .map(|i| (i as f64 / 20.0, (9 - i) as f64 / 20.0 * (i % 2) as f64, 0.0))
+            .collect();
+        let distribution = ClipDistribution::from_fractions(fractions);
+        let (thresholds, retention) = distribution.suggest(target).unwrap();
+        assert_eq!(
+            (
+                thresholds.both_end,
+                thresholds.left_side,
+                thresholds.right_side
+            ),
+            expected
+        );
+        assert_eq!(retention, expected_retention);
+        assert!(retention * 100.0 >= target);
+    }
+
+    #[rstest]
+    #[case(100.0, 1.0)]
+    #[case(75.0, 0.5)]
+    #[case(50.0, 0.5)]
+    #[case(25.0, 0.2)]
+    fn test_thresholds_at_ties(#[case] percentile: f64, #[case] expected_both_end: f64) {
+        let fractions = vec![0.1, 0.2, 0.2, 0.5]
+            .into_iter()
+            .map(|total| (total, 0.0, 0.0))
+            .collect();
+        let thresholds = ClipDistribution::from_fractions(fractions)
+            .thresholds_at(percentile)
+            .unwrap();
+        assert_eq!(thresholds.both_end, expected_both_end);
+    }
+}
@@ -0,0 +1,62 @@
+use thiserror::Error;
+
+/// Everything that can go wrong while filtering an alignment file
+#[derive(Error, Debug)]
+pub enum FilterClippedError {
+    /// reading or writing a plain file failed
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// htslib failed to read, write or index an alignment file
+    #[error("htslib error: {0}")]
+    Htslib(#[from] rust_htslib::errors::Error),
+    /// a remote input couldn't be opened
+    #[error("failed to open remote input {url} after {attempts} attempts ({hint}): {source}")]
+    Remote {
+        url: String,
+        attempts: u32,
+        hint: &'static str,
+        source: rust_htslib::errors::Error,
+    },
+    /// the settings don't work together or with the input
+    #[error("invalid settings: {0}")]
+    InvalidConfig(String),
+    /// an alignment record can't be interpreted (e.g. a malformed SA tag)
+    #[error("invalid alignment record: {0}")]
+    InvalidRecord(String),
+    /// the settings couldn't be serialized
+    #[error("serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// the per-alignment metrics couldn't be written
+    #[cfg(feature = "parquet")]
+    #[error("parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    /// the run went through but its outcome is one the settings ask to fail on (see
+    /// [crate::check_run])
+    #[error("run check failed: {0}")]
+    RunCheck(String),
+    /// the logger couldn't be set up
+    #[error("logger error: {0}")]
+    Logger(#[from] log::SetLoggerError),
+    /// the library panicked in a call through the C API (see [crate::ffi])
+    #[error("internal error: {0}")]
+    Panic(String),
+}
+
+impl FilterClippedError {
+    /// Exit code of the binary for this error: 2 for invalid settings (like clap's usage errors),
+    /// 3 for a run failing a check on its outcome (e.g. `--fail-if-empty`), 1 for everything else
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::error::FilterClippedError;
+    /// let err = FilterClippedError::InvalidConfig(String::from("--write-index needs an output file"));
+    /// assert_eq!(err.exit_code(), 2);
+    /// ```
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FilterClippedError::InvalidConfig(_) => 2,
+            FilterClippedError::RunCheck(_) => 3,
+            _ => 1,
+        }
+    }
+}
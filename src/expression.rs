@@ -0,0 +1,588 @@
+//! Filter expressions in the style of `samtools view -e`, e.g. `mapq >= 20 && [NM] <= 5`,
+//! checked on the alignments on top of the clipping thresholds
+//!
+//! An expression combines:
+//! - numbers (`20`, `0.5`, `0x400`) and strings (`"chr1"` or `'chr1'`)
+//! - fields of the alignment: `qname`, `flag`, `rname`, `pos`, `endpos` (1-based), `mapq`,
+//!   `mrname`, `mpos`, `tlen`, `qlen`, `rlen`, `sclen`, `hclen`, `ncigar`, and the flag bits
+//!   `flag.paired`, `flag.proper_pair`, `flag.unmap`, `flag.munmap`, `flag.reverse`,
+//!   `flag.mreverse`, `flag.read1`, `flag.read2`, `flag.secondary`, `flag.qcfail`,
+//!   `flag.dup` and `flag.supplementary`
+//! - aux tags in brackets (`[NM]`), null when missing, so that `[XS]` alone checks for the tag
+//! - the operators `||`, `&&`, `|`, `&`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `+`, `-`, `*`,
+//!   `/`, `%`, `!` and parentheses, with the precedence of C
+//!
+//! Comparisons involving a null value (a missing tag) are false. Regular expressions
+//! (`=~`, `!~`) are not supported
+
+use crate::error::FilterClippedError;
+use crate::record_filter::RecordFilter;
+use rust_htslib::bam::record::Aux;
+use rust_htslib::bam::{HeaderView, Record};
+use std::cmp::Ordering;
+
+/// Value of an expression on an alignment
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    /// missing, e.g. an aux tag the alignment doesn't have
+    Null,
+    Num(f64),
+    Str(Vec<u8>),
+}
+
+impl Value {
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Num(value) => *value != 0.0,
+            Value::Str(value) => !value.is_empty(),
+        }
+    }
+
+    fn from_bool(value: bool) -> Self {
+        Value::Num(if value { 1.0 } else { 0.0 })
+    }
+}
+
+/// A field of the alignment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Qname,
+    Flag,
+    Rname,
+    Pos,
+    Endpos,
+    Mapq,
+    Mrname,
+    Mpos,
+    Tlen,
+    Qlen,
+    Rlen,
+    Sclen,
+    Hclen,
+    Ncigar,
+    /// a bit of the flag, 1 if set
+    FlagBit(u16),
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        let field = match name {
+            "qname" => Field::Qname,
+            "flag" => Field::Flag,
+            "rname" => Field::Rname,
+            "pos" => Field::Pos,
+            "endpos" => Field::Endpos,
+            "mapq" => Field::Mapq,
+            "mrname" => Field::Mrname,
+            "mpos" => Field::Mpos,
+            "tlen" => Field::Tlen,
+            "qlen" => Field::Qlen,
+            "rlen" => Field::Rlen,
+            "sclen" => Field::Sclen,
+            "hclen" => Field::Hclen,
+            "ncigar" => Field::Ncigar,
+            "flag.paired" => Field::FlagBit(0x1),
+            "flag.proper_pair" => Field::FlagBit(0x2),
+            "flag.unmap" => Field::FlagBit(0x4),
+            "flag.munmap" => Field::FlagBit(0x8),
+            "flag.reverse" => Field::FlagBit(0x10),
+            "flag.mreverse" => Field::FlagBit(0x20),
+            "flag.read1" => Field::FlagBit(0x40),
+            "flag.read2" => Field::FlagBit(0x80),
+            "flag.secondary" => Field::FlagBit(0x100),
+            "flag.qcfail" => Field::FlagBit(0x200),
+            "flag.dup" => Field::FlagBit(0x400),
+            "flag.supplementary" => Field::FlagBit(0x800),
+            _ => return None,
+        };
+        Some(field)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Binary operators by precedence level, lowest first
+const PRECEDENCE: [&[(&str, BinaryOp)]; 7] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+/// Operators of the highest binary precedence level
+const PRODUCT: [(&str, BinaryOp); 3] = [
+    ("*", BinaryOp::Mul),
+    ("/", BinaryOp::Div),
+    ("%", BinaryOp::Rem),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Literal(Value),
+    Field(Field),
+    Tag([u8; 2]),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(Vec<u8>),
+    Tag([u8; 2]),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+/// Operators, the longer ones first so that they are matched before their prefixes
+const OPERATORS: [&str; 17] = [
+    "||", "&&", "==", "!=", "<=", ">=", "|", "&", "<", ">", "+", "-", "*", "/", "%", "!", "=",
+];
+
+/// Error for an invalid expression
+fn invalid(text: &str, message: String) -> FilterClippedError {
+    FilterClippedError::InvalidConfig(format!("expression {:?}: {}", text, message))
+}
+
+/// Split an expression into tokens
+fn tokenize(text: &str) -> Result<Vec<Token>, FilterClippedError> {
+    let bytes: &[u8] = text.as_bytes();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let c: u8 = bytes[i];
+        let start: usize = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'(' || c == b')' {
+            tokens.push(if c == b'(' { Token::Open } else { Token::Close });
+            i += 1;
+        } else if c.is_ascii_digit() || c == b'.' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+            let literal: &str = &text[start..i];
+            let value: Option<f64> = match literal.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16).ok().map(|v| v as f64),
+                None => literal.parse::<f64>().ok(),
+            };
+            match value {
+                Some(value) => tokens.push(Token::Num(value)),
+                None => return Err(invalid(text, format!("invalid number {}", literal))),
+            }
+        } else if c == b'"' || c == b'\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != c {
+                i += 1;
+            }
+            if i == bytes.len() {
+                return Err(invalid(text, format!("unterminated string at {}", start)));
+            }
+            tokens.push(Token::Str(bytes[start + 1..i].to_vec()));
+            i += 1;
+        } else if c == b'[' {
+            match bytes.get(i + 1..i + 4) {
+                Some([a, b, b']']) if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => {
+                    tokens.push(Token::Tag([*a, *b]));
+                    i += 4;
+                }
+                _ => return Err(invalid(text, format!("invalid aux tag at {}", start))),
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(text[start..i].to_string()));
+        } else {
+            if text[i..].starts_with("=~") || text[i..].starts_with("!~") {
+                return Err(invalid(
+                    text,
+                    String::from("regular expressions (=~, !~) aren't supported"),
+                ));
+            }
+            match OPERATORS.iter().find(|op| text[i..].starts_with(**op)) {
+                Some(&"=") => return Err(invalid(text, format!("use == to compare at {}", i))),
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => {
+                    return Err(invalid(
+                        text,
+                        format!("unexpected {:?} at {}", c as char, start),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens of an expression
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    /// Take the next token if it's one of the operators, returning its operation
+    fn take_op(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        let op = match self.peek() {
+            Some(Token::Op(op)) => ops.iter().find(|(name, _)| name == op)?.1,
+            _ => return None,
+        };
+        self.next += 1;
+        Some(op)
+    }
+
+    /// Parse the binary operations of a precedence level and the levels above it
+    fn binary(&mut self, level: usize) -> Result<Node, FilterClippedError> {
+        let ops: &[(&str, BinaryOp)] = match PRECEDENCE.get(level) {
+            Some(ops) => ops,
+            None => &PRODUCT,
+        };
+        let operand = |parser: &mut Self| match level < PRECEDENCE.len() {
+            true => parser.binary(level + 1),
+            false => parser.unary(),
+        };
+        let mut node: Node = operand(self)?;
+        while let Some(op) = self.take_op(ops) {
+            node = Node::Binary(op, Box::new(node), Box::new(operand(self)?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, FilterClippedError> {
+        match self.peek() {
+            Some(Token::Op("!")) => {
+                self.next += 1;
+                Ok(Node::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Op("-")) => {
+                self.next += 1;
+                Ok(Node::Neg(Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, FilterClippedError> {
+        let token: Token = match self.tokens.get(self.next) {
+            Some(token) => token.clone(),
+            None => return Err(invalid(self.text, String::from("unexpected end"))),
+        };
+        self.next += 1;
+        match token {
+            Token::Num(value) => Ok(Node::Literal(Value::Num(value))),
+            Token::Str(value) => Ok(Node::Literal(Value::Str(value))),
+            Token::Tag(tag) => Ok(Node::Tag(tag)),
+            Token::Ident(name) => match Field::from_name(&name) {
+                Some(field) => Ok(Node::Field(field)),
+                None => Err(invalid(self.text, format!("unknown field {}", name))),
+            },
+            Token::Open => {
+                let node: Node = self.binary(0)?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.next += 1;
+                        Ok(node)
+                    }
+                    _ => Err(invalid(self.text, String::from("missing )"))),
+                }
+            }
+            token => Err(invalid(self.text, format!("unexpected {:?}", token))),
+        }
+    }
+}
+
+/// Number of bases of the CIGAR operations of an alignment for which `counted` is true
+fn cigar_bases(record: &Record, counted: impl Fn(u32) -> bool) -> f64 {
+    record
+        .raw_cigar()
+        .iter()
+        .filter(|op| counted(*op & 0xf))
+        .map(|op| (op >> 4) as f64)
+        .sum()
+}
+
+/// Length of the reference covered by an alignment: M, D, N, = and X operations
+fn reference_length(record: &Record) -> f64 {
+    cigar_bases(record, |op| matches!(op, 0 | 2 | 3 | 7 | 8))
+}
+
+/// A parsed filter expression, see the [module documentation](self) for the syntax
+///
+/// # Example
+/// ```
+/// use filter_clipped::expression::Expression;
+/// use filter_clipped::record_filter::RecordFilter;
+/// use rust_htslib::bam::record::{Aux, CigarString, Record};
+///
+/// let expression = Expression::parse("mapq >= 20 && [NM] <= 5").unwrap();
+/// let mut record = Record::new();
+/// let cigar = CigarString::try_from("10S90M").unwrap();
+/// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+/// record.set_mapq(30);
+/// assert!(!expression.keep(&record));
+/// record.push_aux(b"NM", Aux::U8(2)).unwrap();
+/// assert!(expression.keep(&record));
+/// assert!(Expression::parse("mapq >= ").is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Expression {
+    root: Node,
+    /// reference names by tid, for `rname` and `mrname`
+    contigs: Vec<Vec<u8>>,
+}
+
+impl Expression {
+    /// Parse an expression
+    ///
+    /// # Arguments
+    /// - `text`: the expression
+    ///
+    /// # Returns
+    /// - the expression, or [FilterClippedError::InvalidConfig] if it isn't valid
+    pub fn parse(text: &str) -> Result<Self, FilterClippedError> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            next: 0,
+        };
+        let root: Node = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(text, format!("unexpected {:?}", token)));
+        }
+        Ok(Self {
+            root,
+            contigs: Vec::new(),
+        })
+    }
+
+    /// Look up the reference names of `rname` and `mrname` in a header, without one they
+    /// are null for the mapped alignments
+    pub fn with_header(mut self, header: &HeaderView) -> Self {
+        self.contigs = header.target_names().iter().map(|n| n.to_vec()).collect();
+        self
+    }
+
+    /// Reference name of a tid, `*` for none
+    fn contig(&self, tid: i32) -> Value {
+        match usize::try_from(tid) {
+            Ok(tid) => self
+                .contigs
+                .get(tid)
+                .map_or(Value::Null, |name| Value::Str(name.clone())),
+            Err(_) => Value::Str(b"*".to_vec()),
+        }
+    }
+
+    fn field(&self, field: Field, record: &Record) -> Value {
+        let value: f64 = match field {
+            Field::Qname => return Value::Str(record.qname().to_vec()),
+            Field::Rname => return self.contig(record.tid()),
+            Field::Mrname => return self.contig(record.mtid()),
+            Field::Flag => record.flags() as f64,
+            Field::FlagBit(bit) => (record.flags() & bit != 0) as u8 as f64,
+            Field::Pos => (record.pos() + 1) as f64,
+            Field::Endpos => record.pos() as f64 + reference_length(record),
+            Field::Mapq => record.mapq() as f64,
+            Field::Mpos => (record.mpos() + 1) as f64,
+            Field::Tlen => record.insert_size() as f64,
+            Field::Qlen => record.seq_len() as f64,
+            Field::Rlen => reference_length(record),
+            Field::Sclen => cigar_bases(record, |op| op == 4),
+            Field::Hclen => cigar_bases(record, |op| op == 5),
+            Field::Ncigar => record.cigar_len() as f64,
+        };
+        Value::Num(value)
+    }
+
+    fn tag(record: &Record, tag: &[u8; 2]) -> Value {
+        match record.aux(tag) {
+            Ok(Aux::Char(value)) => Value::Str(vec![value]),
+            Ok(Aux::I8(value)) => Value::Num(value as f64),
+            Ok(Aux::U8(value)) => Value::Num(value as f64),
+            Ok(Aux::I16(value)) => Value::Num(value as f64),
+            Ok(Aux::U16(value)) => Value::Num(value as f64),
+            Ok(Aux::I32(value)) => Value::Num(value as f64),
+            Ok(Aux::U32(value)) => Value::Num(value as f64),
+            Ok(Aux::Float(value)) => Value::Num(value as f64),
+            Ok(Aux::Double(value)) => Value::Num(value),
+            Ok(Aux::String(value)) | Ok(Aux::HexByteArray(value)) => {
+                Value::Str(value.as_bytes().to_vec())
+            }
+            // arrays can't be compared
+            _ => Value::Null,
+        }
+    }
+
+    fn eval(&self, node: &Node, record: &Record) -> Value {
+        match node {
+            Node::Literal(value) => value.clone(),
+            Node::Field(field) => self.field(*field, record),
+            Node::Tag(tag) => Self::tag(record, tag),
+            Node::Not(node) => Value::from_bool(!self.eval(node, record).is_true()),
+            Node::Neg(node) => match self.eval(node, record) {
+                Value::Num(value) => Value::Num(-value),
+                _ => Value::Null,
+            },
+            Node::Binary(BinaryOp::Or, left, right) => Value::from_bool(
+                self.eval(left, record).is_true() || self.eval(right, record).is_true(),
+            ),
+            Node::Binary(BinaryOp::And, left, right) => Value::from_bool(
+                self.eval(left, record).is_true() && self.eval(right, record).is_true(),
+            ),
+            Node::Binary(op, left, right) => {
+                binary(*op, self.eval(left, record), self.eval(right, record))
+            }
+        }
+    }
+}
+
+/// Apply an operator other than `||` and `&&` to two values: numbers and strings are
+/// compared with numbers and strings respectively, the arithmetic only works on numbers
+fn binary(op: BinaryOp, left: Value, right: Value) -> Value {
+    let ordering: Option<Ordering> = match (&left, &right) {
+        (Value::Num(l), Value::Num(r)) => l.partial_cmp(r),
+        (Value::Str(l), Value::Str(r)) => Some(l.cmp(r)),
+        _ => None,
+    };
+    let compare = |accepted: fn(Ordering) -> bool| {
+        Value::from_bool(matches!(ordering, Some(o) if accepted(o)))
+    };
+    let (l, r): (f64, f64) = match (op, &left, &right) {
+        (BinaryOp::Eq, _, _) => return compare(Ordering::is_eq),
+        (BinaryOp::Ne, _, _) => return compare(Ordering::is_ne),
+        (BinaryOp::Lt, _, _) => return compare(Ordering::is_lt),
+        (BinaryOp::Le, _, _) => return compare(Ordering::is_le),
+        (BinaryOp::Gt, _, _) => return compare(Ordering::is_gt),
+        (BinaryOp::Ge, _, _) => return compare(Ordering::is_ge),
+        (_, Value::Num(l), Value::Num(r)) => (*l, *r),
+        _ => return Value::Null,
+    };
+    match op {
+        BinaryOp::BitOr => Value::Num(((l as i64) | (r as i64)) as f64),
+        BinaryOp::BitAnd => Value::Num(((l as i64) & (r as i64)) as f64),
+        BinaryOp::Add => Value::Num(l + r),
+        BinaryOp::Sub => Value::Num(l - r),
+        BinaryOp::Mul => Value::Num(l * r),
+        BinaryOp::Div | BinaryOp::Rem if r == 0.0 => Value::Null,
+        BinaryOp::Div => Value::Num(l / r),
+        BinaryOp::Rem => Value::Num(l % r),
+        _ => unreachable!("comparisons and logical operators are handled before"),
+    }
+}
+
+/// An alignment is kept if the expression is true on it: a non-zero number or a non-empty
+/// string, a null value (e.g. a missing tag) being false
+impl RecordFilter for Expression {
+    fn keep(&self, record: &Record) -> bool {
+        self.eval(&self.root, record).is_true()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
+
+    fn record() -> Record {
+        let mut record = Record::new();
+        let cigar = CigarString::try_from("5H10S80M2D10M").unwrap();
+        record.set(b"read1", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(0x1 | 0x10 | 0x40);
+        record.set_tid(1);
+        record.set_pos(99);
+        record.set_mtid(-1);
+        record.set_mpos(-1);
+        record.set_mapq(30);
+        record.push_aux(b"NM", Aux::U8(4)).unwrap();
+        record.push_aux(b"AS", Aux::I32(-12)).unwrap();
+        record.push_aux(b"RG", Aux::String("rg1")).unwrap();
+        record
+    }
+
+    #[rstest]
+    #[case("mapq >= 20 && [NM] <= 5", true)]
+    #[case("mapq >= 20 && [NM] < 4", false)]
+    #[case("mapq > 40 || [NM] == 4", true)]
+    #[case("[XS]", false)]
+    #[case("![XS]", true)]
+    #[case("[XS] < 10", false)]
+    #[case("[XS] >= 10", false)]
+    #[case("[AS] < -10", true)]
+    #[case("[RG] == \"rg1\"", true)]
+    #[case("[RG] != 'rg2'", true)]
+    #[case("qname == \"read1\"", true)]
+    #[case("rname == \"chr2\" && mrname == \"*\"", true)]
+    #[case("pos == 100 && endpos == 191", true)]
+    #[case("rlen == 92 && qlen == 100", true)]
+    #[case("sclen == 10 && hclen == 5 && ncigar == 5", true)]
+    #[case("flag & 0x10", true)]
+    #[case("(flag & 0x10) == 0x10 && flag.reverse && !flag.read2", true)]
+    #[case("flag & 0x10 == 0x10", true)]
+    #[case("flag.dup || flag.secondary", false)]
+    #[case("sclen / qlen < 0.2", true)]
+    #[case("sclen * 2 + 1 == 21 && -mapq == 0 - 30 && 7 % 4 == 3", true)]
+    #[case("1 / 0", false)]
+    #[case("\"x\" > 1", false)]
+    fn test_expression(#[case] text: &str, #[case] expected: bool) {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:100\n@SQ\tSN:chr2\tLN:1000\n");
+        let expression = Expression::parse(text).unwrap().with_header(&header);
+        assert_eq!(expression.keep(&record()), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("mapq >=")]
+    #[case("mapq = 20")]
+    #[case("(mapq > 20")]
+    #[case("mapq > 20)")]
+    #[case("qname =~ \"^read\"")]
+    #[case("[N]")]
+    #[case("seq_length > 2")]
+    #[case("'abc")]
+    #[case("mapq $ 2")]
+    #[case("0x > 1")]
+    fn test_expression_error(#[case] text: &str) {
+        assert!(Expression::parse(text).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! C-compatible API, for linking filter-clipped as a shared library from C/C++ or other
+//! languages. The matching header is `include/filter_clipped.h`, generated with
+//! `cbindgen --config cbindgen.toml --output include/filter_clipped.h src/ffi.rs`
+//!
+//! Functions returning an `int` status return 0 on success, otherwise the exit code of
+//! the error (see [FilterClippedError::exit_code]), with the message available from
+//! [fc_last_error]. A panic doesn't unwind into the caller, it is reported as an error
+
+use crate::clipping::cigar_query_len;
+use crate::config::FilterConfig;
+use crate::error::FilterClippedError;
+use crate::filter::{ClipFilter, FilterDecision};
+use crate::run_with_config;
+use crate::stats::{FailReason, RunStats};
+use rust_htslib::bam::record::{CigarString, Record};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque handle holding the settings of a run
+pub struct FcConfig(FilterConfig);
+
+/// Summary of a run, see [RunStats]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FcRunStats {
+    pub read: u64,
+    pub written: u64,
+    pub unaligned: u64,
+    pub bypassed: u64,
+    pub dropped: u64,
+    pub failed: u64,
+}
+
+impl From<&RunStats> for FcRunStats {
+    fn from(stats: &RunStats) -> Self {
+        Self {
+            read: stats.read,
+            written: stats.written,
+            unaligned: stats.unaligned,
+            bypassed: stats.bypassed,
+            dropped: stats.dropped,
+            failed: stats.failed(),
+        }
+    }
+}
+
+/// Outcome of [fc_evaluate_cigar]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FcDecision {
+    FcPass = 0,
+    FcFailTotalClip = 1,
+    FcFailLeftClip = 2,
+    FcFailRightClip = 3,
+    FcFailOther = 4,
+    FcError = -1,
+}
+
+/// Remember the error for [fc_last_error] and turn it into a status code
+fn set_error(err: FilterClippedError) -> c_int {
+    let code = err.exit_code();
+    let message = CString::new(err.to_string())
+        .unwrap_or_else(|_| CString::new("error message contains a NUL byte").unwrap());
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+/// Run the body of an entry point, catching a panic instead of unwinding across
+/// `extern "C"` (undefined behaviour): the panic is recorded as the last error and the
+/// value of `on_panic` for its status code is returned
+fn catch_panic<T, F>(on_panic: impl FnOnce(c_int) -> T, body: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message: String = match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| String::from("unknown panic")),
+            };
+            on_panic(set_error(FilterClippedError::Panic(message)))
+        }
+    }
+}
+
+/// Read a C string argument
+///
+/// # Safety
+/// `value` must be null or point to a NUL-terminated string
+unsafe fn read_str(value: *const c_char, name: &str) -> Result<String, FilterClippedError> {
+    if value.is_null() {
+        return Err(FilterClippedError::InvalidConfig(format!(
+            "{} is NULL",
+            name
+        )));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map(|value| value.to_string())
+        .map_err(|_| FilterClippedError::InvalidConfig(format!("{} is not valid UTF-8", name)))
+}
+
+/// Run a fallible closure on a config handle, turning the result into a status code
+///
+/// # Safety
+/// `config` must be null or a handle from [fc_config_new]
+unsafe fn with_config<F>(config: *mut FcConfig, update: F) -> c_int
+where
+    F: FnOnce(&mut FilterConfig) -> Result<(), FilterClippedError>,
+{
+    catch_panic(
+        |code| code,
+        || match config.as_mut() {
+            Some(config) => match update(&mut config.0) {
+                Ok(()) => 0,
+                Err(err) => set_error(err),
+            },
+            None => set_error(FilterClippedError::InvalidConfig(String::from(
+                "config is NULL",
+            ))),
+        },
+    )
+}
+
+/// Create a config handle with the default settings, to be freed with [fc_config_free]
+#[no_mangle]
+pub extern "C" fn fc_config_new() -> *mut FcConfig {
+    catch_panic(
+        |_| ptr::null_mut(),
+        || Box::into_raw(Box::new(FcConfig(FilterConfig::default()))),
+    )
+}
+
+/// Free a config handle
+///
+/// # Safety
+/// `config` must be null or a handle from [fc_config_new], not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn fc_config_free(config: *mut FcConfig) {
+    catch_panic(
+        |_| (),
+        || {
+            if !config.is_null() {
+                drop(Box::from_raw(config));
+            }
+        },
+    )
+}
+
+/// Set the input and output alignment file paths ("-" for stdin/stdout)
+///
+/// # Safety
+/// `config` must be a handle from [fc_config_new], `in_bam` and `out_bam` NUL-terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn fc_config_set_paths(
+    config: *mut FcConfig,
+    in_bam: *const c_char,
+    out_bam: *const c_char,
+) -> c_int {
+    with_config(config, |config| {
+        config.in_bam = read_str(in_bam, "in_bam")?;
+        config.out_bam = read_str(out_bam, "out_bam")?;
+        Ok(())
+    })
+}
+
+/// Set the maximum fractions of clipped bases: in total, on the 5' end and on the 3' end
+///
+/// # Safety
+/// `config` must be a handle from [fc_config_new]
+#[no_mangle]
+pub unsafe extern "C" fn fc_config_set_thresholds(
+    config: *mut FcConfig,
+    both_end: f64,
+    left_side: f64,
+    right_side: f64,
+) -> c_int {
+    with_config(config, |config| {
+        // the handle keeps its thresholds if the new ones are rejected
+        let updated = FilterConfig {
+            both_end,
+            left_side,
+            right_side,
+            ..config.clone()
+        };
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    })
+}
+
+/// Write out the failed alignments only (`inverse` non-zero), and/or make the failed
+/// alignments unmapped instead of removing them (`unalign` non-zero)
+///
+/// # Safety
+/// `config` must be a handle from [fc_config_new]
+#[no_mangle]
+pub unsafe extern "C" fn fc_config_set_mode(
+    config: *mut FcConfig,
+    inverse: c_int,
+    unalign: c_int,
+) -> c_int {
+    with_config(config, |config| {
+        config.inverse = inverse != 0;
+        config.unalign = unalign != 0;
+        Ok(())
+    })
+}
+
+/// Filter the input file into the output file
+///
+/// # Arguments
+/// - `config`: settings of the run
+/// - `stats`: filled with the summary of the run if not null
+///
+/// # Safety
+/// `config` must be a handle from [fc_config_new], `stats` null or a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn fc_run(config: *const FcConfig, stats: *mut FcRunStats) -> c_int {
+    catch_panic(
+        |code| code,
+        || {
+            let config = match config.as_ref() {
+                Some(config) => config,
+                None => {
+                    return set_error(FilterClippedError::InvalidConfig(String::from(
+                        "config is NULL",
+                    )))
+                }
+            };
+            match run_with_config(&config.0) {
+                Ok(run_stats) => {
+                    if let Some(stats) = stats.as_mut() {
+                        *stats = FcRunStats::from(&run_stats);
+                    }
+                    0
+                }
+                Err(err) => set_error(err),
+            }
+        },
+    )
+}
+
+/// Check a CIGAR string against the clipping thresholds of a config, without
+/// going through an alignment file
+///
+/// # Safety
+/// `config` must be a handle from [fc_config_new], `cigar` a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn fc_evaluate_cigar(
+    config: *const FcConfig,
+    cigar: *const c_char,
+) -> FcDecision {
+    catch_panic(
+        |_| FcDecision::FcError,
+        || {
+            let config = match config.as_ref() {
+                Some(config) => config,
+                None => {
+                    set_error(FilterClippedError::InvalidConfig(String::from(
+                        "config is NULL",
+                    )));
+                    return FcDecision::FcError;
+                }
+            };
+            match evaluate_cigar(&config.0, cigar) {
+                Ok(FilterDecision::Pass | FilterDecision::Bypass) => FcDecision::FcPass,
+                Ok(FilterDecision::Fail(FailReason::TotalClip)) => FcDecision::FcFailTotalClip,
+                Ok(FilterDecision::Fail(FailReason::LeftClip)) => FcDecision::FcFailLeftClip,
+                Ok(FilterDecision::Fail(FailReason::RightClip)) => FcDecision::FcFailRightClip,
+                Ok(FilterDecision::Fail(_)) => FcDecision::FcFailOther,
+                Err(err) => {
+                    set_error(err);
+                    FcDecision::FcError
+                }
+            }
+        },
+    )
+}
+
+/// Evaluate a mapped, primary alignment with the given CIGAR string
+///
+/// # Safety
+/// `cigar` must be null or a NUL-terminated string
+unsafe fn evaluate_cigar(
+    config: &FilterConfig,
+    cigar: *const c_char,
+) -> Result<FilterDecision, FilterClippedError> {
+    let cigar: String = read_str(cigar, "cigar")?;
+    let cigar = CigarString::try_from(cigar.as_str()).map_err(|_| {
+        FilterClippedError::InvalidRecord(format!("Malformed CIGAR string: {}", cigar))
+    })?;
+    let seq_len: usize = cigar_query_len(&cigar);
+    let mut record = Record::new();
+    record.set(
+        b"cigar",
+        Some(&cigar),
+        &vec![b'N'; seq_len],
+        &vec![255; seq_len],
+    );
+    // mapping quality not available
+    record.set_mapq(255);
+    ClipFilter::new(config).evaluate(&record)
+}
+
+/// Message of the last error on this thread, or null if there was none. The string
+/// is owned by the library and stays valid until the next failing call on this thread
+#[no_mangle]
+pub extern "C" fn fc_last_error() -> *const c_char {
+    // no error can be recorded if this panics, null is all there is to return
+    panic::catch_unwind(|| {
+        LAST_ERROR.with(|last| match last.borrow().as_ref() {
+            Some(message) => message.as_ptr(),
+            None => ptr::null(),
+        })
+    })
+    .unwrap_or(ptr::null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("100M", FcDecision::FcPass)]
+    #[case("10H90M", FcDecision::FcFailLeftClip)]
+    #[case("85M15S", FcDecision::FcFailRightClip)]
+    #[case("15S70M15S", FcDecision::FcFailTotalClip)]
+    #[case("10Q", FcDecision::FcError)]
+    fn test_fc_evaluate_cigar(#[case] cigar: &str, #[case] expected: FcDecision) {
+        let cigar = CString::new(cigar).unwrap();
+        unsafe {
+            let config = fc_config_new();
+            assert_eq!(fc_config_set_thresholds(config, 0.2, 0.1, 0.1), 0);
+            assert_eq!(fc_evaluate_cigar(config, cigar.as_ptr()), expected);
+            fc_config_free(config);
+        }
+    }
+
+    #[test]
+    fn test_fc_run() {
+        let in_bam = CString::new("test/data/test.sam").unwrap();
+        let out_bam = CString::new("test/data/out_ffi.bam").unwrap();
+        let mut stats = FcRunStats::default();
+        unsafe {
+            let config = fc_config_new();
+            assert_eq!(
+                fc_config_set_paths(config, in_bam.as_ptr(), out_bam.as_ptr()),
+                0
+            );
+            assert_eq!(fc_config_set_thresholds(config, 0.1, 0.1, 0.1), 0);
+            assert_eq!(fc_config_set_thresholds(config, 1.5, 0.5, 0.5), 2);
+            assert!(!fc_last_error().is_null());
+            let thresholds = &(*config).0;
+            assert_eq!(
+                (
+                    thresholds.both_end,
+                    thresholds.left_side,
+                    thresholds.right_side
+                ),
+                (0.1, 0.1, 0.1)
+            );
+            assert_eq!(fc_run(config, &mut stats), 0);
+            fc_config_free(config);
+            assert_eq!(fc_run(ptr::null(), ptr::null_mut()), 2);
+        }
+        assert_eq!(stats.read, 9);
+        assert_eq!(stats.written, 6);
+        assert_eq!(stats.failed, 3);
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|code| code, || 0), 0);
+        let code: c_int = catch_panic(|code| code, || panic!("out of range"));
+        assert_eq!(code, 1);
+        let message = unsafe { CStr::from_ptr(fc_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "internal error: out of range");
+        let code: c_int = catch_panic(|code| code, || panic!("{} is {}", "tid", -1));
+        assert_eq!(code, 1);
+        let message = unsafe { CStr::from_ptr(fc_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "internal error: tid is -1");
+    }
+}
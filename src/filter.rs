@@ -0,0 +1,1253 @@
+use crate::adapters::{self, AdapterHit, AdapterPolicy, ClipSide};
+use crate::chimeric;
+use crate::clipping::{
+    count_clipped_bq, full_raw_cigar, longest_aligned_block, nbase_to_frac, record_aligned,
+    record_indels, record_read_len, CigarClips, ClipStat,
+};
+use crate::complexity::{is_low_complexity, is_motif_repeat};
+use crate::config::{
+    is_seqless_secondary, is_unmapped_input, FilterConfig, FlagPolicy, SeqlessPolicy, Strand,
+    Thresholds, UnmappedPolicy,
+};
+use crate::error::FilterClippedError;
+use crate::expression::Expression;
+use crate::primers::PrimerIndex;
+use crate::record_filter::{MinMapq, RecordFilter};
+use crate::screen::ContaminantIndex;
+use crate::stats::FailReason;
+use crate::tags;
+use log::{debug, warn};
+use rust_htslib::bam::{record::Cigar, HeaderView, Record};
+use std::collections::{HashMap, HashSet};
+
+/// Parse the filter expression of the settings. The settings may not have been checked (see
+/// [FilterConfig::validate]), so a parse error is kept, to be returned by
+/// [ClipFilter::evaluate] rather than filtering without the expression
+fn compile_expression(config: &FilterConfig) -> Result<Option<Expression>, String> {
+    match config.expression.as_deref() {
+        None => Ok(None),
+        Some(text) => match Expression::parse(text) {
+            Ok(expression) => Ok(Some(expression)),
+            Err(FilterClippedError::InvalidConfig(message)) => Err(message),
+            Err(e) => Err(e.to_string()),
+        },
+    }
+}
+
+/// Insert size of a paired alignment with its mate mapped, from its TLEN; None when it's
+/// unknown (TLEN 0, e.g. mates on different references)
+fn insert_size(record: &Record) -> Option<u64> {
+    match record.insert_size().unsigned_abs() {
+        0 => None,
+        _ if !record.is_paired() || record.is_mate_unmapped() => None,
+        size => Some(size),
+    }
+}
+
+/// Outcome of evaluating a single alignment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// within all the clipping thresholds
+    Pass,
+    /// not checked, the flags of the alignment say to keep it (see [FlagPolicy::Keep]),
+    /// its read name is listed to be kept (see [ClipFilter::with_keep_names]), it's
+    /// unmapped and passed through (see [UnmappedPolicy::Passthrough]), or it's on the other
+    /// strand (see [FilterConfig::only_strand])
+    Bypass,
+    /// failed the filter, with the reason why
+    Fail(FailReason),
+}
+
+impl FilterDecision {
+    /// Whether the alignment should be kept (before applying `inverse`)
+    pub fn is_kept(&self) -> bool {
+        !matches!(self, FilterDecision::Fail(_))
+    }
+
+    /// Whether the alignment is removed before the clipping is checked (by its flags, cell
+    /// barcode or read name), whatever `inverse` and `unalign` say
+    pub fn is_removed(&self) -> bool {
+        matches!(
+            self,
+            FilterDecision::Fail(
+                FailReason::Flag
+                    | FailReason::CellBarcode
+                    | FailReason::NameList
+                    | FailReason::Unmapped
+            )
+        )
+    }
+}
+
+/// The per-alignment decision logic of filter-clipped, for use inside
+/// other tools' own loops over alignments
+///
+/// The decision only looks at the alignment itself: options working on whole read names
+/// (`paired`, `two_pass`, `cascade`) and what is done with failed alignments (`inverse`,
+/// `unalign`) are left to the caller
+pub struct ClipFilter<'a> {
+    config: &'a FilterConfig,
+    /// thresholds of the reference sequences listed in the settings, by tid
+    contig_thresholds: HashMap<i32, Thresholds>,
+    /// primer intervals listed in the settings, by tid
+    primers: PrimerIndex,
+    /// k-mers of the contaminant sequences of the settings, see [FilterConfig::screen]
+    screen: ContaminantIndex,
+    /// cell barcodes of the alignments to keep, see [ClipFilter::with_cell_whitelist]
+    cell_whitelist: Option<HashSet<Vec<u8>>>,
+    /// read names to keep without checking, see [ClipFilter::with_keep_names]
+    keep_names: HashSet<Vec<u8>>,
+    /// read names to remove, see [ClipFilter::with_drop_names]
+    drop_names: HashSet<Vec<u8>>,
+    /// the filter expression of the settings (see [FilterConfig::expression]), or why it
+    /// couldn't be parsed
+    expression: Result<Option<Expression>, String>,
+}
+
+impl<'a> ClipFilter<'a> {
+    /// Create a filter from a set of settings
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// use filter_clipped::filter::{ClipFilter, FilterDecision};
+    /// use filter_clipped::stats::FailReason;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    ///
+    /// let config = FilterConfig::new().left(0.1).right(0.1).both(0.3);
+    /// let clip_filter = ClipFilter::new(&config);
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("20S80M").unwrap();
+    /// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+    /// assert_eq!(
+    ///     clip_filter.evaluate(&record).unwrap(),
+    ///     FilterDecision::Fail(FailReason::LeftClip)
+    /// );
+    /// ```
+    pub fn new(config: &'a FilterConfig) -> Self {
+        Self {
+            config,
+            contig_thresholds: HashMap::new(),
+            primers: PrimerIndex::default(),
+            screen: ContaminantIndex::new(&config.contaminants, config.screen_kmer_size as usize),
+            cell_whitelist: None,
+            keep_names: HashSet::new(),
+            drop_names: HashSet::new(),
+            expression: compile_expression(config),
+        }
+    }
+
+    /// Create a filter from a set of settings, looking up the reference sequences of the
+    /// per-reference-sequence thresholds (see [FilterConfig::contig_thresholds]) and of the
+    /// primers (see [FilterConfig::primers]) in the header of the alignments. Filters created
+    /// with [ClipFilter::new] apply the same thresholds to all alignments, and no primers
+    ///
+    /// # Arguments
+    /// - `config`: settings for the run
+    /// - `header`: header of the alignments to filter
+    pub fn with_header(config: &'a FilterConfig, header: &HeaderView) -> Self {
+        let mut contig_thresholds: HashMap<i32, Thresholds> = HashMap::new();
+        for (contig, thresholds) in config.contig_thresholds.iter() {
+            match header.tid(contig.as_bytes()) {
+                Some(tid) => {
+                    contig_thresholds.insert(tid as i32, *thresholds);
+                }
+                None => warn!(
+                    "Reference sequence {} with its own thresholds is not in the header",
+                    contig
+                ),
+            }
+        }
+        Self {
+            config,
+            contig_thresholds,
+            primers: PrimerIndex::new(&config.primers, header),
+            screen: ContaminantIndex::new(&config.contaminants, config.screen_kmer_size as usize),
+            cell_whitelist: None,
+            keep_names: HashSet::new(),
+            drop_names: HashSet::new(),
+            expression: compile_expression(config)
+                .map(|expression| expression.map(|expression| expression.with_header(header))),
+        }
+    }
+
+    /// Remove the alignments whose cell barcode (in `cell_tag`) is missing or not one of
+    /// `barcodes`, which are matched with or without the `-1` style suffix of the tag
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// use filter_clipped::filter::{ClipFilter, FilterDecision};
+    /// use filter_clipped::stats::FailReason;
+    /// use rust_htslib::bam::record::{Aux, CigarString, Record};
+    ///
+    /// let config = FilterConfig::new();
+    /// let barcodes = vec![b"AAACCTGAGAAGGCCT".to_vec()].into_iter().collect();
+    /// let clip_filter = ClipFilter::new(&config).with_cell_whitelist(barcodes);
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("100M").unwrap();
+    /// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+    /// record.push_aux(b"CB", Aux::String("AAACCTGAGAAGGCCT-1")).unwrap();
+    /// assert_eq!(clip_filter.evaluate(&record).unwrap(), FilterDecision::Pass);
+    /// record.remove_aux(b"CB").unwrap();
+    /// assert_eq!(
+    ///     clip_filter.evaluate(&record).unwrap(),
+    ///     FilterDecision::Fail(FailReason::CellBarcode)
+    /// );
+    /// ```
+    pub fn with_cell_whitelist(mut self, barcodes: HashSet<Vec<u8>>) -> Self {
+        self.cell_whitelist = Some(barcodes);
+        self
+    }
+
+    /// Keep the alignments of these reads without checking their flags and clipping
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::config::FilterConfig;
+    /// use filter_clipped::filter::{ClipFilter, FilterDecision};
+    /// use filter_clipped::stats::FailReason;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    ///
+    /// let config = FilterConfig::new();
+    /// let clip_filter = ClipFilter::new(&config)
+    ///     .with_keep_names(vec![b"rescued".to_vec()].into_iter().collect())
+    ///     .with_drop_names(vec![b"removed".to_vec()].into_iter().collect());
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("50S50M").unwrap();
+    /// record.set(b"rescued", Some(&cigar), &[b'A'; 100], &[30; 100]);
+    /// assert_eq!(clip_filter.evaluate(&record).unwrap(), FilterDecision::Bypass);
+    /// record.set_qname(b"removed");
+    /// assert_eq!(
+    ///     clip_filter.evaluate(&record).unwrap(),
+    ///     FilterDecision::Fail(FailReason::NameList)
+    /// );
+    /// ```
+    pub fn with_keep_names(mut self, names: HashSet<Vec<u8>>) -> Self {
+        self.keep_names = names;
+        self
+    }
+
+    /// Remove the alignments of these reads, before the read names to keep are looked at
+    pub fn with_drop_names(mut self, names: HashSet<Vec<u8>>) -> Self {
+        self.drop_names = names;
+        self
+    }
+
+    /// The settings of the filter
+    pub fn config(&self) -> &FilterConfig {
+        self.config
+    }
+
+    /// The filter expression of the settings, or an error if it couldn't be parsed
+    fn expression(&self) -> Result<Option<&Expression>, FilterClippedError> {
+        match self.expression.as_ref() {
+            Ok(expression) => Ok(expression.as_ref()),
+            Err(message) => Err(FilterClippedError::InvalidConfig(message.clone())),
+        }
+    }
+
+    /// Decide what to do with an alignment given its cell barcode, read name, flags,
+    /// mapping quality and clipped bases
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    ///
+    /// # Returns
+    /// - the [FilterDecision], or an error if the alignment can't be evaluated
+    ///   (e.g. no sequence or a malformed SA tag) or the filter expression of the settings
+    ///   can't be parsed
+    pub fn evaluate(&self, record: &Record) -> Result<FilterDecision, FilterClippedError> {
+        self.expression()?;
+        if let Some(barcodes) = self.cell_whitelist.as_ref() {
+            let listed: bool = match tags::string_tag(record, self.config.cell_tag.as_bytes()) {
+                Some(barcode) => {
+                    barcodes.contains(barcode.as_bytes())
+                        || barcodes.contains(barcode.split('-').next().unwrap_or("").as_bytes())
+                }
+                None => false,
+            };
+            if !listed {
+                return Ok(FilterDecision::Fail(FailReason::CellBarcode));
+            }
+        }
+        if self.drop_names.contains(record.qname()) {
+            return Ok(FilterDecision::Fail(FailReason::NameList));
+        }
+        if self.keep_names.contains(record.qname()) {
+            return Ok(FilterDecision::Bypass);
+        }
+        if is_unmapped_input(record) {
+            // nothing to check, see is_decisive for how a kept one follows its read name
+            return Ok(match self.config.unmapped {
+                UnmappedPolicy::Keep => FilterDecision::Pass,
+                UnmappedPolicy::Drop => FilterDecision::Fail(FailReason::Unmapped),
+                UnmappedPolicy::Passthrough => FilterDecision::Bypass,
+            });
+        }
+        if self.config.require_proper_pair && !record.is_proper_pair() {
+            return Ok(FilterDecision::Fail(FailReason::ProperPair));
+        }
+        if matches!(self.config.only_strand, Some(strand) if strand != Strand::of(record)) {
+            return Ok(FilterDecision::Bypass);
+        }
+        match self.config.flag_policy(record) {
+            FlagPolicy::Drop => Ok(FilterDecision::Fail(FailReason::Flag)),
+            FlagPolicy::Keep => Ok(FilterDecision::Bypass),
+            // nothing to check, see is_decisive for how an inheriting one follows its read name
+            FlagPolicy::Filter if is_seqless_secondary(record) => {
+                Ok(match self.config.seqless_secondary {
+                    SeqlessPolicy::Inherit => FilterDecision::Pass,
+                    SeqlessPolicy::Keep => FilterDecision::Bypass,
+                    SeqlessPolicy::Drop => FilterDecision::Fail(FailReason::Flag),
+                })
+            }
+            FlagPolicy::Filter => Ok(match self.clip_failure(record)? {
+                Some(reason) => FilterDecision::Fail(reason),
+                None => FilterDecision::Pass,
+            }),
+        }
+    }
+
+    /// Check whether an alignment passes the minimum mapping quality, has no adapter in its
+    /// clipped bases (with [AdapterPolicy::Fail]) nor contaminant (with `screen_fail`), passes all the clipping thresholds and the
+    /// filter expression if there is one (see [FilterConfig::expression]), and then the extra
+    /// filter if there is one (see [FilterConfig::with_extra_filter])
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    ///
+    /// # Returns
+    /// - None if the alignment passes, otherwise the first check it fails
+    pub fn clip_failure(&self, record: &Record) -> Result<Option<FailReason>, FilterClippedError> {
+        let (clip_stat, seq_len) = self.clip_stat(record)?;
+        let adapter_fails = || {
+            self.config.adapter_policy == AdapterPolicy::Fail && self.adapter_hit(record).is_some()
+        };
+        let contaminant_fails =
+            || self.config.screen_fail && self.contaminant_hit(record).is_some();
+        let failure: Option<FailReason> = match MinMapq(self.config.min_mapq).keep(record) {
+            false => Some(FailReason::Mapq),
+            true if adapter_fails() => Some(FailReason::Adapter),
+            true if contaminant_fails() => Some(FailReason::Contaminant),
+            true => match self.clipping_failure(record, &clip_stat, seq_len)? {
+                Some(failure) => Some(failure),
+                None => self.alignment_failure(record, &clip_stat, seq_len)?,
+            },
+        };
+        let extra_filter = match self.config.extra_filter.as_ref() {
+            Some(extra_filter) => extra_filter,
+            None => return Ok(failure),
+        };
+        match (
+            failure,
+            extra_filter.keep(record, &clip_stat, failure.is_none()),
+        ) {
+            (_, true) => Ok(None),
+            (None, false) => Ok(Some(FailReason::ExtraFilter)),
+            (Some(reason), false) => Ok(Some(reason)),
+        }
+    }
+
+    /// Look for a known adapter in the soft clipped bases of an alignment
+    /// (see [adapters::find_adapter])
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    pub fn adapter_hit(&self, record: &Record) -> Option<AdapterHit<'a>> {
+        if self.config.adapters.is_empty() {
+            return None;
+        }
+        adapters::find_adapter(
+            record,
+            &self.config.adapters,
+            self.config.adapter_mismatch_rate,
+        )
+    }
+
+    /// Look for the contaminants of the settings in the soft clipped bases of an alignment
+    /// (see [ContaminantIndex::find])
+    ///
+    /// # Returns
+    /// - the name of the contaminant found, None without contaminants in the settings
+    pub fn contaminant_hit(&self, record: &Record) -> Option<&str> {
+        self.screen.find(record)
+    }
+
+    /// Count the clipped bases of an alignment, only the soft clipped bases with a base quality
+    /// of at least `min_clip_bq` counting, no low-complexity ones with `exempt_low_complexity`,
+    /// and no repeats of the `exempt_motifs`. With [AdapterPolicy::Trim], a soft clip
+    /// holding an adapter is left out, as are its bases from the sequence length. Clips
+    /// whose reference span lies within a primer are left out too
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    ///
+    /// # Returns
+    /// - the [ClipStat] and the sequence length the fractions are relative to
+    pub fn clip_stat(&self, record: &Record) -> Result<(ClipStat, f64), FilterClippedError> {
+        let union_stat: Option<(ClipStat, f64)> = match self.config.sa_aware {
+            true => chimeric::union_clip_stat(record)?,
+            _ => None,
+        };
+        let (clip_stat, seq_len): (ClipStat, f64) = match union_stat {
+            Some(stat) => stat,
+            None => {
+                // only the clips at the ends are needed, not the whole parsed CIGAR string
+                let clips: CigarClips = CigarClips::from_record(record);
+                let count_hardclips: bool =
+                    !(self.config.ignore_supplementary_hardclips && record.is_supplementary());
+                let (mut leading_hardclips, mut trailing_hardclips) = match count_hardclips {
+                    true => (clips.leading_hardclips, clips.trailing_hardclips),
+                    _ => (0, 0),
+                };
+                let (mut leading_softclips, mut trailing_softclips) =
+                    (clips.leading_softclips, clips.trailing_softclips);
+                let exempt_motifs: bool = !self.config.exempt_motifs.is_empty();
+                if self.config.exempt_low_complexity || exempt_motifs {
+                    let seq: Vec<u8> = record.seq().as_bytes();
+                    let trailing_start: usize =
+                        seq.len().saturating_sub(trailing_softclips as usize);
+                    let is_exempt = |clip: &[u8]| {
+                        (self.config.exempt_low_complexity && is_low_complexity(clip))
+                            || (exempt_motifs
+                                && is_motif_repeat(
+                                    clip,
+                                    &self.config.exempt_motifs,
+                                    self.config.motif_purity,
+                                ))
+                    };
+                    if leading_softclips > 0
+                        && is_exempt(&seq[..(leading_softclips as usize).min(seq.len())])
+                    {
+                        leading_softclips = 0;
+                    }
+                    if trailing_softclips > 0 && is_exempt(&seq[trailing_start..]) {
+                        trailing_softclips = 0;
+                    }
+                }
+                let qual: &[u8] = record.qual();
+                // alignments without a sequence (e.g. secondary alignments) count all their clips
+                if self.config.min_clip_bq > 0
+                    && (leading_softclips + trailing_softclips) as usize <= qual.len()
+                {
+                    let trailing_start: usize = qual.len() - trailing_softclips as usize;
+                    leading_softclips = count_clipped_bq(
+                        &qual[..leading_softclips as usize],
+                        self.config.min_clip_bq,
+                    );
+                    trailing_softclips =
+                        count_clipped_bq(&qual[trailing_start..], self.config.min_clip_bq);
+                }
+                let mut seq_len: usize = record.seq_len();
+                // adapters to be trimmed off don't count as clipped, nor as part of the read
+                if self.config.adapter_policy == AdapterPolicy::Trim {
+                    match self.adapter_hit(record) {
+                        Some(AdapterHit {
+                            side: ClipSide::Left,
+                            len,
+                            ..
+                        }) => {
+                            leading_softclips = 0;
+                            seq_len -= len;
+                        }
+                        Some(AdapterHit {
+                            side: ClipSide::Right,
+                            len,
+                            ..
+                        }) => {
+                            trailing_softclips = 0;
+                            seq_len -= len;
+                        }
+                        None => (),
+                    }
+                }
+                // clips lying within a primer are expected (e.g. primer trimmed amplicons)
+                if !self.primers.is_empty() && !record.is_unmapped() {
+                    let leading: i64 = leading_softclips + leading_hardclips;
+                    let trailing: i64 = trailing_softclips + trailing_hardclips;
+                    let (start, end): (i64, i64) = (record.pos(), record.cigar().end_pos());
+                    if leading > 0 && self.primers.covers(record.tid(), start - leading, start) {
+                        leading_softclips = 0;
+                        leading_hardclips = 0;
+                    }
+                    if trailing > 0 && self.primers.covers(record.tid(), end, end + trailing) {
+                        trailing_softclips = 0;
+                        trailing_hardclips = 0;
+                    }
+                }
+                let leading_clipped: Vec<i64> = vec![leading_softclips, leading_hardclips];
+                let trailing_cliped: Vec<i64> = vec![trailing_softclips, trailing_hardclips];
+                (
+                    ClipStat::new(leading_clipped, trailing_cliped),
+                    seq_len as f64,
+                )
+            }
+        };
+
+        let (insertions, deletions) = record_indels(record);
+        let clip_stat: ClipStat = clip_stat
+            .with_indels(insertions, deletions)
+            .with_aligned(record_aligned(record));
+        debug!("{:?} {}", clip_stat, seq_len);
+        Ok((clip_stat, seq_len))
+    }
+
+    /// The thresholds an alignment is checked against: the ones for spliced alignments in the
+    /// splice-aware mode, then the ones of its reference sequence if it has its own, then the ones for its read length (hard clipped bases included),
+    /// then the ones of its mate (read 1 or read 2), otherwise the ones of the settings
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    pub fn thresholds(&self, record: &Record) -> Thresholds {
+        if let (true, Some(thresholds)) =
+            (self.is_rna_spliced(record), self.config.spliced_thresholds)
+        {
+            return thresholds;
+        }
+        if let Some(thresholds) = self.contig_thresholds.get(&record.tid()) {
+            return *thresholds;
+        }
+        if !self.config.length_thresholds.is_empty() {
+            let clips: CigarClips = CigarClips::from_record(record);
+            let read_len: u64 = (record.seq_len() as i64
+                + clips.leading_hardclips
+                + clips.trailing_hardclips) as u64;
+            if let Some(range) = self
+                .config
+                .length_thresholds
+                .iter()
+                .find(|range| range.contains(read_len))
+            {
+                return range.thresholds;
+            }
+        }
+        let mate_thresholds: Option<Thresholds> = match (
+            record.is_paired() && record.is_first_in_template(),
+            record.is_paired() && record.is_last_in_template(),
+        ) {
+            (true, false) => self.config.read1_thresholds,
+            (false, true) => self.config.read2_thresholds,
+            _ => None,
+        };
+        mate_thresholds.unwrap_or_else(|| self.config.thresholds())
+    }
+
+    /// Whether an alignment is spliced (N operations in its CIGAR string), in the
+    /// splice-aware mode (see [FilterConfig::rna])
+    fn is_rna_spliced(&self, record: &Record) -> bool {
+        self.config.rna
+            && record
+                .cigar()
+                .iter()
+                .any(|op| matches!(op, Cigar::RefSkip(_)))
+    }
+
+    /// Check the rest of the alignment once its clipped bases pass: the indels, the
+    /// mismatches, the aligned fraction of the read, its longest aligned block, its insert
+    /// size, then the filter expression
+    fn alignment_failure(
+        &self,
+        record: &Record,
+        clip_stat: &ClipStat,
+        seq_len: f64,
+    ) -> Result<Option<FailReason>, FilterClippedError> {
+        if let Some(max_fraction) = self.config.max_indel_fraction {
+            if clip_stat.indel_fraction(seq_len)? > max_fraction {
+                return Ok(Some(FailReason::Indel));
+            }
+        }
+        if let Some(max_fraction) = self.config.max_mismatch_fraction {
+            // the mismatches are unknown without an NM tag
+            if let Some(nm) = tags::integer_tag(record, b"NM") {
+                if clip_stat.aligned() > 0 && clip_stat.mismatch_fraction(nm)? > max_fraction {
+                    return Ok(Some(FailReason::Mismatch));
+                }
+            }
+        }
+        if let Some(min_coverage) = self.config.min_query_coverage {
+            let read_len: i64 = record_read_len(record);
+            if read_len > 0 && clip_stat.query_coverage(read_len as f64)? < min_coverage {
+                return Ok(Some(FailReason::QueryCoverage));
+            }
+        }
+        if let Some(min_block) = self.config.min_aligned_block {
+            let block: i64 =
+                longest_aligned_block(&full_raw_cigar(record), self.config.aligned_block_max_indel);
+            if (block as u64) < min_block {
+                return Ok(Some(FailReason::AlignedBlock));
+            }
+        }
+        if let Some(insert_size) = insert_size(record) {
+            if matches!(self.config.min_insert_size, Some(min) if insert_size < min)
+                || matches!(self.config.max_insert_size, Some(max) if insert_size > max)
+            {
+                return Ok(Some(FailReason::InsertSize));
+            }
+        }
+        if let Some(expression) = self.expression()? {
+            if !expression.keep(record) {
+                return Ok(Some(FailReason::Expression));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check the clipped bases of an alignment against the thresholds, or, with
+    /// `min_split_coverage`, the fraction of a split read covered by all its segments instead,
+    /// so that a chimeric read isn't failed for the clips of each of its segments
+    fn clipping_failure(
+        &self,
+        record: &Record,
+        clip_stat: &ClipStat,
+        seq_len: f64,
+    ) -> Result<Option<FailReason>, FilterClippedError> {
+        if let Some(min_coverage) = self.config.min_split_coverage {
+            // the alignments without an SA tag aren't split, and get the usual thresholds
+            if let Some(coverage) = chimeric::split_coverage(record)? {
+                return Ok((coverage < min_coverage).then_some(FailReason::SplitCoverage));
+            }
+        }
+        self.threshold_failure(record, clip_stat, seq_len)
+    }
+
+    /// Check the clipped bases against the thresholds, in the order total, 5' and 3',
+    /// or the weighted score against its cutoff in scoring mode
+    fn threshold_failure(
+        &self,
+        record: &Record,
+        clip_stat: &ClipStat,
+        seq_len: f64,
+    ) -> Result<Option<FailReason>, FilterClippedError> {
+        if self.is_rna_spliced(record) && self.config.spliced_thresholds.is_none() {
+            return Ok(None);
+        }
+        if let Some(weights) = self.config.score_weights.as_ref() {
+            let nm: i64 = tags::integer_tag(record, b"NM").unwrap_or(0);
+            let score: f64 = weights.score(
+                clip_stat.left_fraction(seq_len)?,
+                clip_stat.right_fraction(seq_len)?,
+                clip_stat.total_fraction(seq_len)?,
+                nbase_to_frac(nm, seq_len)?,
+            );
+            return Ok(match score > self.config.max_score {
+                true => Some(FailReason::Score),
+                _ => None,
+            });
+        }
+        let thresholds: Thresholds = self.thresholds(record);
+        let failure: Option<FailReason> =
+            if clip_stat.total_fraction(seq_len)? >= thresholds.both_end {
+                Some(FailReason::TotalClip)
+            } else if clip_stat.left_fraction(seq_len)? > thresholds.left_side {
+                Some(FailReason::LeftClip)
+            } else if clip_stat.right_fraction(seq_len)? > thresholds.right_side {
+                Some(FailReason::RightClip)
+            } else {
+                None
+            };
+        Ok(failure)
+    }
+}
+
+/// Iterator adapter yielding every alignment together with its [FilterDecision],
+/// see [FilterClippedExt::evaluate_clipped]
+pub struct ClipDecisionIter<'a, I> {
+    records: I,
+    clip_filter: ClipFilter<'a>,
+}
+
+impl<'a, I, E> Iterator for ClipDecisionIter<'a, I>
+where
+    I: Iterator<Item = Result<Record, E>>,
+    E: Into<FilterClippedError>,
+{
+    type Item = Result<(Record, FilterDecision), FilterClippedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record: Record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(
+            self.clip_filter
+                .evaluate(&record)
+                .map(|decision| (record, decision)),
+        )
+    }
+}
+
+/// Iterator adapter only yielding the alignments kept by the filter (passing or bypassed),
+/// see [FilterClippedExt::filter_clipped]
+pub struct ClipFilterIter<'a, I> {
+    decisions: ClipDecisionIter<'a, I>,
+}
+
+impl<'a, I, E> Iterator for ClipFilterIter<'a, I>
+where
+    I: Iterator<Item = Result<Record, E>>,
+    E: Into<FilterClippedError>,
+{
+    type Item = Result<Record, FilterClippedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.decisions.next()? {
+                Ok((record, decision)) if decision.is_kept() => return Some(Ok(record)),
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Extension methods to filter an iterator over alignments, such as
+/// [rust_htslib::bam::Read::records]
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::filter::FilterClippedExt;
+/// use rust_htslib::bam::{Read, Reader};
+///
+/// let config = FilterConfig::new().left(0.1).right(0.1).both(0.1);
+/// let mut reader = Reader::from_path("test/data/test.sam").unwrap();
+/// let kept = reader
+///     .records()
+///     .filter_clipped(&config)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(kept.len(), 6);
+/// ```
+pub trait FilterClippedExt<'a, E>: Iterator<Item = Result<Record, E>> + Sized
+where
+    E: Into<FilterClippedError>,
+{
+    /// Only yield the alignments kept by the filter
+    fn filter_clipped(self, config: &'a FilterConfig) -> ClipFilterIter<'a, Self> {
+        ClipFilterIter {
+            decisions: self.evaluate_clipped(config),
+        }
+    }
+
+    /// Yield every alignment together with its [FilterDecision]
+    fn evaluate_clipped(self, config: &'a FilterConfig) -> ClipDecisionIter<'a, Self> {
+        ClipDecisionIter {
+            records: self,
+            clip_filter: ClipFilter::new(config),
+        }
+    }
+}
+
+impl<'a, I, E> FilterClippedExt<'a, E> for I
+where
+    I: Iterator<Item = Result<Record, E>>,
+    E: Into<FilterClippedError>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipping::record_from_cigar;
+    use crate::config::{parse_length_thresholds, Preset, ScoreWeights};
+    use rstest::rstest;
+    use rust_htslib::bam::record::{Aux, CigarString};
+    use rust_htslib::bam::{Read, Reader};
+
+    #[rstest]
+    #[case("100M", 0, 60, FilterDecision::Pass)]
+    #[case("5S90M5S", 0, 60, FilterDecision::Pass)]
+    #[case("10S80M10S", 0, 60, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("15H85M", 0, 60, FilterDecision::Fail(FailReason::LeftClip))]
+    #[case("85M15S", 0, 60, FilterDecision::Fail(FailReason::RightClip))]
+    #[case("85M15S", 0x100, 60, FilterDecision::Bypass)]
+    #[case("100M", 0x800, 60, FilterDecision::Fail(FailReason::Flag))]
+    #[case("100M", 0, 0, FilterDecision::Fail(FailReason::Mapq))]
+    #[case("100M", 0x100, 0, FilterDecision::Bypass)]
+    fn test_evaluate(
+        #[case] cigar: &str,
+        #[case] flag: u16,
+        #[case] mapq: u8,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new()
+            .both(0.2)
+            .left(0.1)
+            .right(0.1)
+            .secondary(FlagPolicy::Keep)
+            .supplementary(FlagPolicy::Drop)
+            .min_mapq(10);
+        let mut record: Record = record_from_cigar(cigar);
+        record.set_flags(flag);
+        record.set_mapq(mapq);
+        let decision = ClipFilter::new(&config).evaluate(&record).unwrap();
+        assert_eq!(decision, expected);
+        assert_eq!(
+            decision.is_kept(),
+            !matches!(expected, FilterDecision::Fail(_))
+        );
+    }
+
+    #[rstest]
+    #[case(UnmappedPolicy::Keep, FilterDecision::Pass)]
+    #[case(UnmappedPolicy::Drop, FilterDecision::Fail(FailReason::Unmapped))]
+    #[case(UnmappedPolicy::Passthrough, FilterDecision::Bypass)]
+    fn test_evaluate_unmapped(#[case] unmapped: UnmappedPolicy, #[case] expected: FilterDecision) {
+        // would fail the mapping quality if it were checked
+        let config = FilterConfig::new().min_mapq(10).unmapped(unmapped);
+        let mut record = Record::new();
+        record.set(b"read", None, b"ACGT", &[30; 4]);
+        record.set_flags(0x4);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("100M", None, FilterDecision::Pass)]
+    #[case("40M5I20M5D35M", None, FilterDecision::Pass)]
+    #[case("40M5I20M5D35M", Some(0.1), FilterDecision::Pass)]
+    #[case("40M5I20M6D35M", Some(0.1), FilterDecision::Fail(FailReason::Indel))]
+    #[case(
+        "20S40M5I20M6D15M",
+        Some(0.1),
+        FilterDecision::Fail(FailReason::TotalClip)
+    )]
+    fn test_evaluate_indels(
+        #[case] cigar: &str,
+        #[case] max_indel_fraction: Option<f64>,
+        #[case] expected: FilterDecision,
+    ) {
+        let mut config = FilterConfig::new();
+        if let Some(fraction) = max_indel_fraction {
+            config = config.max_indel_fraction(fraction);
+        }
+        let mut record: Record = record_from_cigar(cigar);
+        record.set_mapq(60);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(None, None, FilterDecision::Pass)]
+    #[case(None, Some(0.05), FilterDecision::Pass)]
+    #[case(Some(5), Some(0.05), FilterDecision::Pass)]
+    #[case(Some(10), Some(0.05), FilterDecision::Pass)]
+    #[case(Some(11), Some(0.05), FilterDecision::Fail(FailReason::Mismatch))]
+    fn test_evaluate_mismatches(
+        #[case] nm: Option<u8>,
+        #[case] max_mismatch_fraction: Option<f64>,
+        #[case] expected: FilterDecision,
+    ) {
+        let mut config = FilterConfig::new();
+        if let Some(fraction) = max_mismatch_fraction {
+            config = config.max_mismatch_fraction(fraction);
+        }
+        // 100 aligned bases and 5 indel bases
+        let cigar = CigarString::try_from("50M3I50M2D").unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 103], &[30; 103]);
+        record.set_mapq(60);
+        if let Some(nm) = nm {
+            record.push_aux(b"NM", Aux::U8(nm)).unwrap();
+        }
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("100M", 0.9, FilterDecision::Pass)]
+    #[case("5S90M5S", 0.9, FilterDecision::Pass)]
+    #[case("5H40M5I40M5S5H", 0.9, FilterDecision::Fail(FailReason::QueryCoverage))]
+    #[case("40S60M", 0.5, FilterDecision::Pass)]
+    #[case("60S40M", 0.5, FilterDecision::Fail(FailReason::QueryCoverage))]
+    fn test_evaluate_query_coverage(
+        #[case] cigar: &str,
+        #[case] min_query_coverage: f64,
+        #[case] expected: FilterDecision,
+    ) {
+        // the clipping thresholds are off, only the aligned fraction counts
+        let config = FilterConfig::new()
+            .both(1.0)
+            .left(1.0)
+            .right(1.0)
+            .min_query_coverage(min_query_coverage);
+        let mut record: Record = record_from_cigar(cigar);
+        record.set_mapq(60);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("60M40S", None, 0.9, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("60M40S", Some("chr1,500,+,65H30M5H,60,0;"), 0.9, FilterDecision::Pass)]
+    #[case(
+        "60M40S",
+        Some("chr1,500,+,65H30M5H,60,0;"),
+        0.95,
+        FilterDecision::Fail(FailReason::SplitCoverage)
+    )]
+    #[case("60M40S", Some("chr1,500,-,5H30M65H,60,0;"), 0.9, FilterDecision::Pass)]
+    #[case(
+        "60M40S",
+        Some("chr1,500,+,40H30M30H,60,0;"),
+        0.9,
+        FilterDecision::Fail(FailReason::SplitCoverage)
+    )]
+    fn test_evaluate_split_coverage(
+        #[case] cigar: &str,
+        #[case] sa: Option<&str>,
+        #[case] min_coverage: f64,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().min_split_coverage(min_coverage);
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_mapq(60);
+        if let Some(sa) = sa {
+            record.push_aux(b"SA", Aux::String(sa)).unwrap();
+        }
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("100M", 100, 5, FilterDecision::Pass)]
+    #[case("50M2I48M", 90, 5, FilterDecision::Pass)]
+    #[case("50M2I48M", 90, 1, FilterDecision::Fail(FailReason::AlignedBlock))]
+    #[case("4S45M20D45M4S", 60, 5, FilterDecision::Fail(FailReason::AlignedBlock))]
+    fn test_evaluate_aligned_block(
+        #[case] cigar: &str,
+        #[case] min_block: u64,
+        #[case] max_indel: u32,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().min_aligned_block(min_block, max_indel);
+        let mut record: Record = record_from_cigar(cigar);
+        record.set_mapq(60);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(0x1, 300, FilterDecision::Pass)]
+    #[case(0x1, -300, FilterDecision::Pass)]
+    #[case(0x1, 50, FilterDecision::Fail(FailReason::InsertSize))]
+    #[case(0x1, -5000, FilterDecision::Fail(FailReason::InsertSize))]
+    #[case(0x1, 0, FilterDecision::Pass)]
+    #[case(0x1 | 0x8, 5000, FilterDecision::Pass)]
+    #[case(0x0, 5000, FilterDecision::Pass)]
+    fn test_evaluate_insert_size(
+        #[case] flag: u16,
+        #[case] tlen: i64,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().insert_size(Some(100), Some(1000));
+        let cigar = CigarString::try_from("100M").unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(flag);
+        record.set_insert_size(tlen);
+        record.set_mapq(60);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(None, 0x0, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case(None, 0x10, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case(Some(Strand::Fwd), 0x0, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case(Some(Strand::Fwd), 0x10, FilterDecision::Bypass)]
+    #[case(Some(Strand::Rev), 0x0, FilterDecision::Bypass)]
+    #[case(Some(Strand::Rev), 0x10, FilterDecision::Fail(FailReason::TotalClip))]
+    fn test_evaluate_only_strand(
+        #[case] only_strand: Option<Strand>,
+        #[case] flag: u16,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig {
+            only_strand,
+            ..FilterConfig::default()
+        };
+        let cigar = CigarString::try_from("60S40M").unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(flag);
+        record.set_mapq(60);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(0x1 | 0x2, false, FilterDecision::Pass)]
+    #[case(0x1 | 0x2, true, FilterDecision::Pass)]
+    #[case(0x1, false, FilterDecision::Pass)]
+    #[case(0x1, true, FilterDecision::Fail(FailReason::ProperPair))]
+    #[case(0x0, true, FilterDecision::Fail(FailReason::ProperPair))]
+    #[case(0x1 | 0x100, true, FilterDecision::Fail(FailReason::ProperPair))]
+    fn test_evaluate_proper_pair(
+        #[case] flag: u16,
+        #[case] require_proper_pair: bool,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().require_proper_pair(require_proper_pair);
+        let cigar = CigarString::try_from("100M").unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(flag);
+        record.set_mapq(60);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("30H70M", 0x800, FilterDecision::Pass)]
+    #[case("30H60M10S", 0x800, FilterDecision::Pass)]
+    #[case("30H55M15S", 0x800, FilterDecision::Fail(FailReason::RightClip))]
+    #[case("30H70M", 0, FilterDecision::Fail(FailReason::TotalClip))]
+    fn test_evaluate_long_read(
+        #[case] cigar: &str,
+        #[case] flag: u16,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().preset(Preset::LongRead);
+        let mut record: Record = record_from_cigar(cigar);
+        record.set_flags(flag);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("AAAAAAAAAAAAAAA", false, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("AAAAAAAAAAAAAAA", true, FilterDecision::Pass)]
+    #[case("CACACACACACACAC", true, FilterDecision::Pass)]
+    #[case("AGATCGGAAGAGCAC", true, FilterDecision::Fail(FailReason::TotalClip))]
+    fn test_evaluate_low_complexity(
+        #[case] clipped: &str,
+        #[case] exempt_low_complexity: bool,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().exempt_low_complexity(exempt_low_complexity);
+        let cigar = CigarString::try_from("85M15S").unwrap();
+        let seq: Vec<u8> = [
+            b"ACGT".repeat(22)[..85].to_vec(),
+            clipped.as_bytes().to_vec(),
+        ]
+        .concat();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &seq, &[30; 100]);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("TTAGGGTTAGGGTTA", vec![], FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("TTAGGGTTAGGGTTA", vec!["TTAGGG"], FilterDecision::Pass)]
+    #[case("CCCTAACCCTAACCC", vec!["TTAGGG"], FilterDecision::Pass)]
+    #[case("TTAGGGTTACCGTTA", vec!["TTAGGG"], FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("TTAGGGTTAGGGTTA", vec!["AATGG"], FilterDecision::Fail(FailReason::TotalClip))]
+    fn test_evaluate_motif_exemption(
+        #[case] clipped: &str,
+        #[case] motifs: Vec<&str>,
+        #[case] expected: FilterDecision,
+    ) {
+        let motifs: Vec<String> = motifs.into_iter().map(String::from).collect();
+        let config = FilterConfig::new().exempt_motifs(motifs, 0.9);
+        let cigar = CigarString::try_from("85M15S").unwrap();
+        let seq: Vec<u8> = [
+            b"ACGT".repeat(22)[..85].to_vec(),
+            clipped.as_bytes().to_vec(),
+        ]
+        .concat();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &seq, &[30; 100]);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(0, 15, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case(20, 15, FilterDecision::Pass)]
+    #[case(20, 8, FilterDecision::Pass)]
+    #[case(20, 5, FilterDecision::Fail(FailReason::TotalClip))]
+    fn test_evaluate_min_clip_bq(
+        #[case] min_clip_bq: u8,
+        #[case] low_bq_bases: usize,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new().min_clip_bq(min_clip_bq);
+        let cigar = CigarString::try_from("15S85M").unwrap();
+        let mut qual: Vec<u8> = vec![30; 100];
+        qual[..low_bq_bases].fill(2);
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &qual);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("15S85M", None, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("15S40M500N45M", None, FilterDecision::Pass)]
+    #[case("15S85M", Some("0.2"), FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("15S40M500N45M", Some("0.2"), FilterDecision::Pass)]
+    #[case(
+        "25S30M500N45M",
+        Some("0.2"),
+        FilterDecision::Fail(FailReason::TotalClip)
+    )]
+    fn test_evaluate_rna(
+        #[case] cigar: &str,
+        #[case] spliced_thresholds: Option<&str>,
+        #[case] expected: FilterDecision,
+    ) {
+        let config = FilterConfig::new()
+            .rna(spliced_thresholds.map(|spec| Thresholds::parse(spec).unwrap()));
+        let record: Record = record_from_cigar(cigar);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("10S90M", FilterDecision::Pass)]
+    #[case("15S85M", FilterDecision::Fail(FailReason::TotalClip))]
+    #[case("10H5S90M", FilterDecision::Pass)]
+    #[case("20S980M", FilterDecision::Pass)]
+    #[case("60S940M", FilterDecision::Fail(FailReason::LeftClip))]
+    fn test_evaluate_length_thresholds(#[case] cigar: &str, #[case] expected: FilterDecision) {
+        let config = FilterConfig::new().length_thresholds(
+            parse_length_thresholds("0-101:0.15,101-500:0.2/0.15/0.15,500-:0.1/0.05/0.05").unwrap(),
+        );
+        let record: Record = record_from_cigar(cigar);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(0x0, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case(0x1 | 0x40, FilterDecision::Fail(FailReason::TotalClip))]
+    #[case(0x1 | 0x80, FilterDecision::Pass)]
+    #[case(0x40, FilterDecision::Fail(FailReason::TotalClip))]
+    fn test_evaluate_mate_thresholds(#[case] flag: u16, #[case] expected: FilterDecision) {
+        let config = FilterConfig::new().read2_thresholds(Thresholds {
+            both_end: 0.5,
+            left_side: 0.1,
+            right_side: 0.5,
+        });
+        let cigar = CigarString::try_from("70M30S").unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(flag);
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("10S90M", 0, FilterDecision::Pass)]
+    #[case("10S80M10S", 0, FilterDecision::Fail(FailReason::Score))]
+    #[case("5S90M5S", 0, FilterDecision::Pass)]
+    #[case("5S90M5S", 5, FilterDecision::Fail(FailReason::Score))]
+    fn test_evaluate_score(#[case] cigar: &str, #[case] nm: u8, #[case] expected: FilterDecision) {
+        let weights = ScoreWeights::parse("left=0.5,right=0.5,total=0.5,nm=1").unwrap();
+        let config = FilterConfig::new().score(weights, 0.1);
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.push_aux(b"NM", Aux::U8(nm)).unwrap();
+        assert_eq!(
+            ClipFilter::new(&config).evaluate(&record).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case(0.1, false, 6)]
+    #[case(0.2, false, 9)]
+    #[case(0.1, true, 9)]
+    fn test_filter_clipped(
+        #[case] threshold: f64,
+        #[case] with_decisions: bool,
+        #[case] expected: usize,
+    ) {
+        let config = FilterConfig::new()
+            .left(threshold)
+            .right(threshold)
+            .both(threshold);
+        let mut reader = Reader::from_path("test/data/test.sam").unwrap();
+        let count = match with_decisions {
+            true => reader
+                .records()
+                .evaluate_clipped(&config)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .len(),
+            _ => reader
+                .records()
+                .filter_clipped(&config)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .len(),
+        };
+        assert_eq!(count, expected);
+    }
+
+    #[test]
+    fn test_evaluate_clipped_reasons() {
+        let config = FilterConfig::new();
+        let mut reader = Reader::from_path("test/data/test.sam").unwrap();
+        let failed: Vec<FilterDecision> = reader
+            .records()
+            .evaluate_clipped(&config)
+            .map(|r| r.unwrap().1)
+            .filter(|decision| !decision.is_kept())
+            .collect();
+        assert_eq!(failed, vec![FilterDecision::Fail(FailReason::TotalClip); 3]);
+    }
+
+    #[rstest]
+    #[case("mapq >= 30", Some(FilterDecision::Pass))]
+    #[case("mapq >= 70", Some(FilterDecision::Fail(FailReason::Expression)))]
+    #[case("mapq >=", None)]
+    fn test_evaluate_expression(
+        #[case] expression: &str,
+        #[case] expected: Option<FilterDecision>,
+    ) {
+        // not checked with FilterConfig::validate, a bad expression fails the evaluation
+        let config = FilterConfig::new().expression(expression);
+        let mut record: Record = record_from_cigar("100M");
+        record.set_mapq(60);
+        assert_eq!(ClipFilter::new(&config).evaluate(&record).ok(), expected);
+    }
+}
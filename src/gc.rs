@@ -0,0 +1,170 @@
+use crate::error::FilterClippedError;
+use crate::histogram::{fraction_bin, FRACTION_BINS};
+use rust_htslib::bam::{record::CigarStringView, Record};
+use std::io::Write;
+
+/// GC content of a sequence, over its A/C/G/T bases (N and other codes are left out);
+/// None if it has none
+///
+/// # Example
+/// ```
+/// use filter_clipped::gc::gc_fraction;
+/// assert_eq!(gc_fraction(b"ACGT"), Some(0.5));
+/// assert_eq!(gc_fraction(b"ggcN"), Some(1.0));
+/// assert_eq!(gc_fraction(b"NN"), None);
+/// ```
+pub fn gc_fraction(seq: &[u8]) -> Option<f64> {
+    let (gc, total) = seq.iter().fold((0usize, 0usize), |(gc, total), base| {
+        match base.to_ascii_uppercase() {
+            b'G' | b'C' => (gc + 1, total + 1),
+            b'A' | b'T' => (gc, total + 1),
+            _ => (gc, total),
+        }
+    });
+    match total {
+        0 => None,
+        _ => Some(gc as f64 / total as f64),
+    }
+}
+
+/// Binned GC content of the soft clipped and the aligned sequence of the evaluated
+/// alignments: adapter clips pile up at the fixed GC content of the adapter, while
+/// genomic clips follow the GC content of the aligned sequence
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GcProfile {
+    /// counts of the GC content of the soft clipped bases (both ends together), by bin
+    clipped: Vec<u64>,
+    /// counts of the GC content of the rest of the sequence, by bin
+    aligned: Vec<u64>,
+}
+
+impl Default for GcProfile {
+    fn default() -> Self {
+        Self {
+            clipped: vec![0; FRACTION_BINS],
+            aligned: vec![0; FRACTION_BINS],
+        }
+    }
+}
+
+impl GcProfile {
+    /// Count the GC content of the soft clipped and aligned sequence of an alignment;
+    /// alignments without soft clips only count in the aligned sequence
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::gc::GcProfile;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    /// let mut profile = GcProfile::default();
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("4S4M").unwrap();
+    /// record.set(b"read", Some(&cigar), b"GGCCATAT", &[30; 8]);
+    /// profile.add(&record);
+    /// assert_eq!(profile.clipped_len(), 1);
+    /// assert_eq!(profile.aligned_len(), 1);
+    /// ```
+    pub fn add(&mut self, record: &Record) {
+        let seq: Vec<u8> = record.seq().as_bytes();
+        let cigar: CigarStringView = record.cigar();
+        let leading: usize = (cigar.leading_softclips() as usize).min(seq.len());
+        let trailing: usize = (cigar.trailing_softclips() as usize).min(seq.len() - leading);
+        let aligned_end: usize = seq.len() - trailing;
+        let clipped: Vec<u8> = [&seq[..leading], &seq[aligned_end..]].concat();
+        if let Some(gc) = gc_fraction(&clipped) {
+            self.clipped[fraction_bin(gc)] += 1;
+        }
+        if let Some(gc) = gc_fraction(&seq[leading..aligned_end]) {
+            self.aligned[fraction_bin(gc)] += 1;
+        }
+    }
+
+    /// Add the counts of another profile, e.g. of another part of the input
+    pub fn merge(&mut self, other: &GcProfile) {
+        for (counts, other_counts) in [
+            (&mut self.clipped, &other.clipped),
+            (&mut self.aligned, &other.aligned),
+        ] {
+            for (count, other_count) in counts.iter_mut().zip(other_counts.iter()) {
+                *count += other_count;
+            }
+        }
+    }
+
+    /// Number of alignments with soft clipped bases counted
+    pub fn clipped_len(&self) -> u64 {
+        self.clipped.iter().sum()
+    }
+
+    /// Number of alignments with aligned bases counted
+    pub fn aligned_len(&self) -> u64 {
+        self.aligned.iter().sum()
+    }
+
+    /// Write the profile as a long-format TSV: one line per segment (`clipped` or `aligned`)
+    /// and bin, with the start (included) and end (excluded) of the bin and the count
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the TSV
+    pub fn write_tsv<W: Write>(&self, writer: &mut W) -> Result<(), FilterClippedError> {
+        writeln!(writer, "segment\tbin_start\tbin_end\tcount")?;
+        for (segment, counts) in [("clipped", &self.clipped), ("aligned", &self.aligned)] {
+            for (bin, count) in counts.iter().enumerate() {
+                writeln!(
+                    writer,
+                    "{}\t{:.2}\t{:.2}\t{}",
+                    segment,
+                    bin as f64 / FRACTION_BINS as f64,
+                    (bin + 1) as f64 / FRACTION_BINS as f64,
+                    count
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
+
+    #[rstest]
+    #[case("8M", "GGCCATAT", None, Some(50))]
+    #[case("4S4M", "GGCCATAT", Some(99), Some(0))]
+    #[case("2S4M2S", "GCATATGC", Some(99), Some(0))]
+    #[case("2S6M", "NNATGCGC", None, Some(66))]
+    #[case("8S", "ATATATAT", Some(0), None)]
+    fn test_add(
+        #[case] cigar: &str,
+        #[case] seq: &str,
+        #[case] clipped_bin: Option<usize>,
+        #[case] aligned_bin: Option<usize>,
+    ) {
+        let mut profile = GcProfile::default();
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), seq.as_bytes(), &vec![30; seq.len()]);
+        profile.add(&record);
+        let bins = |counts: &[u64]| counts.iter().position(|count| *count > 0);
+        assert_eq!(bins(&profile.clipped), clipped_bin);
+        assert_eq!(bins(&profile.aligned), aligned_bin);
+    }
+
+    #[test]
+    fn test_write_tsv() {
+        let mut profile = GcProfile::default();
+        profile.clipped[25] = 2;
+        profile.merge(&profile.clone());
+        let mut tsv: Vec<u8> = Vec::new();
+        profile.write_tsv(&mut tsv).unwrap();
+        let text: String = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * FRACTION_BINS);
+        assert!(lines.contains(&"clipped\t0.25\t0.26\t4"));
+        assert!(lines.contains(&"aligned\t0.25\t0.26\t0"));
+    }
+}
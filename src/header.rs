@@ -0,0 +1,850 @@
+use crate::config::{FilterConfig, Preset};
+use crate::error::FilterClippedError;
+use crate::subsample::fnv1a;
+use rust_htslib::bam::{header::HeaderRecord, Header, HeaderView};
+use serde::Serialize;
+use std::time::SystemTime;
+
+/// Name of this program as written to the @PG record
+pub const PROGRAM_NAME: &str = env!("CARGO_PKG_NAME");
+/// Version of this program as written to the @PG record
+pub const PROGRAM_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Start of the DS field of the @PG record, followed by the fingerprint of the settings
+const SETTINGS_PREFIX: &str = "settings=";
+
+/// Settings left out of [settings_fingerprint]: the files read and written, the reports
+/// and side outputs, and how the run is carried out, none of which change what is filtered
+const UNFINGERPRINTED: [&str; 41] = [
+    "in_bam",
+    "extra_inputs",
+    "out_bam",
+    "unaligned_out",
+    "command_line",
+    "header_comment",
+    "if_already_filtered",
+    "output_fmt",
+    "reference",
+    "write_index",
+    "region_threads",
+    "region_size",
+    "unordered",
+    "shard_by_chrom",
+    "split_by_reason",
+    "metrics_out",
+    "metrics_interval",
+    "require_sorted",
+    "fail_if_empty",
+    "fail_if_removed_frac",
+    "progress",
+    "flagstat",
+    "hist_out",
+    "html_report",
+    "clip_profile",
+    "profile_window",
+    "breakpoint_bed",
+    "min_breakpoint_support",
+    "clip_consensus",
+    "min_consensus_support",
+    "gc_out",
+    "kmer_out",
+    "kmer_size",
+    "top_kmers",
+    "cell_stats",
+    "removed_names",
+    "removed_details",
+    "metrics_parquet",
+    "junction_bedpe",
+    "min_junction_clip",
+    "collate_max_pending",
+];
+
+/// Collect the ID and PP fields of all @PG records in a header
+///
+/// # Arguments
+/// * `header_text`: SAM header text
+///
+/// # Return
+/// * a list of (ID, PP) for each @PG record, in the order they appear in the header
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::program_records;
+/// let header = b"@HD\tVN:1.6\n@PG\tID:bwa\tPN:bwa\n@PG\tID:samtools\tPP:bwa\n";
+/// assert_eq!(
+///     program_records(header),
+///     vec![
+///         ("bwa".to_string(), None),
+///         ("samtools".to_string(), Some("bwa".to_string())),
+///     ]
+/// );
+/// ```
+pub fn program_records(header_text: &[u8]) -> Vec<(String, Option<String>)> {
+    String::from_utf8_lossy(header_text)
+        .lines()
+        .filter(|line| line.starts_with("@PG\t"))
+        .filter_map(|line| {
+            let mut id: Option<String> = None;
+            let mut previous: Option<String> = None;
+            for field in line.split('\t').skip(1) {
+                if let Some(value) = field.strip_prefix("ID:") {
+                    id = Some(value.to_string());
+                } else if let Some(value) = field.strip_prefix("PP:") {
+                    previous = Some(value.to_string());
+                }
+            }
+            id.map(|id| (id, previous))
+        })
+        .collect()
+}
+
+/// Work out where a new @PG record goes in the program chain
+///
+/// # Arguments
+/// * `programs`: (ID, PP) of the existing @PG records, see [program_records]
+/// * `name`: the preferred ID for the new record
+///
+/// # Return
+/// * (ID, PP) for the new record: the ID is made unique by appending `.1`, `.2`, ... if needed,
+///   and PP is the last program of the chain (a program no other program points to), if any
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::next_program;
+/// let programs = vec![
+///     ("filter-clipped".to_string(), None),
+///     ("samtools".to_string(), Some("filter-clipped".to_string())),
+/// ];
+/// assert_eq!(
+///     next_program(&programs, "filter-clipped"),
+///     ("filter-clipped.1".to_string(), Some("samtools".to_string()))
+/// );
+/// ```
+pub fn next_program(programs: &[(String, Option<String>)], name: &str) -> (String, Option<String>) {
+    let taken = |id: &str| programs.iter().any(|(existing, _)| existing == id);
+    let mut id: String = name.to_string();
+    let mut suffix: usize = 0;
+    while taken(&id) {
+        suffix += 1;
+        id = format!("{}.{}", name, suffix);
+    }
+
+    let previous: Option<String> = programs
+        .iter()
+        .rev()
+        .find(|(candidate, _)| {
+            !programs
+                .iter()
+                .any(|(_, pp)| pp.as_deref() == Some(candidate.as_str()))
+        })
+        .map(|(candidate, _)| candidate.clone());
+    (id, previous)
+}
+
+/// Fingerprint of the settings deciding what is filtered (see [UNFINGERPRINTED] for the
+/// ones left out), written to the DS field of the @PG record so that a later run can tell
+/// its input was already filtered the same way
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::header::settings_fingerprint;
+/// let config = FilterConfig::new();
+/// let fingerprint = settings_fingerprint(&config).unwrap();
+/// assert_eq!(fingerprint.len(), 16);
+/// assert_eq!(fingerprint, settings_fingerprint(&config.clone().out_bam("out.bam")).unwrap());
+/// assert_ne!(fingerprint, settings_fingerprint(&config.left(0.2)).unwrap());
+/// ```
+pub fn settings_fingerprint(config: &FilterConfig) -> Result<String, FilterClippedError> {
+    let mut settings: serde_json::Value = serde_json::to_value(config)?;
+    if let Some(fields) = settings.as_object_mut() {
+        for field in UNFINGERPRINTED.iter() {
+            fields.remove(*field);
+        }
+        // the file the singletons are written into is an output too, only the policy counts
+        if let Some(singletons) = fields.get_mut("singletons") {
+            if singletons.get("separate-out").is_some() {
+                *singletons = serde_json::Value::from("separate-out");
+            }
+        }
+    }
+    Ok(format!("{:016x}", fnv1a(settings.to_string().as_bytes())))
+}
+
+/// Fingerprints of the settings of the earlier runs of this program recorded in a header,
+/// see [settings_fingerprint]
+///
+/// # Arguments
+/// * `header_text`: SAM header text
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::previous_settings;
+/// let header = b"@PG\tID:filter-clipped\tPN:filter-clipped\tDS:settings=00ff\n\
+///                @PG\tID:samtools\tPN:samtools\tDS:settings=0a0a\n";
+/// assert_eq!(previous_settings(header), vec!["00ff".to_string()]);
+/// ```
+pub fn previous_settings(header_text: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(header_text)
+        .lines()
+        .filter(|line| line.starts_with("@PG\t"))
+        .filter(|line| header_field(line, "PN") == Some(PROGRAM_NAME))
+        .filter_map(|line| header_field(line, "DS")?.strip_prefix(SETTINGS_PREFIX))
+        .map(|fingerprint| fingerprint.to_string())
+        .collect()
+}
+
+/// Append a @PG record for this program to the output header, chained to the
+/// last program in the input header
+///
+/// # Arguments
+/// * `header`: output header to add the record to
+/// * `template`: input header
+/// * `command_line`: the full command line, written to the CL field if given
+/// * `settings`: fingerprint of the settings (see [settings_fingerprint]), written to the
+///   DS field if given
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::add_program_record;
+/// use rust_htslib::bam::{header::HeaderRecord, Header, HeaderView};
+///
+/// let mut input = Header::new();
+/// input.push_record(HeaderRecord::new(b"PG").push_tag(b"ID", &"bwa"));
+/// let template = HeaderView::from_header(&input);
+/// let mut header = Header::from_template(&template);
+/// add_program_record(
+///     &mut header,
+///     &template,
+///     Some("filter-clipped -i in.bam"),
+///     Some("0123456789abcdef"),
+/// );
+/// let text = String::from_utf8(header.to_bytes()).unwrap();
+/// assert!(text.contains("@PG\tID:filter-clipped\tPN:filter-clipped"));
+/// assert!(text.contains("PP:bwa"));
+/// assert!(text.contains("CL:filter-clipped -i in.bam"));
+/// assert!(text.contains("DS:settings=0123456789abcdef"));
+/// ```
+pub fn add_program_record(
+    header: &mut Header,
+    template: &HeaderView,
+    command_line: Option<&str>,
+    settings: Option<&str>,
+) {
+    let programs = program_records(template.as_bytes());
+    let (id, previous) = next_program(&programs, PROGRAM_NAME);
+    let name: String = PROGRAM_NAME.to_string();
+    let version: String = PROGRAM_VERSION.to_string();
+    let command_line: Option<String> = command_line.map(|cl| cl.to_string());
+
+    let mut record = HeaderRecord::new(b"PG");
+    record
+        .push_tag(b"ID", &id)
+        .push_tag(b"PN", &name)
+        .push_tag(b"VN", &version);
+    if let Some(previous) = previous.as_ref() {
+        record.push_tag(b"PP", previous);
+    }
+    if let Some(command_line) = command_line.as_ref() {
+        record.push_tag(b"CL", command_line);
+    }
+    if let Some(settings) = settings {
+        record.push_tag(b"DS", format!("{}{}", SETTINGS_PREFIX, settings));
+    }
+    header.push_record(&record);
+}
+
+/// Aligner that made an alignment file, see [detect_aligner]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aligner {
+    /// bwa (bwa mem, bwa-mem2)
+    Bwa,
+    /// bowtie2
+    Bowtie2,
+    /// minimap2
+    Minimap2,
+    /// STAR
+    Star,
+}
+
+impl Aligner {
+    /// The preset of the thresholds expected to suit the clipping of the alignments of
+    /// this aligner
+    pub fn preset(&self) -> Preset {
+        match self {
+            Aligner::Bwa | Aligner::Bowtie2 => Preset::ShortRead,
+            Aligner::Minimap2 => Preset::LongRead,
+            Aligner::Star => Preset::Rna,
+        }
+    }
+
+    /// The aligner with a program name (PN or ID of a @PG record), if it's a known one
+    fn from_program(name: &str) -> Option<Self> {
+        let name: String = name.to_ascii_lowercase();
+        match name.as_str() {
+            name if name.starts_with("bwa") => Some(Aligner::Bwa),
+            name if name.starts_with("bowtie2") => Some(Aligner::Bowtie2),
+            name if name.starts_with("minimap2") => Some(Aligner::Minimap2),
+            name if name.starts_with("star") => Some(Aligner::Star),
+            _ => None,
+        }
+    }
+}
+
+/// Find the aligner that made an alignment file from its @PG records: the first one naming a
+/// known aligner in its PN field (or its ID without one)
+///
+/// # Arguments
+/// * `header_text`: SAM header text
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::{detect_aligner, Aligner};
+/// let header = b"@HD\tVN:1.6\n@PG\tID:mm2\tPN:minimap2\tVN:2.24\n@PG\tID:samtools\tPP:mm2\n";
+/// assert_eq!(detect_aligner(header), Some(Aligner::Minimap2));
+/// assert_eq!(detect_aligner(b"@PG\tID:bwa-mem2\n"), Some(Aligner::Bwa));
+/// assert_eq!(detect_aligner(b"@PG\tID:samtools\tPN:samtools\n"), None);
+/// ```
+pub fn detect_aligner(header_text: &[u8]) -> Option<Aligner> {
+    String::from_utf8_lossy(header_text)
+        .lines()
+        .filter(|line| line.starts_with("@PG\t"))
+        .find_map(|line| {
+            header_field(line, "PN")
+                .or_else(|| header_field(line, "ID"))
+                .and_then(Aligner::from_program)
+        })
+}
+
+/// Sort order of an alignment file, as declared in the @HD header record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// no @HD record or no SO field
+    Unknown,
+    /// SO:unsorted without grouping
+    Unsorted,
+    /// SO:queryname
+    Queryname,
+    /// SO:coordinate
+    Coordinate,
+    /// not sorted, but alignments of the same read are grouped together (GO:query)
+    Collated,
+}
+
+impl SortOrder {
+    /// Value of the SO field for this sort order
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Unknown => "unknown",
+            SortOrder::Unsorted | SortOrder::Collated => "unsorted",
+            SortOrder::Queryname => "queryname",
+            SortOrder::Coordinate => "coordinate",
+        }
+    }
+
+    /// Whether all alignments sharing a read name are next to each other
+    pub fn is_grouped_by_name(&self) -> bool {
+        matches!(self, SortOrder::Queryname | SortOrder::Collated)
+    }
+}
+
+/// Read the sort order from the @HD record of a header
+///
+/// # Arguments
+/// * `header_text`: SAM header text
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::{sort_order, SortOrder};
+/// assert_eq!(sort_order(b"@HD\tVN:1.6\tSO:coordinate\n"), SortOrder::Coordinate);
+/// assert_eq!(sort_order(b"@HD\tVN:1.6\tSO:unsorted\tGO:query\n"), SortOrder::Collated);
+/// assert_eq!(sort_order(b"@SQ\tSN:chr1\tLN:100\n"), SortOrder::Unknown);
+/// ```
+pub fn sort_order(header_text: &[u8]) -> SortOrder {
+    let text = String::from_utf8_lossy(header_text);
+    let hd_line = match text.lines().find(|line| line.starts_with("@HD\t")) {
+        Some(line) => line,
+        None => return SortOrder::Unknown,
+    };
+    let mut order = SortOrder::Unknown;
+    let mut grouped = false;
+    for field in hd_line.split('\t').skip(1) {
+        match field {
+            "SO:coordinate" => order = SortOrder::Coordinate,
+            "SO:queryname" => order = SortOrder::Queryname,
+            "SO:unsorted" => order = SortOrder::Unsorted,
+            "GO:query" => grouped = true,
+            _ => (),
+        }
+    }
+    match (order, grouped) {
+        (SortOrder::Unsorted | SortOrder::Unknown, true) => SortOrder::Collated,
+        _ => order,
+    }
+}
+
+/// Rewrite the SO field of the @HD record (dropping any SS sub-sort field, which no longer applies)
+///
+/// # Arguments
+/// * `header_text`: SAM header text
+/// * `order`: the new sort order
+///
+/// # Return
+/// * the updated header text, a @HD record is added if there is none
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::{set_sort_order, SortOrder};
+/// let header = b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:100\n";
+/// assert_eq!(
+///     set_sort_order(header, SortOrder::Unsorted),
+///     b"@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:100\n".to_vec()
+/// );
+/// ```
+pub fn set_sort_order(header_text: &[u8], order: SortOrder) -> Vec<u8> {
+    let text = String::from_utf8_lossy(header_text);
+    let mut lines: Vec<String> = Vec::new();
+    let mut found = false;
+    for line in text.lines() {
+        if !found && line.starts_with("@HD\t") {
+            found = true;
+            let mut fields: Vec<String> = line
+                .split('\t')
+                .filter(|field| !field.starts_with("SO:") && !field.starts_with("SS:"))
+                .map(|field| field.to_string())
+                .collect();
+            if order != SortOrder::Collated {
+                fields.retain(|field| !field.starts_with("GO:"));
+            } else if !fields.iter().any(|field| field.starts_with("GO:")) {
+                fields.push(String::from("GO:query"));
+            }
+            fields.insert(2.min(fields.len()), format!("SO:{}", order.as_str()));
+            lines.push(fields.join("\t"));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !found {
+        let grouping: &str = match order {
+            SortOrder::Collated => "\tGO:query",
+            _ => "",
+        };
+        lines.insert(0, format!("@HD\tVN:1.6\tSO:{}{}", order.as_str(), grouping));
+    }
+    let mut updated: Vec<u8> = lines.join("\n").into_bytes();
+    updated.push(b'\n');
+    updated
+}
+
+/// Header of several input files merged into one, see [merge_headers]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedHeader {
+    /// SAM header text of the merged header
+    pub text: Vec<u8>,
+    /// for each input, the tid in the merged header of each of its reference sequences
+    pub tid_maps: Vec<Vec<i32>>,
+}
+
+/// Value of a field of a SAM header line, e.g. the SN of a @SQ record
+fn header_field<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    line.split('\t')
+        .skip(1)
+        .find_map(|field| field.strip_prefix(tag)?.strip_prefix(':'))
+}
+
+/// Merge the headers of several input files, to filter them into a single output:
+/// the @SQ records are the union of the reference sequences (in the order they are first seen),
+/// and so are the @RG, @PG and @CO records. The @HD record and any other records come from the
+/// first header. The merged output is collated (GO:query) if all inputs are grouped by read name,
+/// and unsorted otherwise
+///
+/// # Arguments
+/// * `headers`: SAM header text of each input
+///
+/// # Return
+/// * the merged header, or an error if a reference sequence has different lengths or a read
+///   group ID different records in two of the inputs
+///
+/// # Example
+/// ```
+/// use filter_clipped::header::merge_headers;
+/// let merged = merge_headers(&[
+///     b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:100\n@RG\tID:lane1\n".to_vec(),
+///     b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr2\tLN:50\n@SQ\tSN:chr1\tLN:100\n@RG\tID:lane2\n".to_vec(),
+/// ])
+/// .unwrap();
+/// assert_eq!(
+///     String::from_utf8(merged.text).unwrap(),
+///     "@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:100\n@SQ\tSN:chr2\tLN:50\n\
+///      @RG\tID:lane1\n@RG\tID:lane2\n"
+/// );
+/// assert_eq!(merged.tid_maps, vec![vec![0], vec![1, 0]]);
+/// ```
+pub fn merge_headers(headers: &[Vec<u8>]) -> Result<MergedHeader, FilterClippedError> {
+    let mut hd: Option<String> = None;
+    let mut sequences: Vec<(String, String)> = Vec::new();
+    let mut read_groups: Vec<(String, String)> = Vec::new();
+    let mut programs: Vec<(String, String)> = Vec::new();
+    let mut comments: Vec<String> = Vec::new();
+    let mut others: Vec<String> = Vec::new();
+    let mut tid_maps: Vec<Vec<i32>> = Vec::with_capacity(headers.len());
+    let mut grouped: bool = true;
+    for (i, header_text) in headers.iter().enumerate() {
+        grouped &= sort_order(header_text).is_grouped_by_name();
+        let text = String::from_utf8_lossy(header_text);
+        let mut tid_map: Vec<i32> = Vec::new();
+        for line in text.lines() {
+            let conflict = |what: &str, id: &str| {
+                FilterClippedError::InvalidConfig(format!(
+                    "input {} has a different {} record for {} than an earlier input",
+                    i + 1,
+                    what,
+                    id
+                ))
+            };
+            if line.starts_with("@SQ\t") {
+                let name: &str = header_field(line, "SN").unwrap_or_default();
+                let length: Option<&str> = header_field(line, "LN");
+                match sequences.iter().position(|(existing, _)| existing == name) {
+                    Some(tid) if header_field(&sequences[tid].1, "LN") != length => {
+                        return Err(conflict("@SQ length", name));
+                    }
+                    Some(tid) => tid_map.push(tid as i32),
+                    None => {
+                        tid_map.push(sequences.len() as i32);
+                        sequences.push((name.to_string(), line.to_string()));
+                    }
+                }
+            } else if line.starts_with("@RG\t") {
+                let id: &str = header_field(line, "ID").unwrap_or_default();
+                match read_groups.iter().find(|(existing, _)| existing == id) {
+                    Some((_, existing)) if existing != line => return Err(conflict("@RG", id)),
+                    Some(_) => (),
+                    None => read_groups.push((id.to_string(), line.to_string())),
+                }
+            } else if line.starts_with("@PG\t") {
+                // the same program run on each input (e.g. the aligner) is only listed once
+                let id: &str = header_field(line, "ID").unwrap_or_default();
+                if !programs.iter().any(|(existing, _)| existing == id) {
+                    programs.push((id.to_string(), line.to_string()));
+                }
+            } else if line.starts_with("@CO\t") {
+                if !comments.iter().any(|existing| existing == line) {
+                    comments.push(line.to_string());
+                }
+            } else if line.starts_with("@HD\t") {
+                if i == 0 {
+                    hd = Some(line.to_string());
+                }
+            } else if i == 0 && !line.is_empty() {
+                others.push(line.to_string());
+            }
+        }
+        tid_maps.push(tid_map);
+    }
+    let mut lines: Vec<String> = hd.into_iter().collect();
+    lines.extend(sequences.into_iter().map(|(_, line)| line));
+    lines.extend(read_groups.into_iter().map(|(_, line)| line));
+    lines.extend(others);
+    lines.extend(programs.into_iter().map(|(_, line)| line));
+    lines.extend(comments);
+    let order: SortOrder = match grouped {
+        true => SortOrder::Collated,
+        _ => SortOrder::Unsorted,
+    };
+    Ok(MergedHeader {
+        text: set_sort_order(lines.join("\n").as_bytes(), order),
+        tid_maps,
+    })
+}
+
+/// Content of the @CO comment describing a run
+#[derive(Serialize)]
+struct RunComment<'a> {
+    program: &'a str,
+    version: &'a str,
+    timestamp: String,
+    config: &'a FilterConfig,
+}
+
+/// Append a @CO comment holding the settings of the run as a JSON object, so that
+/// it is possible to tell how an alignment file was filtered from its header
+///
+/// # Arguments
+/// * `header`: output header to add the comment to
+/// * `config`: settings of the run
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::header::add_config_comment;
+/// use rust_htslib::bam::Header;
+///
+/// let mut header = Header::new();
+/// add_config_comment(&mut header, &FilterConfig::default()).unwrap();
+/// let text = String::from_utf8(header.to_bytes()).unwrap();
+/// assert!(text.starts_with("@CO\t{\"program\":\"filter-clipped\""));
+/// assert!(text.contains("\"left_side\":0.1"));
+/// ```
+pub fn add_config_comment(
+    header: &mut Header,
+    config: &FilterConfig,
+) -> Result<(), FilterClippedError> {
+    let comment = RunComment {
+        program: PROGRAM_NAME,
+        version: PROGRAM_VERSION,
+        timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        config,
+    };
+    let comment: String = serde_json::to_string(&comment)?;
+    header.push_comment(comment.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SingletonPolicy;
+    use rstest::rstest;
+
+    fn programs(records: &[(&str, Option<&str>)]) -> Vec<(String, Option<String>)> {
+        records
+            .iter()
+            .map(|(id, pp)| (id.to_string(), pp.map(|p| p.to_string())))
+            .collect()
+    }
+
+    #[rstest]
+    #[case("@PG\tID:bwa\tPN:bwa\tVN:0.7.17", Some(Aligner::Bwa))]
+    #[case(
+        "@PG\tID:bowtie2\tPN:bowtie2\n@PG\tID:samtools\tPN:samtools\tPP:bowtie2",
+        Some(Aligner::Bowtie2)
+    )]
+    #[case("@PG\tID:STAR\tPN:STAR\tVN:2.7.10a", Some(Aligner::Star))]
+    #[case(
+        "@PG\tID:samtools\tPN:samtools\n@PG\tID:minimap2\tPN:minimap2",
+        Some(Aligner::Minimap2)
+    )]
+    #[case("@PG\tID:samtools\tPN:samtools", None)]
+    #[case("@HD\tVN:1.6\n@CO\tbwa mem ref.fa", None)]
+    fn test_detect_aligner(#[case] header: &str, #[case] expected: Option<Aligner>) {
+        assert_eq!(detect_aligner(header.as_bytes()), expected);
+    }
+
+    #[rstest]
+    #[case("@HD\tVN:1.6\tSO:coordinate", SortOrder::Coordinate)]
+    #[case(
+        "@HD\tVN:1.6\tSO:queryname\tSS:queryname:natural",
+        SortOrder::Queryname
+    )]
+    #[case("@HD\tVN:1.6\tSO:unsorted", SortOrder::Unsorted)]
+    #[case("@HD\tVN:1.6\tGO:query", SortOrder::Collated)]
+    #[case("@HD\tVN:1.6", SortOrder::Unknown)]
+    #[case("@SQ\tSN:chr1\tLN:100", SortOrder::Unknown)]
+    fn test_sort_order(#[case] header: &str, #[case] expected: SortOrder) {
+        assert_eq!(sort_order(header.as_bytes()), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "@HD\tVN:1.6\tSO:coordinate\n",
+        SortOrder::Unsorted,
+        "@HD\tVN:1.6\tSO:unsorted\n"
+    )]
+    #[case(
+        "@HD\tVN:1.6\tSO:queryname\tSS:queryname:natural\n",
+        SortOrder::Coordinate,
+        "@HD\tVN:1.6\tSO:coordinate\n"
+    )]
+    #[case(
+        "@HD\tVN:1.6\tSO:unsorted\tGO:query\n",
+        SortOrder::Collated,
+        "@HD\tVN:1.6\tSO:unsorted\tGO:query\n"
+    )]
+    #[case(
+        "@SQ\tSN:chr1\tLN:100\n",
+        SortOrder::Unsorted,
+        "@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:100\n"
+    )]
+    #[case(
+        "@HD\tVN:1.6\tSO:queryname\n",
+        SortOrder::Collated,
+        "@HD\tVN:1.6\tSO:unsorted\tGO:query\n"
+    )]
+    fn test_set_sort_order(#[case] header: &str, #[case] order: SortOrder, #[case] expected: &str) {
+        let updated = set_sort_order(header.as_bytes(), order);
+        assert_eq!(String::from_utf8(updated).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(vec![], "filter-clipped", None)]
+    #[case(vec![("bwa", None)], "filter-clipped", Some("bwa"))]
+    #[case(vec![("bwa", None), ("samtools", Some("bwa"))], "filter-clipped", Some("samtools"))]
+    #[case(vec![("filter-clipped", None)], "filter-clipped.1", Some("filter-clipped"))]
+    #[case(
+        vec![("filter-clipped", None), ("filter-clipped.1", Some("filter-clipped"))],
+        "filter-clipped.2",
+        Some("filter-clipped.1")
+    )]
+    #[case(vec![("samtools", Some("bwa")), ("bwa", None)], "filter-clipped", Some("samtools"))]
+    fn test_next_program(
+        #[case] existing: Vec<(&str, Option<&str>)>,
+        #[case] expected_id: &str,
+        #[case] expected_previous: Option<&str>,
+    ) {
+        let (id, previous) = next_program(&programs(&existing), "filter-clipped");
+        assert_eq!(id, expected_id);
+        assert_eq!(previous.as_deref(), expected_previous);
+    }
+    #[rstest]
+    #[case(
+        vec!["@HD\tVN:1.6\tSO:queryname\n@SQ\tSN:chr1\tLN:100\n", "@HD\tVN:1.6\tSO:queryname\n@SQ\tSN:chr1\tLN:100\n"],
+        Ok("@HD\tVN:1.6\tSO:unsorted\tGO:query\n@SQ\tSN:chr1\tLN:100\n")
+    )]
+    #[case(
+        vec!["@SQ\tSN:chr1\tLN:100\n@PG\tID:bwa\tCL:bwa mem a\n", "@SQ\tSN:chr1\tLN:100\n@PG\tID:bwa\tCL:bwa mem b\n@CO\tlane 2\n"],
+        Ok("@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:100\n@PG\tID:bwa\tCL:bwa mem a\n@CO\tlane 2\n")
+    )]
+    #[case(vec!["@SQ\tSN:chr1\tLN:100\n", "@SQ\tSN:chr1\tLN:200\n"], Err("@SQ length"))]
+    #[case(
+        vec!["@RG\tID:rg1\tSM:a\n", "@RG\tID:rg1\tSM:a\n", "@RG\tID:rg1\tSM:b\n"],
+        Err("input 3 has a different @RG record for rg1")
+    )]
+    fn test_merge_headers(#[case] headers: Vec<&str>, #[case] expected: Result<&str, &str>) {
+        let headers: Vec<Vec<u8>> = headers.iter().map(|h| h.as_bytes().to_vec()).collect();
+        match (merge_headers(&headers), expected) {
+            (Ok(merged), Ok(text)) => assert_eq!(String::from_utf8(merged.text).unwrap(), text),
+            (Err(err), Err(message)) => assert!(err.to_string().contains(message)),
+            (result, _) => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[rstest]
+    #[case("@PG\tID:filter-clipped\tPN:filter-clipped\tDS:settings=ab12", vec!["ab12"])]
+    #[case(
+        "@PG\tID:filter-clipped\tPN:filter-clipped\tDS:settings=ab12\n@PG\tID:filter-clipped.1\tPN:filter-clipped\tPP:filter-clipped\tDS:settings=cd34",
+        vec!["ab12", "cd34"]
+    )]
+    #[case("@PG\tID:filter-clipped\tPN:filter-clipped\tVN:0.1.0", vec![])]
+    #[case("@PG\tID:other\tPN:other\tDS:settings=ab12", vec![])]
+    #[case("@CO\tDS:settings=ab12", vec![])]
+    fn test_previous_settings(#[case] header: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(previous_settings(header.as_bytes()), expected);
+    }
+
+    #[test]
+    fn test_fingerprinted_fields() {
+        // the settings changing what is filtered, every other one has to be in UNFINGERPRINTED
+        let fingerprinted: &[&str] = &[
+            "inverse",
+            "both_end",
+            "left_side",
+            "right_side",
+            "unalign",
+            "zero_mapq",
+            "reason_tag",
+            "unalign_strip_tags",
+            "restore_orientation",
+            "unaligned_to_end",
+            "secondary",
+            "supplementary",
+            "unmapped",
+            "seqless_secondary",
+            "duplicates",
+            "qcfail",
+            "require_proper_pair",
+            "only_strand",
+            "paired",
+            "collate",
+            "pair_policy",
+            "two_pass",
+            "cascade",
+            "sa_aware",
+            "promote_supplementary",
+            "fix_orphans",
+            "singletons",
+            "skip_records",
+            "max_records",
+            "tolerant",
+            "max_malformed",
+            "cell_tag",
+            "cb_whitelist",
+            "keep_names",
+            "drop_names",
+            "min_mapq",
+            "max_indel_fraction",
+            "max_mismatch_fraction",
+            "min_query_coverage",
+            "min_aligned_block",
+            "aligned_block_max_indel",
+            "min_split_coverage",
+            "min_insert_size",
+            "max_insert_size",
+            "expression",
+            "exempt_low_complexity",
+            "exempt_motifs",
+            "motif_purity",
+            "min_clip_bq",
+            "ignore_supplementary_hardclips",
+            "rna",
+            "spliced_thresholds",
+            "contig_thresholds",
+            "length_thresholds",
+            "read1_thresholds",
+            "read2_thresholds",
+            "score_weights",
+            "max_score",
+            "adapters",
+            "adapter_policy",
+            "adapter_mismatch_rate",
+            "contaminants",
+            "screen_kmer_size",
+            "screen_fail",
+            "primers",
+            "auto_threshold",
+            "auto_preset",
+            "subsample",
+            "seed",
+        ];
+        let settings: serde_json::Value = serde_json::to_value(FilterConfig::default()).unwrap();
+        for field in settings.as_object().unwrap().keys() {
+            assert!(
+                fingerprinted.contains(&field.as_str())
+                    != UNFINGERPRINTED.contains(&field.as_str()),
+                "{} should be in exactly one of UNFINGERPRINTED and the fingerprinted settings",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn test_settings_fingerprint() {
+        let config = FilterConfig::new();
+        let fingerprint: String = settings_fingerprint(&config).unwrap();
+        let same = FilterConfig::new()
+            .in_bam("in.bam")
+            .hist_out("hist.tsv")
+            .header_comment(true)
+            .unaligned_out("unaligned.bam");
+        assert_eq!(settings_fingerprint(&same).unwrap(), fingerprint);
+        let separate = |path: &str| {
+            FilterConfig::new()
+                .paired(true)
+                .singletons(SingletonPolicy::SeparateOut(path.to_string()))
+        };
+        assert_eq!(
+            settings_fingerprint(&separate("a.bam")).unwrap(),
+            settings_fingerprint(&separate("b.bam")).unwrap()
+        );
+        assert_ne!(
+            settings_fingerprint(&separate("a.bam")).unwrap(),
+            settings_fingerprint(&separate("a.bam").singletons(SingletonPolicy::Keep)).unwrap()
+        );
+        for other in [
+            FilterConfig::new().right(0.5),
+            FilterConfig::new().paired(true),
+            FilterConfig::new().min_mapq(10),
+        ] {
+            assert_ne!(settings_fingerprint(&other).unwrap(), fingerprint);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::clipping::ClipStat;
+use crate::error::FilterClippedError;
+use serde::ser::{SerializeMap, Serializer};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Number of bins of the clip fractions, each 0.01 wide; the last bin also holds
+/// the fractions above 1 (hard clipped bases aren't part of the sequence length)
+pub const FRACTION_BINS: usize = 100;
+
+/// Binned clip fractions and clip lengths of the evaluated alignments of a run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClipHistogram {
+    /// counts of the 5' clip fractions, by bin
+    left_fraction: Vec<u64>,
+    /// counts of the 3' clip fractions, by bin
+    right_fraction: Vec<u64>,
+    /// counts of the total clip fractions, by bin
+    total_fraction: Vec<u64>,
+    /// counts of the 5' clip lengths
+    left_length: BTreeMap<i64, u64>,
+    /// counts of the 3' clip lengths
+    right_length: BTreeMap<i64, u64>,
+    /// counts of the total clip lengths
+    total_length: BTreeMap<i64, u64>,
+}
+
+impl Default for ClipHistogram {
+    fn default() -> Self {
+        Self {
+            left_fraction: vec![0; FRACTION_BINS],
+            right_fraction: vec![0; FRACTION_BINS],
+            total_fraction: vec![0; FRACTION_BINS],
+            left_length: BTreeMap::new(),
+            right_length: BTreeMap::new(),
+            total_length: BTreeMap::new(),
+        }
+    }
+}
+
+/// Bin of a clip fraction, with some slack for fractions like 0.29 landing just below their bin
+pub(crate) fn fraction_bin(fraction: f64) -> usize {
+    ((fraction * FRACTION_BINS as f64 + 1e-9) as usize).min(FRACTION_BINS - 1)
+}
+
+impl ClipHistogram {
+    /// Count the clipping of an alignment
+    ///
+    /// # Arguments
+    /// * `clip_stat`: clipped bases of the alignment
+    /// * `seq_len`: sequence length of the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// use filter_clipped::histogram::ClipHistogram;
+    /// let mut histogram = ClipHistogram::default();
+    /// histogram.add(&ClipStat::new(vec![5, 0], vec![0, 0]), 100.0).unwrap();
+    /// assert_eq!(histogram.len(), 1);
+    /// ```
+    pub fn add(&mut self, clip_stat: &ClipStat, seq_len: f64) -> Result<(), FilterClippedError> {
+        self.left_fraction[fraction_bin(clip_stat.left_fraction(seq_len)?)] += 1;
+        self.right_fraction[fraction_bin(clip_stat.right_fraction(seq_len)?)] += 1;
+        self.total_fraction[fraction_bin(clip_stat.total_fraction(seq_len)?)] += 1;
+        *self.left_length.entry(clip_stat.left()).or_insert(0) += 1;
+        *self.right_length.entry(clip_stat.right()).or_insert(0) += 1;
+        *self
+            .total_length
+            .entry(clip_stat.total_clipped())
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Add the counts of another histogram, e.g. of another part of the input
+    pub fn merge(&mut self, other: &ClipHistogram) {
+        let fractions = [
+            (&mut self.left_fraction, &other.left_fraction),
+            (&mut self.right_fraction, &other.right_fraction),
+            (&mut self.total_fraction, &other.total_fraction),
+        ];
+        for (counts, other_counts) in fractions {
+            for (count, other_count) in counts.iter_mut().zip(other_counts.iter()) {
+                *count += other_count;
+            }
+        }
+        let lengths = [
+            (&mut self.left_length, &other.left_length),
+            (&mut self.right_length, &other.right_length),
+            (&mut self.total_length, &other.total_length),
+        ];
+        for (counts, other_counts) in lengths {
+            for (length, count) in other_counts.iter() {
+                *counts.entry(*length).or_insert(0) += count;
+            }
+        }
+    }
+
+    /// Number of alignments counted
+    pub fn len(&self) -> u64 {
+        self.total_fraction.iter().sum()
+    }
+
+    /// Whether no alignments were counted
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counts of the 5', 3' and total clip fractions by bin, with the names of the metrics
+    pub fn fractions(&self) -> [(&'static str, &[u64]); 3] {
+        [
+            ("left_fraction", &self.left_fraction),
+            ("right_fraction", &self.right_fraction),
+            ("total_fraction", &self.total_fraction),
+        ]
+    }
+
+    /// Counts of the 5', 3' and total clip lengths (in bases), with the names of the metrics
+    pub fn lengths(&self) -> [(&'static str, &BTreeMap<i64, u64>); 3] {
+        [
+            ("left_length", &self.left_length),
+            ("right_length", &self.right_length),
+            ("total_length", &self.total_length),
+        ]
+    }
+
+    /// Serialize the 5' and 3' clip lengths (in bases) of the run summary: adapter
+    /// contamination shows up as a sharp peak at a clip length, which the fractions smear
+    /// out over reads of different lengths
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::clipping::ClipStat;
+    /// use filter_clipped::histogram::ClipHistogram;
+    /// let mut histogram = ClipHistogram::default();
+    /// histogram.add(&ClipStat::new(vec![5, 0], vec![0, 0]), 100.0).unwrap();
+    /// let json = histogram.serialize_lengths(serde_json::value::Serializer).unwrap();
+    /// assert_eq!(json["left_length"]["5"], 1);
+    /// assert_eq!(json["right_length"]["0"], 1);
+    /// ```
+    pub fn serialize_lengths<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("left_length", &self.left_length)?;
+        map.serialize_entry("right_length", &self.right_length)?;
+        map.end()
+    }
+
+    /// Write the histograms as a long-format TSV: one line per metric and bin, with the
+    /// start (included) and end (excluded) of the bin and the count. All the fraction bins are
+    /// written, the length bins (1 base wide) only if any alignment falls in them
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the TSV
+    pub fn write_tsv<W: Write>(&self, writer: &mut W) -> Result<(), FilterClippedError> {
+        writeln!(writer, "metric\tbin_start\tbin_end\tcount")?;
+        for (metric, counts) in self.fractions() {
+            for (bin, count) in counts.iter().enumerate() {
+                writeln!(
+                    writer,
+                    "{}\t{:.2}\t{:.2}\t{}",
+                    metric,
+                    bin as f64 / FRACTION_BINS as f64,
+                    (bin + 1) as f64 / FRACTION_BINS as f64,
+                    count
+                )?;
+            }
+        }
+        for (metric, counts) in self.lengths() {
+            for (length, count) in counts.iter() {
+                writeln!(writer, "{}\t{}\t{}\t{}", metric, length, length + 1, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0.0, 0)]
+    #[case(0.005, 0)]
+    #[case(0.15, 15)]
+    #[case(29.0 / 100.0, 29)]
+    #[case(0.999, 99)]
+    #[case(1.0, 99)]
+    #[case(1.5, 99)]
+    fn test_fraction_bin(#[case] fraction: f64, #[case] expected: usize) {
+        assert_eq!(fraction_bin(fraction), expected);
+    }
+
+    #[test]
+    fn test_write_tsv() {
+        let mut histogram = ClipHistogram::default();
+        histogram
+            .add(&ClipStat::new(vec![10, 0], vec![0, 5]), 100.0)
+            .unwrap();
+        histogram
+            .add(&ClipStat::new(vec![0, 0], vec![0, 0]), 100.0)
+            .unwrap();
+        let mut tsv: Vec<u8> = Vec::new();
+        histogram.write_tsv(&mut tsv).unwrap();
+        let text: String = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 1 + 3 * FRACTION_BINS + 2 + 2 + 2);
+        assert!(lines.contains(&"left_fraction\t0.10\t0.11\t1"));
+        assert!(lines.contains(&"total_fraction\t0.15\t0.16\t1"));
+        assert!(lines.contains(&"right_length\t5\t6\t1"));
+        assert!(lines.contains(&"total_length\t0\t1\t1"));
+    }
+}
@@ -0,0 +1,899 @@
+use crate::chimeric;
+use crate::config::{FilterConfig, OutputFormat, SingletonPolicy};
+use crate::error::FilterClippedError;
+use crate::stats::RunStats;
+use log::{debug, warn};
+use rust_htslib::bam::Record;
+use rust_htslib::bam::{self, index, Header, HeaderView, IndexedReader, Read, Reader, Writer};
+use rust_htslib::bgzf;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+/// URL schemes handed over to htslib as remote inputs
+const REMOTE_SCHEMES: [&str; 4] = ["http", "https", "ftp", "s3"];
+/// Longest reference sequence a .bai index can address (2^29 - 1)
+const MAX_BAI_LENGTH: u64 = (1 << 29) - 1;
+/// min_shift used for .csi indexes, same as `samtools index -c`
+const CSI_MIN_SHIFT: u32 = 14;
+/// How many times opening a remote input is attempted before giving up
+const REMOTE_ATTEMPTS: u32 = 3;
+
+/// Check if an input path points to a remote file (http, https, ftp or s3 URL)
+///
+/// # Arguments
+/// - `path`: input path
+///
+/// # Returns
+/// - the parsed URL if the path is a remote location, otherwise None
+///
+/// # Example
+/// ```
+/// use filter_clipped::io::remote_url;
+/// assert!(remote_url("https://example.com/sample.bam").is_some());
+/// assert!(remote_url("s3://bucket/sample.bam").is_some());
+/// assert!(remote_url("test/data/test.sam").is_none());
+/// assert!(remote_url("-").is_none());
+/// ```
+pub fn remote_url(path: &str) -> Option<Url> {
+    Url::parse(path)
+        .ok()
+        .filter(|url| REMOTE_SCHEMES.contains(&url.scheme()))
+}
+
+/// Open a remote alignment file, retrying with a growing delay as network
+/// hiccups are common on cloud storage. Credentials for s3:// are picked up by htslib
+/// (e.g. from AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY or ~/.aws/credentials)
+///
+/// # Arguments
+/// - `url`: location of the file
+///
+/// # Returns
+/// - a bam reader
+fn open_remote_reader(url: &Url) -> Result<Reader, FilterClippedError> {
+    let mut attempt: u32 = 1;
+    loop {
+        match bam::Reader::from_url(url) {
+            Ok(reader) => return Ok(reader),
+            Err(e) if attempt < REMOTE_ATTEMPTS => {
+                warn!(
+                    "Failed to open {} (attempt {}/{}): {}, retrying",
+                    url, attempt, REMOTE_ATTEMPTS, e
+                );
+                thread::sleep(Duration::from_secs(2_u64.pow(attempt - 1)));
+                attempt += 1;
+            }
+            Err(e) => {
+                let hint = match url.scheme() {
+                    "s3" => "check the AWS credentials (AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_DEFAULT_REGION or ~/.aws/credentials)",
+                    _ => "check the URL and the network connection",
+                };
+                return Err(FilterClippedError::Remote {
+                    url: url.to_string(),
+                    attempts: REMOTE_ATTEMPTS,
+                    hint,
+                    source: e,
+                });
+            }
+        }
+    }
+}
+
+/// Open an in-memory SAM text as an input, e.g. for tests or for alignments
+/// generated by another part of a program
+///
+/// # Arguments
+/// - `text`: SAM text, header included
+///
+/// # Returns
+/// - a bam reader
+///
+/// # Example
+/// ```
+/// use filter_clipped::io::reader_from_sam_text;
+/// use rust_htslib::bam::Read;
+/// let sam = "@SQ\tSN:chr1\tLN:1000\nread\t0\tchr1\t10\t60\t4M\t*\t0\t0\tACGT\tFFFF\n";
+/// let mut reader = reader_from_sam_text(sam).unwrap();
+/// assert_eq!(reader.records().count(), 1);
+/// ```
+pub fn reader_from_sam_text(text: &str) -> Result<Reader, FilterClippedError> {
+    // htslib reads data: URLs, with the content percent-encoded
+    let mut url: String = String::from("data:,");
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    let url: Url = Url::parse(&url)
+        .map_err(|e| FilterClippedError::InvalidRecord(format!("Invalid SAM text: {}", e)))?;
+    Ok(bam::Reader::from_url(&url)?)
+}
+
+/// Open the input alignment file
+///
+/// # Arguments
+/// - `config`: settings holding the input path ("-" for stdin, or an http/https/ftp/s3 URL)
+///   and the optional reference
+///
+/// # Returns
+/// - a bam reader
+pub fn open_reader(config: &FilterConfig) -> Result<Reader, FilterClippedError> {
+    open_input(config, &config.in_bam)
+}
+
+/// Open an input alignment file, with the reference of the settings
+pub(crate) fn open_input(config: &FilterConfig, path: &str) -> Result<Reader, FilterClippedError> {
+    let mut reader: Reader = match (path.eq("-"), remote_url(path)) {
+        (true, _) => bam::Reader::from_stdin()?,
+        (_, Some(url)) => open_remote_reader(&url)?,
+        _ => bam::Reader::from_path(path)?,
+    };
+    if let Some(reference) = config.reference.as_ref() {
+        reader.set_reference(reference)?;
+    }
+    Ok(reader)
+}
+
+/// Open the input alignment file with its index, for random access
+///
+/// # Arguments
+/// - `config`: settings holding the input path and the optional reference
+///
+/// # Returns
+/// - the indexed reader, or an error if the input has no index (or is stdin)
+pub fn open_indexed(config: &FilterConfig) -> Result<IndexedReader, FilterClippedError> {
+    let mut reader: IndexedReader = match remote_url(&config.in_bam) {
+        Some(url) => IndexedReader::from_url(&url)?,
+        None => IndexedReader::from_path(&config.in_bam)?,
+    };
+    if let Some(reference) = config.reference.as_ref() {
+        reader.set_reference(reference)?;
+    }
+    Ok(reader)
+}
+
+/// Open all the input alignment files: `in_bam` and then the `extra_inputs`
+///
+/// # Arguments
+/// - `config`: settings holding the input paths and the optional reference
+pub fn open_inputs(config: &FilterConfig) -> Result<Vec<Reader>, FilterClippedError> {
+    let mut readers: Vec<Reader> = vec![open_reader(config)?];
+    for path in config.extra_inputs.iter() {
+        readers.push(open_input(config, path)?);
+    }
+    Ok(readers)
+}
+
+/// Deal with a record that can't be read: abort the run unless `tolerant` is set, in which
+/// case it's counted and skipped, up to `max_malformed` of them
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `skipped`: number of records skipped so far, updated
+/// - `error`: why the record couldn't be read
+pub fn skip_malformed(
+    config: &FilterConfig,
+    skipped: &mut u64,
+    error: FilterClippedError,
+) -> Result<(), FilterClippedError> {
+    if !config.tolerant {
+        return Err(error);
+    }
+    *skipped += 1;
+    if *skipped > config.max_malformed {
+        return Err(FilterClippedError::InvalidRecord(format!(
+            "skipped {} malformed records, more than max_malformed, the last one: {}",
+            skipped, error
+        )));
+    }
+    debug!("Skipping a malformed record: {}", error);
+    Ok(())
+}
+
+/// Total size in bytes of the input files, None if any of them is stdin or remote
+///
+/// # Arguments
+/// - `config`: settings holding the input paths
+pub fn input_bytes(config: &FilterConfig) -> Option<u64> {
+    let mut bytes: u64 = 0;
+    for path in std::iter::once(&config.in_bam).chain(config.extra_inputs.iter()) {
+        if path == "-" || remote_url(path).is_some() {
+            return None;
+        }
+        bytes += fs::metadata(path).ok()?.len();
+    }
+    Some(bytes)
+}
+
+/// Size in bytes of the output file once it's closed, None for stdout
+///
+/// # Arguments
+/// - `config`: settings holding the output path
+pub fn output_bytes(config: &FilterConfig) -> Option<u64> {
+    match config.out_bam.as_str() {
+        "-" => None,
+        path => fs::metadata(path).ok().map(|metadata| metadata.len()),
+    }
+}
+
+/// Read the alignments of several input files one file after the other, moving them to
+/// the reference sequences of the merged header (see [crate::header::merge_headers])
+///
+/// # Arguments
+/// - `readers`: the opened input files
+/// - `tid_maps`: for each input, the tid in the merged header of each of its reference sequences
+pub fn merged_records(
+    readers: Vec<Reader>,
+    tid_maps: Vec<Vec<i32>>,
+) -> impl Iterator<Item = Result<Record, FilterClippedError>> {
+    let mut inputs = readers.into_iter().zip(tid_maps);
+    let mut current: Option<(Reader, Vec<i32>)> = inputs.next();
+    std::iter::from_fn(move || loop {
+        let (reader, tid_map) = current.as_mut()?;
+        let mut record = Record::new();
+        match reader.read(&mut record) {
+            None => current = inputs.next(),
+            Some(Err(err)) => return Some(Err(err.into())),
+            Some(Ok(())) => {
+                let merged_tid = |tid: i32| match tid {
+                    tid if tid >= 0 => tid_map.get(tid as usize).copied().unwrap_or(-1),
+                    _ => tid,
+                };
+                record.set_tid(merged_tid(record.tid()));
+                record.set_mtid(merged_tid(record.mtid()));
+                return Some(Ok(record));
+            }
+        }
+    })
+}
+
+/// Format of the output file, as given by `--output-fmt` or otherwise guessed
+/// from the extension of the output path
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::{FilterConfig, OutputFormat};
+/// use filter_clipped::io::output_format;
+/// let config = FilterConfig {
+///     out_bam: "out.sam".to_string(),
+///     ..FilterConfig::default()
+/// };
+/// assert_eq!(output_format(&config), OutputFormat::Sam);
+/// ```
+pub fn output_format(config: &FilterConfig) -> OutputFormat {
+    config
+        .output_fmt
+        .unwrap_or_else(|| OutputFormat::from_path(&config.out_bam))
+}
+
+/// Open the output alignment file, in the format given by `--output-fmt` or
+/// otherwise by the extension of the output path
+///
+/// # Arguments
+/// - `config`: settings holding the output path ("-" for stdout), format and the optional reference
+/// - `header`: header of the output file
+///
+/// # Returns
+/// - a bam writer
+pub fn open_writer(config: &FilterConfig, header: &Header) -> Result<Writer, FilterClippedError> {
+    let format: bam::Format = output_format(config).into();
+    let mut writer: Writer = match config.out_bam.eq("-") {
+        true => bam::Writer::from_stdout(header, format)?,
+        _ => bam::Writer::from_path(&config.out_bam, header, format)?,
+    };
+    if let Some(reference) = config.reference.as_ref() {
+        writer.set_reference(reference)?;
+    }
+    Ok(writer)
+}
+
+/// Make sure the output can be indexed once it's written, so that a run doesn't
+/// fail only after the whole input has been filtered
+///
+/// # Arguments
+/// - `config`: settings holding the output path and format
+pub fn check_indexable(config: &FilterConfig) -> Result<(), FilterClippedError> {
+    if config.out_bam.eq("-") {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "--write-index needs an output file path, not stdout",
+        )));
+    }
+    if output_format(config) == OutputFormat::Sam {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "--write-index needs BAM or CRAM output, SAM files can't be indexed",
+        )));
+    }
+    Ok(())
+}
+
+/// Index the output file once it has been written and closed: a .crai for CRAM,
+/// a .bai for BAM, or a .csi if any reference sequence is too long for a .bai
+///
+/// # Arguments
+/// - `config`: settings holding the output path
+/// - `header`: header of the output file
+pub fn write_index(config: &FilterConfig, header: &HeaderView) -> Result<(), FilterClippedError> {
+    check_indexable(config)?;
+    let long_contig: bool = (0..header.target_count())
+        .filter_map(|tid| header.target_len(tid))
+        .any(|len| len > MAX_BAI_LENGTH);
+    let index_type: index::Type = match long_contig {
+        true => index::Type::Csi(CSI_MIN_SHIFT),
+        _ => index::Type::Bai,
+    };
+    // htslib refuses to index unsorted files, which is warned about before the run
+    index::build(&config.out_bam, None, index_type, 1)?;
+    Ok(())
+}
+
+/// Read a list of names (e.g. read names or cell barcodes) from a plain or gzipped file,
+/// taking the first word of each line and skipping empty lines
+///
+/// # Arguments
+/// - `path`: the file
+///
+/// # Returns
+/// - the names
+///
+/// # Example
+/// ```
+/// use filter_clipped::io::read_name_list;
+/// let barcodes = read_name_list("test/data/cb_whitelist.txt").unwrap();
+/// assert!(barcodes.contains(b"AAACCTGAGAAGGCCT".as_slice()));
+/// ```
+pub fn read_name_list(path: &str) -> Result<HashSet<Vec<u8>>, FilterClippedError> {
+    let reader = BufReader::new(bgzf::Reader::from_path(path)?);
+    let mut names: HashSet<Vec<u8>> = HashSet::new();
+    for line in reader.split(b'\n') {
+        let line: Vec<u8> = line?;
+        if let Some(name) = line
+            .split(|c| c.is_ascii_whitespace())
+            .find(|word| !word.is_empty())
+        {
+            names.insert(name.to_vec());
+        }
+    }
+    Ok(names)
+}
+
+/// Writes the names of the alignments removed by the filter into a file, one per line,
+/// followed by their flag and the reason they were removed if asked for
+/// (see [FilterConfig::removed_names])
+pub struct RemovedNames {
+    writer: BufWriter<File>,
+    /// write the flag and the reason after the read name
+    details: bool,
+}
+
+impl RemovedNames {
+    /// Create the file of removed read names if the settings ask for it
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the path
+    pub fn create(config: &FilterConfig) -> Result<Option<Self>, FilterClippedError> {
+        match config.removed_names.as_deref() {
+            Some(path) => Ok(Some(Self {
+                writer: BufWriter::new(File::create(path)?),
+                details: config.removed_details,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Record a removed alignment
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    /// - `reason`: why it was removed
+    pub fn write(&mut self, record: &Record, reason: &str) -> Result<(), FilterClippedError> {
+        self.writer.write_all(record.qname())?;
+        if self.details {
+            write!(self.writer, "\t{}\t{}", record.flags(), reason)?;
+        }
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flush the file once all the alignments are filtered
+    pub fn finish(mut self) -> Result<(), FilterClippedError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the junctions between clipped primary alignments and the segments in their
+/// SA tags into a BEDPE file (see [FilterConfig::junction_bedpe]): both breakpoints as
+/// 1-base intervals, the read name, the lower of the two mapping qualities and the strands
+pub struct JunctionBedpe {
+    writer: BufWriter<File>,
+    /// fewest clipped bases on one side of an alignment to report its junctions
+    min_clip: i64,
+}
+
+impl JunctionBedpe {
+    /// Create the BEDPE file if the settings ask for it
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the path
+    pub fn create(config: &FilterConfig) -> Result<Option<Self>, FilterClippedError> {
+        match config.junction_bedpe.as_deref() {
+            Some(path) => Ok(Some(Self {
+                writer: BufWriter::new(File::create(path)?),
+                min_clip: config.min_junction_clip as i64,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Record the junctions of an alignment, if it is a mapped primary alignment
+    /// clipped enough on one side
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    /// - `header`: header of the alignments, for the reference names
+    pub fn write(
+        &mut self,
+        record: &Record,
+        header: &HeaderView,
+    ) -> Result<(), FilterClippedError> {
+        if record.is_unmapped()
+            || record.is_secondary()
+            || record.is_supplementary()
+            || record.tid() < 0
+        {
+            return Ok(());
+        }
+        let cigar = record.cigar();
+        let clipped: i64 = (cigar.leading_softclips() + cigar.leading_hardclips())
+            .max(cigar.trailing_softclips() + cigar.trailing_hardclips());
+        if clipped < self.min_clip {
+            return Ok(());
+        }
+        let strand = |reverse: bool| if reverse { '-' } else { '+' };
+        let rname: String =
+            String::from_utf8_lossy(header.tid2name(record.tid() as u32)).to_string();
+        for (breakpoint, segment, mapq) in chimeric::junctions(record, &rname)? {
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                breakpoint.rname,
+                breakpoint.pos,
+                breakpoint.pos + 1,
+                segment.rname,
+                segment.pos,
+                segment.pos + 1,
+                String::from_utf8_lossy(record.qname()),
+                record.mapq().min(mapq),
+                strand(breakpoint.reverse),
+                strand(segment.reverse),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Flush the file once all the alignments are filtered
+    pub fn finish(mut self) -> Result<(), FilterClippedError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Name of the shard file of a reference sequence, path separators in the name
+/// being replaced so that every shard lands in the output directory
+///
+/// # Example
+/// ```
+/// use filter_clipped::io::shard_file_name;
+/// assert_eq!(shard_file_name("chr1"), "chr1.bam");
+/// assert_eq!(shard_file_name("HLA-A*01:01/x"), "HLA-A*01:01_x.bam");
+/// ```
+pub fn shard_file_name(contig: &str) -> String {
+    format!("{}.bam", contig.replace(['/', '\\'], "_"))
+}
+
+/// Name of the shard file of the alignments without a reference sequence
+pub const UNMAPPED_SHARD: &str = "unmapped.bam";
+
+/// One BAM file per reference sequence of the output, plus one for the alignments
+/// without a reference sequence (`unmapped.bam`). Every shard gets the full output
+/// header so that the reference ids and the mate positions stay valid, and the files
+/// are opened as the alignments come (the shards of the reference sequences without
+/// any alignment are written empty at the end)
+pub struct ChromShards {
+    /// directory holding the shards
+    dir: PathBuf,
+    /// header of the output, copied into every shard
+    header: Header,
+    /// reference names by tid
+    contigs: Vec<String>,
+    /// open shards by tid, -1 for the unmapped alignments
+    writers: HashMap<i32, Writer>,
+}
+
+impl ChromShards {
+    /// Create the output directory of the shards if asked for by the settings
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the directory
+    /// - `header`: header of the output
+    pub fn create(
+        config: &FilterConfig,
+        header: &HeaderView,
+    ) -> Result<Option<Self>, FilterClippedError> {
+        let dir: &str = match config.shard_by_chrom.as_deref() {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        fs::create_dir_all(dir)?;
+        let contigs: Vec<String> = header
+            .target_names()
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect();
+        Ok(Some(Self {
+            dir: PathBuf::from(dir),
+            header: Header::from_template(header),
+            contigs,
+            writers: HashMap::new(),
+        }))
+    }
+
+    /// Path of the shard of a tid
+    fn path(&self, tid: i32) -> PathBuf {
+        match usize::try_from(tid).ok().and_then(|i| self.contigs.get(i)) {
+            Some(contig) => self.dir.join(shard_file_name(contig)),
+            None => self.dir.join(UNMAPPED_SHARD),
+        }
+    }
+
+    /// Open the shard of a tid
+    fn open(&self, tid: i32) -> Result<Writer, FilterClippedError> {
+        Ok(Writer::from_path(
+            self.path(tid),
+            &self.header,
+            bam::Format::Bam,
+        )?)
+    }
+
+    /// Write an alignment into the shard of its reference sequence, the unmapped
+    /// alignments placed next to their mate go with their mate
+    pub fn write(&mut self, record: &Record) -> Result<(), FilterClippedError> {
+        let tid: i32 = if record.tid() < 0 { -1 } else { record.tid() };
+        if !self.writers.contains_key(&tid) {
+            let writer: Writer = self.open(tid)?;
+            self.writers.insert(tid, writer);
+        }
+        self.writers
+            .get_mut(&tid)
+            .expect("shard was just opened")
+            .write(record)?;
+        Ok(())
+    }
+
+    /// Close the shards, writing the ones no alignment went into
+    pub fn finish(self) -> Result<(), FilterClippedError> {
+        for tid in -1..self.contigs.len() as i32 {
+            if !self.writers.contains_key(&tid) {
+                self.open(tid)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One BAM file per reason the alignments failed for (see [FilterConfig::split_by_reason]),
+/// named after the reason as in the JSON summary, with the full output header. The files
+/// are opened as the failing alignments come, so only the reasons seen in the run get one
+pub struct ReasonSplit {
+    /// directory holding the files
+    dir: PathBuf,
+    /// header of the output, copied into every file
+    header: Header,
+    /// open files by reason
+    writers: HashMap<String, Writer>,
+}
+
+impl ReasonSplit {
+    /// Create the output directory if asked for by the settings
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the directory
+    /// - `header`: header of the output
+    pub fn create(
+        config: &FilterConfig,
+        header: &HeaderView,
+    ) -> Result<Option<Self>, FilterClippedError> {
+        let dir: &str = match config.split_by_reason.as_deref() {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        fs::create_dir_all(dir)?;
+        Ok(Some(Self {
+            dir: PathBuf::from(dir),
+            header: Header::from_template(header),
+            writers: HashMap::new(),
+        }))
+    }
+
+    /// Write a failing alignment into the file of the reason it failed for
+    ///
+    /// # Arguments
+    /// - `record`: the alignment
+    /// - `reason`: name of the reason, e.g. "left_clip"
+    pub fn write(&mut self, record: &Record, reason: &str) -> Result<(), FilterClippedError> {
+        if !self.writers.contains_key(reason) {
+            let path: PathBuf = self.dir.join(format!("{}.bam", reason));
+            let writer = Writer::from_path(path, &self.header, bam::Format::Bam)?;
+            self.writers.insert(reason.to_string(), writer);
+        }
+        self.writers
+            .get_mut(reason)
+            .expect("file was just opened")
+            .write(record)?;
+        Ok(())
+    }
+}
+
+/// Unaligned BAM holding the alignments made unmapped (see
+/// [FilterConfig::unaligned_out]), its header only keeps the @HD, @RG and @PG lines of
+/// the output header
+pub struct UnalignedOut {
+    writer: Writer,
+}
+
+impl UnalignedOut {
+    /// Create the file if asked for by the settings
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the path
+    /// - `header`: header of the output
+    pub fn create(
+        config: &FilterConfig,
+        header: &HeaderView,
+    ) -> Result<Option<Self>, FilterClippedError> {
+        let path: &str = match config.unaligned_out.as_deref() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut text: Vec<u8> = b"@HD\tVN:1.6\tSO:unsorted\n".to_vec();
+        for line in header.as_bytes().split(|c| *c == b'\n') {
+            if line.starts_with(b"@RG\t") || line.starts_with(b"@PG\t") {
+                text.extend_from_slice(line);
+                text.push(b'\n');
+            }
+        }
+        let header = Header::from_template(&HeaderView::from_bytes(&text));
+        let format: bam::Format = OutputFormat::from_path(path).into();
+        Ok(Some(Self {
+            writer: Writer::from_path(path, &header, format)?,
+        }))
+    }
+
+    /// Write an alignment made unmapped, without the position of its mate as the file has
+    /// no reference sequences
+    pub fn write(&mut self, record: &mut Record) -> Result<(), FilterClippedError> {
+        if record.is_paired() {
+            crate::set_mate_unmapped(record)?;
+        } else {
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            record.set_insert_size(0);
+        }
+        Ok(self.writer.write(record)?)
+    }
+}
+
+/// Alignment file holding the mates passing the filter without their mate, with
+/// [SingletonPolicy::SeparateOut] (see [FilterConfig::singletons]), with the output header
+pub struct SingletonsOut {
+    writer: Writer,
+}
+
+impl SingletonsOut {
+    /// Create the file if asked for by the settings
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the path
+    /// - `header`: header of the output
+    pub fn create(
+        config: &FilterConfig,
+        header: &HeaderView,
+    ) -> Result<Option<Self>, FilterClippedError> {
+        let path: &str = match config.singletons.as_ref() {
+            Some(SingletonPolicy::SeparateOut(path)) => path,
+            _ => return Ok(None),
+        };
+        let format: bam::Format = OutputFormat::from_path(path).into();
+        Ok(Some(Self {
+            writer: Writer::from_path(path, &Header::from_template(header), format)?,
+        }))
+    }
+
+    /// Write a mate passing the filter without its mate
+    pub fn write(&mut self, record: &Record) -> Result<(), FilterClippedError> {
+        Ok(self.writer.write(record)?)
+    }
+}
+
+/// Temporary BAM file holding the alignments made unmapped until all the mapped ones are
+/// written, to be appended at the end of the output (see [FilterConfig::unaligned_to_end]);
+/// the file is next to the output, or in the temporary directory when writing to stdout
+pub struct DeferredUnaligned {
+    path: PathBuf,
+    writer: Option<Writer>,
+}
+
+impl DeferredUnaligned {
+    /// Create the temporary file if asked for by the settings
+    ///
+    /// # Arguments
+    /// - `config`: settings holding the output path
+    /// - `header`: header of the output
+    pub fn create(
+        config: &FilterConfig,
+        header: &HeaderView,
+    ) -> Result<Option<Self>, FilterClippedError> {
+        if !config.unaligned_to_end {
+            return Ok(None);
+        }
+        let path: PathBuf = match config.out_bam.as_str() {
+            "-" => std::env::temp_dir().join(format!(
+                "filter-clipped.{}.unaligned.tmp.bam",
+                std::process::id()
+            )),
+            out_bam => PathBuf::from(format!("{}.unaligned.tmp.bam", out_bam)),
+        };
+        let mut writer =
+            Writer::from_path(&path, &Header::from_template(header), bam::Format::Bam)?;
+        // the file is read back in the same run, compressing it would only cost time
+        writer.set_compression_level(bam::CompressionLevel::Uncompressed)?;
+        Ok(Some(Self {
+            path,
+            writer: Some(writer),
+        }))
+    }
+
+    /// Hold an alignment made unmapped
+    pub fn write(&mut self, record: &Record) -> Result<(), FilterClippedError> {
+        match self.writer.as_mut() {
+            Some(writer) => Ok(writer.write(record)?),
+            None => unreachable!("alignments are only held before they are appended"),
+        }
+    }
+
+    /// Append the alignments held to the output, once all the other alignments are written,
+    /// and remove the temporary file
+    ///
+    /// # Returns
+    /// - the number of alignments appended
+    pub fn append_to(mut self, out_bam: &mut Writer) -> Result<u64, FilterClippedError> {
+        // closing the writer flushes the file
+        drop(self.writer.take());
+        let mut reader = Reader::from_path(&self.path)?;
+        let mut record = Record::new();
+        let mut count: u64 = 0;
+        while let Some(r) = reader.read(&mut record) {
+            r?;
+            out_bam.write(&record)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for DeferredUnaligned {
+    /// Remove the temporary file, once appended or when the run fails
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The files written alongside the output alignments while filtering, the ones
+/// not asked for by the settings are None
+#[derive(Default)]
+pub struct SideOutputs {
+    /// names of the removed alignments
+    pub removed_names: Option<RemovedNames>,
+    /// junctions of the chimeric reads
+    pub junctions: Option<JunctionBedpe>,
+    /// clip metrics of every alignment
+    #[cfg(feature = "parquet")]
+    pub metrics: Option<crate::metrics::ReadMetrics>,
+    /// copies of the output alignments split by reference sequence
+    pub shards: Option<ChromShards>,
+    /// the alignments made unmapped, kept out of the output
+    pub unaligned: Option<UnalignedOut>,
+    /// the alignments made unmapped, held until the end of the output
+    pub deferred: Option<DeferredUnaligned>,
+    /// the failing alignments split by reason
+    pub reasons: Option<ReasonSplit>,
+    /// the mates passing without their mate
+    pub singletons: Option<SingletonsOut>,
+    /// counters of the run for Prometheus
+    pub progress: Option<crate::prometheus::ProgressMetrics>,
+}
+
+impl SideOutputs {
+    /// Create the side output files asked for by the settings
+    ///
+    /// # Arguments
+    /// - `config`: settings for the run
+    /// - `header`: header of the output
+    pub fn create(config: &FilterConfig, header: &HeaderView) -> Result<Self, FilterClippedError> {
+        Ok(Self {
+            removed_names: RemovedNames::create(config)?,
+            junctions: JunctionBedpe::create(config)?,
+            #[cfg(feature = "parquet")]
+            metrics: match config.metrics_parquet.as_deref() {
+                Some(path) => Some(crate::metrics::ReadMetrics::create(path)?),
+                None => None,
+            },
+            shards: ChromShards::create(config, header)?,
+            unaligned: UnalignedOut::create(config, header)?,
+            deferred: DeferredUnaligned::create(config, header)?,
+            reasons: ReasonSplit::create(config, header)?,
+            singletons: SingletonsOut::create(config, header)?,
+            progress: crate::prometheus::ProgressMetrics::create(config)?,
+        })
+    }
+
+    /// Flush the files once all the alignments are filtered
+    ///
+    /// # Arguments
+    /// - `stats`: totals of the run
+    pub fn finish(self, stats: &RunStats) -> Result<(), FilterClippedError> {
+        if let Some(removed_names) = self.removed_names {
+            removed_names.finish()?;
+        }
+        if let Some(junctions) = self.junctions {
+            junctions.finish()?;
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(metrics) = self.metrics {
+            metrics.finish()?;
+        }
+        if let Some(shards) = self.shards {
+            shards.finish()?;
+        }
+        if let Some(progress) = self.progress {
+            progress.finish(stats)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("http://example.com/sample.bam", true)]
+    #[case("https://example.com/sample.bam?token=abc", true)]
+    #[case("ftp://example.com/sample.bam", true)]
+    #[case("s3://bucket/path/sample.bam", true)]
+    #[case("test/data/test.sam", false)]
+    #[case("/data/sample.bam", false)]
+    #[case("C:/data/sample.bam", false)]
+    #[case("-", false)]
+    fn test_remote_url(#[case] path: &str, #[case] expected: bool) {
+        assert_eq!(remote_url(path).is_some(), expected);
+    }
+
+    #[rstest]
+    #[case("test/data/cb_whitelist.txt")]
+    #[case("test/data/cb_whitelist.txt.gz")]
+    fn test_read_name_list(#[case] path: &str) {
+        let names = read_name_list(path).unwrap();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(b"AAACCTGAGAAGGTGA".as_slice()));
+    }
+}
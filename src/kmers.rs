@@ -0,0 +1,277 @@
+//! K-mer enrichment of the soft clipped sequence (`--kmer-out`): the k-mers of the soft
+//! clipped and the aligned sequence of the evaluated alignments are counted, and the ones
+//! most over-represented in the clips are reported, e.g. to discover the unknown adapters or
+//! contaminants driving the clipping. K-mers are counted on both strands together (as the
+//! smaller of the k-mer and its reverse complement), k-mers with other bases than A/C/G/T
+//! are skipped
+
+use crate::error::FilterClippedError;
+use rust_htslib::bam::{record::CigarStringView, Record};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Longest k-mers that can be counted, packed 2 bits a base into a u64
+pub const MAX_KMER_SIZE: usize = 32;
+
+/// 2-bit code of a base, None for anything else than A/C/G/T
+fn base_code(base: u8) -> Option<u64> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// Sequence of a packed k-mer
+fn decode(kmer: u64, k: usize) -> String {
+    (0..k)
+        .map(|i| b"ACGT"[((kmer >> (2 * (k - 1 - i))) & 3) as usize] as char)
+        .collect()
+}
+
+/// The canonical k-mers of a sequence (the smaller of each k-mer and its reverse complement,
+/// packed 2 bits a base), skipping the k-mers with other bases than A/C/G/T
+///
+/// # Arguments
+/// * `seq`: the sequence
+/// * `k`: length of the k-mers, from 1 to [MAX_KMER_SIZE]
+///
+/// # Example
+/// ```
+/// use filter_clipped::kmers::canonical_kmers;
+/// assert_eq!(canonical_kmers(b"ACGNTT", 2).collect::<Vec<u64>>(), vec![0b0001, 0b0110, 0b0000]);
+/// ```
+pub fn canonical_kmers(seq: &[u8], k: usize) -> impl Iterator<Item = u64> + '_ {
+    let mask: u64 = match k {
+        MAX_KMER_SIZE => u64::MAX,
+        _ => (1 << (2 * k)) - 1,
+    };
+    let shift: usize = 2 * (k - 1);
+    let (mut forward, mut reverse, mut length) = (0u64, 0u64, 0usize);
+    seq.iter().filter_map(move |base| {
+        match base_code(*base) {
+            Some(code) => {
+                forward = ((forward << 2) | code) & mask;
+                reverse = (reverse >> 2) | ((3 - code) << shift);
+                length += 1;
+            }
+            None => length = 0,
+        }
+        match length >= k {
+            true => Some(forward.min(reverse)),
+            _ => None,
+        }
+    })
+}
+
+/// Counts of the canonical k-mers of the soft clipped and the aligned sequence
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KmerCounts {
+    /// length of the k-mers
+    k: usize,
+    /// counts of the k-mers of the soft clipped sequences
+    clipped: HashMap<u64, u64>,
+    /// counts of the k-mers of the rest of the sequences
+    aligned: HashMap<u64, u64>,
+}
+
+/// A k-mer over-represented in the soft clipped sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnrichedKmer {
+    /// sequence of the k-mer (the smaller of the k-mer and its reverse complement)
+    pub kmer: String,
+    /// occurrences in the soft clipped sequence
+    pub clipped: u64,
+    /// occurrences in the aligned sequence
+    pub aligned: u64,
+    /// frequency of the k-mer in the soft clipped sequence over its frequency in the aligned
+    /// sequence, both with a pseudo-count of 1
+    pub enrichment: f64,
+}
+
+impl KmerCounts {
+    /// Create empty counts
+    ///
+    /// # Arguments
+    /// * `k`: length of the k-mers, up to [MAX_KMER_SIZE]
+    pub fn new(k: usize) -> Self {
+        Self {
+            k: k.clamp(1, MAX_KMER_SIZE),
+            clipped: HashMap::new(),
+            aligned: HashMap::new(),
+        }
+    }
+
+    /// Count the canonical k-mers of a sequence
+    fn count(k: usize, seq: &[u8], counts: &mut HashMap<u64, u64>) {
+        for kmer in canonical_kmers(seq, k) {
+            *counts.entry(kmer).or_insert(0) += 1;
+        }
+    }
+
+    /// Count the k-mers of the soft clipped and the aligned sequence of an alignment, each
+    /// soft clip on its own
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::kmers::KmerCounts;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    /// let mut counts = KmerCounts::new(4);
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("6M5S").unwrap();
+    /// record.set(b"read", Some(&cigar), b"ACGTACAGATC", &[30; 11]);
+    /// counts.add(&record);
+    /// assert_eq!(counts.clipped_len(), 2);
+    /// assert_eq!(counts.aligned_len(), 3);
+    /// ```
+    pub fn add(&mut self, record: &Record) {
+        let seq: Vec<u8> = record.seq().as_bytes();
+        let cigar: CigarStringView = record.cigar();
+        let leading: usize = (cigar.leading_softclips() as usize).min(seq.len());
+        let trailing: usize = (cigar.trailing_softclips() as usize).min(seq.len() - leading);
+        let aligned_end: usize = seq.len() - trailing;
+        Self::count(self.k, &seq[..leading], &mut self.clipped);
+        Self::count(self.k, &seq[aligned_end..], &mut self.clipped);
+        Self::count(self.k, &seq[leading..aligned_end], &mut self.aligned);
+    }
+
+    /// Add the counts of another part of the input, counted with the same k
+    pub fn merge(&mut self, other: &KmerCounts) {
+        for (counts, other_counts) in [
+            (&mut self.clipped, &other.clipped),
+            (&mut self.aligned, &other.aligned),
+        ] {
+            for (kmer, count) in other_counts.iter() {
+                *counts.entry(*kmer).or_insert(0) += count;
+            }
+        }
+    }
+
+    /// Number of k-mers counted in the soft clipped sequence
+    pub fn clipped_len(&self) -> u64 {
+        self.clipped.values().sum()
+    }
+
+    /// Number of k-mers counted in the aligned sequence
+    pub fn aligned_len(&self) -> u64 {
+        self.aligned.values().sum()
+    }
+
+    /// The k-mers of the soft clipped sequence, the most enriched first (ties broken by the
+    /// number of occurrences in the clips, then by sequence)
+    ///
+    /// # Arguments
+    /// * `top`: most k-mers to return
+    pub fn enriched(&self, top: usize) -> Vec<EnrichedKmer> {
+        let clipped_total: f64 = self.clipped_len() as f64 + 1.0;
+        let aligned_total: f64 = self.aligned_len() as f64 + 1.0;
+        let mut kmers: Vec<EnrichedKmer> = self
+            .clipped
+            .iter()
+            .map(|(kmer, clipped)| {
+                let aligned: u64 = self.aligned.get(kmer).copied().unwrap_or(0);
+                EnrichedKmer {
+                    kmer: decode(*kmer, self.k),
+                    clipped: *clipped,
+                    aligned,
+                    enrichment: ((*clipped + 1) as f64 / clipped_total)
+                        / ((aligned + 1) as f64 / aligned_total),
+                }
+            })
+            .collect();
+        kmers.sort_by(|a, b| {
+            b.enrichment
+                .total_cmp(&a.enrichment)
+                .then(b.clipped.cmp(&a.clipped))
+                .then_with(|| a.kmer.cmp(&b.kmer))
+        });
+        kmers.truncate(top);
+        kmers
+    }
+
+    /// Write the `top` most enriched k-mers of the soft clipped sequence as a TSV (columns:
+    /// kmer, clipped, aligned, enrichment), the most enriched first
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the TSV
+    /// * `top`: most k-mers to write
+    pub fn write_tsv<W: Write>(
+        &self,
+        writer: &mut W,
+        top: usize,
+    ) -> Result<(), FilterClippedError> {
+        writeln!(writer, "kmer\tclipped\taligned\tenrichment")?;
+        for kmer in self.enriched(top) {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{:.3}",
+                kmer.kmer, kmer.clipped, kmer.aligned, kmer.enrichment
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(3, "ACGT", vec![("ACG", 2)])]
+    #[case(3, "AAAT", vec![("AAA", 1), ("AAT", 1)])]
+    #[case(3, "AANAAT", vec![("AAT", 1)])]
+    #[case(2, "tttt", vec![("AA", 3)])]
+    #[case(4, "ACG", vec![])]
+    fn test_count(#[case] k: usize, #[case] seq: &str, #[case] expected: Vec<(&str, u64)>) {
+        let mut counts: HashMap<u64, u64> = HashMap::new();
+        KmerCounts::count(k, seq.as_bytes(), &mut counts);
+        let mut found: Vec<(String, u64)> = counts
+            .iter()
+            .map(|(kmer, count)| (decode(*kmer, k), *count))
+            .collect();
+        found.sort();
+        let expected: Vec<(String, u64)> = expected
+            .into_iter()
+            .map(|(kmer, count)| (kmer.to_string(), count))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_count_max_size() {
+        let seq: &[u8] = b"ACGTACGTACGTACGTACGTACGTACGTACGTA";
+        let mut counts: HashMap<u64, u64> = HashMap::new();
+        KmerCounts::count(MAX_KMER_SIZE, seq, &mut counts);
+        assert_eq!(counts.values().sum::<u64>(), 2);
+        assert!(counts
+            .keys()
+            .any(|kmer| decode(*kmer, MAX_KMER_SIZE) == "ACGTACGTACGTACGTACGTACGTACGTACGT"));
+    }
+
+    #[test]
+    fn test_enriched() {
+        let mut counts = KmerCounts::new(3);
+        KmerCounts::count(3, b"GGGGG", &mut counts.clipped);
+        KmerCounts::count(3, b"AAAC", &mut counts.clipped);
+        KmerCounts::count(3, b"AAACAAAC", &mut counts.aligned);
+        let kmers: Vec<EnrichedKmer> = counts.enriched(2);
+        assert_eq!(kmers.len(), 2);
+        assert_eq!(kmers[0].kmer, "CCC");
+        assert_eq!((kmers[0].clipped, kmers[0].aligned), (3, 0));
+        assert!(kmers[0].enrichment > 1.0);
+        assert!(kmers[1].enrichment < 1.0);
+        let mut tsv: Vec<u8> = Vec::new();
+        counts.write_tsv(&mut tsv, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap().lines().count(),
+            2,
+            "header and the top k-mer"
+        );
+    }
+}
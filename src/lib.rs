@@ -1,18 +1,69 @@
+pub mod adapters;
+pub mod chimeric;
 pub mod cli;
 pub mod clipping;
+pub mod collate;
+pub mod compare;
+pub mod complexity;
+pub mod config;
+pub mod consensus;
+pub mod distribution;
+pub mod error;
+pub mod expression;
+pub mod ffi;
+pub mod filter;
+pub mod gc;
+pub mod header;
+pub mod histogram;
+pub mod io;
+pub mod kmers;
+pub mod logging;
+pub mod man;
+#[cfg(feature = "parquet")]
+pub mod metrics;
+pub mod parallel;
+pub mod primers;
+pub mod profile;
+pub mod progress;
+pub mod prometheus;
+pub mod record_filter;
+pub mod report;
+pub mod screen;
+pub mod stats;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod subsample;
+pub mod tags;
+pub mod version;
 
-use cli::Parser;
-use clipping::ClipStat;
+use adapters::AdapterPolicy;
+use cli::FromArgMatches;
+use config::{
+    is_seqless_secondary, is_unmapped_input, AlreadyFilteredPolicy, FilterConfig, FlagPolicy,
+    PairPolicy, SingletonPolicy, Thresholds, UnmappedPolicy,
+};
+use error::FilterClippedError;
+use filter::{ClipFilter, FilterDecision};
+use header::SortOrder;
+use stats::{FailReason, RunStats};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use rust_htslib::{
     bam,
-    bam::{record::CigarStringView, Header, Read, Reader, Record},
+    bam::{Header, HeaderView, Read, Reader, Record},
 };
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io::Write;
+use std::time::Instant;
 
 /// Workflow to process an input bam file and write the pass-filter alignments
 /// into a new bam file
 ///
+/// Deprecated: the positional arguments are easy to mix up, use [run_with_config]
+/// with a [FilterConfig] instead
+///
 /// # Arguments
 /// - `in_bam`: input bam file
 /// - `out_bam`: output bam file
@@ -24,6 +75,7 @@ use rust_htslib::{
 /// # Examples
 ///
 /// ```
+/// #![allow(deprecated)]
 /// use filter_clipped::run;
 /// use rust_htslib::bam;
 /// use rust_htslib::bam::Read;
@@ -50,6 +102,10 @@ use rust_htslib::{
 ///     );
 /// count_bam(out_bam.to_string(), 6);
 /// ```
+#[deprecated(
+    since = "0.3.0",
+    note = "use run_with_config with a FilterConfig, e.g. FilterConfig::new().left(0.1).right(0.1)"
+)]
 pub fn run(
     in_bam: String,
     out_bam: String,
@@ -58,142 +114,3523 @@ pub fn run(
     left_side: f64,
     right_side: f64,
     unalign: bool,
-) -> Result<u8, String> {
-    let mut out_count: u32 = 0;
-    let mut in_count: u32 = 0;
-    let mut unaligned_count: u32 = 0;
-    info!("Reading from alignment file: {}", in_bam);
-    info!("Writing to alignment file: {}", out_bam);
-    info!(
-        "Thresholds: trailing clipped: {}, leading clipped: {}, total clipped: {}",
-        right_side, left_side, both_end
-    );
-    let mut in_bam: Reader = match in_bam.eq("-") {
-        true => bam::Reader::from_stdin().map_err(|e| e.to_string())?,
-        _ => bam::Reader::from_path(&in_bam).map_err(|e| e.to_string())?,
+) -> Result<u8, FilterClippedError> {
+    run_with_config(&FilterConfig {
+        in_bam,
+        out_bam,
+        inverse,
+        both_end,
+        left_side,
+        right_side,
+        unalign,
+        ..FilterConfig::default()
+    })
+    .map(|_| 0) // exit code 0
+}
+
+/// Workflow to process an input alignment file and write the pass-filter alignments
+/// into a new alignment file, with all the settings taken from a [FilterConfig]
+///
+/// # Arguments
+/// - `config`: settings for the run, see [FilterConfig]
+///
+/// # Returns
+/// - a summary of what happened to the alignments, see [RunStats]
+///
+/// # Examples
+///
+/// ```
+/// use filter_clipped::run_with_config;
+/// use filter_clipped::config::{FilterConfig, FlagPolicy};
+///
+/// let config = FilterConfig::new()
+///     .in_bam("test/data/secondary.sam")
+///     .out_bam("out_config.sam")
+///     .secondary(FlagPolicy::Drop)
+///     .supplementary(FlagPolicy::Drop);
+/// let stats = run_with_config(&config).unwrap();
+/// assert_eq!(stats.written, 2);
+/// assert_eq!(stats.dropped, 3);
+/// ```
+pub fn run_with_config(config: &FilterConfig) -> Result<RunStats, FilterClippedError> {
+    let started: Instant = Instant::now();
+    let config: Cow<FilterConfig> = prepare_run(config)?;
+    let config: &FilterConfig = &config;
+
+    if config.region_threads > 1 {
+        match io::open_indexed(config) {
+            Ok(in_bam) => {
+                if already_filtered(config, in_bam.header())? {
+                    return Ok(RunStats::skipped());
+                }
+                let header: Header = output_header(config, in_bam.header())?;
+                let mut out_bam: bam::Writer = io::open_writer(config, &header)?;
+                let stats: RunStats = parallel::filter_windows(config, &mut out_bam)?;
+                return finish_run(config, out_bam, stats, started);
+            }
+            Err(e) => warn!(
+                "Filtering {} as a single stream, it can't be read by region: {}",
+                config.in_bam, e
+            ),
+        }
+    }
+    let mut in_bams: Vec<Reader> = io::open_inputs(config)?;
+    let merged: Option<header::MergedHeader> = match in_bams.len() {
+        1 => None,
+        _ => {
+            let headers: Vec<Vec<u8>> = in_bams
+                .iter()
+                .map(|in_bam| in_bam.header().as_bytes().to_vec())
+                .collect();
+            Some(header::merge_headers(&headers)?)
+        }
+    };
+    let in_header: HeaderView = match merged.as_ref() {
+        Some(merged) => HeaderView::from_bytes(&merged.text),
+        None => in_bams[0].header().clone(),
+    };
+    if already_filtered(config, &in_header)? {
+        return Ok(RunStats::skipped());
+    }
+    // only once the run isn't skipped, the first pass reading the whole input
+    let first_pass: Option<FirstPass> = match config.two_pass {
+        true => Some(run_first_pass(config)?),
+        _ => None,
     };
-    let header: Header = bam::Header::from_template(in_bam.header());
+    let header: Header = output_header(config, &in_header)?;
+    let mut out_bam: bam::Writer = io::open_writer(config, &header)?;
+    let stats: RunStats = match merged {
+        None => process_records(&mut in_bams[0], &mut out_bam, config, first_pass.as_ref())?,
+        Some(merged) => filter_records(
+            io::merged_records(in_bams, merged.tid_maps),
+            &mut out_bam,
+            config,
+            first_pass.as_ref(),
+        )?,
+    };
+    finish_run(config, out_bam, stats, started)
+}
 
-    let mut out_bam = match out_bam.eq("-") {
-        true => bam::Writer::from_stdout(&header, bam::Format::Bam).map_err(|e| e.to_string())?,
-        _ => bam::Writer::from_path(&out_bam, &header, bam::Format::Bam)
-            .map_err(|e| e.to_string())?,
+/// Get the settings ready for a run, the same for every way of running: check them, apply
+/// the preset of the aligner and the derived thresholds if asked for, log them, and check
+/// that the output can be indexed if `write_index` is set
+///
+/// # Arguments
+/// - `config`: settings for the run
+///
+/// # Returns
+/// - the settings the run goes with
+fn prepare_run(config: &FilterConfig) -> Result<Cow<'_, FilterConfig>, FilterClippedError> {
+    config.validate()?;
+    // checked before the input is opened, reading the header of stdin would wait for it
+    if config.two_pass && config.in_bam.eq("-") {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "two-pass mode needs to read the input twice and doesn't work with stdin",
+        )));
+    }
+    let config: Cow<FilterConfig> = match apply_auto_preset(config)? {
+        Cow::Borrowed(config) => apply_auto_threshold(config)?,
+        Cow::Owned(config) => Cow::Owned(apply_auto_threshold(&config)?.into_owned()),
     };
+    log_settings(&config);
+    if config.write_index {
+        io::check_indexable(&config)?;
+    }
+    Ok(config)
+}
 
-    for r in in_bam.records() {
-        in_count += 1;
-        let mut record: Record = r.map_err(|e| e.to_string())?;
-        let seq_len: f64 = record.seq().len() as f64;
-        let cigar: CigarStringView = record.cigar();
+/// Set the preset of the aligner that made the input if `auto_preset` is set, from the
+/// @PG records of its header
+///
+/// # Arguments
+/// - `config`: settings for the run
+///
+/// # Returns
+/// - the settings with the preset of the aligner, or the settings as they are
+fn apply_auto_preset(config: &FilterConfig) -> Result<Cow<'_, FilterConfig>, FilterClippedError> {
+    if !config.auto_preset {
+        return Ok(Cow::Borrowed(config));
+    }
+    if config.in_bam.eq("-") {
+        warn!("The header of stdin can't be read ahead to pick a preset, keeping the thresholds as they are");
+        return Ok(Cow::Borrowed(config));
+    }
+    let in_bam: Reader = io::open_reader(config)?;
+    match header::detect_aligner(in_bam.header().as_bytes()) {
+        Some(aligner) => {
+            info!(
+                "Input aligned with {:?}, using the {:?} preset",
+                aligner,
+                aligner.preset()
+            );
+            Ok(Cow::Owned(config.clone().preset(aligner.preset())))
+        }
+        None => {
+            warn!("No known aligner in the @PG records of the input, keeping the thresholds as they are");
+            Ok(Cow::Borrowed(config))
+        }
+    }
+}
 
-        let leading_clipped: Vec<i64> = vec![cigar.leading_softclips(), cigar.leading_hardclips()];
-        let trailing_cliped: Vec<i64> =
-            vec![cigar.trailing_softclips(), cigar.trailing_hardclips()];
+/// Derive the thresholds from a first pass over the input if `auto_threshold` is set
+///
+/// # Arguments
+/// - `config`: settings for the run
+///
+/// # Returns
+/// - the settings with the derived thresholds, or the settings as they are
+fn apply_auto_threshold(
+    config: &FilterConfig,
+) -> Result<Cow<'_, FilterConfig>, FilterClippedError> {
+    let percentile: f64 = match config.auto_threshold {
+        Some(percentile) => percentile,
+        None => return Ok(Cow::Borrowed(config)),
+    };
+    let clip_distribution = distribution::scan(config)?;
+    let thresholds: Thresholds = match clip_distribution.thresholds_at(percentile) {
+        Some(thresholds) => thresholds,
+        None => {
+            warn!("No alignments to derive thresholds from, keeping the thresholds as they are");
+            return Ok(Cow::Borrowed(config));
+        }
+    };
+    info!(
+        "Thresholds at the {} percentile of {} alignments: trailing clipped: {}, leading clipped: {}, total clipped: {}",
+        percentile,
+        clip_distribution.len(),
+        thresholds.right_side,
+        thresholds.left_side,
+        thresholds.both_end
+    );
+    Ok(Cow::Owned(config.clone().thresholds_from(thresholds)))
+}
 
-        let clip_stat: ClipStat = ClipStat::new(leading_clipped, trailing_cliped);
+/// Log the settings of a run, and warn about settings that don't go well together
+fn log_settings(config: &FilterConfig) {
+    info!("Reading from alignment file: {}", config.in_bam);
+    for path in config.extra_inputs.iter() {
+        info!("Reading from alignment file: {}", path);
+    }
+    info!("Writing to alignment file: {}", config.out_bam);
+    info!(
+        "Thresholds: trailing clipped: {}, leading clipped: {}, total clipped: {}",
+        config.right_side, config.left_side, config.both_end
+    );
+    info!(
+        "Secondary alignments: {:?}, supplementary alignments: {:?}, duplicates: {:?}, QC-fail: {:?}",
+        config.secondary, config.supplementary, config.duplicates, config.qcfail
+    );
+    for (mate, thresholds) in [
+        ("first", config.read1_thresholds),
+        ("second", config.read2_thresholds),
+    ] {
+        if let Some(thresholds) = thresholds {
+            info!("Using {:?} for the {} mates of the pairs", thresholds, mate);
+        }
+    }
+    if config.require_proper_pair {
+        info!("Removing the alignments without the proper-pair flag");
+    }
+    if let Some(strand) = config.only_strand {
+        info!(
+            "Only checking the alignments on the {:?} strand, writing the others out",
+            strand
+        );
+    }
+    if config.paired {
+        info!(
+            "Paired mode: keeping read pairs if {:?} mates pass",
+            config.pair_policy
+        );
+    }
+    if config.collate {
+        info!(
+            "Grouping the alignments by read name, holding up to {} alignments in memory",
+            config.collate_max_pending
+        );
+    }
+    if let Some(policy) = config.singletons.as_ref() {
+        info!("Mates passing without their mate: {:?}", policy);
+    }
+    if config.promote_supplementary {
+        info!("Promoting passing supplementary alignments in place of failing primary alignments");
+    }
+    if config.zero_mapq {
+        info!("Keeping the failed alignments with MAPQ set to 0");
+    }
+    if config.unalign && !(config.paired || config.two_pass) {
+        warn!("Mate information of unaligned reads is only updated in --paired or --two-pass mode");
+    }
+    if config.skip_records > 0 || config.max_records.is_some() {
+        info!(
+            "Filtering {} alignments after skipping {}",
+            config
+                .max_records
+                .map_or(String::from("all the"), |max| max.to_string()),
+            config.skip_records
+        );
+    }
+    if let Some(fraction) = config.max_indel_fraction {
+        info!(
+            "Also removing the alignments with more than {} of their bases inserted or deleted",
+            fraction
+        );
+    }
+    if let Some(fraction) = config.max_mismatch_fraction {
+        info!(
+            "Also removing the alignments with more than {} of their aligned bases mismatched",
+            fraction
+        );
+    }
+    if let Some(fraction) = config.min_query_coverage {
+        info!(
+            "Also removing the alignments with less than {} of their read aligned",
+            fraction
+        );
+    }
+    if let Some(len) = config.min_aligned_block {
+        info!(
+            "Also removing the alignments without {} contiguous aligned bases (indels of up to {} bases allowed)",
+            len, config.aligned_block_max_indel
+        );
+    }
+    if let Some(fraction) = config.min_split_coverage {
+        info!(
+            "Gating the split reads on at least {} of the read covered by all their segments",
+            fraction
+        );
+    }
+    if config.min_insert_size.is_some() || config.max_insert_size.is_some() {
+        info!(
+            "Also removing the paired alignments with an insert size outside of {}..={}",
+            config.min_insert_size.unwrap_or(0),
+            config
+                .max_insert_size
+                .map_or(String::from(""), |max| max.to_string())
+        );
+    }
+    if let Some(expression) = config.expression.as_deref() {
+        info!("Also keeping only the alignments matching: {}", expression);
+    }
+    if config.tolerant {
+        info!("Skipping up to {} malformed records", config.max_malformed);
+    }
+    if config.sa_aware {
+        info!("SA-aware mode: clipping of chimeric reads is computed over all of their segments");
+    }
+    for (contig, thresholds) in config.contig_thresholds.iter() {
+        info!(
+            "Thresholds on {}: trailing clipped: {}, leading clipped: {}, total clipped: {}",
+            contig, thresholds.right_side, thresholds.left_side, thresholds.both_end
+        );
+    }
+    if let Some(weights) = config.score_weights.as_ref() {
+        info!(
+            "Scoring mode: failing alignments with a score above {}, weights: {:?}",
+            config.max_score, weights
+        );
+    }
+    for range in config.length_thresholds.iter() {
+        info!(
+            "Thresholds for reads of {}-{} bases: trailing clipped: {}, leading clipped: {}, total clipped: {}",
+            range.min_len,
+            range.max_len.map_or(String::new(), |len| len.to_string()),
+            range.thresholds.right_side,
+            range.thresholds.left_side,
+            range.thresholds.both_end
+        );
+    }
+    if !config.adapters.is_empty() {
+        info!(
+            "{} adapters looked for in soft clipped bases, alignments clipped at an adapter: {:?}",
+            config.adapters.len(),
+            config.adapter_policy
+        );
+    }
+    if !config.contaminants.is_empty() {
+        info!(
+            "{} contaminants screened for in soft clipped bases ({}-mers), alignments with a hit {}",
+            config.contaminants.len(),
+            config.screen_kmer_size,
+            if config.screen_fail { "fail" } else { "are only counted" }
+        );
+    }
+    if !config.primers.is_empty() {
+        info!(
+            "Clips within {} primer intervals are not counted as clipped",
+            config.primers.len()
+        );
+    }
+    if config.rna {
+        match config.spliced_thresholds {
+            Some(thresholds) => info!(
+                "Thresholds of spliced alignments: trailing clipped: {}, leading clipped: {}, total clipped: {}",
+                thresholds.right_side, thresholds.left_side, thresholds.both_end
+            ),
+            None => info!("Spliced alignments are not filtered on clipping"),
+        }
+    }
+    if config.exempt_low_complexity {
+        info!("Low-complexity soft clips are not counted as clipped");
+    }
+    if !config.exempt_motifs.is_empty() {
+        info!(
+            "Soft clips repeating {} (with a purity of at least {}) are not counted as clipped",
+            config.exempt_motifs.join(", "),
+            config.motif_purity
+        );
+    }
+    if config.min_clip_bq > 0 {
+        info!(
+            "Only soft clipped bases with a base quality of at least {} are counted as clipped",
+            config.min_clip_bq
+        );
+    }
+    if config.ignore_supplementary_hardclips {
+        info!("Hard clipped bases of supplementary alignments are not counted as clipped");
+    }
+    if config.cascade {
+        info!("Cascade mode: secondary/supplementary alignments follow their primary alignments");
+    }
+    if config.two_pass {
+        info!(
+            "Two-pass mode: removing read pairs unless {:?} mates pass",
+            config.pair_policy
+        );
+    }
+}
 
-        let keep: bool = clip_stat.total_fraction(seq_len)? < both_end
-            && clip_stat.left_fraction(seq_len)? <= left_side
-            && clip_stat.right_fraction(seq_len)? <= right_side;
+/// Whether the run stops here, the input having been filtered by this program with the
+/// same settings already (see [header::settings_fingerprint]): depending on
+/// `if_already_filtered`, a warning is logged and the input is filtered again, or the run
+/// stops without writing any output
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `in_header`: header of the input file (merged if there are several)
+fn already_filtered(
+    config: &FilterConfig,
+    in_header: &HeaderView,
+) -> Result<bool, FilterClippedError> {
+    let settings: String = header::settings_fingerprint(config)?;
+    if !header::previous_settings(in_header.as_bytes()).contains(&settings) {
+        return Ok(false);
+    }
+    match config.if_already_filtered {
+        AlreadyFilteredPolicy::Warn => {
+            warn!(
+                "{} was already filtered by {} with the same settings, filtering it again",
+                config.in_bam,
+                header::PROGRAM_NAME
+            );
+            Ok(false)
+        }
+        AlreadyFilteredPolicy::Skip => {
+            info!(
+                "Skipping {}: it was already filtered by {} with the same settings, nothing is written",
+                config.in_bam,
+                header::PROGRAM_NAME
+            );
+            Ok(true)
+        }
+        AlreadyFilteredPolicy::Proceed => Ok(false),
+    }
+}
 
-        debug!("{:?} {}", clip_stat, seq_len);
-        if !(unalign) {
-            if (keep && !inverse) || (inverse && !keep) {
-                out_bam.write(&record).map_err(|e| e.to_string())?;
-                out_count += 1;
-            }
-        } else {
-            if keep {
-                out_bam.write(&record).map_err(|e| e.to_string())?;
-            } else {
-                record.set_unmapped();
-                record.unset_reverse();
-                record.unset_proper_pair();
-                record.set_tid(-1);
-                record.set_pos(-1);
-                out_bam.write(&record).map_err(|e| e.to_string())?;
-                unaligned_count += 1
-            }
-            out_count += 1;
+/// Build the header of the output file from the header of the input: the sort order
+/// is checked (and updated if the output order differs) and a @PG record is added
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `in_header`: header of the input file (merged if there are several)
+///
+/// # Returns
+/// - the output header, or an error if the input order doesn't fit the settings
+///   and `require_sorted` is set
+fn output_header(
+    config: &FilterConfig,
+    in_header: &HeaderView,
+) -> Result<Header, FilterClippedError> {
+    let input_order: SortOrder = header::sort_order(in_header.as_bytes());
+    let output_order: SortOrder = check_sort_order(config, input_order)?;
+    let template: HeaderView = match output_order == input_order {
+        true => in_header.clone(),
+        _ => {
+            info!(
+                "Sort order of the output changes from {} to {}",
+                input_order.as_str(),
+                output_order.as_str()
+            );
+            HeaderView::from_bytes(&header::set_sort_order(in_header.as_bytes(), output_order))
         }
+    };
+    let mut header: Header = bam::Header::from_template(&template);
+    let settings: String = header::settings_fingerprint(config)?;
+    header::add_program_record(
+        &mut header,
+        &template,
+        config.command_line.as_deref(),
+        Some(&settings),
+    );
+    if config.header_comment {
+        header::add_config_comment(&mut header, config)?;
+    }
+    Ok(header)
+}
+
+/// Close the output file, index it if asked for and log the totals of the run
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `out_bam`: the output file, all alignments written
+/// - `stats`: totals of the run
+/// - `started`: when the run started, for its throughput
+fn finish_run(
+    config: &FilterConfig,
+    out_bam: bam::Writer,
+    mut stats: RunStats,
+    started: Instant,
+) -> Result<RunStats, FilterClippedError> {
+    let empty_profile = profile::ClipProfile::new(config.profile_window);
+    let clip_profile: &profile::ClipProfile = stats.profile.as_ref().unwrap_or(&empty_profile);
+    if let Some(path) = config.clip_profile.as_deref() {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        clip_profile.write_tsv(&mut file, out_bam.header())?;
+        file.flush()?;
+        info!("Written the clipping profile to {}", path);
+    }
+    if let Some(path) = config.breakpoint_bed.as_deref() {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        clip_profile.write_breakpoints(
+            &mut file,
+            out_bam.header(),
+            config.min_breakpoint_support,
+        )?;
+        file.flush()?;
+        info!("Written soft clip breakpoints to {}", path);
     }
+    if let Some(path) = config.clip_consensus.as_deref() {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        stats
+            .consensus
+            .write_fasta(&mut file, out_bam.header(), config.min_consensus_support)?;
+        file.flush()?;
+        info!(
+            "Written clip consensus sequences of {} clip boundaries to {}",
+            stats.consensus.len(),
+            path
+        );
+    }
+    // the output has to be flushed and closed before it can be indexed or measured
+    let header_view: HeaderView = out_bam.header().clone();
+    drop(out_bam);
+    let throughput = stats::Throughput::new(
+        started.elapsed(),
+        stats.read,
+        io::input_bytes(config),
+        io::output_bytes(config),
+    );
+    if config.write_index {
+        io::write_index(config, &header_view)?;
+        info!("Indexed alignment file: {}", config.out_bam);
+    }
+
     info!(
         "Read {} alignments; Written {} alignments; Making {} to unaligned",
-        in_count, out_count, unaligned_count,
+        stats.read, stats.written, stats.unaligned,
+    );
+    info!(
+        "Kept {} alignments without filtering and dropped {} alignments based on their flags",
+        stats.bypassed, stats.dropped,
+    );
+    if stats.already_unmapped > 0 {
+        info!(
+            "{} alignments were already unmapped in the input ({:?})",
+            stats.already_unmapped, config.unmapped
+        );
+    }
+    if stats.seqless_secondary > 0 {
+        warn!(
+            "{} secondary alignments have no sequence to work out their clip fractions from ({:?})",
+            stats.seqless_secondary, config.seqless_secondary
+        );
+    }
+    if let Some(fraction) = config.subsample {
+        info!(
+            "Left {} alignments out of the subsample of {} of the reads (seed {})",
+            stats.subsampled_out, fraction, config.seed
+        );
+    }
+    if config.promote_supplementary {
+        info!(
+            "Made {} supplementary alignments primary in place of failing primary alignments",
+            stats.promoted
+        );
+    }
+    if let Some(policy) = config.singletons.as_ref() {
+        info!(
+            "Found {} mates passing the filter without their mate ({:?})",
+            stats.singletons, policy
+        );
+    }
+    if config.zero_mapq {
+        info!("Set the MAPQ of {} failed alignments to 0", stats.zero_mapq);
+    }
+    if config.fix_orphans {
+        info!(
+            "Marked the mate of {} alignments written without their mate as unmapped",
+            stats.orphans
+        );
+    }
+    if config.require_proper_pair {
+        info!(
+            "{} alignments without the proper-pair flag",
+            stats
+                .failed_by_reason
+                .get(&FailReason::ProperPair)
+                .copied()
+                .unwrap_or(0)
+        );
+    }
+    info!(
+        "{} alignments failed the filter: {:?}",
+        stats.failed(),
+        stats.failed_by_reason
     );
-    Ok(0) // exit code 0
+    if stats.malformed > 0 {
+        warn!(
+            "Skipped {} malformed records that couldn't be read",
+            stats.malformed
+        );
+    }
+    for (contig, counts) in stats.contigs.iter() {
+        info!(
+            "{}: read {}; written {}; unaligned {}; removed {} ({:.2}% filtered)",
+            contig,
+            counts.read,
+            counts.written,
+            counts.unaligned,
+            counts.removed,
+            counts.filtered_percent()
+        );
+    }
+    if !stats.adapter_hits.is_empty() {
+        info!("Adapters found in clipped bases: {:?}", stats.adapter_hits);
+    }
+    if !stats.contaminant_hits.is_empty() {
+        info!(
+            "Contaminants found in clipped bases: {:?}",
+            stats.contaminant_hits
+        );
+    }
+    let rate =
+        |rate: Option<f64>| rate.map_or(String::from("unknown"), |r| format!("{:.1} MB/s", r));
+    info!(
+        "Took {:.2}s: {:.0} alignments/s; input {}; output {}",
+        throughput.seconds,
+        throughput.records_per_second,
+        rate(throughput.input_mb_per_second),
+        rate(throughput.output_mb_per_second)
+    );
+    stats.throughput = Some(throughput);
+    logging::log_counters(&stats);
+    if config.flagstat {
+        let categories = stats.flag_categories.unwrap_or_default();
+        categories.write_table(&mut std::io::stderr().lock())?;
+    }
+    if let Some(path) = config.hist_out.as_deref() {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        stats.histogram.write_tsv(&mut file)?;
+        file.flush()?;
+        info!(
+            "Written clip histograms of {} alignments to {}",
+            stats.histogram.len(),
+            path
+        );
+    }
+    if let Some(path) = config.gc_out.as_deref() {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        stats.gc.write_tsv(&mut file)?;
+        file.flush()?;
+        info!(
+            "Written the GC content of {} clipped and {} aligned sequences to {}",
+            stats.gc.clipped_len(),
+            stats.gc.aligned_len(),
+            path
+        );
+    }
+    if let Some(path) = config.kmer_out.as_deref() {
+        let kmer_counts = stats
+            .kmers
+            .take()
+            .unwrap_or_else(|| kmers::KmerCounts::new(config.kmer_size as usize));
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        kmer_counts.write_tsv(&mut file, config.top_kmers as usize)?;
+        file.flush()?;
+        info!(
+            "Written the most enriched of {} clipped {}-mers to {}",
+            kmer_counts.clipped_len(),
+            config.kmer_size,
+            path
+        );
+        if let Some(top) = kmer_counts.enriched(1).first() {
+            info!(
+                "Most enriched clipped {}-mer: {} ({} in clips, {} in aligned sequence)",
+                config.kmer_size, top.kmer, top.clipped, top.aligned
+            );
+        }
+    }
+    if let Some(path) = config.cell_stats.as_deref() {
+        stats::write_cell_stats(&stats.cells, path)?;
+        info!(
+            "Written statistics of {} cell barcodes to {}",
+            stats.cells.len(),
+            path
+        );
+    }
+    if let Some(path) = config.html_report.as_deref() {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        report::write_html(&mut file, &stats, config.command_line.as_deref())?;
+        file.flush()?;
+        info!("Written the HTML report to {}", path);
+    }
+    Ok(stats)
 }
 
-/// Just a wrapper function to read command line arguments and pass it to `run`
+/// Filter the alignments from an already opened reader into an already opened writer,
+/// e.g. to work on in-memory data (see [io::reader_from_sam_text]) instead of file paths.
 ///
-pub fn wrapper() {
-    let args = cli::Command::parse();
-    let result = run(
-        args.in_bam,
-        args.out_bam,
-        args.inverse,
-        args.both_end,
-        args.left_side,
-        args.right_side,
-        args.unalign,
-    );
-    match result {
-        Ok(_) => (),
-        Err(err) => println!("{}", err),
+/// Unlike [run_with_config], the output header is left as it is (no @PG record is added),
+/// and `two_pass` is not supported as the input can't be read twice
+///
+/// # Arguments
+/// - `reader`: input alignments
+/// - `writer`: output alignment file
+/// - `config`: settings for the run, the input/output paths are not used
+///
+/// # Returns
+/// - a summary of what happened to the alignments, see [RunStats]
+///
+/// # Examples
+///
+/// ```
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::io::reader_from_sam_text;
+/// use filter_clipped::process;
+/// use rust_htslib::bam::{self, Read};
+///
+/// let sam = "@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n\
+///     kept\t0\tchr1\t10\t60\t10M\t*\t0\t0\tACGTACGTAC\tFFFFFFFFFF\n\
+///     clipped\t0\tchr1\t10\t60\t5S5M\t*\t0\t0\tACGTACGTAC\tFFFFFFFFFF\n";
+/// let mut reader = reader_from_sam_text(sam).unwrap();
+/// let header = bam::Header::from_template(reader.header());
+/// let mut writer = bam::Writer::from_path("out_process.sam", &header, bam::Format::Sam).unwrap();
+/// let stats = process(&mut reader, &mut writer, &FilterConfig::new()).unwrap();
+/// assert_eq!(stats.read, 2);
+/// assert_eq!(stats.written, 1);
+/// ```
+pub fn process<R: Read>(
+    reader: &mut R,
+    writer: &mut bam::Writer,
+    config: &FilterConfig,
+) -> Result<RunStats, FilterClippedError> {
+    config.validate()?;
+    if config.two_pass || config.auto_threshold.is_some() {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "two-pass mode and auto_threshold need to read the input twice, use run_with_config with an input file",
+        )));
+    }
+    process_records(reader, writer, config, None)
+}
+
+/// The filtering loop shared by [run_with_config] and [process]
+///
+/// # Arguments
+/// - `reader`: input alignments
+/// - `writer`: output alignment file
+/// - `config`: settings for the run
+/// - `first_pass`: what the first pass found out about the read names in two-pass mode
+fn process_records<R: Read>(
+    reader: &mut R,
+    writer: &mut bam::Writer,
+    config: &FilterConfig,
+    first_pass: Option<&FirstPass>,
+) -> Result<RunStats, FilterClippedError> {
+    filter_records(
+        reader
+            .records()
+            .map(|r| r.map_err(FilterClippedError::from)),
+        writer,
+        config,
+        first_pass,
+    )
+}
+
+/// Filter a stream of alignments into the output file
+///
+/// # Arguments
+/// - `records`: input alignments, in the order of the input file
+/// - `writer`: output alignment file
+/// - `config`: settings for the run
+/// - `first_pass`: what the first pass found out about the read names in two-pass mode
+fn filter_records<I>(
+    records: I,
+    writer: &mut bam::Writer,
+    config: &FilterConfig,
+    first_pass: Option<&FirstPass>,
+) -> Result<RunStats, FilterClippedError>
+where
+    I: Iterator<Item = Result<Record, FilterClippedError>>,
+{
+    let clip_filter = run_clip_filter(config, writer.header())?;
+    let mut side_outputs = io::SideOutputs::create(config, writer.header())?;
+    let bar = progress::progress_bar(config, "Filtering");
+    let records = records
+        .enumerate()
+        .skip_while(|(index, _)| config.record_position(*index as u64) == Ordering::Less)
+        .take_while(|(index, _)| config.record_position(*index as u64) == Ordering::Equal)
+        .map(|(_, r)| r);
+    let records: Box<dyn Iterator<Item = Result<Record, FilterClippedError>> + '_> =
+        match config.collate {
+            true => Box::new(collate::Collator::new(records, config, writer.header())),
+            false => Box::new(records),
+        };
+    let stats: RunStats = filter_stream(
+        records,
+        &clip_filter,
+        first_pass,
+        writer,
+        &mut side_outputs,
+        &bar,
+    )?;
+    bar.finish_and_clear();
+    if let Some(deferred) = side_outputs.deferred.take() {
+        let appended: u64 = deferred.append_to(writer)?;
+        debug!(
+            "Appended {} alignments made unmapped after the mapped ones",
+            appended
+        );
+    }
+    side_outputs.finish(&stats)?;
+    Ok(stats)
+}
+
+/// The loop of [filter_records], grouping the alignments to evaluate together
+///
+/// # Arguments
+/// - `records`: input alignments, in the order of the input file
+/// - `clip_filter`: the filter holding the settings for the run
+/// - `first_pass`: what the first pass found out about the read names in two-pass mode
+/// - `writer`: output alignment file
+/// - `side_outputs`: writers for the side output files asked for
+/// - `bar`: progress bar counting the alignments read
+fn filter_stream<I>(
+    records: I,
+    clip_filter: &ClipFilter,
+    first_pass: Option<&FirstPass>,
+    writer: &mut bam::Writer,
+    side_outputs: &mut io::SideOutputs,
+    bar: &indicatif::ProgressBar,
+) -> Result<RunStats, FilterClippedError>
+where
+    I: Iterator<Item = Result<Record, FilterClippedError>>,
+{
+    let config: &FilterConfig = clip_filter.config();
+    let mut stats = RunStats::default();
+    // in paired mode, all alignments sharing a read name are buffered
+    // and evaluated together, otherwise each alignment is its own group
+    let mut read_group: Vec<Record> = Vec::new();
+    for r in records {
+        bar.inc(1);
+        let record: Record = match r {
+            Ok(record) => record,
+            Err(e) => {
+                io::skip_malformed(config, &mut stats.malformed, e)?;
+                continue;
+            }
+        };
+        stats.read += 1;
+        if let Some(progress) = side_outputs.progress.as_mut() {
+            progress.update(&stats)?;
+        }
+        if !read_group.is_empty() && (!config.paired || read_group[0].qname() != record.qname()) {
+            filter_read_group(
+                &mut read_group,
+                clip_filter,
+                first_pass,
+                writer,
+                side_outputs,
+                &mut stats,
+            )?;
+            read_group.clear();
+        }
+        read_group.push(record);
+    }
+    filter_read_group(
+        &mut read_group,
+        clip_filter,
+        first_pass,
+        writer,
+        side_outputs,
+        &mut stats,
+    )?;
+    Ok(stats)
+}
+
+/// Check that the input is sorted the way the settings need it to be, and
+/// work out the sort order of the output
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `input_order`: sort order declared in the input header
+///
+/// # Returns
+/// - the sort order of the output, or an error if the input order doesn't fit
+///   the settings and `require_sorted` is set (otherwise only a warning is logged)
+fn check_sort_order(
+    config: &FilterConfig,
+    input_order: SortOrder,
+) -> Result<SortOrder, FilterClippedError> {
+    let mut problems: Vec<String> = Vec::new();
+    if config.paired && !config.collate && !input_order.is_grouped_by_name() {
+        problems.push(format!(
+            "--paired needs the alignments of a read name next to each other (queryname sorted or collated), but the input is {}",
+            input_order.as_str()
+        ));
+    }
+    // unaligned alignments lose their position, which breaks the coordinate order
+    // unless they go to their own file or to the end of the output
+    let output_order: SortOrder = match (
+        config.unalign && config.unaligned_out.is_none() && !config.unaligned_to_end,
+        input_order,
+    ) {
+        // the alignments come out grouped by read name
+        _ if config.collate => SortOrder::Collated,
+        // the windows filtered in parallel come out as they are done
+        _ if config.unordered => SortOrder::Unsorted,
+        (true, SortOrder::Coordinate) => SortOrder::Unsorted,
+        _ => input_order,
     };
+    if config.write_index && output_order != SortOrder::Coordinate {
+        problems.push(format!(
+            "--write-index needs a coordinate sorted output, but the output is {}",
+            output_order.as_str()
+        ));
+    }
+    for problem in problems.iter() {
+        warn!("{}", problem);
+    }
+    match (config.require_sorted, problems.is_empty()) {
+        (true, false) => Err(FilterClippedError::InvalidConfig(problems.join("; "))),
+        _ => Ok(output_order),
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rstest::rstest;
-    use std::string::String;
+/// Set up the [ClipFilter] of a run, loading the cell barcode whitelist and the read name
+/// lists if there are any
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `header`: header of the alignments to filter
+fn run_clip_filter<'a>(
+    config: &'a FilterConfig,
+    header: &HeaderView,
+) -> Result<ClipFilter<'a>, FilterClippedError> {
+    let mut clip_filter = ClipFilter::with_header(config, header);
+    if let Some(path) = config.cb_whitelist.as_deref() {
+        let barcodes: HashSet<Vec<u8>> = io::read_name_list(path)?;
+        info!("Keeping the alignments of {} cell barcodes", barcodes.len());
+        clip_filter = clip_filter.with_cell_whitelist(barcodes);
+    }
+    if let Some(path) = config.keep_names.as_deref() {
+        let names: HashSet<Vec<u8>> = io::read_name_list(path)?;
+        info!("Keeping the alignments of {} listed reads", names.len());
+        clip_filter = clip_filter.with_keep_names(names);
+    }
+    if let Some(path) = config.drop_names.as_deref() {
+        let names: HashSet<Vec<u8>> = io::read_name_list(path)?;
+        info!("Removing the alignments of {} listed reads", names.len());
+        clip_filter = clip_filter.with_drop_names(names);
+    }
+    Ok(clip_filter)
+}
 
-    fn count_bam(bam_file: String, expected_count: i32, expected_unaligned: i32) {
-        let mut bam_reader = bam::Reader::from_path(bam_file).unwrap();
-        let mut aln_count = 0;
-        let mut unaligned_count = 0;
-        for r in bam_reader.records() {
-            let _record = r.unwrap();
-            aln_count += 1;
-            if _record.is_unmapped() {
-                unaligned_count += 1;
+/// What the first pass of the two-pass mode found out about the read names
+#[derive(Debug, Default)]
+struct FirstPass {
+    /// names of the reads failing the clipping thresholds given the pair policy
+    failed: HashSet<Vec<u8>>,
+    /// names and mate (whether first in template) of the primary alignments removed on their
+    /// own, by their flags, cell barcode or read name, with `fix_orphans`
+    removed_mates: HashSet<(Vec<u8>, bool)>,
+    /// names and mate (whether first in template) of the failing alignments, with `singletons`
+    failed_mates: HashSet<(Vec<u8>, bool)>,
+}
+
+/// First pass of the two-pass mode: read through the whole input file and collect
+/// the names of the reads that should be removed
+///
+/// # Arguments
+/// - `config`: settings for the run, the input must be a file (not stdin)
+///
+/// # Returns
+/// - names of the reads failing the clipping thresholds given the pair policy, and of the
+///   mates removed on their own with `fix_orphans`
+fn run_first_pass(config: &FilterConfig) -> Result<FirstPass, FilterClippedError> {
+    let mut failed: HashSet<Vec<u8>> = HashSet::new();
+    let mut passed: HashSet<Vec<u8>> = HashSet::new();
+    let mut removed_mates: HashSet<(Vec<u8>, bool)> = HashSet::new();
+    let mut failed_mates: HashSet<(Vec<u8>, bool)> = HashSet::new();
+    let bar = progress::progress_bar(config, "First pass");
+    let (mut index, mut malformed): (u64, u64) = (0, 0);
+    'inputs: for mut in_bam in io::open_inputs(config)? {
+        // per input, as the thresholds of reference sequences are looked up by tid
+        let clip_filter = run_clip_filter(config, in_bam.header())?;
+        for r in in_bam.records() {
+            bar.inc(1);
+            index += 1;
+            match config.record_position(index - 1) {
+                Ordering::Less => continue,
+                Ordering::Greater => break 'inputs,
+                Ordering::Equal => (),
+            }
+            let record: Record = match r {
+                Ok(record) => record,
+                Err(e) => {
+                    io::skip_malformed(config, &mut malformed, e.into())?;
+                    continue;
+                }
+            };
+            if config.fix_orphans
+                && record.is_paired()
+                && !record.is_secondary()
+                && !record.is_supplementary()
+                && clip_filter.evaluate(&record)?.is_removed()
+            {
+                removed_mates.insert((record.qname().to_vec(), record.is_first_in_template()));
+            }
+            if !config.is_decisive(&record) {
+                continue;
+            }
+            if clip_filter.clip_failure(&record)?.is_some() {
+                if config.singletons.is_some() {
+                    failed_mates.insert((record.qname().to_vec(), record.is_first_in_template()));
+                }
+                failed.insert(record.qname().to_vec());
+            } else if config.pair_policy == PairPolicy::Any {
+                // only needed to rescue a failing mate
+                passed.insert(record.qname().to_vec());
             }
         }
-        assert_eq!(expected_count, aln_count);
+    }
+    bar.finish_and_clear();
+    if config.pair_policy == PairPolicy::Any {
+        failed.retain(|name| !passed.contains(name));
+    }
+    info!("First pass: found {} failing reads", failed.len());
+    Ok(FirstPass {
+        failed,
+        removed_mates,
+        failed_mates,
+    })
+}
 
-        if expected_unaligned > 0 {
-            assert_eq!(expected_unaligned, unaligned_count);
+/// Evaluate a group of alignments (a single alignment, or all alignments of a read pair in paired mode)
+/// and write out the ones that should be kept
+///
+/// # Arguments
+/// - `read_group`: alignments sharing the same read name
+/// - `clip_filter`: the filter holding the settings for the run
+/// - `first_pass`: what the first pass found out about the read names in two-pass mode,
+///   the failing names taking the place of checking the clipping on the alignments
+/// - `out_bam`: writer for the output alignment file
+/// - `side_outputs`: writers for the side output files asked for
+/// - `stats`: running totals to update
+fn filter_read_group(
+    read_group: &mut [Record],
+    clip_filter: &ClipFilter,
+    first_pass: Option<&FirstPass>,
+    out_bam: &mut bam::Writer,
+    side_outputs: &mut io::SideOutputs,
+    stats: &mut RunStats,
+) -> Result<(), FilterClippedError> {
+    let config: &FilterConfig = clip_filter.config();
+    let mut decisions: Vec<FilterDecision> = Vec::with_capacity(read_group.len());
+    for record in read_group.iter() {
+        decisions.push(clip_filter.evaluate(record)?);
+    }
+    // failing primary alignments replaced by a supplementary alignment are left out
+    let demoted: Vec<bool> = match config.promote_supplementary {
+        true => promote_supplementaries(read_group, &decisions, out_bam.header())?,
+        false => vec![false; read_group.len()],
+    };
+    stats.promoted += demoted.iter().filter(|d| **d).count() as u64;
+    let keep: bool = match first_pass {
+        Some(first_pass) => !read_group
+            .iter()
+            .any(|r| first_pass.failed.contains(r.qname())),
+        None => {
+            let passes: Vec<bool> = read_group
+                .iter()
+                .zip(decisions.iter())
+                .zip(demoted.iter())
+                .filter(|((record, _), demoted)| !**demoted && config.is_decisive(record))
+                .map(|((_, decision), _)| decision.is_kept())
+                .collect();
+            match config.pair_policy {
+                PairPolicy::Both => passes.iter().all(|p| *p),
+                PairPolicy::Any => passes.is_empty() || passes.iter().any(|p| *p),
+            }
+        }
+    };
+    // in a failing pair, the alignments of the mate passing on its own are singletons
+    let singletons: Vec<bool> = match (config.singletons.as_ref(), keep) {
+        (Some(_), false) => read_group
+            .iter()
+            .map(|record| {
+                record.is_paired()
+                    && mate_passes(record, read_group, &decisions, &demoted, config, first_pass)
+            })
+            .collect(),
+        _ => vec![false; read_group.len()],
+    };
+    let kept: Vec<bool> = singletons
+        .iter()
+        .map(|singleton| keep || (*singleton && config.singletons != Some(SingletonPolicy::Drop)))
+        .collect();
+    stats.singletons += read_group
+        .iter()
+        .zip(singletons.iter())
+        .filter(|(record, singleton)| {
+            **singleton && !record.is_secondary() && !record.is_supplementary()
+        })
+        .count() as u64;
+
+    let mut fates: Vec<Fate> = read_group
+        .iter()
+        .zip(decisions.iter())
+        .zip(demoted.iter().zip(kept.iter()))
+        .map(|((record, decision), (demoted, keep))| match decision {
+            _ if *demoted => Fate::Remove,
+            decision if decision.is_removed() => Fate::Remove,
+            // kept like the passing alignments, so left out of the output of --inverse, apart
+            // from the unmapped ones passed through whatever is decided
+            FilterDecision::Bypass
+                if config.unalign
+                    || config.zero_mapq
+                    || !config.inverse
+                    || (config.unmapped == UnmappedPolicy::Passthrough
+                        && is_unmapped_input(record)) =>
+            {
+                Fate::Write
+            }
+            FilterDecision::Bypass => Fate::Remove,
+            _ if config.unalign && !keep && !is_unmapped_input(record) => Fate::Unalign,
+            _ if config.zero_mapq && !keep && !is_unmapped_input(record) => Fate::ZeroMapq,
+            _ if config.unalign || config.zero_mapq || *keep != config.inverse => Fate::Write,
+            _ => Fate::Remove,
+        })
+        .collect();
+    // the whole read name is in or out of the subsample
+    let subsampled_out: bool = match (config.subsample, read_group.first()) {
+        (Some(fraction), Some(record)) => {
+            !subsample::is_sampled(record.qname(), config.seed, fraction)
+        }
+        _ => false,
+    };
+    let mut left_out: Vec<bool> = vec![false; fates.len()];
+    if subsampled_out {
+        for (fate, left_out) in fates.iter_mut().zip(left_out.iter_mut()) {
+            if *fate == Fate::Write {
+                *fate = Fate::Remove;
+                *left_out = true;
+            }
         }
     }
 
-    #[rstest]
-    #[case(1, 0.2, 0.3, true, 0, 0, false)]
-    #[case(2, 0.2, 0.3, false, 9, 0, false)]
-    #[case(3, 0.1, 0.1, false, 6, 0, false)]
-    #[case(4, 0.1, 0.1, false, 9, 3, true)]
-    #[case(4, 0.1, 0.1, false, 9, 3, true)]
-    fn test_run(
-        #[case] test_case: usize,
-        #[case] max_both_end: f64,
-        #[case] max_single_end: f64,
-        #[case] inverse: bool,
-        #[case] expected_count: i32,
-        #[case] expected_unaligned: i32,
-        #[case] unalign: bool,
-    ) {
-        let out_bam: &str = &format!("test/data/out_{}.bam", test_case);
-        let result = run(
-            "test/data/test.sam".to_string(),
-            out_bam.to_string(),
-            inverse,
-            max_both_end,
-            max_single_end,
-            max_single_end,
-            unalign,
-        )
-        .unwrap();
-        assert_eq!(result, 0);
-        count_bam(out_bam.to_string(), expected_count, expected_unaligned);
+    if config.sa_aware {
+        update_sa_tags(read_group, &fates, config, out_bam.header())?;
+    }
+
+    // when the decision is made for the whole read name, a failing read has
+    // all of its alignments unaligned, so the mates can be fixed up here too,
+    // only the kept singletons when the failing mate alone is unaligned
+    let fix_mates: bool = config.unalign && !keep && (config.paired || first_pass.is_some());
+    let partial: bool = kept.iter().any(|kept| *kept);
+    let orphaned: Vec<bool> = match config.fix_orphans {
+        true => orphaned_alignments(read_group, &fates, first_pass),
+        false => vec![false; read_group.len()],
+    };
+
+    for (i, record) in read_group.iter_mut().enumerate() {
+        let (decision, fate, left_out, keep) = (decisions[i], fates[i], left_out[i], kept[i]);
+        match decision {
+            FilterDecision::Fail(reason) if demoted[i] => stats.add_failure(reason),
+            FilterDecision::Fail(reason) if decision.is_removed() => {
+                stats.dropped += 1;
+                stats.add_failure(reason);
+            }
+            FilterDecision::Bypass if !left_out => stats.bypassed += 1,
+            FilterDecision::Fail(reason) if !keep => stats.add_failure(reason),
+            FilterDecision::Pass if !keep => stats.add_failure(FailReason::Linked),
+            _ => (),
+        }
+        if left_out {
+            stats.subsampled_out += 1;
+        }
+        if is_unmapped_input(record) {
+            stats.already_unmapped += 1;
+        }
+        if is_seqless_secondary(record) {
+            stats.seqless_secondary += 1;
+        }
+        let contig: Cow<str> = match record.tid() {
+            tid if tid >= 0 => String::from_utf8_lossy(out_bam.header().tid2name(tid as u32)),
+            _ => Cow::Borrowed("*"),
+        };
+        let (written, unaligned): (bool, bool) = (fate != Fate::Remove, fate == Fate::Unalign);
+        stats.contig_mut(&contig).add(written, unaligned);
+        if config.flagstat {
+            stats
+                .flag_categories
+                .get_or_insert_with(Default::default)
+                .add(record.flags(), written, unaligned);
+        }
+        let evaluated: bool = decision != FilterDecision::Bypass
+            && !decision.is_removed()
+            && !is_unmapped_input(record)
+            && !is_seqless_secondary(record);
+        if evaluated && config.needs_clip_profile() {
+            stats
+                .profile
+                .get_or_insert_with(|| profile::ClipProfile::new(config.profile_window))
+                .add(record);
+        }
+        if evaluated && config.clip_consensus.is_some() {
+            stats.consensus.add(record);
+        }
+        if evaluated && config.gc_out.is_some() {
+            stats.gc.add(record);
+        }
+        if evaluated && config.kmer_out.is_some() {
+            stats
+                .kmers
+                .get_or_insert_with(|| kmers::KmerCounts::new(config.kmer_size as usize))
+                .add(record);
+        }
+        let label: &str = match decision {
+            _ if left_out => "subsampled_out",
+            FilterDecision::Fail(reason) => reason.as_str(),
+            FilterDecision::Bypass => "bypassed",
+            FilterDecision::Pass if !keep => FailReason::Linked.as_str(),
+            FilterDecision::Pass => "passed",
+        };
+        let failed: bool = matches!(decision, FilterDecision::Fail(_))
+            || (decision == FilterDecision::Pass && !keep);
+        if let Some(reasons) = side_outputs
+            .reasons
+            .as_mut()
+            .filter(|_| failed && !left_out)
+        {
+            reasons.write(record, label)?;
+        }
+        let needs_fractions: bool = config.needs_histogram()
+            || config.cell_stats.is_some()
+            || config.metrics_parquet.is_some();
+        let mut fractions: Option<(f64, f64, f64)> = None;
+        if evaluated && needs_fractions {
+            let (clip_stat, seq_len) = clip_filter.clip_stat(record)?;
+            if config.needs_histogram() {
+                stats.histogram.add(&clip_stat, seq_len)?;
+            }
+            fractions = Some((
+                clip_stat.left_fraction(seq_len)?,
+                clip_stat.right_fraction(seq_len)?,
+                clip_stat.total_fraction(seq_len)?,
+            ));
+        }
+        let barcode: Option<String> = match (config.cell_stats.as_ref(), fractions) {
+            (Some(_), Some(_)) => tags::string_tag(record, config.cell_tag.as_bytes()),
+            _ => None,
+        };
+        if let (Some(barcode), Some(fractions)) = (barcode, fractions) {
+            stats
+                .cells
+                .entry(barcode)
+                .or_default()
+                .add(keep && decision.is_kept(), fractions);
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(metrics) = side_outputs.metrics.as_mut() {
+            metrics.write(record, fractions, label)?;
+        }
+        if let Some(hit) = clip_filter.adapter_hit(record).filter(|_| evaluated) {
+            *stats
+                .adapter_hits
+                .entry(hit.adapter.name.clone())
+                .or_insert(0) += 1;
+            if fate == Fate::Write && config.adapter_policy == AdapterPolicy::Trim {
+                adapters::trim(record, &hit)?;
+            }
+        }
+        if let Some(name) = clip_filter.contaminant_hit(record).filter(|_| evaluated) {
+            *stats.contaminant_hits.entry(name.to_string()).or_insert(0) += 1;
+        }
+        if let Some(junctions) = side_outputs.junctions.as_mut().filter(|_| evaluated) {
+            junctions.write(record, out_bam.header())?;
+        }
+        if fate == Fate::Remove {
+            if let Some(removed_names) = side_outputs.removed_names.as_mut() {
+                removed_names.write(record, label)?;
+            }
+            continue;
+        }
+        if fate == Fate::Unalign {
+            unalign_record(record, config)?;
+            stats.unaligned += 1;
+        }
+        if fate == Fate::ZeroMapq {
+            record.set_mapq(0);
+            if let Some(tag) = config.reason_tag.as_deref() {
+                let _ = record.remove_aux(tag.as_bytes());
+                record.push_aux(tag.as_bytes(), bam::record::Aux::String(label))?;
+            }
+            stats.zero_mapq += 1;
+        }
+        if fix_mates && (keep || !partial) && record.is_paired() {
+            set_mate_unmapped(record)?;
+        } else if orphaned[i] {
+            set_mate_unmapped(record)?;
+            stats.orphans += 1;
+        }
+        if let Some(unaligned) = side_outputs
+            .unaligned
+            .as_mut()
+            .filter(|_| fate == Fate::Unalign)
+        {
+            unaligned.write(record)?;
+            continue;
+        }
+        if let Some(singletons) = side_outputs.singletons.as_mut().filter(|_| singletons[i]) {
+            singletons.write(record)?;
+            continue;
+        }
+        match side_outputs
+            .deferred
+            .as_mut()
+            .filter(|_| fate == Fate::Unalign)
+        {
+            Some(deferred) => deferred.write(record)?,
+            None => out_bam.write(record)?,
+        }
+        if let Some(shards) = side_outputs.shards.as_mut() {
+            shards.write(record)?;
+        }
+        stats.written += 1;
+    }
+    Ok(())
+}
+
+/// Whether the mate of an alignment passes the filter on its own, all of its alignments counting
+/// towards the decision passing (see [FilterConfig::singletons])
+///
+/// # Arguments
+/// - `record`: the alignment
+/// - `read_group`: alignments sharing the same read name
+/// - `decisions`: the decisions made on each of the alignments
+/// - `demoted`: which alignments are failing primary alignments replaced by a supplementary one
+/// - `config`: settings for the run
+/// - `first_pass`: what the first pass found out about the read names in two-pass mode
+fn mate_passes(
+    record: &Record,
+    read_group: &[Record],
+    decisions: &[FilterDecision],
+    demoted: &[bool],
+    config: &FilterConfig,
+    first_pass: Option<&FirstPass>,
+) -> bool {
+    let first: bool = record.is_first_in_template();
+    match first_pass {
+        Some(first_pass) => !first_pass
+            .failed_mates
+            .contains(&(record.qname().to_vec(), first)),
+        None => read_group
+            .iter()
+            .zip(decisions.iter().zip(demoted))
+            .filter(|(mate, (_, demoted))| {
+                !**demoted && mate.is_first_in_template() == first && config.is_decisive(mate)
+            })
+            .all(|(_, (decision, _))| decision.is_kept()),
+    }
+}
+
+/// Which alignments of a read group are written while the primary alignment of their mate
+/// isn't (see [FilterConfig::fix_orphans]): in paired mode, the mate is missing from the group
+/// or not written as it is, in two-pass mode it was removed on its own in the first pass
+///
+/// # Arguments
+/// - `read_group`: alignments sharing the same read name
+/// - `fates`: what happens to each of the alignments
+/// - `first_pass`: what the first pass found out about the read names in two-pass mode
+fn orphaned_alignments(
+    read_group: &[Record],
+    fates: &[Fate],
+    first_pass: Option<&FirstPass>,
+) -> Vec<bool> {
+    read_group
+        .iter()
+        .zip(fates)
+        .map(|(record, fate)| {
+            if *fate == Fate::Remove || !record.is_paired() || record.is_mate_unmapped() {
+                return false;
+            }
+            let first: bool = record.is_first_in_template();
+            match first_pass {
+                Some(first_pass) => first_pass
+                    .removed_mates
+                    .contains(&(record.qname().to_vec(), !first)),
+                None => !read_group.iter().zip(fates).any(|(mate, fate)| {
+                    matches!(fate, Fate::Write | Fate::ZeroMapq)
+                        && !mate.is_secondary()
+                        && !mate.is_supplementary()
+                        && mate.is_first_in_template() != first
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Make a passing supplementary alignment primary in place of each failing primary alignment
+/// of a read group, picking the one with the most aligned bases among the supplementary
+/// alignments of the same read (or mate), and rewrite the SA tags of the segments left
+/// (see [FilterConfig::promote_supplementary])
+///
+/// # Arguments
+/// - `read_group`: alignments sharing the same read name
+/// - `decisions`: the decisions made on each of the alignments
+/// - `header`: header of the output alignment file, to look up reference names
+///
+/// # Returns
+/// - which of the alignments are failing primary alignments replaced by a supplementary one
+fn promote_supplementaries(
+    read_group: &mut [Record],
+    decisions: &[FilterDecision],
+    header: &HeaderView,
+) -> Result<Vec<bool>, FilterClippedError> {
+    let mut demoted: Vec<bool> = vec![false; read_group.len()];
+    let mate = |record: &Record| (record.is_first_in_template(), record.is_last_in_template());
+    for i in 0..read_group.len() {
+        let primary: &Record = &read_group[i];
+        if primary.is_secondary()
+            || primary.is_supplementary()
+            || decisions[i].is_kept()
+            || decisions[i].is_removed()
+        {
+            continue;
+        }
+        let segments: Vec<usize> = (0..read_group.len())
+            .filter(|j| {
+                let record: &Record = &read_group[*j];
+                record.is_supplementary() && !record.is_secondary() && mate(record) == mate(primary)
+            })
+            .collect();
+        let promoted: usize = match segments
+            .iter()
+            .filter(|j| decisions[**j] == FilterDecision::Pass)
+            .max_by_key(|j| clipping::record_aligned(&read_group[**j]))
+        {
+            Some(j) => *j,
+            None => continue,
+        };
+        let primary: Record = primary.clone();
+        let chimeric: bool = read_group[promoted].aux(b"SA").is_ok();
+        chimeric::promote_supplementary(&primary, &mut read_group[promoted])?;
+        demoted[i] = true;
+        if !chimeric {
+            continue;
+        }
+
+        // the primary alignment comes first in the SA tags of the supplementary alignments
+        let members: Vec<usize> = std::iter::once(promoted)
+            .chain(segments.into_iter().filter(|j| *j != promoted))
+            .collect();
+        let entries: Vec<chimeric::SaSegment> = members
+            .iter()
+            .map(|j| chimeric::SaSegment::from_record(&read_group[*j], header))
+            .collect();
+        for (n, j) in members.iter().enumerate() {
+            let others: Vec<chimeric::SaSegment> = entries
+                .iter()
+                .enumerate()
+                .filter(|(m, _)| *m != n)
+                .map(|(_, entry)| entry.clone())
+                .collect();
+            chimeric::set_sa_tag(&mut read_group[*j], &others)?;
+        }
+    }
+    Ok(demoted)
+}
+
+/// What happens to an alignment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Fate {
+    /// written out as it is
+    Write,
+    /// made unmapped and written out
+    Unalign,
+    /// written out where it is with MAPQ 0
+    ZeroMapq,
+    /// not written out
+    Remove,
+}
+
+/// Update the SA tags of the alignments in a read group, so that they don't point
+/// at segments that are removed or unaligned
+///
+/// # Arguments
+/// - `read_group`: alignments sharing the same read name
+/// - `fates`: what happens to each of the alignments
+/// - `config`: settings for the run
+/// - `header`: header of the output alignment file, to look up reference names
+fn update_sa_tags(
+    read_group: &mut [Record],
+    fates: &[Fate],
+    config: &FilterConfig,
+    header: &HeaderView,
+) -> Result<(), FilterClippedError> {
+    let removed: Vec<(Vec<u8>, i64, bool)> = read_group
+        .iter()
+        .zip(fates)
+        .filter(|(record, fate)| !matches!(fate, Fate::Write | Fate::ZeroMapq) && record.tid() >= 0)
+        .map(|(record, _)| {
+            (
+                header.tid2name(record.tid() as u32).to_vec(),
+                record.pos() + 1,
+                record.is_reverse(),
+            )
+        })
+        .collect();
+
+    for (record, fate) in read_group.iter_mut().zip(fates) {
+        let strip_all: bool = *fate == Fate::Unalign
+            // all segments listed on a primary alignment are supplementary alignments
+            || (config.supplementary == FlagPolicy::Drop
+                && !record.is_secondary()
+                && !record.is_supplementary());
+        chimeric::retain_sa_segments(record, |segment| {
+            !strip_all
+                && !removed.contains(&(
+                    segment.rname.as_bytes().to_vec(),
+                    segment.pos,
+                    segment.reverse,
+                ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Make an alignment unmapped: set the unmapped flag, clear its position, CIGAR and MAPQ,
+/// remove the aux tags describing the alignment (see [FilterConfig::unalign_strip_tags]),
+/// and put a reverse strand alignment back in the orientation of the read with
+/// [FilterConfig::restore_orientation]
+///
+/// # Arguments
+/// - `record`: the alignment to be made unmapped
+/// - `config`: settings for the run
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::unalign_record;
+/// use rust_htslib::bam::record::{Aux, Cigar, CigarString, Record};
+///
+/// let config = FilterConfig::new()
+///     .unalign_strip_tags(vec![String::from("NM")])
+///     .restore_orientation(true);
+/// let mut record = Record::new();
+/// let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(2)]);
+/// record.set(b"read", Some(&cigar), b"AACG", &[10, 20, 30, 40]);
+/// record.set_flags(0x10);
+/// record.set_mapq(60);
+/// record.push_aux(b"NM", Aux::U8(1)).unwrap();
+/// record.push_aux(b"RG", Aux::String("rg1")).unwrap();
+/// unalign_record(&mut record, &config).unwrap();
+/// assert!(record.is_unmapped() && !record.is_reverse());
+/// assert_eq!((record.tid(), record.pos(), record.mapq()), (-1, -1, 0));
+/// assert_eq!(record.cigar_len(), 0);
+/// assert_eq!(record.seq().as_bytes(), b"CGTT");
+/// assert_eq!(record.qual(), &[40, 30, 20, 10]);
+/// assert!(record.aux(b"NM").is_err());
+/// assert!(record.aux(b"RG").is_ok());
+/// ```
+pub fn unalign_record(
+    record: &mut Record,
+    config: &FilterConfig,
+) -> Result<(), FilterClippedError> {
+    let qname: Vec<u8> = record.qname().to_vec();
+    let (mut seq, mut qual): (Vec<u8>, Vec<u8>) = (record.seq().as_bytes(), record.qual().to_vec());
+    if config.restore_orientation && record.is_reverse() {
+        seq = adapters::reverse_complement(&seq);
+        qual.reverse();
+    }
+    record.set(&qname, None, &seq, &qual);
+    record.set_unmapped();
+    record.unset_reverse();
+    record.unset_proper_pair();
+    record.set_tid(-1);
+    record.set_pos(-1);
+    record.set_mapq(0);
+    for tag in config.unalign_strip_tags.iter() {
+        if record.aux(tag.as_bytes()).is_ok() {
+            record.remove_aux(tag.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Update the mate information on an alignment whose mate has been made unmapped:
+/// set the mate unmapped flag, clear RNEXT/PNEXT/TLEN and remove the MC and MQ tags
+///
+/// # Arguments
+/// - `record`: the alignment to be updated
+///
+/// # Example
+/// ```
+/// use filter_clipped::set_mate_unmapped;
+/// use rust_htslib::bam::record::{Aux, Record};
+///
+/// let mut record = Record::new();
+/// record.set_flags(0x1 | 0x20);
+/// record.set_mtid(0);
+/// record.set_mpos(100);
+/// record.push_aux(b"MQ", Aux::I32(60)).unwrap();
+/// set_mate_unmapped(&mut record).unwrap();
+/// assert!(record.is_mate_unmapped());
+/// assert!(!record.is_mate_reverse());
+/// assert_eq!(record.mtid(), -1);
+/// assert!(record.aux(b"MQ").is_err());
+/// ```
+pub fn set_mate_unmapped(record: &mut Record) -> Result<(), FilterClippedError> {
+    record.set_mate_unmapped();
+    record.unset_mate_reverse();
+    record.unset_proper_pair();
+    record.set_mtid(-1);
+    record.set_mpos(-1);
+    record.set_insert_size(0);
+    for tag in [b"MC", b"MQ"] {
+        if record.aux(tag).is_ok() {
+            record.remove_aux(tag)?;
+        }
+    }
+    Ok(())
+}
+
+/// Just a wrapper function to read command line arguments and pass it to `run`
+///
+/// # Returns
+/// - the error of the run if any, see [FilterClippedError::exit_code] for the exit code of the binary
+pub fn wrapper() -> Result<RunStats, FilterClippedError> {
+    // built once per run, clap needs it for the lifetime of the command
+    let long_version: &'static str = Box::leak(version::long_version().into_boxed_str());
+    let matches = cli::command_with_env()
+        .long_version(long_version)
+        .get_matches();
+    let args = cli::Command::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(cli::Utility::Man) = args.utility {
+        man::write_man_page(&mut std::io::stdout().lock(), &cli::command_with_env())?;
+        return Ok(RunStats::default());
+    }
+    logging::init_logger(
+        args.verbose,
+        args.quiet,
+        args.log_file.as_deref(),
+        args.log_format,
+    )?;
+    let mut config: FilterConfig = args.to_config(&matches)?;
+    if let Some(cli::Utility::Compare { first, second, out }) = args.utility.as_ref() {
+        let mut writer: Box<dyn Write> = match out.as_str() {
+            "-" => Box::new(std::io::stdout().lock()),
+            path => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        };
+        let stats = compare::compare(&config, first, second, &mut writer)?;
+        writer.flush()?;
+        info!(
+            "{} alignments in both files, {} only in {}, {} only in {}",
+            stats.shared, stats.only_first, first, stats.only_second, second
+        );
+        return Ok(RunStats::default());
+    }
+    if let Some(path) = args.dump_config.as_deref() {
+        config.validate()?;
+        config.write_file(path)?;
+        info!("Written settings to {}", path);
+        return Ok(RunStats::default());
+    }
+    if let Some(targets) = args.suggest_thresholds.as_ref() {
+        config.validate()?;
+        let targets: &[f64] = match targets.is_empty() {
+            true => &distribution::DEFAULT_RETENTION,
+            _ => targets,
+        };
+        let clip_distribution = distribution::scan(&config)?;
+        info!(
+            "Suggesting thresholds from {} alignments",
+            clip_distribution.len()
+        );
+        clip_distribution.write_suggestions(&mut std::io::stdout().lock(), targets)?;
+        return Ok(RunStats::default());
+    }
+    config.command_line = Some(std::env::args().collect::<Vec<String>>().join(" "));
+    // remote inputs are read ahead of the filtering when built with the async feature
+    #[cfg(feature = "async")]
+    if io::remote_url(&config.in_bam).is_some()
+        && config.extra_inputs.is_empty()
+        && config.region_threads <= 1
+    {
+        let stats: RunStats = stream::run_blocking(config.clone())?;
+        check_run(&config, &stats)?;
+        return Ok(stats);
+    }
+    let stats: RunStats = run_with_config(&config)?;
+    check_run(&config, &stats)?;
+    Ok(stats)
+}
+
+/// Check the outcome of a run against the settings asking to fail pathological runs
+/// (`fail_if_empty` and `fail_if_removed_frac`), once all the outputs are written
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `stats`: totals of the run
+///
+/// # Returns
+/// - [FilterClippedError::RunCheck] for the first check failing
+///
+/// # Example
+/// ```
+/// use filter_clipped::check_run;
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::stats::{FailReason, RunStats};
+/// let mut stats = RunStats { read: 4, ..RunStats::default() };
+/// stats.add_failure(FailReason::LeftClip);
+/// assert!(check_run(&FilterConfig::new().fail_if_empty(true), &stats).is_ok());
+/// assert!(check_run(&FilterConfig::new().fail_if_removed_frac(0.25), &stats).is_ok());
+/// assert!(check_run(&FilterConfig::new().fail_if_removed_frac(0.2), &stats).is_err());
+/// ```
+pub fn check_run(config: &FilterConfig, stats: &RunStats) -> Result<(), FilterClippedError> {
+    if stats.skipped {
+        return Ok(());
+    }
+    let failed: u64 = stats.failed();
+    if config.fail_if_empty && stats.read <= failed {
+        return Err(FilterClippedError::RunCheck(format!(
+            "none of the {} alignments read passed the filter",
+            stats.read
+        )));
+    }
+    if let Some(max_fraction) = config.fail_if_removed_frac {
+        let fraction: f64 = failed as f64 / stats.read.max(1) as f64;
+        if fraction > max_fraction {
+            return Err(FilterClippedError::RunCheck(format!(
+                "{} of the {} alignments read ({:.2}%) failed the filter, more than {}",
+                failed,
+                stats.read,
+                fraction * 100.0,
+                max_fraction
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{OutputFormat, SeqlessPolicy};
+    use rstest::rstest;
+    use std::collections::BTreeMap;
+    use std::string::String;
+
+    fn count_bam(bam_file: String, expected_count: i32, expected_unaligned: i32) {
+        let mut bam_reader = bam::Reader::from_path(bam_file).unwrap();
+        let mut aln_count = 0;
+        let mut unaligned_count = 0;
+        for r in bam_reader.records() {
+            let _record = r.unwrap();
+            aln_count += 1;
+            if _record.is_unmapped() {
+                unaligned_count += 1;
+                // nothing of the alignment is left on the unaligned alignments
+                assert_eq!((_record.cigar_len(), _record.mapq()), (0, 0));
+                assert!(_record.aux(b"NM").is_err() && _record.aux(b"AS").is_err());
+            }
+        }
+        assert_eq!(expected_count, aln_count);
+
+        if expected_unaligned > 0 {
+            assert_eq!(expected_unaligned, unaligned_count);
+        }
+    }
+
+    #[rstest]
+    #[case(1, 0.2, 0.3, true, 0, 0, false)]
+    #[case(2, 0.2, 0.3, false, 9, 0, false)]
+    #[case(3, 0.1, 0.1, false, 6, 0, false)]
+    #[case(4, 0.1, 0.1, false, 9, 3, true)]
+    #[case(4, 0.1, 0.1, false, 9, 3, true)]
+    fn test_run(
+        #[case] test_case: usize,
+        #[case] max_both_end: f64,
+        #[case] max_single_end: f64,
+        #[case] inverse: bool,
+        #[case] expected_count: i32,
+        #[case] expected_unaligned: i32,
+        #[case] unalign: bool,
+    ) {
+        let out_bam: &str = &format!("test/data/out_{}.bam", test_case);
+        #[allow(deprecated)]
+        let result = run(
+            "test/data/test.sam".to_string(),
+            out_bam.to_string(),
+            inverse,
+            max_both_end,
+            max_single_end,
+            max_single_end,
+            unalign,
+        )
+        .unwrap();
+        assert_eq!(result, 0);
+        count_bam(out_bam.to_string(), expected_count, expected_unaligned);
+    }
+
+    #[rstest]
+    #[case(0.1, 0.1, vec![(FailReason::TotalClip, 3)])]
+    #[case(1.0, 0.05, vec![(FailReason::LeftClip, 4), (FailReason::RightClip, 5)])]
+    #[case(1.0, 1.0, vec![])]
+    fn test_run_stats(
+        #[case] both_end: f64,
+        #[case] single_end: f64,
+        #[case] expected_failures: Vec<(FailReason, u64)>,
+    ) {
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: format!("test/data/out_stats_{}_{}.bam", both_end, single_end),
+            both_end,
+            left_side: single_end,
+            right_side: single_end,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.read, 9);
+        assert_eq!(stats.written + stats.failed(), 9);
+        assert_eq!(
+            stats.failed_by_reason,
+            expected_failures.into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_run_contig_counts() {
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: "test/data/out_contig_counts.bam".to_string(),
+            both_end: 0.1,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.contigs.len(), 7);
+        assert_eq!(stats.contigs.values().map(|c| c.read).sum::<u64>(), 9);
+        assert_eq!(
+            stats.contigs.values().map(|c| c.written).sum::<u64>(),
+            stats.written
+        );
+        assert_eq!(
+            stats.contigs["chrUn_gl000220"],
+            stats::FateCounts {
+                read: 2,
+                written: 1,
+                unaligned: 0,
+                removed: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_flagstat() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/secondary.sam")
+            .out_bam("test/data/out_flagstat.bam")
+            .flagstat(true);
+        let stats = run_with_config(&config).unwrap();
+        let categories = stats.flag_categories.unwrap();
+        assert_eq!(categories.total.read, 6);
+        assert_eq!(categories.primary.read, 3);
+        assert_eq!(categories.secondary.read, 2);
+        assert_eq!(categories.supplementary.read, 1);
+        assert_eq!(categories.total.written, stats.written);
+        assert_eq!(categories.paired.read, 0);
+
+        let config = FilterConfig::new()
+            .in_bam("test/data/paired.sam")
+            .out_bam("test/data/out_flagstat_paired.bam")
+            .paired(true)
+            .flagstat(true);
+        let stats = run_with_config(&config).unwrap();
+        let categories = stats.flag_categories.unwrap();
+        assert_eq!(categories.paired.read, 6);
+        assert_eq!(categories.read1.read, 3);
+        assert_eq!(categories.read2.read, 3);
+        assert_eq!(categories.proper_pair.read, 6);
+        assert_eq!(categories.read1.written + categories.read2.written, 2);
+    }
+
+    #[test]
+    fn test_run_html_report() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam("test/data/out_report.bam")
+            .html_report("test/data/out_report.html")
+            .flagstat(true);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.histogram.len(), 9);
+        let html: String = std::fs::read_to_string("test/data/out_report.html").unwrap();
+        assert!(html.contains("<tr><td>alignments read</td><td>9</td></tr>"));
+        assert!(html.contains("By flag category"));
+        assert!(html.contains("<td>chrUn_gl000220</td>"));
+        assert_eq!(html.matches("<svg").count(), 5);
+        let json: serde_json::Value = serde_json::to_value(&stats).unwrap();
+        let left_lengths: u64 = json["clip_lengths"]["left_length"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|count| count.as_u64().unwrap())
+            .sum();
+        assert_eq!(left_lengths, 9);
+        assert!(json["clip_lengths"].get("total_length").is_none());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_run_metrics_parquet() {
+        use arrow_array::{Array, Float64Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let config = FilterConfig::new()
+            .in_bam("test/data/secondary.sam")
+            .out_bam("test/data/out_metrics.bam")
+            .secondary(FlagPolicy::Keep)
+            .metrics_parquet("test/data/out_metrics.parquet");
+        run_with_config(&config).unwrap();
+        let file = std::fs::File::open("test/data/out_metrics.parquet").unwrap();
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 6);
+        let total_fraction = batch
+            .column_by_name("total_fraction")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        let decision = batch
+            .column_by_name("decision")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        // read2 (20S30M) fails, the kept secondary alignments are not evaluated
+        assert_eq!(total_fraction.value(1), 0.4);
+        assert_eq!(decision.value(1), "total_clip");
+        assert!(total_fraction.is_null(2));
+        assert_eq!(decision.value(2), "bypassed");
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    #[test]
+    fn test_metrics_parquet_without_feature() {
+        let config = FilterConfig::new().metrics_parquet("out.parquet");
+        assert!(matches!(
+            config.validate(),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_extra_inputs() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .extra_inputs(vec![String::from("test/data/lane2.sam")])
+            .out_bam("test/data/out_extra_inputs.sam");
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.read, 8);
+        assert_eq!(stats.written, 3);
+        let mut reader = bam::Reader::from_path("test/data/out_extra_inputs.sam").unwrap();
+        let header = reader.header().clone();
+        assert_eq!(header.target_names(), vec![&b"chr1"[..], &b"chr2"[..]]);
+        assert_eq!(
+            header::sort_order(header.as_bytes()),
+            header::SortOrder::Unsorted
+        );
+        assert!(String::from_utf8_lossy(header.as_bytes()).contains("@RG\tID:lane2"));
+        let positions: Vec<(Vec<u8>, i32, i64)> = reader
+            .records()
+            .map(|r| r.unwrap())
+            .map(|record| (record.qname().to_vec(), record.tid(), record.pos()))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (b"r4".to_vec(), 0, 399),
+                (b"l1".to_vec(), 1, 99),
+                (b"l2".to_vec(), 0, 599)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_extra_inputs_conflict() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .extra_inputs(vec![String::from("test/data/chimeric.sam")])
+            .out_bam("test/data/out_extra_inputs_conflict.bam");
+        assert!(matches!(
+            run_with_config(&config),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_contig_thresholds() {
+        let text: String = std::fs::read_to_string("test/data/contig_thresholds.tsv").unwrap();
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: "test/data/out_contig_thresholds.bam".to_string(),
+            contig_thresholds: config::parse_contig_thresholds(&text).unwrap(),
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        // the chrUn_gl000220 read is rescued, the chrM read fails
+        assert_eq!(stats.written, 6);
+        assert_eq!(
+            stats.failed_by_reason,
+            vec![(FailReason::TotalClip, 3)].into_iter().collect()
+        );
+    }
+
+    #[rstest]
+    #[case(false, 5, vec![])]
+    #[case(true, 3, vec![(FailReason::Contaminant, 2)])]
+    fn test_run_screen(
+        #[case] fail: bool,
+        #[case] expected_written: u64,
+        #[case] expected_failed: Vec<(FailReason, u64)>,
+    ) {
+        let text: String = std::fs::read_to_string("test/data/contaminants.fa").unwrap();
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam(format!("test/data/out_screen_{}.bam", fail))
+            .both(1.0)
+            .left(1.0)
+            .right(1.0)
+            .screen(adapters::parse_fasta(&text).unwrap(), fail)
+            .screen_kmer_size(8);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_written);
+        assert_eq!(
+            stats.failed_by_reason,
+            expected_failed.into_iter().collect()
+        );
+        // r5 has a mismatch in every 8-mer of the adapter in its clip
+        assert_eq!(
+            stats.contaminant_hits,
+            vec![("TruSeq_read1".to_string(), 2)].into_iter().collect()
+        );
+    }
+
+    #[rstest]
+    #[case(AdapterPolicy::Trim, 4, vec![(FailReason::TotalClip, 1)])]
+    #[case(AdapterPolicy::Fail, 1, vec![(FailReason::Adapter, 3), (FailReason::TotalClip, 1)])]
+    fn test_run_adapters(
+        #[case] policy: AdapterPolicy,
+        #[case] expected_written: u64,
+        #[case] expected_failed: Vec<(FailReason, u64)>,
+    ) {
+        let text: String = std::fs::read_to_string("test/data/adapters.fa").unwrap();
+        let out_bam: String = format!("test/data/out_adapters_{:?}.bam", policy);
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam(&out_bam)
+            .adapters(adapters::parse_fasta(&text).unwrap(), policy);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_written);
+        assert_eq!(
+            stats.failed_by_reason,
+            expected_failed.into_iter().collect()
+        );
+        assert_eq!(
+            stats.adapter_hits,
+            vec![("TruSeq".to_string(), 3)].into_iter().collect()
+        );
+
+        let mut written = bam::Reader::from_path(&out_bam).unwrap();
+        for record in written.records() {
+            let record = record.unwrap();
+            assert_eq!(
+                record.cigar().to_string(),
+                if record.qname() == b"r4" {
+                    "40M"
+                } else {
+                    "30M"
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_cell_stats() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/cells.sam")
+            .out_bam("test/data/out_cells.bam")
+            .cell_stats("test/data/out_cells.tsv");
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, 3);
+        assert_eq!(stats.cells.len(), 2);
+        let text: String = std::fs::read_to_string("test/data/out_cells.tsv").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[1..],
+            [
+                "AAACCTGAGAAGGCCT-1\t1\t1\t0.0000\t0.1250\t0.1250",
+                "TTTGTCATCTGCGTAA-1\t1\t0\t0.0000\t0.0000\t0.0000"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_cb_whitelist() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/cells.sam")
+            .out_bam("test/data/out_cb_whitelist.bam")
+            .cb_whitelist("test/data/cb_whitelist.txt.gz");
+        let stats = run_with_config(&config).unwrap();
+        // r3 has another cell barcode, r4 none
+        assert_eq!(stats.written, 1);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(
+            stats.failed_by_reason,
+            vec![(FailReason::TotalClip, 1), (FailReason::CellBarcode, 2)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn test_run_name_lists() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_name_lists.bam")
+            .keep_names("test/data/keep_names.txt")
+            .drop_names("test/data/drop_names.txt.gz");
+        let stats = run_with_config(&config).unwrap();
+        // r3 is rescued, r4 is listed in both and removed
+        assert_eq!(stats.written, 1);
+        assert_eq!(stats.bypassed, 1);
+        assert_eq!(
+            stats.failed_by_reason,
+            vec![(FailReason::TotalClip, 3), (FailReason::NameList, 1)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[rstest]
+    #[case(false, "r1\nr2\nr3\nr4\nr5\n")]
+    #[case(
+        true,
+        "r1\t0\ttotal_clip\nr2\t16\ttotal_clip\nr3\t0\ttotal_clip\nr4\t0\tname_list\nr5\t0\ttotal_clip\n"
+    )]
+    fn test_run_removed_names(#[case] details: bool, #[case] expected: &str) {
+        let out_names: String = format!("test/data/out_removed_names_{}.txt", details);
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam(format!("test/data/out_removed_names_{}.bam", details))
+            .drop_names("test/data/drop_names.txt.gz")
+            .removed_names(&out_names, details);
+        run_with_config(&config).unwrap();
+        let text: String = std::fs::read_to_string(&out_names).unwrap();
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn test_run_gc_out() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_gc.bam")
+            .gc_out("test/data/out_gc.tsv");
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.gc.clipped_len(), 4);
+        assert_eq!(stats.gc.aligned_len(), 5);
+        let text: String = std::fs::read_to_string("test/data/out_gc.tsv").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        // the adapter clips share a GC content, the poly-T clip stands apart
+        assert!(lines.contains(&"clipped\t0.50\t0.51\t3"));
+        assert!(lines.contains(&"clipped\t0.00\t0.01\t1"));
+        assert!(lines.contains(&"aligned\t0.50\t0.51\t5"));
+    }
+
+    #[test]
+    fn test_run_kmer_out() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_kmers.bam")
+            .kmer_out("test/data/out_kmers.tsv", 8, 3);
+        let stats = run_with_config(&config).unwrap();
+        assert!(stats.kmers.is_none());
+        let text: String = std::fs::read_to_string("test/data/out_kmers.tsv").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        // the poly-T clip (counted as poly-A on the other strand), then the adapter shared
+        // by the clips of both strands
+        assert_eq!(
+            lines[1].split('\t').take(3).collect::<Vec<_>>(),
+            ["AAAAAAAA", "5", "0"]
+        );
+        assert_eq!(
+            lines[2].split('\t').take(3).collect::<Vec<_>>(),
+            ["AGATCGGA", "2", "0"]
+        );
+    }
+
+    #[test]
+    fn test_run_hist_out() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_hist.bam")
+            .hist_out("test/data/out_hist.tsv");
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.histogram.len(), 5);
+        let text: String = std::fs::read_to_string("test/data/out_hist.tsv").unwrap();
+        // 4 reads with 12 of 42 bases clipped, 1 unclipped
+        assert!(text.contains("total_fraction\t0.28\t0.29\t4\n"));
+        assert!(text.contains("total_length\t0\t1\t1\n"));
+        assert!(text.contains("total_length\t12\t13\t4\n"));
+    }
+
+    #[test]
+    fn test_run_clip_profile() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_profile.bam")
+            .clip_profile("test/data/out_profile.tsv", 100);
+        run_with_config(&config).unwrap();
+        let text: String = std::fs::read_to_string("test/data/out_profile.tsv").unwrap();
+        assert_eq!(
+            text,
+            "contig\tstart\tend\tleft_clips\tright_clips\n\
+             chr1\t100\t200\t1\t1\n\
+             chr1\t300\t400\t0\t1\n\
+             chr1\t500\t600\t0\t1\n"
+        );
+    }
+
+    #[test]
+    fn test_run_breakpoint_bed() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_breakpoints.bam")
+            .clip_profile("test/data/out_breakpoints_profile.tsv", 100)
+            .breakpoint_bed("test/data/out_breakpoints.bed", 2);
+        run_with_config(&config).unwrap();
+        let text: String = std::fs::read_to_string("test/data/out_breakpoints.bed").unwrap();
+        assert_eq!(text, "chr1\t100\t200\tclip_breakpoint\t2\n");
+    }
+
+    #[rstest]
+    #[case(20, "chr1\t1058\t1059\tchr1\t4999\t5000\treadA\t60\t+\t+\nchr1\t2048\t2049\tchr1\t7999\t8000\treadB\t60\t+\t+\nchr1\t3999\t4000\tchr1\t8999\t9000\treadD\t60\t-\t+\n")]
+    #[case(45, "chr1\t2048\t2049\tchr1\t7999\t8000\treadB\t60\t+\t+\n")]
+    fn test_run_junction_bedpe(#[case] min_clip: u64, #[case] expected: &str) {
+        let out_bedpe: String = format!("test/data/out_junctions_{}.bedpe", min_clip);
+        let config = FilterConfig::new()
+            .in_bam("test/data/chimeric.sam")
+            .out_bam(format!("test/data/out_junctions_{}.bam", min_clip))
+            .junction_bedpe(&out_bedpe, min_clip);
+        run_with_config(&config).unwrap();
+        let text: String = std::fs::read_to_string(&out_bedpe).unwrap();
+        assert_eq!(text, expected);
+    }
+
+    #[rstest]
+    #[case(0, Some(3), 3)]
+    #[case(2, Some(3), 3)]
+    #[case(7, None, 2)]
+    #[case(20, Some(3), 0)]
+    fn test_run_record_range(
+        #[case] skip: u64,
+        #[case] max: Option<u64>,
+        #[case] expected_read: u64,
+    ) {
+        let out_bam: String = format!("test/data/out_records_{}.sam", skip);
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(&out_bam)
+            .inverse(true)
+            .both(0.0)
+            .records(skip, max);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.read, expected_read);
+        let names = |path: &str, skip: usize, max: usize| -> Vec<Vec<u8>> {
+            bam::Reader::from_path(path)
+                .unwrap()
+                .records()
+                .skip(skip)
+                .take(max)
+                .map(|r| r.unwrap().qname().to_vec())
+                .collect()
+        };
+        assert_eq!(
+            names(&out_bam, 0, usize::MAX),
+            names("test/data/test.sam", skip as usize, expected_read as usize)
+        );
+    }
+
+    #[rstest]
+    #[case("mapq >= 60", 4)]
+    #[case("flag.reverse && mapq > 0", 3)]
+    #[case("rname == \"chr7\" || [ZZ]", 2)]
+    fn test_run_expression(#[case] expression: &str, #[case] expected_written: u64) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam("test/data/out_expression.sam")
+            .both(1.0)
+            .left(1.0)
+            .right(1.0)
+            .expression(expression);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_written);
+        assert_eq!(
+            stats.failed_by_reason.get(&FailReason::Expression).copied(),
+            Some(9 - expected_written).filter(|failed| *failed > 0)
+        );
+    }
+
+    #[test]
+    fn test_run_metrics_out() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam("test/data/out_metrics_out.sam")
+            .metrics_out("test/data/out_metrics_out.prom", 10);
+        let stats: RunStats = run_with_config(&config).unwrap();
+        let text: String = std::fs::read_to_string("test/data/out_metrics_out.prom").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.contains(&"filter_clipped_records_read_total 9"));
+        assert!(lines
+            .contains(&format!("filter_clipped_records_written_total {}", stats.written).as_str()));
+        assert!(lines.contains(&"filter_clipped_done 1"));
+        assert!(!std::path::Path::new("test/data/out_metrics_out.prom.tmp").exists());
+        std::fs::remove_file("test/data/out_metrics_out.prom").unwrap();
+        std::fs::remove_file(&config.out_bam).unwrap();
+    }
+
+    #[test]
+    fn test_run_split_by_reason() {
+        let dir: &str = "test/data/out_reasons";
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam("test/data/out_reasons.sam")
+            .both(0.2)
+            .left(0.05)
+            .right(0.05)
+            .split_by_reason(dir);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.failed_by_reason.len(), 2);
+        let mut split: BTreeMap<String, u64> = BTreeMap::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let reason: String = path.file_stem().unwrap().to_string_lossy().to_string();
+            let count = bam::Reader::from_path(&path).unwrap().records().count();
+            split.insert(reason, count as u64);
+        }
+        let expected: BTreeMap<String, u64> = stats
+            .failed_by_reason
+            .iter()
+            .map(|(reason, count)| (reason.as_str().to_string(), *count))
+            .collect();
+        assert_eq!(split, expected);
+    }
+
+    #[test]
+    fn test_run_unaligned_out() {
+        let (out_bam, unaligned_out) = ("test/data/out_passing.sam", "test/data/out_unaligned.sam");
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(out_bam)
+            .both(0.1)
+            .unalign(true)
+            .unaligned_out(unaligned_out);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!((stats.read, stats.written, stats.unaligned), (9, 6, 3));
+        let mut passing = bam::Reader::from_path(out_bam).unwrap();
+        assert!(passing.records().all(|r| !r.unwrap().is_unmapped()));
+        let mut unaligned = bam::Reader::from_path(unaligned_out).unwrap();
+        assert_eq!(unaligned.header().target_count(), 0);
+        let header: String = String::from_utf8(unaligned.header().as_bytes().to_vec()).unwrap();
+        assert!(header.starts_with("@HD\tVN:1.6\tSO:unsorted\n@PG\tID:filter-clipped"));
+        let records: Vec<Record> = unaligned.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len() as u64, stats.unaligned);
+        assert!(records
+            .iter()
+            .all(|r| r.is_unmapped() && r.cigar_len() == 0));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_run_restore_orientation(#[case] restore: bool) {
+        let out_bam: String = format!("test/data/out_restore_{}.sam", restore);
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(&out_bam)
+            .both(0.0)
+            .unalign(true)
+            .restore_orientation(restore);
+        run_with_config(&config).unwrap();
+        let mut reversed: usize = 0;
+        let mut inputs = bam::Reader::from_path("test/data/test.sam").unwrap();
+        let mut outputs = bam::Reader::from_path(&out_bam).unwrap();
+        for (input, output) in inputs.records().zip(outputs.records()) {
+            let (input, output) = (input.unwrap(), output.unwrap());
+            assert!(output.is_unmapped() && !output.is_reverse());
+            let (mut seq, mut qual) = (input.seq().as_bytes(), input.qual().to_vec());
+            if restore && input.is_reverse() {
+                seq = adapters::reverse_complement(&seq);
+                qual.reverse();
+                reversed += 1;
+            }
+            assert_eq!(
+                (output.seq().as_bytes(), output.qual().to_vec()),
+                (seq, qual)
+            );
+        }
+        assert_eq!(reversed > 0, restore);
+    }
+
+    #[rstest]
+    #[case(UnmappedPolicy::Keep, false, vec!["p1", "p1", "u1"])]
+    #[case(UnmappedPolicy::Keep, true, vec!["p2", "p2"])]
+    #[case(UnmappedPolicy::Drop, false, vec!["p1"])]
+    #[case(UnmappedPolicy::Passthrough, false, vec!["p1", "p1", "p2", "u1"])]
+    #[case(UnmappedPolicy::Passthrough, true, vec!["p1", "p2", "p2", "u1"])]
+    fn test_run_unmapped(
+        #[case] unmapped: UnmappedPolicy,
+        #[case] inverse: bool,
+        #[case] expected: Vec<&str>,
+    ) {
+        let out_bam: String = format!("test/data/out_unmapped_{:?}_{}.sam", unmapped, inverse);
+        let config = FilterConfig::new()
+            .in_bam("test/data/unmapped_mates.sam")
+            .out_bam(&out_bam)
+            .both(0.2)
+            .paired(true)
+            .inverse(inverse)
+            .unmapped(unmapped);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.already_unmapped, 3);
+        assert_eq!(
+            stats.failed_by_reason.get(&FailReason::Unmapped).copied(),
+            (unmapped == UnmappedPolicy::Drop).then_some(3)
+        );
+        let names: Vec<String> = bam::Reader::from_path(&out_bam)
+            .unwrap()
+            .records()
+            .map(|r| String::from_utf8(r.unwrap().qname().to_vec()).unwrap())
+            .collect();
+        assert_eq!(names, expected);
+    }
+
+    #[rstest]
+    #[case(true, 1000, Some(2))]
+    #[case(true, 2, Some(2))]
+    #[case(true, 1, None)]
+    #[case(false, 1000, None)]
+    fn test_run_malformed(
+        #[case] tolerant: bool,
+        #[case] max_malformed: u64,
+        #[case] expected: Option<u64>,
+    ) {
+        let out_bam: String = format!("test/data/out_malformed_{}_{}.sam", tolerant, max_malformed);
+        let mut config = FilterConfig::new()
+            .in_bam("test/data/malformed.sam")
+            .out_bam(&out_bam)
+            .both(0.2);
+        if tolerant {
+            config = config.tolerant(max_malformed);
+        }
+        let result = run_with_config(&config);
+        match expected {
+            Some(malformed) => {
+                let stats = result.unwrap();
+                assert_eq!(stats.malformed, malformed);
+                assert_eq!(stats.read, 5);
+                assert_eq!(stats.written, 1);
+            }
+            None => assert!(result.is_err()),
+        }
+    }
+
+    #[rstest]
+    #[case(0.5, 1)]
+    #[case(0.5, 2)]
+    #[case(0.0, 1)]
+    #[case(1.0, 1)]
+    fn test_run_subsample(#[case] fraction: f64, #[case] seed: u64) {
+        let out_bam: String = format!("test/data/out_subsample_{}_{}.sam", fraction, seed);
+        let config = FilterConfig::new()
+            .in_bam("test/data/paired.sam")
+            .out_bam(&out_bam)
+            .both(1.0)
+            .left(1.0)
+            .right(1.0)
+            .subsample(fraction, seed);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written + stats.subsampled_out, stats.read);
+        let names = |path: &str| -> Vec<Vec<u8>> {
+            bam::Reader::from_path(path)
+                .unwrap()
+                .records()
+                .map(|r| r.unwrap().qname().to_vec())
+                .collect()
+        };
+        // all the alignments of the sampled read names, mates included
+        let expected: Vec<Vec<u8>> = names("test/data/paired.sam")
+            .into_iter()
+            .filter(|name| subsample::is_sampled(name, seed, fraction))
+            .collect();
+        assert_eq!(names(&out_bam), expected);
+    }
+
+    #[test]
+    fn test_run_throughput() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam("test/data/out_throughput.bam");
+        let stats = run_with_config(&config).unwrap();
+        let throughput = stats.throughput.unwrap();
+        assert!(throughput.seconds > 0.0);
+        assert!(throughput.records_per_second > 0.0);
+        assert_eq!(
+            throughput.input_bytes,
+            Some(std::fs::metadata("test/data/test.sam").unwrap().len())
+        );
+        assert_eq!(
+            throughput.output_bytes,
+            Some(
+                std::fs::metadata("test/data/out_throughput.bam")
+                    .unwrap()
+                    .len()
+            )
+        );
+        let json: serde_json::Value = serde_json::to_value(&stats).unwrap();
+        assert!(json["throughput"]["output_mb_per_second"].is_number());
+    }
+
+    #[test]
+    fn test_run_shard_by_chrom() {
+        let dir: &str = "test/data/out_shards";
+        let config = FilterConfig::new()
+            .in_bam("test/data/lane2.sam")
+            .out_bam("test/data/out_shards.bam")
+            .unalign(true)
+            .shard_by_chrom(dir);
+        run_with_config(&config).unwrap();
+        for (file, expected) in [
+            ("chr2.bam", vec![b"l1".to_vec()]),
+            ("chr1.bam", vec![b"l2".to_vec()]),
+            ("unmapped.bam", vec![b"l3".to_vec()]),
+        ] {
+            let mut reader = bam::Reader::from_path(format!("{}/{}", dir, file)).unwrap();
+            assert_eq!(reader.header().target_count(), 2);
+            let names: Vec<Vec<u8>> = reader
+                .records()
+                .map(|r| r.unwrap().qname().to_vec())
+                .collect();
+            assert_eq!(names, expected);
+        }
+    }
+
+    #[test]
+    fn test_run_clip_consensus() {
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_consensus.bam")
+            .clip_consensus("test/data/out_consensus.fa", 1);
+        run_with_config(&config).unwrap();
+        let text: String = std::fs::read_to_string("test/data/out_consensus.fa").unwrap();
+        assert_eq!(
+            text,
+            ">chr1:129:right_clip reads=1\nAGATCGGAAGAG\n\
+             >chr1:200:left_clip reads=1\nCTCTTCCGATCT\n\
+             >chr1:329:right_clip reads=1\nTTTTTTTTTTTT\n\
+             >chr1:529:right_clip reads=1\nAGATCGGTAGAG\n"
+        );
+    }
+
+    #[test]
+    fn test_run_primers() {
+        let text: String = std::fs::read_to_string("test/data/primers.bed").unwrap();
+        let config = FilterConfig::new()
+            .in_bam("test/data/adapters.sam")
+            .out_bam("test/data/out_primers.bam")
+            .primers(primers::parse_bed(&text).unwrap());
+        let stats = run_with_config(&config).unwrap();
+        // r2 and r3 are clipped within a primer, r1 and r5 are not
+        assert_eq!(stats.written, 3);
+        assert_eq!(
+            stats.failed_by_reason,
+            vec![(FailReason::TotalClip, 2)].into_iter().collect()
+        );
+    }
+
+    #[rstest]
+    #[case(100.0, 9)]
+    #[case(70.0, 3)]
+    fn test_run_auto_threshold(#[case] percentile: f64, #[case] expected_written: u64) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(format!("test/data/out_auto_threshold_{}.bam", percentile))
+            .auto_threshold(percentile);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_written);
+    }
+
+    #[rstest]
+    #[case(1, FlagPolicy::Filter, FlagPolicy::Filter, false, 3)]
+    #[case(2, FlagPolicy::Keep, FlagPolicy::Keep, false, 5)]
+    #[case(3, FlagPolicy::Drop, FlagPolicy::Drop, false, 2)]
+    #[case(4, FlagPolicy::Keep, FlagPolicy::Drop, false, 4)]
+    #[case(5, FlagPolicy::Keep, FlagPolicy::Keep, true, 1)]
+    #[case(6, FlagPolicy::Drop, FlagPolicy::Drop, true, 1)]
+    fn test_run_flag_policy(
+        #[case] test_case: usize,
+        #[case] secondary: FlagPolicy,
+        #[case] supplementary: FlagPolicy,
+        #[case] inverse: bool,
+        #[case] expected_count: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_flag_policy_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/secondary.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            inverse,
+            secondary,
+            supplementary,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, 0);
+    }
+
+    #[rstest]
+    #[case(1, FlagPolicy::Filter, FlagPolicy::Filter, 2)]
+    #[case(2, FlagPolicy::Drop, FlagPolicy::Filter, 1)]
+    #[case(3, FlagPolicy::Keep, FlagPolicy::Filter, 4)]
+    #[case(4, FlagPolicy::Keep, FlagPolicy::Drop, 3)]
+    fn test_run_skip_duplicates(
+        #[case] test_case: usize,
+        #[case] duplicates: FlagPolicy,
+        #[case] secondary: FlagPolicy,
+        #[case] expected_count: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_duplicates_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/duplicates.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            duplicates,
+            secondary,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, 0);
+    }
+
+    #[rstest]
+    #[case(1, FlagPolicy::Filter, false, 2)]
+    #[case(2, FlagPolicy::Drop, false, 1)]
+    #[case(3, FlagPolicy::Filter, true, 2)]
+    #[case(4, FlagPolicy::Drop, true, 1)]
+    fn test_run_qcfail(
+        #[case] test_case: usize,
+        #[case] qcfail: FlagPolicy,
+        #[case] inverse: bool,
+        #[case] expected_count: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_qcfail_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/qcfail.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            qcfail,
+            inverse,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, 0);
+    }
+
+    #[rstest]
+    #[case(1, false, PairPolicy::Both, false, false, 4, 0)]
+    #[case(2, true, PairPolicy::Both, false, false, 3, 0)]
+    #[case(3, true, PairPolicy::Any, false, false, 5, 0)]
+    #[case(4, true, PairPolicy::Both, true, false, 4, 0)]
+    #[case(5, true, PairPolicy::Both, false, true, 7, 4)]
+    fn test_run_paired(
+        #[case] test_case: usize,
+        #[case] paired: bool,
+        #[case] pair_policy: PairPolicy,
+        #[case] inverse: bool,
+        #[case] unalign: bool,
+        #[case] expected_count: i32,
+        #[case] expected_unaligned: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_paired_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/paired.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            paired,
+            pair_policy,
+            inverse,
+            unalign,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, expected_unaligned);
+    }
+
+    #[rstest]
+    #[case(1, false, PairPolicy::Both, false, false, 4, 0)]
+    #[case(2, true, PairPolicy::Both, false, false, 3, 0)]
+    #[case(3, true, PairPolicy::Any, false, false, 5, 0)]
+    #[case(4, true, PairPolicy::Both, true, false, 4, 0)]
+    #[case(5, true, PairPolicy::Both, false, true, 7, 4)]
+    fn test_run_two_pass(
+        #[case] test_case: usize,
+        #[case] two_pass: bool,
+        #[case] pair_policy: PairPolicy,
+        #[case] inverse: bool,
+        #[case] unalign: bool,
+        #[case] expected_count: i32,
+        #[case] expected_unaligned: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_two_pass_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/paired_sorted.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            two_pass,
+            pair_policy,
+            inverse,
+            unalign,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, expected_unaligned);
+    }
+
+    #[rstest]
+    #[case(1, "test/data/paired.sam", true, false)]
+    #[case(2, "test/data/paired_sorted.sam", false, true)]
+    fn test_run_unalign_fix_mates(
+        #[case] test_case: usize,
+        #[case] in_bam: &str,
+        #[case] paired: bool,
+        #[case] two_pass: bool,
+    ) {
+        let out_bam: &str = &format!("test/data/out_fix_mates_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: in_bam.to_string(),
+            out_bam: out_bam.to_string(),
+            paired,
+            two_pass,
+            unalign: true,
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+
+        let mut bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        let mut unaligned_count = 0;
+        for r in bam_reader.records() {
+            let record = r.unwrap();
+            if record.is_unmapped() {
+                unaligned_count += 1;
+                assert!(record.is_mate_unmapped());
+                assert_eq!(record.mtid(), -1);
+                assert_eq!(record.insert_size(), 0);
+                assert!(record.aux(b"MC").is_err());
+                assert!(record.aux(b"MQ").is_err());
+            } else {
+                assert!(!record.is_mate_unmapped());
+            }
+        }
+        assert_eq!(unaligned_count, 4);
+    }
+
+    #[rstest]
+    #[case(1, false, FlagPolicy::Filter, false, Some(2))]
+    #[case(2, true, FlagPolicy::Filter, false, Some(4))]
+    #[case(3, true, FlagPolicy::Keep, false, None)]
+    #[case(4, true, FlagPolicy::Drop, false, Some(2))]
+    #[case(5, true, FlagPolicy::Filter, true, Some(2))]
+    fn test_run_cascade(
+        #[case] test_case: usize,
+        #[case] cascade: bool,
+        #[case] secondary: FlagPolicy,
+        #[case] inverse: bool,
+        #[case] expected_count: Option<i32>,
+    ) {
+        let out_bam: &str = &format!("test/data/out_cascade_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/secondary.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            two_pass: true,
+            cascade,
+            secondary,
+            supplementary: secondary,
+            inverse,
+            ..FilterConfig::default()
+        };
+        let expected_count: i32 = match expected_count {
+            Some(count) => count,
+            None => {
+                assert!(matches!(
+                    run_with_config(&config),
+                    Err(FilterClippedError::InvalidConfig(_))
+                ));
+                return;
+            }
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, 0);
+    }
+
+    #[rstest]
+    #[case(1, false, FlagPolicy::Filter, false, 1, 0)]
+    #[case(2, true, FlagPolicy::Filter, false, 5, 0)]
+    #[case(3, true, FlagPolicy::Drop, false, 3, 0)]
+    #[case(4, true, FlagPolicy::Filter, true, 7, 2)]
+    fn test_run_sa_aware(
+        #[case] test_case: usize,
+        #[case] sa_aware: bool,
+        #[case] supplementary: FlagPolicy,
+        #[case] unalign: bool,
+        #[case] expected_count: i32,
+        #[case] expected_unaligned: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_sa_aware_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/chimeric.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            paired: true,
+            sa_aware,
+            supplementary,
+            unalign,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.to_string(), expected_count, expected_unaligned);
+
+        let mut bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        for r in bam_reader.records() {
+            let record = r.unwrap();
+            if record.is_unmapped() || supplementary == FlagPolicy::Drop {
+                assert!(record.aux(b"SA").is_err());
+            } else {
+                assert!(record.aux(b"SA").is_ok() || record.qname() == b"readC");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(1, true, None, 3, 0)]
+    #[case(2, true, Some(SingletonPolicy::Keep), 4, 0)]
+    #[case(3, true, Some(SingletonPolicy::Drop), 3, 0)]
+    #[case(
+        4,
+        true,
+        Some(SingletonPolicy::SeparateOut(String::from("test/data/out_singletons_4.sam"))),
+        3,
+        1
+    )]
+    #[case(5, false, Some(SingletonPolicy::Keep), 4, 0)]
+    fn test_run_singletons(
+        #[case] test_case: usize,
+        #[case] paired: bool,
+        #[case] singletons: Option<SingletonPolicy>,
+        #[case] expected_count: i32,
+        #[case] expected_separate: i32,
+    ) {
+        let out_bam: &str = &format!("test/data/out_singletons_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: match paired {
+                true => "test/data/paired.sam".to_string(),
+                false => "test/data/paired_sorted.sam".to_string(),
+            },
+            out_bam: out_bam.to_string(),
+            paired,
+            two_pass: !paired,
+            singletons: singletons.clone(),
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        // pairB fails on its second mate only
+        assert_eq!(stats.singletons, singletons.is_some() as u64);
+        count_bam(out_bam.to_string(), expected_count, 0);
+        if let Some(SingletonPolicy::SeparateOut(path)) = singletons {
+            count_bam(path, expected_separate, 0);
+        }
+    }
+
+    #[rstest]
+    #[case(1, true, false, false, 0)]
+    #[case(2, true, false, true, 2)]
+    #[case(3, false, true, true, 1)]
+    fn test_run_fix_orphans(
+        #[case] test_case: usize,
+        #[case] paired: bool,
+        #[case] two_pass: bool,
+        #[case] fix_orphans: bool,
+        #[case] expected_orphans: u64,
+    ) {
+        let out_bam: &str = &format!("test/data/out_fix_orphans_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/orphans.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            paired,
+            two_pass,
+            fix_orphans,
+            qcfail: FlagPolicy::Drop,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.orphans, expected_orphans);
+        count_bam(out_bam.to_string(), 4, 0);
+
+        let mut bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        for r in bam_reader.records() {
+            let record = r.unwrap();
+            // the mate of pairB is dropped, the one of pairC is missing from the input
+            let orphan: bool = match record.qname() {
+                b"pairB" => fix_orphans,
+                b"pairC" => fix_orphans && paired,
+                _ => false,
+            };
+            assert_eq!(record.is_mate_unmapped(), orphan);
+            assert_eq!(record.mtid() == -1, orphan);
+            assert_eq!(record.aux(b"MC").is_err(), orphan);
+        }
+    }
+
+    #[rstest]
+    #[case(1, false, 1, 0)]
+    #[case(2, true, 4, 3)]
+    fn test_run_promote_supplementary(
+        #[case] test_case: usize,
+        #[case] promote_supplementary: bool,
+        #[case] expected_count: i32,
+        #[case] expected_promoted: u64,
+    ) {
+        let out_bam: &str = &format!("test/data/out_promote_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/chimeric.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            paired: true,
+            ignore_supplementary_hardclips: true,
+            promote_supplementary,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.promoted, expected_promoted);
+        count_bam(out_bam.to_string(), expected_count, 0);
+
+        let mut primaries: HashSet<Vec<u8>> = HashSet::new();
+        let mut bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        for r in bam_reader.records() {
+            let record = r.unwrap();
+            if promote_supplementary {
+                // one primary alignment holding the whole read by read name, without SA
+                // tags pointing at the removed primary alignments
+                assert!(!record.is_supplementary());
+                assert!(primaries.insert(record.qname().to_vec()));
+                assert_eq!(record.seq_len(), 100);
+                assert!(record.aux(b"SA").is_err());
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(AlreadyFilteredPolicy::Warn, 0.2, false, true)]
+    #[case(AlreadyFilteredPolicy::Skip, 0.2, false, false)]
+    #[case(AlreadyFilteredPolicy::Skip, 0.2, true, false)]
+    #[case(AlreadyFilteredPolicy::Skip, 0.3, false, true)]
+    #[case(AlreadyFilteredPolicy::Proceed, 0.2, false, true)]
+    fn test_run_already_filtered(
+        #[case] policy: AlreadyFilteredPolicy,
+        #[case] rerun_both_end: f64,
+        #[case] two_pass: bool,
+        #[case] expected_rerun: bool,
+    ) {
+        let first_out: &str = &format!(
+            "test/data/out_already_filtered_{:?}_{}_{}.bam",
+            policy, rerun_both_end, two_pass
+        );
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(first_out)
+            .both(0.2)
+            .two_pass(two_pass);
+        let first_stats: RunStats = run_with_config(&config).unwrap();
+        assert!(first_stats.written > 0);
+
+        let rerun_out: String = first_out.replace(".bam", ".rerun.bam");
+        let _ = std::fs::remove_file(&rerun_out);
+        let rerun = config
+            .in_bam(first_out)
+            .out_bam(&rerun_out)
+            .both(rerun_both_end)
+            .if_already_filtered(policy);
+        let stats: RunStats = run_with_config(&rerun).unwrap();
+        assert_eq!(stats.skipped, !expected_rerun);
+        assert_eq!(stats.written == first_stats.written, expected_rerun);
+        assert_eq!(std::path::Path::new(&rerun_out).exists(), expected_rerun);
+        // a skipped re-run is no empty output
+        assert!(check_run(&rerun.fail_if_empty(true), &stats).is_ok());
+    }
+
+    #[test]
+    fn test_run_program_record() {
+        let out_bam = "test/data/out_program_record.bam";
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            command_line: Some("filter-clipped -i test/data/test.sam".to_string()),
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+        let bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        let programs = header::program_records(bam_reader.header().as_bytes());
+        assert_eq!(programs, vec![("filter-clipped".to_string(), None)]);
+        let text = String::from_utf8(bam_reader.header().as_bytes().to_vec()).unwrap();
+        assert!(text.contains("CL:filter-clipped -i test/data/test.sam"));
+    }
+
+    #[rstest]
+    #[case(1, true)]
+    #[case(2, false)]
+    fn test_run_header_comment(#[case] test_case: usize, #[case] header_comment: bool) {
+        let out_bam: &str = &format!("test/data/out_header_comment_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            header_comment,
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+        let bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        let text = String::from_utf8(bam_reader.header().as_bytes().to_vec()).unwrap();
+        let comment = text.lines().find(|line| line.starts_with("@CO\t"));
+        assert_eq!(comment.is_some(), header_comment);
+        if let Some(comment) = comment {
+            let parsed: serde_json::Value = serde_json::from_str(&comment[4..]).unwrap();
+            assert_eq!(parsed["config"]["in_bam"], "test/data/test.sam");
+            assert_eq!(parsed["config"]["both_end"], 0.1);
+            assert_eq!(parsed["config"]["secondary"], "filter");
+        }
+    }
+
+    #[rstest]
+    #[case("test/data/out_format.sam", None, b"@HD")]
+    #[case("test/data/out_format.bam", None, b"\x1f\x8b\x08")]
+    #[case(
+        "test/data/out_format_override.sam",
+        Some(OutputFormat::Bam),
+        b"\x1f\x8b\x08"
+    )]
+    #[case("test/data/out_format_override.bam", Some(OutputFormat::Sam), b"@HD")]
+    fn test_run_output_format(
+        #[case] out_bam: &str,
+        #[case] output_fmt: Option<OutputFormat>,
+        #[case] expected_magic: &[u8],
+    ) {
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            output_fmt,
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+        let content = std::fs::read(out_bam).unwrap();
+        assert_eq!(&content[..expected_magic.len()], expected_magic);
+        count_bam(out_bam.to_string(), 6, 0);
+    }
+
+    #[rstest]
+    #[case("test/data/out_index.bam", "test/data/out_index.bam.bai")]
+    #[case("test/data/out_index.cram", "test/data/out_index.cram.crai")]
+    fn test_run_write_index(#[case] out_bam: &str, #[case] expected_index: &str) {
+        let _ = std::fs::remove_file(expected_index);
+        let config = FilterConfig {
+            in_bam: "test/data/paired_sorted.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            write_index: true,
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+        assert!(std::path::Path::new(expected_index).exists());
+        let mut reader = bam::IndexedReader::from_path(out_bam).unwrap();
+        reader.fetch("chr1").unwrap();
+        assert!(reader.records().count() > 0);
+    }
+
+    #[test]
+    fn test_run_region_threads() {
+        // an indexed copy of the input, keeping everything
+        let in_bam: &str = "test/data/out_region_input.bam";
+        let config = FilterConfig::new()
+            .in_bam("test/data/paired_sorted.sam")
+            .out_bam(in_bam)
+            .both(1.0)
+            .left(1.0)
+            .right(1.0)
+            .write_index(true);
+        run_with_config(&config).unwrap();
+
+        let records = |path: &str| -> Vec<(Vec<u8>, i32, i64)> {
+            bam::Reader::from_path(path)
+                .unwrap()
+                .records()
+                .map(|r| r.unwrap())
+                .map(|record| (record.qname().to_vec(), record.tid(), record.pos()))
+                .collect()
+        };
+        let serial = FilterConfig::new()
+            .in_bam(in_bam)
+            .out_bam("test/data/out_region_serial.bam");
+        let serial_stats = run_with_config(&serial).unwrap();
+        // windows of 1024 bases, with pairB overlapping the first two
+        let parallel = serial
+            .clone()
+            .out_bam("test/data/out_region_parallel.bam")
+            .region_threads(3, 1024);
+        let parallel_stats = run_with_config(&parallel).unwrap();
+        assert_eq!(parallel_stats.read, 7);
+        assert_eq!(parallel_stats.read, serial_stats.read);
+        assert_eq!(parallel_stats.written, serial_stats.written);
+        assert_eq!(
+            parallel_stats.failed_by_reason,
+            serial_stats.failed_by_reason
+        );
+        assert_eq!(
+            records("test/data/out_region_parallel.bam"),
+            records("test/data/out_region_serial.bam")
+        );
+        assert!(!std::path::Path::new("test/data/out_region_parallel.bam.0.tmp.bam").exists());
+
+        // the same alignments as they come, in an unsorted output
+        let unordered = parallel
+            .out_bam("test/data/out_region_unordered.bam")
+            .unordered(true);
+        let unordered_stats = run_with_config(&unordered).unwrap();
+        assert_eq!(unordered_stats.written, serial_stats.written);
+        let mut unordered_records = records("test/data/out_region_unordered.bam");
+        unordered_records.sort_by_key(|(_, tid, pos)| (*tid, *pos));
+        assert_eq!(
+            unordered_records,
+            records("test/data/out_region_serial.bam")
+        );
+        let reader = bam::Reader::from_path("test/data/out_region_unordered.bam").unwrap();
+        let text = String::from_utf8(reader.header().as_bytes().to_vec()).unwrap();
+        assert!(text.lines().next().unwrap().contains("SO:unsorted"));
+    }
+
+    #[rstest]
+    #[case("-", None)]
+    #[case("test/data/out_index_fail.sam", None)]
+    #[case("test/data/out_index_fail.bam", Some(OutputFormat::Sam))]
+    #[should_panic]
+    fn test_run_write_index_panic(#[case] out_bam: &str, #[case] output_fmt: Option<OutputFormat>) {
+        let config = FilterConfig {
+            in_bam: "test/data/paired_sorted.sam".to_string(),
+            out_bam: out_bam.to_string(),
+            output_fmt,
+            write_index: true,
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+    }
+
+    #[rstest]
+    #[case(SortOrder::Queryname, true, false, false, Some(SortOrder::Queryname))]
+    #[case(SortOrder::Collated, true, false, false, Some(SortOrder::Collated))]
+    #[case(SortOrder::Coordinate, true, false, false, None)]
+    #[case(SortOrder::Unknown, true, false, false, None)]
+    #[case(SortOrder::Coordinate, false, false, true, Some(SortOrder::Coordinate))]
+    #[case(SortOrder::Coordinate, false, true, false, Some(SortOrder::Unsorted))]
+    #[case(SortOrder::Coordinate, false, true, true, None)]
+    #[case(SortOrder::Queryname, false, true, true, None)]
+    fn test_check_sort_order(
+        #[case] input_order: SortOrder,
+        #[case] paired: bool,
+        #[case] unalign: bool,
+        #[case] write_index: bool,
+        #[case] expected: Option<SortOrder>,
+    ) {
+        let config = FilterConfig {
+            paired,
+            unalign,
+            write_index,
+            require_sorted: true,
+            ..FilterConfig::default()
+        };
+        assert_eq!(check_sort_order(&config, input_order).ok(), expected);
+
+        // without --require-sorted, only warnings are given
+        let config = FilterConfig {
+            require_sorted: false,
+            ..config
+        };
+        assert!(check_sort_order(&config, input_order).is_ok());
+    }
+
+    #[rstest]
+    #[case(false, "SO:coordinate")]
+    #[case(true, "SO:unsorted")]
+    fn test_run_sort_order_header(#[case] unalign: bool, #[case] expected: &str) {
+        let out_bam = format!("test/data/out_sort_order_{}.bam", unalign);
+        let config = FilterConfig {
+            in_bam: "test/data/paired_sorted.sam".to_string(),
+            out_bam: out_bam.clone(),
+            unalign,
+            ..FilterConfig::default()
+        };
+        run_with_config(&config).unwrap();
+        let bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        let text = String::from_utf8(bam_reader.header().as_bytes().to_vec()).unwrap();
+        let hd_line = text.lines().next().unwrap();
+        assert!(hd_line.starts_with("@HD"));
+        assert!(hd_line.contains(expected));
+        assert!(text.contains("@SQ\tSN:chr1"));
+    }
+
+    #[rstest]
+    #[case(false, false, 4, None)]
+    #[case(true, false, 3, Some(1))]
+    #[case(true, true, 7, Some(1))]
+    fn test_run_require_proper_pair(
+        #[case] require_proper_pair: bool,
+        #[case] unalign: bool,
+        #[case] expected_count: i32,
+        #[case] expected_improper: Option<u64>,
+    ) {
+        let out_bam: String = format!(
+            "test/data/out_proper_pair_{}_{}.bam",
+            require_proper_pair, unalign
+        );
+        let config = FilterConfig::new()
+            .in_bam("test/data/paired.sam")
+            .out_bam(&out_bam)
+            .require_proper_pair(require_proper_pair)
+            .unalign(unalign)
+            .paired(unalign);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        assert_eq!(
+            stats.failed_by_reason.get(&FailReason::ProperPair).copied(),
+            expected_improper
+        );
+    }
+
+    #[rstest]
+    #[case(None)]
+    #[case(Some("XF"))]
+    fn test_run_zero_mapq(#[case] reason_tag: Option<&str>) {
+        let out_bam: String = format!("test/data/out_zero_mapq_{:?}.bam", reason_tag);
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(&out_bam)
+            .zero_mapq(true, reason_tag);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.read, stats.written);
+        assert_eq!(stats.zero_mapq, stats.failed());
+        assert!(stats.zero_mapq > 0);
+        let records: Vec<Record> = bam::Reader::from_path(&out_bam)
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap())
+            .collect();
+        let tagged: Vec<&Record> = records
+            .iter()
+            .filter(|record| record.aux(b"XF").is_ok())
+            .collect();
+        match reason_tag {
+            Some(_) => {
+                assert_eq!(tagged.len() as u64, stats.zero_mapq);
+                assert!(tagged.iter().all(|record| record.mapq() == 0));
+            }
+            None => assert!(tagged.is_empty()),
+        }
+        assert!(
+            records.iter().filter(|record| record.mapq() == 0).count() as u64 >= stats.zero_mapq
+        );
+    }
+
+    #[rstest]
+    #[case("test/data/lane2.sam", true, 0.1)]
+    #[case("test/data/lane2.sam", false, 0.5)]
+    #[case("test/data/test.sam", true, 0.5)]
+    fn test_apply_auto_preset(
+        #[case] in_bam: &str,
+        #[case] auto_preset: bool,
+        #[case] expected_left_side: f64,
+    ) {
+        let config = FilterConfig::new()
+            .in_bam(in_bam)
+            .left(0.5)
+            .auto_preset(auto_preset);
+        let config = apply_auto_preset(&config).unwrap();
+        assert_eq!(config.left_side, expected_left_side);
+    }
+
+    #[rstest]
+    #[case(SeqlessPolicy::Inherit, false, 3)]
+    #[case(SeqlessPolicy::Inherit, true, 2)]
+    #[case(SeqlessPolicy::Keep, false, 3)]
+    #[case(SeqlessPolicy::Keep, true, 3)]
+    #[case(SeqlessPolicy::Drop, false, 1)]
+    #[case(SeqlessPolicy::Drop, true, 1)]
+    fn test_run_seqless_secondary(
+        #[case] policy: SeqlessPolicy,
+        #[case] paired: bool,
+        #[case] expected_count: u64,
+    ) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/seqless.sam")
+            .out_bam(format!("test/data/out_seqless_{:?}_{}.bam", policy, paired))
+            .seqless_secondary(policy)
+            .paired(paired);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.seqless_secondary, 2);
+        assert_eq!(stats.written, expected_count);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_run_unaligned_to_end(#[case] two_pass: bool) {
+        let out_bam: String = format!("test/data/out_unaligned_to_end_{}.bam", two_pass);
+        let config = FilterConfig::new()
+            .in_bam("test/data/paired_sorted.sam")
+            .out_bam(&out_bam)
+            .unalign(true)
+            .unaligned_to_end(true)
+            .two_pass(two_pass)
+            .write_index(true)
+            .require_sorted(true);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, 7);
+        assert!(stats.unaligned > 0);
+        let mut reader = bam::Reader::from_path(&out_bam).unwrap();
+        let text = String::from_utf8(reader.header().as_bytes().to_vec()).unwrap();
+        assert!(text.lines().next().unwrap().contains("SO:coordinate"));
+        let tids: Vec<i32> = reader.records().map(|r| r.unwrap().tid()).collect();
+        assert_eq!(tids.len(), 7);
+        // all the unaligned ones at the end
+        let first_unaligned: usize = tids.iter().position(|tid| *tid < 0).unwrap();
+        assert_eq!(
+            tids[first_unaligned..]
+                .iter()
+                .filter(|tid| **tid < 0)
+                .count() as u64,
+            stats.unaligned
+        );
+        assert!(std::path::Path::new(&format!("{}.bai", out_bam)).exists());
+        assert!(!std::path::Path::new(&format!("{}.unaligned.tmp.bam", out_bam)).exists());
+    }
+
+    #[test]
+    fn test_check_sort_order_collate() {
+        let config = FilterConfig {
+            paired: true,
+            collate: true,
+            require_sorted: true,
+            ..FilterConfig::default()
+        };
+        assert_eq!(
+            check_sort_order(&config, SortOrder::Coordinate).ok(),
+            Some(SortOrder::Collated)
+        );
+    }
+
+    #[rstest]
+    #[case(1, PairPolicy::Both, 1_000_000, 3)]
+    #[case(2, PairPolicy::Any, 1_000_000, 5)]
+    #[case(3, PairPolicy::Both, 1, 3)]
+    #[case(4, PairPolicy::Any, 0, 5)]
+    fn test_run_collate(
+        #[case] test_case: usize,
+        #[case] pair_policy: PairPolicy,
+        #[case] collate_max_pending: usize,
+        #[case] expected_count: i32,
+    ) {
+        // coordinate sorted input gives the same reads as the name grouped one in test_run_paired
+        let out_bam: String = format!("test/data/out_collate_run_{}.bam", test_case);
+        let config = FilterConfig {
+            in_bam: "test/data/paired_sorted.sam".to_string(),
+            out_bam: out_bam.clone(),
+            paired: true,
+            collate: true,
+            collate_max_pending,
+            pair_policy,
+            require_sorted: true,
+            ..FilterConfig::default()
+        };
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count as u64);
+        count_bam(out_bam.clone(), expected_count, 0);
+        let bam_reader = bam::Reader::from_path(out_bam).unwrap();
+        let text = String::from_utf8(bam_reader.header().as_bytes().to_vec()).unwrap();
+        assert!(text.lines().next().unwrap().contains("GO:query"));
+    }
+
+    #[test]
+    fn test_run_two_pass_stdin() {
+        let config = FilterConfig {
+            in_bam: "-".to_string(),
+            out_bam: "test/data/out_two_pass_stdin.bam".to_string(),
+            two_pass: true,
+            ..FilterConfig::default()
+        };
+        assert!(matches!(
+            run_with_config(&config),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+
+    #[rstest]
+    #[case("test/data/missing.bam", "test/data/out_error_1.bam", 1)]
+    #[case("test/data/test.sam", "test/data/missing_dir/out.bam", 1)]
+    #[case("test/data/test.sam", "-", 2)]
+    fn test_run_error_exit_code(
+        #[case] in_bam: &str,
+        #[case] out_bam: &str,
+        #[case] expected: i32,
+    ) {
+        let config = FilterConfig {
+            in_bam: in_bam.to_string(),
+            out_bam: out_bam.to_string(),
+            // only fails for stdout
+            write_index: true,
+            ..FilterConfig::default()
+        };
+        let err = run_with_config(&config).unwrap_err();
+        assert_eq!(err.exit_code(), expected);
+    }
+
+    #[rstest]
+    #[case(false, None, 0.1, true)]
+    #[case(true, None, 0.1, true)]
+    #[case(true, None, 0.0, false)]
+    #[case(false, Some(0.5), 0.1, true)]
+    #[case(false, Some(0.3), 0.1, false)]
+    #[case(false, Some(1.0), 0.0, true)]
+    fn test_check_run(
+        #[case] fail_if_empty: bool,
+        #[case] fail_if_removed_frac: Option<f64>,
+        #[case] both_end: f64,
+        #[case] expected_ok: bool,
+    ) {
+        let config = FilterConfig {
+            in_bam: "test/data/test.sam".to_string(),
+            out_bam: format!(
+                "test/data/out_check_{}_{:?}_{}.sam",
+                fail_if_empty, fail_if_removed_frac, both_end
+            ),
+            both_end,
+            left_side: both_end,
+            right_side: both_end,
+            fail_if_empty,
+            fail_if_removed_frac,
+            ..FilterConfig::default()
+        };
+        let stats: RunStats = run_with_config(&config).unwrap();
+        let result = check_run(&config, &stats);
+        assert_eq!(result.is_ok(), expected_ok);
+        if let Err(err) = result {
+            assert_eq!(err.exit_code(), 3);
+        }
+        std::fs::remove_file(&config.out_bam).unwrap();
+    }
+
+    #[rstest]
+    #[case(false, false, 3)]
+    #[case(true, false, 2)]
+    #[case(false, true, 1)]
+    fn test_process(#[case] paired: bool, #[case] inverse: bool, #[case] expected_count: u64) {
+        let sam = "@HD\tVN:1.6\tSO:queryname\n@SQ\tSN:chr1\tLN:1000\n\
+            pairA\t99\tchr1\t10\t60\t10M\t=\t100\t100\tACGTACGTAC\tFFFFFFFFFF\n\
+            pairA\t147\tchr1\t100\t60\t10M\t=\t10\t-100\tACGTACGTAC\tFFFFFFFFFF\n\
+            pairB\t99\tchr1\t10\t60\t10M\t=\t100\t100\tACGTACGTAC\tFFFFFFFFFF\n\
+            pairB\t147\tchr1\t100\t60\t5M5S\t=\t10\t-100\tACGTACGTAC\tFFFFFFFFFF\n";
+        let out_bam = format!("test/data/out_process_{}_{}.bam", paired, inverse);
+        let mut reader = io::reader_from_sam_text(sam).unwrap();
+        let header = Header::from_template(reader.header());
+        let mut writer = bam::Writer::from_path(&out_bam, &header, bam::Format::Bam).unwrap();
+        let config = FilterConfig::new().paired(paired).inverse(inverse);
+        let stats = process(&mut reader, &mut writer, &config).unwrap();
+        drop(writer);
+        assert_eq!(stats.read, 4);
+        assert_eq!(stats.written, expected_count);
+        count_bam(out_bam, expected_count as i32, 0);
+    }
+
+    #[test]
+    fn test_process_two_pass() {
+        let mut reader = io::reader_from_sam_text("@SQ\tSN:chr1\tLN:1000\n").unwrap();
+        let header = Header::from_template(reader.header());
+        let mut writer = bam::Writer::from_path(
+            "test/data/out_process_two_pass.bam",
+            &header,
+            bam::Format::Bam,
+        )
+        .unwrap();
+        let config = FilterConfig::new().two_pass(true);
+        assert!(matches!(
+            process(&mut reader, &mut writer, &config),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+
+    #[rstest]
+    #[case(false, 0, vec![(FailReason::TotalClip, 3), (FailReason::ExtraFilter, 6)])]
+    #[case(true, 9, vec![])]
+    fn test_run_extra_filter(
+        #[case] keep_all: bool,
+        #[case] expected_count: u64,
+        #[case] expected_failures: Vec<(FailReason, u64)>,
+    ) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam(format!("test/data/out_extra_filter_{}.bam", keep_all))
+            .with_extra_filter(move |_, clip_stat, _| keep_all && clip_stat.total_clipped() >= 0);
+        let stats = run_with_config(&config).unwrap();
+        assert_eq!(stats.written, expected_count);
+        assert_eq!(
+            stats.failed_by_reason,
+            expected_failures.into_iter().collect()
+        );
     }
 }
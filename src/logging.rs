@@ -0,0 +1,179 @@
+use crate::error::FilterClippedError;
+use env_logger::{Builder, Env, Target};
+use log::{info, LevelFilter};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Target of the log event carrying the counters of a run, only emitted with JSON logs
+pub const COUNTERS_TARGET: &str = "filter_clipped::counters";
+
+/// Whether the logs are JSON, set up by [init_logger]
+static JSON_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// How the log events are written
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// human readable lines
+    Text,
+    /// one JSON object per line: timestamp, level, target and message, and the counters of
+    /// the run for the summary event
+    Json,
+}
+
+/// JSON line of a log event
+///
+/// # Arguments
+/// - `timestamp`: time of the event (RFC 3339)
+/// - `level`: level of the event
+/// - `target`: target of the event, the counters of [COUNTERS_TARGET] are embedded as JSON
+/// - `message`: the formatted message
+///
+/// # Example
+/// ```
+/// use filter_clipped::logging::{json_line, COUNTERS_TARGET};
+/// assert_eq!(
+///     json_line("2022-01-01T00:00:00Z", "INFO", "filter_clipped", "Read 3 \"alignments\""),
+///     r#"{"level":"INFO","message":"Read 3 \"alignments\"","target":"filter_clipped","timestamp":"2022-01-01T00:00:00Z"}"#
+/// );
+/// assert_eq!(
+///     json_line("2022-01-01T00:00:00Z", "INFO", COUNTERS_TARGET, r#"{"read":3}"#),
+///     r#"{"counters":{"read":3},"level":"INFO","message":"run summary","target":"filter_clipped::counters","timestamp":"2022-01-01T00:00:00Z"}"#
+/// );
+/// ```
+pub fn json_line(timestamp: &str, level: &str, target: &str, message: &str) -> String {
+    let mut event: Value = json!({
+        "timestamp": timestamp,
+        "level": level,
+        "target": target,
+        "message": message,
+    });
+    if target == COUNTERS_TARGET {
+        if let Ok(counters) = serde_json::from_str::<Value>(message) {
+            event["message"] = json!("run summary");
+            event["counters"] = counters;
+        }
+    }
+    event.to_string()
+}
+
+/// Log the counters of a run as a single event, for the log aggregators parsing JSON logs
+/// (the text logs already have them in their summary lines)
+pub fn log_counters<T: Serialize>(counters: &T) {
+    if JSON_LOGS.load(Ordering::Relaxed) {
+        if let Ok(counters) = serde_json::to_string(counters) {
+            info!(target: COUNTERS_TARGET, "{}", counters);
+        }
+    }
+}
+
+/// Log level picked from the verbosity flags
+///
+/// # Arguments
+/// - `verbose`: number of times `-v` was given
+/// - `quiet`: whether `--quiet` was given
+///
+/// # Returns
+/// - None if no flag was given, so that RUST_LOG (or the default "info") applies
+///
+/// # Example
+/// ```
+/// use filter_clipped::logging::level_filter;
+/// use log::LevelFilter;
+/// assert_eq!(level_filter(0, false), None);
+/// assert_eq!(level_filter(2, false), Some(LevelFilter::Trace));
+/// assert_eq!(level_filter(0, true), Some(LevelFilter::Error));
+/// ```
+pub fn level_filter(verbose: u8, quiet: bool) -> Option<LevelFilter> {
+    match (quiet, verbose) {
+        (true, _) => Some(LevelFilter::Error),
+        (_, 0) => None,
+        (_, 1) => Some(LevelFilter::Debug),
+        _ => Some(LevelFilter::Trace),
+    }
+}
+
+/// Set up the logger: the level comes from the verbosity flags if any is given,
+/// otherwise from RUST_LOG, falling back to "info"
+///
+/// # Arguments
+/// - `verbose`: number of times `-v` was given
+/// - `quiet`: whether `--quiet` was given
+/// - `log_file`: file to write the logs to instead of stderr
+/// - `format`: how the log events are written
+pub fn init_logger(
+    verbose: u8,
+    quiet: bool,
+    log_file: Option<&str>,
+    format: LogFormat,
+) -> Result<(), FilterClippedError> {
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    if let Some(level) = level_filter(verbose, quiet) {
+        builder.filter_level(level);
+    }
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line: String = json_line(
+                &buf.timestamp().to_string(),
+                record.level().as_str(),
+                record.target(),
+                &record.args().to_string(),
+            );
+            writeln!(buf, "{}", line)
+        });
+    }
+    if let Some(log_file) = log_file {
+        let file = File::create(log_file)?;
+        builder.target(Target::Pipe(Box::new(file)));
+    }
+    builder.try_init()?;
+    JSON_LOGS.store(format == LogFormat::Json, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, false, None)]
+    #[case(1, false, Some(LevelFilter::Debug))]
+    #[case(2, false, Some(LevelFilter::Trace))]
+    #[case(5, false, Some(LevelFilter::Trace))]
+    #[case(0, true, Some(LevelFilter::Error))]
+    fn test_level_filter(
+        #[case] verbose: u8,
+        #[case] quiet: bool,
+        #[case] expected: Option<LevelFilter>,
+    ) {
+        assert_eq!(level_filter(verbose, quiet), expected);
+    }
+
+    #[rstest]
+    #[case("filter_clipped", "Read 9 alignments", None)]
+    #[case(COUNTERS_TARGET, r#"{"read":9,"written":6}"#, Some(json!({"read": 9, "written": 6})))]
+    #[case(COUNTERS_TARGET, "not json", None)]
+    fn test_json_line(
+        #[case] target: &str,
+        #[case] message: &str,
+        #[case] expected_counters: Option<Value>,
+    ) {
+        let line: String = json_line("2022-01-01T00:00:00Z", "INFO", target, message);
+        let event: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["target"], target);
+        match expected_counters {
+            Some(counters) => {
+                assert_eq!(event["counters"], counters);
+                assert_eq!(event["message"], "run summary");
+            }
+            None => {
+                assert!(event.get("counters").is_none());
+                assert_eq!(event["message"], message);
+            }
+        }
+    }
+}
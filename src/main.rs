@@ -1,5 +1,9 @@
 use filter_clipped::wrapper;
+use std::process;
+
 fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    wrapper();
+    if let Err(err) = wrapper() {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
 }
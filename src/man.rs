@@ -0,0 +1,227 @@
+//! Roff man page of the command line (the `man` subcommand), rendered from the clap
+//! definitions so it never drifts from `--help`
+
+use clap::{Arg, Command};
+use std::io::Write;
+
+/// Escape text for roff: backslashes, dashes (so they aren't hyphenated or turned into
+/// typographic dashes) and lines starting with a control character
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+        .replace('-', "\\-")
+        .lines()
+        .map(
+            |line| match line.starts_with('.') || line.starts_with('\'') {
+                true => format!("\\&{}", line),
+                false => line.to_string(),
+            },
+        )
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Bold text
+fn bold(text: &str) -> String {
+    format!("\\fB{}\\fR", escape(text))
+}
+
+/// Italic text
+fn italic(text: &str) -> String {
+    format!("\\fI{}\\fR", escape(text))
+}
+
+/// Flags and value names of an option, e.g. `-l, --left-side <LEFT_SIDE>`
+fn option_header(arg: &Arg) -> String {
+    let mut flags: Vec<String> = Vec::new();
+    if let Some(short) = arg.get_short() {
+        flags.push(bold(&format!("-{}", short)));
+    }
+    if let Some(long) = arg.get_long() {
+        flags.push(bold(&format!("--{}", long)));
+    }
+    if flags.is_empty() {
+        flags.push(bold(arg.get_id()));
+    }
+    let mut header: String = flags.join(", ");
+    if arg.is_takes_value_set() {
+        let value_names: Vec<String> = match arg.get_value_names() {
+            Some(names) => names.iter().map(|name| name.to_string()).collect(),
+            None => vec![arg.get_id().to_uppercase().replace('-', "_")],
+        };
+        for name in value_names {
+            header.push(' ');
+            header.push_str(&italic(&format!("<{}>", name)));
+        }
+    }
+    header
+}
+
+/// Help text of an option, with its default and possible values like in `--help`
+fn option_help(arg: &Arg) -> String {
+    let mut help: String = arg
+        .get_long_help()
+        .or_else(|| arg.get_help())
+        .unwrap_or_default()
+        .to_string();
+    if arg.is_takes_value_set() && !arg.is_hide_default_value_set() {
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy().to_string())
+            .collect();
+        if !defaults.is_empty() {
+            help.push_str(&format!(" [default: {}]", defaults.join(",")));
+        }
+    }
+    if let Some(env) = arg.get_env() {
+        help.push_str(&format!(" [env: {}]", env.to_string_lossy()));
+    }
+    if let Some(values) = arg.get_possible_values() {
+        let names: Vec<&str> = values
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name())
+            .collect();
+        if !names.is_empty() && !arg.is_hide_possible_values_set() {
+            help.push_str(&format!(" [possible values: {}]", names.join(", ")));
+        }
+    }
+    escape(help.trim())
+}
+
+/// Render the man page (section 1) of a command
+///
+/// # Arguments
+/// - `command`: the command line definitions, e.g. `cli::Command::command()`
+///
+/// # Example
+/// ```
+/// use clap::{Arg, Command};
+/// use filter_clipped::man::render;
+/// let command = Command::new("tool")
+///     .version("1.0")
+///     .about("does things")
+///     .arg(Arg::new("in-bam").short('i').long("in-bam").takes_value(true).help("input"));
+/// let page = render(&command);
+/// assert!(page.starts_with(".TH tool 1 \"tool 1.0\"\n"));
+/// assert!(page.contains("tool \\- does things"));
+/// assert!(page.contains("\\fB\\-i\\fR, \\fB\\-\\-in\\-bam\\fR \\fI<IN_BAM>\\fR\ninput"));
+/// ```
+pub fn render(command: &Command) -> String {
+    let name: &str = command.get_name();
+    let version: &str = command.get_version().unwrap_or_default();
+    let about: &str = command.get_about().unwrap_or_default();
+    let mut page: String = format!(
+        ".TH {} 1 \"{}\"\n",
+        name,
+        format!("{} {}", name, version).trim()
+    );
+
+    page.push_str(&format!(".SH NAME\n{} \\- {}\n", name, escape(about)));
+
+    page.push_str(&format!(
+        ".SH SYNOPSIS\n{} [{}]",
+        bold(name),
+        italic("OPTIONS")
+    ));
+    if command.get_subcommands().next().is_some() {
+        page.push_str(&format!(" [{}]", italic("SUBCOMMAND")));
+    }
+    page.push('\n');
+
+    if let Some(long_about) = command.get_long_about() {
+        page.push_str(&format!(".SH DESCRIPTION\n{}\n", escape(long_about)));
+    }
+
+    let args: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+    if !args.is_empty() {
+        page.push_str(".SH OPTIONS\n");
+        for arg in args {
+            page.push_str(&format!(
+                ".TP\n{}\n{}\n",
+                option_header(arg),
+                option_help(arg)
+            ));
+        }
+    }
+
+    let subcommands: Vec<&Command> = command.get_subcommands().collect();
+    if !subcommands.is_empty() {
+        page.push_str(".SH SUBCOMMANDS\n");
+        for subcommand in subcommands {
+            page.push_str(&format!(
+                ".TP\n{}\n{}\n",
+                bold(subcommand.get_name()),
+                escape(subcommand.get_about().unwrap_or_default())
+            ));
+        }
+    }
+
+    if !version.is_empty() {
+        page.push_str(&format!(".SH VERSION\nv{}\n", escape(version)));
+    }
+    if let Some(author) = command.get_author() {
+        page.push_str(&format!(".SH AUTHORS\n{}\n", escape(author)));
+    }
+    page
+}
+
+/// Write the man page of a command, e.g. to stdout for `filter-clipped man > filter-clipped.1`
+///
+/// # Arguments
+/// - `writer`: where to write the page
+/// - `command`: the command line definitions
+pub fn write_man_page<W: Write>(writer: &mut W, command: &Command) -> std::io::Result<()> {
+    writer.write_all(render(command).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("plain text", "plain text")]
+    #[case("--left-side", "\\-\\-left\\-side")]
+    #[case("a\\b", "a\\eb")]
+    #[case(".starts with a dot", "\\&.starts with a dot")]
+    #[case("line\n'quoted", "line\n\\&'quoted")]
+    fn test_escape(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(escape(text), expected);
+    }
+
+    #[rstest]
+    #[case(Arg::new("unalign").short('u').long("unalign"), "\\fB\\-u\\fR, \\fB\\-\\-unalign\\fR")]
+    #[case(
+        Arg::new("left-side").long("left-side").takes_value(true),
+        "\\fB\\-\\-left\\-side\\fR \\fI<LEFT_SIDE>\\fR"
+    )]
+    #[case(
+        Arg::new("config").long("config").takes_value(true).value_name("FILE"),
+        "\\fB\\-\\-config\\fR \\fI<FILE>\\fR"
+    )]
+    fn test_option_header(#[case] arg: Arg, #[case] expected: &str) {
+        assert_eq!(option_header(&arg), expected);
+    }
+
+    #[rstest]
+    #[case(Arg::new("flag").long("flag").help("a flag"), "a flag")]
+    #[case(
+        Arg::new("size").long("size").takes_value(true).help("a size").default_value("10"),
+        "a size [default: 10]"
+    )]
+    #[case(
+        Arg::new("size").long("size").takes_value(true).help("a size").env("SIZE"),
+        "a size [env: SIZE]"
+    )]
+    #[case(
+        Arg::new("mode").long("mode").takes_value(true).help("a mode").possible_values(["keep", "drop"]),
+        "a mode [possible values: keep, drop]"
+    )]
+    fn test_option_help(#[case] arg: Arg, #[case] expected: &str) {
+        assert_eq!(option_help(&arg), expected);
+    }
+}
@@ -0,0 +1,136 @@
+//! Per-alignment clip metrics in a Parquet file (feature `parquet`), for analysing the
+//! clipping of millions of reads downstream (e.g. with pandas, polars or DuckDB)
+
+use crate::error::FilterClippedError;
+use arrow_array::builder::{
+    Float64Builder, Int32Builder, Int64Builder, StringBuilder, UInt16Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use rust_htslib::bam::Record;
+use std::fs::File;
+use std::sync::Arc;
+
+/// Number of alignments buffered before they are written out as a row group
+const BATCH_SIZE: usize = 65536;
+
+/// Columns of the metrics file, the clip fractions are null for the alignments
+/// that were not evaluated (kept or removed because of their flags or read names)
+fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("flag", DataType::UInt16, false),
+        Field::new("tid", DataType::Int32, false),
+        Field::new("pos", DataType::Int64, false),
+        Field::new("left_fraction", DataType::Float64, true),
+        Field::new("right_fraction", DataType::Float64, true),
+        Field::new("total_fraction", DataType::Float64, true),
+        Field::new("decision", DataType::Utf8, false),
+    ])
+}
+
+/// Streams the clip metrics and the decision of each alignment into a Parquet file
+/// (see [crate::config::FilterConfig::metrics_parquet]), one row group per [BATCH_SIZE]
+/// alignments
+pub struct ReadMetrics {
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    rows: usize,
+    name: StringBuilder,
+    flag: UInt16Builder,
+    tid: Int32Builder,
+    pos: Int64Builder,
+    left_fraction: Float64Builder,
+    right_fraction: Float64Builder,
+    total_fraction: Float64Builder,
+    decision: StringBuilder,
+}
+
+impl ReadMetrics {
+    /// Create the Parquet file (snappy compressed)
+    ///
+    /// # Arguments
+    /// - `path`: the Parquet file
+    pub fn create(path: &str) -> Result<Self, FilterClippedError> {
+        let schema: Arc<Schema> = Arc::new(schema());
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties))?;
+        Ok(Self {
+            writer,
+            schema,
+            rows: 0,
+            name: StringBuilder::new(),
+            flag: UInt16Builder::new(),
+            tid: Int32Builder::new(),
+            pos: Int64Builder::new(),
+            left_fraction: Float64Builder::new(),
+            right_fraction: Float64Builder::new(),
+            total_fraction: Float64Builder::new(),
+            decision: StringBuilder::new(),
+        })
+    }
+
+    /// Add the row of an alignment
+    ///
+    /// # Arguments
+    /// - `record`: the alignment, before it is made unmapped
+    /// - `fractions`: 5', 3' and total clip fractions, None if the alignment was not evaluated
+    /// - `decision`: what was decided for the alignment, e.g. "passed" or the reason it failed
+    pub fn write(
+        &mut self,
+        record: &Record,
+        fractions: Option<(f64, f64, f64)>,
+        decision: &str,
+    ) -> Result<(), FilterClippedError> {
+        self.name
+            .append_value(String::from_utf8_lossy(record.qname()));
+        self.flag.append_value(record.flags());
+        self.tid.append_value(record.tid());
+        self.pos.append_value(record.pos());
+        self.left_fraction.append_option(fractions.map(|f| f.0));
+        self.right_fraction.append_option(fractions.map(|f| f.1));
+        self.total_fraction.append_option(fractions.map(|f| f.2));
+        self.decision.append_value(decision);
+        self.rows += 1;
+        if self.rows == BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered rows as a row group
+    fn write_batch(&mut self) -> Result<(), FilterClippedError> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.name.finish()),
+            Arc::new(self.flag.finish()),
+            Arc::new(self.tid.finish()),
+            Arc::new(self.pos.finish()),
+            Arc::new(self.left_fraction.finish()),
+            Arc::new(self.right_fraction.finish()),
+            Arc::new(self.total_fraction.finish()),
+            Arc::new(self.decision.finish()),
+        ];
+        let batch =
+            RecordBatch::try_new(self.schema.clone(), columns).map_err(ParquetError::from)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Write the last rows and the footer of the file once all the alignments are filtered
+    pub fn finish(mut self) -> Result<(), FilterClippedError> {
+        self.write_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! Parallel filtering of an indexed input: the reference is split into windows that are
+//! filtered over a pool of threads, each into a temporary BAM file, and the filtered
+//! windows are copied into the output in coordinate order, or as soon as they are done
+//! with `unordered`
+
+use crate::config::FilterConfig;
+use crate::error::FilterClippedError;
+use crate::io;
+use crate::stats::RunStats;
+use indicatif::ProgressBar;
+use log::info;
+use rust_htslib::bam::{self, Header, HeaderView, IndexedReader, Read, Record};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// A window of the reference, the alignments starting in it are filtered together
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// reference sequence, -1 for the alignments without a position
+    pub tid: i32,
+    /// first position (0-based)
+    pub start: i64,
+    /// position after the last one
+    pub end: i64,
+}
+
+impl Window {
+    /// Whether an alignment fetched for the window starts in it, so that alignments
+    /// overlapping several windows are only filtered once
+    fn holds(&self, record: &Record) -> bool {
+        self.tid < 0 || record.pos() >= self.start
+    }
+}
+
+/// Split the reference sequences of a header into windows of `size` bases, in the order
+/// of the header, followed by a window for the alignments without a position
+///
+/// # Example
+/// ```
+/// use filter_clipped::parallel::{windows, Window};
+/// use rust_htslib::bam::HeaderView;
+/// let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:250\n@SQ\tSN:chr2\tLN:100\n");
+/// let windows = windows(&header, 100);
+/// assert_eq!(windows.len(), 5);
+/// assert_eq!(windows[2], Window { tid: 0, start: 200, end: 250 });
+/// assert_eq!(windows[4].tid, -1);
+/// ```
+pub fn windows(header: &HeaderView, size: u64) -> Vec<Window> {
+    let size: i64 = size.max(1) as i64;
+    let mut windows: Vec<Window> = Vec::new();
+    for tid in 0..header.target_count() {
+        let len: i64 = header.target_len(tid).unwrap_or(0) as i64;
+        let mut start: i64 = 0;
+        while start < len {
+            windows.push(Window {
+                tid: tid as i32,
+                start,
+                end: (start + size).min(len),
+            });
+            start += size;
+        }
+    }
+    windows.push(Window {
+        tid: -1,
+        start: 0,
+        end: 0,
+    });
+    windows
+}
+
+/// Temporary file of a filtered window, next to the output file (or in the temporary
+/// directory when writing to stdout)
+fn window_path(config: &FilterConfig, index: usize) -> PathBuf {
+    match config.out_bam.as_str() {
+        "-" => std::env::temp_dir().join(format!(
+            "filter-clipped.{}.{}.tmp.bam",
+            std::process::id(),
+            index
+        )),
+        out_bam => PathBuf::from(format!("{}.{}.tmp.bam", out_bam, index)),
+    }
+}
+
+/// Filter the alignments starting in a window into its temporary file
+///
+/// # Arguments
+/// - `reader`: the indexed input
+/// - `window`: the window to filter
+/// - `path`: the temporary file
+/// - `header`: header of the output
+/// - `config`: settings for the run
+/// - `bar`: progress bar counting the alignments read
+fn filter_window(
+    reader: &mut IndexedReader,
+    window: Window,
+    path: &PathBuf,
+    header: &Header,
+    config: &FilterConfig,
+    bar: &ProgressBar,
+) -> Result<RunStats, FilterClippedError> {
+    let mut writer = bam::Writer::from_path(path, header, bam::Format::Bam)?;
+    // the file is read back right away, compressing it would only cost time
+    writer.set_compression_level(bam::CompressionLevel::Uncompressed)?;
+    match window.tid {
+        -1 => reader.fetch("*")?,
+        tid => reader.fetch((tid, window.start, window.end))?,
+    }
+    let clip_filter = crate::run_clip_filter(config, writer.header())?;
+    let records = reader
+        .records()
+        .filter(|r| r.as_ref().map_or(true, |record| window.holds(record)))
+        .map(|r| r.map_err(FilterClippedError::from));
+    crate::filter_stream(
+        records,
+        &clip_filter,
+        None,
+        &mut writer,
+        &mut io::SideOutputs::default(),
+        bar,
+    )
+}
+
+/// Filter the windows handed out by `next` until there are none left, sending the totals
+/// of each window back as it's done
+///
+/// # Arguments
+/// - `config`: settings for the run
+/// - `header`: header of the output
+/// - `windows`: all the windows
+/// - `next`: index of the next window to filter
+/// - `cancelled`: set when the run has failed
+/// - `sender`: where to send the window index and its totals
+/// - `bar`: progress bar counting the alignments read
+fn worker(
+    config: &FilterConfig,
+    header: &Header,
+    windows: &[Window],
+    next: &AtomicUsize,
+    cancelled: &AtomicBool,
+    sender: mpsc::Sender<(usize, Result<RunStats, FilterClippedError>)>,
+    bar: &ProgressBar,
+) {
+    let mut reader: IndexedReader = match io::open_indexed(config) {
+        Ok(reader) => reader,
+        Err(e) => {
+            let _ = sender.send((next.load(Ordering::SeqCst), Err(e)));
+            return;
+        }
+    };
+    while !cancelled.load(Ordering::SeqCst) {
+        let index: usize = next.fetch_add(1, Ordering::SeqCst);
+        let window: Window = match windows.get(index) {
+            Some(window) => *window,
+            None => return,
+        };
+        let path: PathBuf = window_path(config, index);
+        let result = filter_window(&mut reader, window, &path, header, config, bar);
+        let failed: bool = result.is_err();
+        if sender.send((index, result)).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Copy the alignments of a filtered window into the output, and remove its temporary file
+fn append_window(
+    path: &PathBuf,
+    out_bam: &mut bam::Writer,
+    shards: &mut Option<io::ChromShards>,
+) -> Result<(), FilterClippedError> {
+    let mut reader = bam::Reader::from_path(path)?;
+    let mut record = Record::new();
+    while let Some(r) = reader.read(&mut record) {
+        r?;
+        out_bam.write(&record)?;
+        if let Some(shards) = shards.as_mut() {
+            shards.write(&record)?;
+        }
+    }
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Filter the windows of an indexed input over `region_threads` threads, writing the
+/// filtered windows into the output in the order of the windows, or in the order they are
+/// done with `unordered`
+///
+/// # Arguments
+/// - `config`: settings for the run, with `region_threads` above 1
+/// - `out_bam`: the output file, its header already written
+///
+/// # Returns
+/// - the totals of all the windows
+pub fn filter_windows(
+    config: &FilterConfig,
+    out_bam: &mut bam::Writer,
+) -> Result<RunStats, FilterClippedError> {
+    let windows: Arc<Vec<Window>> = Arc::new(windows(out_bam.header(), config.region_size));
+    info!(
+        "Filtering {} windows of up to {} bases over {} threads",
+        windows.len(),
+        config.region_size,
+        config.region_threads
+    );
+    let header: Arc<Header> = Arc::new(Header::from_template(out_bam.header()));
+    let config_arc: Arc<FilterConfig> = Arc::new(config.clone());
+    let next: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    let cancelled: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let bar: ProgressBar = crate::progress::progress_bar(config, "Filtering");
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<thread::JoinHandle<()>> = (0..config.region_threads.min(windows.len()))
+        .map(|_| {
+            let (config, header, windows) = (config_arc.clone(), header.clone(), windows.clone());
+            let (next, cancelled, sender, bar) =
+                (next.clone(), cancelled.clone(), sender.clone(), bar.clone());
+            thread::spawn(move || {
+                worker(&config, &header, &windows, &next, &cancelled, sender, &bar)
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut shards: Option<io::ChromShards> = io::ChromShards::create(config, out_bam.header())?;
+    let mut stats = RunStats::default();
+    // windows done but not written yet, by index
+    let mut done: BTreeMap<usize, RunStats> = BTreeMap::new();
+    let mut next_write: usize = 0;
+    let mut written: Vec<bool> = vec![false; windows.len()];
+    let mut result: Result<(), FilterClippedError> = Ok(());
+    for (index, window_stats) in receiver.iter() {
+        match window_stats {
+            Ok(window_stats) => done.insert(index, window_stats),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        loop {
+            let index: usize = match config.unordered {
+                true => match done.keys().next() {
+                    Some(index) => *index,
+                    None => break,
+                },
+                false if done.contains_key(&next_write) => next_write,
+                false => break,
+            };
+            let window_stats: RunStats = done.remove(&index).unwrap_or_default();
+            if let Err(e) = append_window(&window_path(config, index), out_bam, &mut shards) {
+                result = Err(e);
+                break;
+            }
+            stats.merge(window_stats);
+            written[index] = true;
+            next_write += 1;
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    // all the windows are done unless the run failed, in which case the workers stop
+    cancelled.store(true, Ordering::SeqCst);
+    drop(receiver);
+    let mut panic = None;
+    for worker in workers {
+        if let Err(payload) = worker.join() {
+            panic = Some(payload);
+        }
+    }
+    bar.finish_and_clear();
+    if result.is_err() || panic.is_some() {
+        // leave no temporary files behind
+        for index in (0..windows.len()).filter(|index| !written[*index]) {
+            let _ = std::fs::remove_file(window_path(config, index));
+        }
+    }
+    if let Some(payload) = panic {
+        std::panic::resume_unwind(payload);
+    }
+    result?;
+    if let Some(shards) = shards {
+        shards.finish()?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(1000, vec![(0, 0, 250), (1, 0, 100), (-1, 0, 0)])]
+    #[case(100, vec![(0, 0, 100), (0, 100, 200), (0, 200, 250), (1, 0, 100), (-1, 0, 0)])]
+    #[case(150, vec![(0, 0, 150), (0, 150, 250), (1, 0, 100), (-1, 0, 0)])]
+    fn test_windows(#[case] size: u64, #[case] expected: Vec<(i32, i64, i64)>) {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:250\n@SQ\tSN:chr2\tLN:100\n");
+        let windows: Vec<(i32, i64, i64)> = windows(&header, size)
+            .iter()
+            .map(|w| (w.tid, w.start, w.end))
+            .collect();
+        assert_eq!(windows, expected);
+    }
+}
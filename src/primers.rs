@@ -0,0 +1,149 @@
+use crate::error::FilterClippedError;
+use log::warn;
+use rust_htslib::bam::HeaderView;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A primer interval on a reference sequence, 0-based and half-open as in BED files
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Primer {
+    /// name of the reference sequence
+    pub contig: String,
+    /// first base of the primer
+    pub start: i64,
+    /// one past the last base of the primer
+    pub end: i64,
+}
+
+/// Parse primer intervals from the first three columns of a BED file
+///
+/// # Arguments
+/// - `text`: content of the BED file
+///
+/// # Example
+/// ```
+/// use filter_clipped::primers::parse_bed;
+/// let primers = parse_bed("track name=primers\nMN908947.3\t30\t54\tnCoV-2019_1_LEFT\t1\t+\n").unwrap();
+/// assert_eq!(primers.len(), 1);
+/// assert_eq!(primers[0].contig, "MN908947.3");
+/// assert_eq!((primers[0].start, primers[0].end), (30, 54));
+/// ```
+pub fn parse_bed(text: &str) -> Result<Vec<Primer>, FilterClippedError> {
+    let mut primers: Vec<Primer> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let malformed = || {
+            FilterClippedError::InvalidConfig(format!(
+                "line {} of the primer BED file should be <name>\\t<start>\\t<end>: {}",
+                i + 1,
+                line
+            ))
+        };
+        let fields: Vec<&str> = line.trim_end().split('\t').collect();
+        if fields.len() < 3 {
+            return Err(malformed());
+        }
+        let start: i64 = fields[1].parse().map_err(|_| malformed())?;
+        let end: i64 = fields[2].parse().map_err(|_| malformed())?;
+        if start < 0 || end < start {
+            return Err(malformed());
+        }
+        primers.push(Primer {
+            contig: fields[0].to_string(),
+            start,
+            end,
+        });
+    }
+    Ok(primers)
+}
+
+/// Primer intervals by tid, for looking up the genomic span of clips
+#[derive(Clone, Debug, Default)]
+pub struct PrimerIndex {
+    /// intervals of each reference sequence, sorted by start
+    intervals: HashMap<i32, Vec<(i64, i64)>>,
+}
+
+impl PrimerIndex {
+    /// Look up the reference sequences of the primers in the header of the alignments
+    ///
+    /// # Arguments
+    /// - `primers`: the primer intervals
+    /// - `header`: header of the alignments to filter
+    pub fn new(primers: &[Primer], header: &HeaderView) -> Self {
+        let mut intervals: HashMap<i32, Vec<(i64, i64)>> = HashMap::new();
+        for primer in primers {
+            match header.tid(primer.contig.as_bytes()) {
+                Some(tid) => intervals
+                    .entry(tid as i32)
+                    .or_default()
+                    .push((primer.start, primer.end)),
+                None => warn!(
+                    "Reference sequence {} of a primer is not in the header",
+                    primer.contig
+                ),
+            }
+        }
+        for contig_intervals in intervals.values_mut() {
+            contig_intervals.sort_unstable();
+        }
+        Self { intervals }
+    }
+
+    /// Whether there are no primers to look up
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Whether the span `start..end` of reference sequence `tid` lies within a primer
+    ///
+    /// # Arguments
+    /// - `tid`: the reference sequence
+    /// - `start`: first base of the span (0-based)
+    /// - `end`: one past the last base of the span
+    pub fn covers(&self, tid: i32, start: i64, end: i64) -> bool {
+        match self.intervals.get(&tid) {
+            Some(contig_intervals) => contig_intervals
+                .iter()
+                .take_while(|(primer_start, _)| *primer_start <= start)
+                .any(|(_, primer_end)| end <= *primer_end),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("chr1\t10")]
+    #[case("chr1\tten\t20")]
+    #[case("chr1\t30\t20")]
+    fn test_parse_bed_error(#[case] text: &str) {
+        assert!(parse_bed(text).is_err());
+    }
+
+    #[rstest]
+    #[case(0, 10, 20, true)]
+    #[case(0, 12, 18, true)]
+    #[case(0, 5, 15, false)]
+    #[case(0, 15, 25, false)]
+    #[case(0, 30, 38, true)]
+    #[case(0, 30, 42, false)]
+    #[case(1, 12, 18, false)]
+    fn test_covers(#[case] tid: i32, #[case] start: i64, #[case] end: i64, #[case] expected: bool) {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:1000\n");
+        let primers = parse_bed("chr1\t30\t40\nchr1\t10\t20\nchr3\t10\t20\n").unwrap();
+        let index = PrimerIndex::new(&primers, &header);
+        assert_eq!(index.covers(tid, start, end), expected);
+    }
+}
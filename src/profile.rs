@@ -0,0 +1,250 @@
+use crate::error::FilterClippedError;
+use rust_htslib::bam::{record::CigarStringView, HeaderView, Record};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Counts of the alignments with a soft clip boundary in a window of the reference
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WindowCounts {
+    /// alignments whose leading soft clip ends here (the alignment starts after a clip)
+    pub left_clips: u64,
+    /// alignments whose trailing soft clip begins here (the alignment ends before a clip)
+    pub right_clips: u64,
+}
+
+/// Where along the reference the soft clips of the alignments begin or end, in windows
+/// of a fixed size, e.g. to screen for breakpoints or adapter pile-ups
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClipProfile {
+    /// size of the windows in bases
+    window: i64,
+    /// counts by tid and window index, only for the windows with clipped alignments
+    counts: BTreeMap<(i32, i64), WindowCounts>,
+}
+
+impl ClipProfile {
+    /// Create an empty profile
+    ///
+    /// # Arguments
+    /// * `window`: size of the windows in bases (1 for single positions)
+    pub fn new(window: u64) -> Self {
+        Self {
+            window: window.max(1) as i64,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Count the soft clip boundaries of an alignment, unmapped alignments are skipped
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::profile::ClipProfile;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    /// let mut profile = ClipProfile::new(10);
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("5S90M5S").unwrap();
+    /// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+    /// record.set_flags(0);
+    /// record.set_tid(0);
+    /// record.set_pos(100);
+    /// profile.add(&record);
+    /// assert_eq!(profile.window_counts(0, 100).left_clips, 1);
+    /// assert_eq!(profile.window_counts(0, 195).right_clips, 1);
+    /// ```
+    pub fn add(&mut self, record: &Record) {
+        if record.is_unmapped() || record.tid() < 0 {
+            return;
+        }
+        let cigar: CigarStringView = record.cigar();
+        if cigar.leading_softclips() > 0 {
+            self.counts
+                .entry((record.tid(), record.pos() / self.window))
+                .or_default()
+                .left_clips += 1;
+        }
+        if cigar.trailing_softclips() > 0 {
+            self.counts
+                .entry((record.tid(), cigar.end_pos() / self.window))
+                .or_default()
+                .right_clips += 1;
+        }
+    }
+
+    /// Add the counts of another profile with the same window size, e.g. of another part
+    /// of the input
+    pub fn merge(&mut self, other: &ClipProfile) {
+        for (key, counts) in other.counts.iter() {
+            let merged: &mut WindowCounts = self.counts.entry(*key).or_default();
+            merged.left_clips += counts.left_clips;
+            merged.right_clips += counts.right_clips;
+        }
+    }
+
+    /// Counts of the window holding a reference position
+    ///
+    /// # Arguments
+    /// * `tid`: the reference sequence
+    /// * `pos`: the position (0-based)
+    pub fn window_counts(&self, tid: i32, pos: i64) -> WindowCounts {
+        self.counts
+            .get(&(tid, pos / self.window))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Write the windows with clipped alignments as a TSV, in the order of the reference
+    /// sequences in the header: reference name, window start (0-based), window end, and the
+    /// numbers of alignments with a leading soft clip ending and a trailing soft clip
+    /// beginning in the window
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the TSV
+    /// * `header`: header of the alignments, for the reference names and lengths
+    pub fn write_tsv<W: Write>(
+        &self,
+        writer: &mut W,
+        header: &HeaderView,
+    ) -> Result<(), FilterClippedError> {
+        writeln!(writer, "contig\tstart\tend\tleft_clips\tright_clips")?;
+        for ((tid, window), counts) in self.counts.iter() {
+            let contig: String = String::from_utf8_lossy(header.tid2name(*tid as u32)).to_string();
+            let start: i64 = window * self.window;
+            let mut end: i64 = start + self.window;
+            if let Some(len) = header.target_len(*tid as u32) {
+                end = end.min(len as i64);
+            }
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                contig, start, end, counts.left_clips, counts.right_clips
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the clusters of soft clip boundaries supported by at least `min_support`
+    /// alignments as BED intervals, adjacent windows above the threshold making up one
+    /// cluster, with the number of supporting alignments in the score column
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the BED intervals
+    /// * `header`: header of the alignments, for the reference names and lengths
+    /// * `min_support`: fewest alignments with a clip boundary in a window to report it
+    pub fn write_breakpoints<W: Write>(
+        &self,
+        writer: &mut W,
+        header: &HeaderView,
+        min_support: u64,
+    ) -> Result<(), FilterClippedError> {
+        // (tid, first window, last window, support)
+        let mut clusters: Vec<(i32, i64, i64, u64)> = Vec::new();
+        for ((tid, window), counts) in self.counts.iter() {
+            let support: u64 = counts.left_clips + counts.right_clips;
+            if support < min_support {
+                continue;
+            }
+            match clusters.last_mut() {
+                Some(cluster) if cluster.0 == *tid && cluster.2 + 1 == *window => {
+                    cluster.2 = *window;
+                    cluster.3 += support;
+                }
+                _ => clusters.push((*tid, *window, *window, support)),
+            }
+        }
+        for (tid, first_window, last_window, support) in clusters {
+            let contig: String = String::from_utf8_lossy(header.tid2name(tid as u32)).to_string();
+            let start: i64 = first_window * self.window;
+            let mut end: i64 = (last_window + 1) * self.window;
+            if let Some(len) = header.target_len(tid as u32) {
+                end = end.min(len as i64);
+            }
+            writeln!(
+                writer,
+                "{}\t{}\t{}\tclip_breakpoint\t{}",
+                contig, start, end, support
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
+
+    #[rstest]
+    #[case("5S90M5S", 1, "chr1\t100\t101\t1\t0\nchr1\t190\t191\t0\t1\n")]
+    #[case("5S90M5S", 100, "chr1\t100\t200\t1\t1\n")]
+    #[case("5S90M5S", 150, "chr1\t0\t150\t1\t0\nchr1\t150\t250\t0\t1\n")]
+    #[case("100M", 1, "")]
+    #[case("5H95M", 1, "")]
+    fn test_write_tsv(#[case] cigar: &str, #[case] window: u64, #[case] expected: &str) {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:250\n");
+        let mut profile = ClipProfile::new(window);
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+        record.set_flags(0);
+        record.set_tid(0);
+        record.set_pos(100);
+        profile.add(&record);
+        let mut tsv: Vec<u8> = Vec::new();
+        profile.write_tsv(&mut tsv, &header).unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap(),
+            format!("contig\tstart\tend\tleft_clips\tright_clips\n{}", expected)
+        );
+    }
+
+    #[rstest]
+    #[case(
+        1,
+        "chr1\t100\t101\tclip_breakpoint\t3\nchr1\t102\t103\tclip_breakpoint\t1\n"
+    )]
+    #[case(2, "chr1\t100\t101\tclip_breakpoint\t3\n")]
+    #[case(4, "")]
+    fn test_write_breakpoints(#[case] min_support: u64, #[case] expected: &str) {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:250\n");
+        let mut profile = ClipProfile::new(1);
+        for (cigar, pos) in [("5S95M", 100), ("5S95M", 100), ("95M5S", 5), ("5S95M", 102)] {
+            let cigar = CigarString::try_from(cigar).unwrap();
+            let mut record = Record::new();
+            record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+            record.set_flags(0);
+            record.set_tid(0);
+            record.set_pos(pos);
+            profile.add(&record);
+        }
+        let mut bed: Vec<u8> = Vec::new();
+        profile
+            .write_breakpoints(&mut bed, &header, min_support)
+            .unwrap();
+        assert_eq!(String::from_utf8(bed).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_write_breakpoints_merge() {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:250\n");
+        let mut profile = ClipProfile::new(10);
+        for pos in [100, 112, 125, 150] {
+            let cigar = CigarString::try_from("5S95M").unwrap();
+            let mut record = Record::new();
+            record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+            record.set_flags(0);
+            record.set_tid(0);
+            record.set_pos(pos);
+            profile.add(&record);
+        }
+        let mut bed: Vec<u8> = Vec::new();
+        profile.write_breakpoints(&mut bed, &header, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(bed).unwrap(),
+            "chr1\t100\t130\tclip_breakpoint\t3\nchr1\t150\t160\tclip_breakpoint\t1\n"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use crate::config::FilterConfig;
+use indicatif::{ProgressBar, ProgressStyle};
+use rust_htslib::bam::IndexedReader;
+
+/// Estimate the number of records in the input from its index (.bai/.csi/.crai)
+///
+/// # Arguments
+/// - `config`: settings holding the input path
+///
+/// # Returns
+/// - the number of mapped and unmapped records listed in the index, or None if the
+///   input is stdin or isn't indexed
+pub fn estimate_total(config: &FilterConfig) -> Option<u64> {
+    if config.in_bam.eq("-") {
+        return None;
+    }
+    let mut reader = IndexedReader::from_path(&config.in_bam).ok()?;
+    let stats = reader.index_stats().ok()?;
+    Some(
+        stats
+            .iter()
+            .map(|(_, _, mapped, unmapped)| mapped + unmapped)
+            .sum(),
+    )
+}
+
+/// Create a progress bar for a pass over the input, showing the records processed,
+/// the throughput and (when the input is indexed) an ETA
+///
+/// # Arguments
+/// - `config`: settings for the run, the bar is hidden unless `progress` is set
+/// - `message`: label shown in front of the bar
+///
+/// # Returns
+/// - a progress bar drawing to stderr, to be advanced with [ProgressBar::inc]
+pub fn progress_bar(config: &FilterConfig, message: &'static str) -> ProgressBar {
+    if !config.progress {
+        return ProgressBar::hidden();
+    }
+    let (bar, template) = match estimate_total(config) {
+        Some(total) => (
+            ProgressBar::new(total),
+            "{msg} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} records ({per_sec}, ETA {eta})",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{msg} [{elapsed_precise}] {spinner} {human_pos} records ({per_sec})",
+        ),
+    };
+    if let Ok(style) = ProgressStyle::with_template(template) {
+        bar.set_style(style);
+    }
+    bar.set_message(message);
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_with_config;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("-")]
+    #[case("test/data/paired_sorted.sam")]
+    #[case("test/data/missing.bam")]
+    fn test_estimate_total_unindexed(#[case] in_bam: &str) {
+        let config = FilterConfig {
+            in_bam: in_bam.to_string(),
+            ..FilterConfig::default()
+        };
+        assert_eq!(estimate_total(&config), None);
+    }
+
+    #[test]
+    fn test_estimate_total() {
+        let indexed = FilterConfig {
+            in_bam: "test/data/paired_sorted.sam".to_string(),
+            out_bam: "test/data/out_progress.bam".to_string(),
+            both_end: 1.0,
+            left_side: 1.0,
+            right_side: 1.0,
+            write_index: true,
+            ..FilterConfig::default()
+        };
+        run_with_config(&indexed).unwrap();
+        let config = FilterConfig {
+            in_bam: indexed.out_bam,
+            progress: true,
+            ..FilterConfig::default()
+        };
+        assert_eq!(estimate_total(&config), Some(7));
+        assert_eq!(progress_bar(&config, "test").length(), Some(7));
+    }
+}
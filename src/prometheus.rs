@@ -0,0 +1,220 @@
+//! Progress counters of a run in the Prometheus text format (`--metrics-out`), rewritten
+//! every few seconds so that a node exporter (textfile collector) can scrape long runs
+
+use crate::config::FilterConfig;
+use crate::error::FilterClippedError;
+use crate::io;
+use crate::stats::RunStats;
+use std::fmt::Write as _;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// The counters of a run in the Prometheus text format
+///
+/// # Arguments
+/// - `stats`: the counters so far
+/// - `elapsed`: time since the run started
+/// - `input_bytes`: size of the input files, if known
+/// - `output_bytes`: size written to the output file so far, if known
+/// - `done`: whether the run is over
+///
+/// # Example
+/// ```
+/// use filter_clipped::prometheus::render;
+/// use filter_clipped::stats::RunStats;
+/// use std::time::Duration;
+/// let stats = RunStats { read: 100, written: 90, ..RunStats::default() };
+/// let text = render(&stats, Duration::from_secs(2), None, Some(2048), false);
+/// assert!(text.contains("\nfilter_clipped_records_read_total 100\n"));
+/// assert!(text.contains("\nfilter_clipped_records_per_second 50\n"));
+/// assert!(text.contains("\nfilter_clipped_output_bytes 2048\n"));
+/// assert!(!text.contains("filter_clipped_input_bytes"));
+/// assert!(text.ends_with("\nfilter_clipped_done 0\n"));
+/// ```
+pub fn render(
+    stats: &RunStats,
+    elapsed: Duration,
+    input_bytes: Option<u64>,
+    output_bytes: Option<u64>,
+    done: bool,
+) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        let _ = writeln!(text, "# HELP filter_clipped_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE filter_clipped_{} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(text, "filter_clipped_{}{} {}", name, labels, value);
+        }
+    };
+    let seconds: f64 = elapsed.as_secs_f64();
+    metric(
+        "records_read_total",
+        "counter",
+        "Alignments read from the input.",
+        vec![(String::new(), stats.read as f64)],
+    );
+    metric(
+        "records_written_total",
+        "counter",
+        "Alignments written to the output.",
+        vec![(String::new(), stats.written as f64)],
+    );
+    metric(
+        "records_unaligned_total",
+        "counter",
+        "Alignments made unmapped.",
+        vec![(String::new(), stats.unaligned as f64)],
+    );
+    metric(
+        "records_failed_total",
+        "counter",
+        "Alignments failing the filter, by the first reason they failed for.",
+        stats
+            .failed_by_reason
+            .iter()
+            .map(|(reason, count)| (format!("{{reason=\"{}\"}}", reason.as_str()), *count as f64))
+            .collect(),
+    );
+    metric(
+        "elapsed_seconds",
+        "gauge",
+        "Time since the run started.",
+        vec![(String::new(), seconds)],
+    );
+    metric(
+        "records_per_second",
+        "gauge",
+        "Alignments read per second since the run started.",
+        vec![(
+            String::new(),
+            match seconds > 0.0 {
+                true => stats.read as f64 / seconds,
+                false => 0.0,
+            },
+        )],
+    );
+    if let Some(bytes) = input_bytes {
+        metric(
+            "input_bytes",
+            "gauge",
+            "Size of the input files.",
+            vec![(String::new(), bytes as f64)],
+        );
+    }
+    if let Some(bytes) = output_bytes {
+        metric(
+            "output_bytes",
+            "gauge",
+            "Size written to the output file so far.",
+            vec![(String::new(), bytes as f64)],
+        );
+    }
+    metric(
+        "done",
+        "gauge",
+        "Whether the run is over.",
+        vec![(String::new(), done as u8 as f64)],
+    );
+    text
+}
+
+/// Rewrites the counters of a run into a file every `metrics_interval` seconds (see
+/// [FilterConfig::metrics_out]); the file is replaced in one go so that it is never scraped
+/// half-written
+pub struct ProgressMetrics {
+    path: String,
+    input_bytes: Option<u64>,
+    out_bam: String,
+    interval: Duration,
+    started: Instant,
+    written: Instant,
+}
+
+impl ProgressMetrics {
+    /// Write the first (zero) counters, if the settings ask for them
+    ///
+    /// # Arguments
+    /// - `config`: settings for the run
+    pub fn create(config: &FilterConfig) -> Result<Option<Self>, FilterClippedError> {
+        let path: String = match config.metrics_out.as_deref() {
+            Some(path) => path.to_string(),
+            None => return Ok(None),
+        };
+        let started: Instant = Instant::now();
+        let metrics = Self {
+            path,
+            input_bytes: io::input_bytes(config),
+            out_bam: config.out_bam.clone(),
+            interval: Duration::from_secs(config.metrics_interval),
+            started,
+            written: started,
+        };
+        metrics.write(&RunStats::default(), false)?;
+        Ok(Some(metrics))
+    }
+
+    /// Replace the file with the current counters
+    fn write(&self, stats: &RunStats, done: bool) -> Result<(), FilterClippedError> {
+        // the output file grows as the alignments are written, unknown for stdout
+        let output_bytes: Option<u64> = match self.out_bam.as_str() {
+            "-" => None,
+            path => fs::metadata(path).ok().map(|metadata| metadata.len()),
+        };
+        let text: String = render(
+            stats,
+            self.started.elapsed(),
+            self.input_bytes,
+            output_bytes,
+            done,
+        );
+        let tmp_path: String = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, text)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Rewrite the file if the interval has passed since it was last written
+    ///
+    /// # Arguments
+    /// - `stats`: the counters so far
+    pub fn update(&mut self, stats: &RunStats) -> Result<(), FilterClippedError> {
+        if self.written.elapsed() >= self.interval {
+            self.write(stats, false)?;
+            self.written = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Write the final counters once all the alignments are filtered
+    pub fn finish(self, stats: &RunStats) -> Result<(), FilterClippedError> {
+        self.write(stats, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::FailReason;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        FailReason::LeftClip,
+        2,
+        "filter_clipped_records_failed_total{reason=\"left_clip\"} 2"
+    )]
+    #[case(
+        FailReason::TotalClip,
+        1,
+        "filter_clipped_records_failed_total{reason=\"total_clip\"} 1"
+    )]
+    fn test_render_failed(#[case] reason: FailReason, #[case] count: u64, #[case] expected: &str) {
+        let mut stats = RunStats::default();
+        for _ in 0..count {
+            stats.add_failure(reason);
+        }
+        let text: String = render(&stats, Duration::from_secs(1), None, None, true);
+        assert!(text.lines().any(|line| line == expected), "{}", text);
+        assert!(text.ends_with("filter_clipped_done 1\n"));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::filter::ClipFilter;
+use rust_htslib::bam::Record;
+
+/// A criterion deciding whether an alignment is kept, which can be combined
+/// with others using [And], [Or] and [Not]
+///
+/// # Example
+/// ```
+/// use filter_clipped::config::FilterConfig;
+/// use filter_clipped::filter::ClipFilter;
+/// use filter_clipped::record_filter::{FlagFilter, MinMapq, RecordFilter};
+/// use rust_htslib::bam::record::{CigarString, Record};
+///
+/// let config = FilterConfig::new();
+/// let criteria = ClipFilter::new(&config)
+///     .and(MinMapq(30))
+///     .and(FlagFilter::exclude(0x400));
+///
+/// let mut record = Record::new();
+/// let cigar = CigarString::try_from("100M").unwrap();
+/// record.set(b"read", Some(&cigar), &[b'A'; 100], &[30; 100]);
+/// record.set_mapq(60);
+/// assert!(criteria.keep(&record));
+/// record.set_flags(0x400);
+/// assert!(!criteria.keep(&record));
+/// ```
+pub trait RecordFilter {
+    /// Whether the alignment is kept
+    fn keep(&self, record: &Record) -> bool;
+
+    /// Keep the alignments kept by both filters
+    fn and<F: RecordFilter>(self, other: F) -> And<Self, F>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Keep the alignments kept by either filter
+    fn or<F: RecordFilter>(self, other: F) -> Or<Self, F>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// Keep the alignments not kept by this filter
+    fn negate(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+/// Keeps the alignments kept by both filters
+pub struct And<A, B>(pub A, pub B);
+
+impl<A: RecordFilter, B: RecordFilter> RecordFilter for And<A, B> {
+    fn keep(&self, record: &Record) -> bool {
+        self.0.keep(record) && self.1.keep(record)
+    }
+}
+
+/// Keeps the alignments kept by either filter
+pub struct Or<A, B>(pub A, pub B);
+
+impl<A: RecordFilter, B: RecordFilter> RecordFilter for Or<A, B> {
+    fn keep(&self, record: &Record) -> bool {
+        self.0.keep(record) || self.1.keep(record)
+    }
+}
+
+/// Keeps the alignments not kept by the inner filter
+pub struct Not<A>(pub A);
+
+impl<A: RecordFilter> RecordFilter for Not<A> {
+    fn keep(&self, record: &Record) -> bool {
+        !self.0.keep(record)
+    }
+}
+
+/// Keeps the alignments with a mapping quality of at least the given value
+pub struct MinMapq(pub u8);
+
+impl RecordFilter for MinMapq {
+    fn keep(&self, record: &Record) -> bool {
+        record.mapq() >= self.0
+    }
+}
+
+/// Keeps the alignments with all the `required` flag bits set and none of
+/// the `excluded` flag bits set (like `samtools view -f/-F`)
+pub struct FlagFilter {
+    /// flag bits that must be set
+    pub required: u16,
+    /// flag bits that must not be set
+    pub excluded: u16,
+}
+
+impl FlagFilter {
+    /// Keep the alignments with all of these flag bits set
+    pub fn require(required: u16) -> Self {
+        Self {
+            required,
+            excluded: 0,
+        }
+    }
+
+    /// Keep the alignments with none of these flag bits set
+    pub fn exclude(excluded: u16) -> Self {
+        Self {
+            required: 0,
+            excluded,
+        }
+    }
+}
+
+impl RecordFilter for FlagFilter {
+    fn keep(&self, record: &Record) -> bool {
+        let flags: u16 = record.flags();
+        flags & self.required == self.required && flags & self.excluded == 0
+    }
+}
+
+/// The clip filter keeps the alignments passing it or bypassing it because of their flags,
+/// alignments that can't be evaluated (see [ClipFilter::evaluate]) are not kept
+impl RecordFilter for ClipFilter<'_> {
+    fn keep(&self, record: &Record) -> bool {
+        self.evaluate(record)
+            .map(|decision| decision.is_kept())
+            .unwrap_or(false)
+    }
+}
+
+/// Any closure taking an alignment can be used as a filter
+impl<F: Fn(&Record) -> bool> RecordFilter for F {
+    fn keep(&self, record: &Record) -> bool {
+        self(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn record(flags: u16, mapq: u8) -> Record {
+        let mut record = Record::new();
+        record.set_flags(flags);
+        record.set_mapq(mapq);
+        record
+    }
+
+    #[rstest]
+    #[case(0, 60, true, true, false)]
+    #[case(0, 10, false, true, true)]
+    #[case(0x400, 60, false, true, true)]
+    #[case(0x400, 10, false, false, true)]
+    #[case(0x1, 60, false, true, true)]
+    fn test_combinators(
+        #[case] flags: u16,
+        #[case] mapq: u8,
+        #[case] expected_and: bool,
+        #[case] expected_or: bool,
+        #[case] expected_not: bool,
+    ) {
+        let record = record(flags, mapq);
+        let criteria = || MinMapq(30).and(FlagFilter::exclude(0x401));
+        assert_eq!(criteria().keep(&record), expected_and);
+        assert_eq!(
+            MinMapq(30).or(FlagFilter::exclude(0x400)).keep(&record),
+            expected_or
+        );
+        assert_eq!(criteria().negate().keep(&record), expected_not);
+    }
+
+    #[rstest]
+    #[case(0x1 | 0x40, true)]
+    #[case(0x1 | 0x80, false)]
+    #[case(0x40, false)]
+    fn test_flag_filter_require(#[case] flags: u16, #[case] expected: bool) {
+        assert_eq!(FlagFilter::require(0x41).keep(&record(flags, 0)), expected);
+    }
+
+    #[test]
+    fn test_boxed_closure() {
+        let filters: Vec<Box<dyn RecordFilter>> = vec![
+            Box::new(MinMapq(20)),
+            Box::new(|record: &Record| !record.is_reverse()),
+        ];
+        let record = record(0x10, 60);
+        let kept: Vec<bool> = filters.iter().map(|f| f.keep(&record)).collect();
+        assert_eq!(kept, vec![true, false]);
+    }
+}
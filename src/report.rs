@@ -0,0 +1,264 @@
+use crate::error::FilterClippedError;
+use crate::stats::{FateCounts, RunStats};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Style sheet of the report, inlined so that the page is a single file
+const STYLE: &str = "body{font-family:sans-serif;margin:2em auto;max-width:60em;color:#222}\
+h1{font-size:1.6em}h2{font-size:1.2em;margin-top:2em;border-bottom:1px solid #ccc}\
+table{border-collapse:collapse}td,th{padding:.2em .8em;text-align:right}\
+td:first-child,th:first-child{text-align:left}tr:nth-child(even){background:#f4f4f4}\
+code{background:#f4f4f4;padding:.1em .3em}svg{margin:.5em 2em .5em 0}\
+.bar{fill:#4a7fb5}.axis{stroke:#888}figure{display:inline-block;margin:0}";
+
+/// Width and height of the histogram charts in pixels
+const CHART_SIZE: (usize, usize) = (300, 120);
+
+/// Escape the characters with a meaning in HTML
+///
+/// # Example
+/// ```
+/// use filter_clipped::report::escape_html;
+/// assert_eq!(escape_html("chr1 <\"x\" & y>"), "chr1 &lt;&quot;x&quot; &amp; y&gt;");
+/// ```
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Bar chart of binned counts as an inline SVG, the bars scaled to the highest count
+///
+/// # Arguments
+/// * `counts`: count of each bin, left to right
+/// * `label`: accessible name of the chart
+fn svg_histogram(counts: &[u64], label: &str) -> String {
+    let (width, height) = CHART_SIZE;
+    let max_count: u64 = counts.iter().copied().max().unwrap_or(0).max(1);
+    let bar_width: f64 = width as f64 / counts.len().max(1) as f64;
+    let mut svg: String = format!(
+        "<svg width=\"{w}\" height=\"{h}\" role=\"img\" aria-label=\"{label}\">",
+        w = width,
+        h = height + 1,
+        label = escape_html(label)
+    );
+    for (bin, count) in counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+        let bar_height: f64 = (*count as f64 / max_count as f64 * height as f64).max(1.0);
+        svg.push_str(&format!(
+            "<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\">\
+             <title>{}</title></rect>",
+            bin as f64 * bar_width,
+            height as f64 - bar_height,
+            bar_width,
+            bar_height,
+            count
+        ));
+    }
+    svg.push_str(&format!(
+        "<line class=\"axis\" x1=\"0\" y1=\"{h}\" x2=\"{w}\" y2=\"{h}\"/></svg>",
+        h = height,
+        w = width
+    ));
+    svg
+}
+
+/// Counts of the clip lengths from 0 to the longest clip, one bin per base
+fn length_bins(counts: &BTreeMap<i64, u64>) -> Vec<u64> {
+    let longest: usize = counts
+        .keys()
+        .next_back()
+        .map_or(0, |length| *length as usize);
+    let mut bins: Vec<u64> = vec![0; longest + 1];
+    for (length, count) in counts.iter() {
+        bins[*length as usize] += count;
+    }
+    bins
+}
+
+/// Table row of the fates of a set of alignments
+fn fate_row(name: &str, counts: &FateCounts) -> String {
+    format!(
+        "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}%</td></tr>",
+        escape_html(name),
+        counts.read,
+        counts.written,
+        counts.unaligned,
+        counts.removed,
+        counts.filtered_percent()
+    )
+}
+
+/// Header row of the tables of fates
+const FATE_HEADER: &str = "<tr><th></th><th>read</th><th>written</th><th>unaligned</th>\
+<th>removed</th><th>filtered</th></tr>";
+
+/// Write the summary of a run as a single static HTML page (no external resources), with
+/// the totals, the failures by reason, the clip fraction and 5'/3' clip length histograms, and
+/// the counts by flag
+/// category and reference sequence when they were collected
+///
+/// # Arguments
+/// * `writer`: where to write the page
+/// * `stats`: totals of the run
+/// * `command_line`: how the run was started, shown on top of the page if given
+pub fn write_html<W: Write>(
+    writer: &mut W,
+    stats: &RunStats,
+    command_line: Option<&str>,
+) -> Result<(), FilterClippedError> {
+    writeln!(
+        writer,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>filter-clipped report</title>\n<style>{}</style>\n</head>\n<body>",
+        STYLE
+    )?;
+    writeln!(
+        writer,
+        "<h1>filter-clipped report</h1>\n<p>filter-clipped {}</p>",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    if let Some(command_line) = command_line {
+        writeln!(writer, "<p><code>{}</code></p>", escape_html(command_line))?;
+    }
+
+    writeln!(writer, "<h2>Summary</h2>\n<table>")?;
+    let totals = [
+        ("alignments read", stats.read),
+        ("alignments written", stats.written),
+        ("made unmapped", stats.unaligned),
+        ("kept without filtering (flags)", stats.bypassed),
+        ("dropped (flags)", stats.dropped),
+        ("already unmapped in the input", stats.already_unmapped),
+        ("secondary without SEQ", stats.seqless_secondary),
+        ("failed the filter", stats.failed()),
+    ];
+    for (name, count) in totals {
+        writeln!(writer, "<tr><td>{}</td><td>{}</td></tr>", name, count)?;
+    }
+    writeln!(writer, "</table>")?;
+
+    if !stats.failed_by_reason.is_empty() {
+        writeln!(writer, "<h2>Failures by reason</h2>\n<table>")?;
+        writeln!(writer, "<tr><th>reason</th><th>alignments</th></tr>")?;
+        for (reason, count) in stats.failed_by_reason.iter() {
+            writeln!(
+                writer,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                reason.as_str(),
+                count
+            )?;
+        }
+        writeln!(writer, "</table>")?;
+    }
+
+    if !stats.histogram.is_empty() {
+        writeln!(
+            writer,
+            "<h2>Clip fractions</h2>\n<p>{} evaluated alignments, bins of 0.01 from 0 to 1</p>",
+            stats.histogram.len()
+        )?;
+        for (metric, counts) in stats.histogram.fractions() {
+            writeln!(
+                writer,
+                "<figure>{}<figcaption>{}</figcaption></figure>",
+                svg_histogram(counts, metric),
+                metric
+            )?;
+        }
+        writeln!(
+            writer,
+            "<h2>Clip lengths</h2>\n<p>bins of 1 base from 0 to the longest clip</p>"
+        )?;
+        for (metric, counts) in stats.histogram.lengths().into_iter().take(2) {
+            writeln!(
+                writer,
+                "<figure>{}<figcaption>{}</figcaption></figure>",
+                svg_histogram(&length_bins(counts), metric),
+                metric
+            )?;
+        }
+    }
+
+    if let Some(categories) = stats.flag_categories.as_ref() {
+        writeln!(
+            writer,
+            "<h2>By flag category</h2>\n<table>\n{}",
+            FATE_HEADER
+        )?;
+        for (name, counts) in categories.rows() {
+            writeln!(writer, "{}", fate_row(name, counts))?;
+        }
+        writeln!(writer, "</table>")?;
+    }
+
+    if !stats.contigs.is_empty() {
+        writeln!(
+            writer,
+            "<h2>By reference sequence</h2>\n<table>\n{}",
+            FATE_HEADER
+        )?;
+        for (contig, counts) in stats.contigs.iter() {
+            writeln!(writer, "{}", fate_row(contig, counts))?;
+        }
+        writeln!(writer, "</table>")?;
+    }
+    writeln!(writer, "</body>\n</html>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipping::ClipStat;
+    use crate::stats::FailReason;
+
+    #[test]
+    fn test_svg_histogram() {
+        let svg: String = svg_histogram(&[0, 2, 1], "total");
+        assert_eq!(svg.matches("<rect").count(), 2);
+        assert!(svg.contains("x=\"100.0\" y=\"0.0\" width=\"100.0\" height=\"120.0\""));
+        assert!(svg.contains("x=\"200.0\" y=\"60.0\" width=\"100.0\" height=\"60.0\""));
+    }
+
+    #[test]
+    fn test_length_bins() {
+        let counts: BTreeMap<i64, u64> = [(0, 3), (4, 1)].into_iter().collect();
+        assert_eq!(length_bins(&counts), vec![3, 0, 0, 0, 1]);
+        assert_eq!(length_bins(&BTreeMap::new()), vec![0]);
+    }
+
+    #[test]
+    fn test_write_html() {
+        let mut stats = RunStats {
+            read: 3,
+            written: 2,
+            ..RunStats::default()
+        };
+        stats.add_failure(FailReason::LeftClip);
+        stats.contig_mut("chr<1>").add(false, false);
+        stats
+            .histogram
+            .add(&ClipStat::new(vec![10, 0], vec![0, 0]), 100.0)
+            .unwrap();
+        let mut html: Vec<u8> = Vec::new();
+        write_html(&mut html, &stats, Some("filter-clipped -i in.bam")).unwrap();
+        let html: String = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.ends_with("</html>\n"));
+        assert!(html.contains("<code>filter-clipped -i in.bam</code>"));
+        assert!(html.contains("<tr><td>left_clip</td><td>1</td></tr>"));
+        assert!(html.contains("<td>chr&lt;1&gt;</td>"));
+        assert_eq!(html.matches("<svg").count(), 5);
+        assert!(html.contains("<figcaption>left_length</figcaption>"));
+        assert!(!html.contains("By flag category"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Screening of the soft clipped bases against a small database of contaminant sequences
+//! (`--screen-fasta`), e.g. cloning vectors, primers or spike-ins: a clip is a hit when it
+//! shares a k-mer with a contaminant, on either strand
+
+use crate::adapters::Adapter;
+use crate::kmers::canonical_kmers;
+use rust_htslib::bam::{record::CigarStringView, Record};
+use std::collections::HashMap;
+
+/// Index of the k-mers of the contaminant sequences
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContaminantIndex {
+    /// length of the k-mers
+    k: usize,
+    /// contaminants having each canonical k-mer, by their index in `names`
+    kmers: HashMap<u64, Vec<usize>>,
+    /// names of the contaminants
+    names: Vec<String>,
+}
+
+impl ContaminantIndex {
+    /// Index the k-mers of the contaminant sequences
+    ///
+    /// # Arguments
+    /// * `contaminants`: the contaminant sequences, read from a FASTA file like the adapters
+    ///   (see [crate::adapters::parse_fasta])
+    /// * `k`: length of the k-mers
+    pub fn new(contaminants: &[Adapter], k: usize) -> Self {
+        let mut kmers: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, contaminant) in contaminants.iter().enumerate() {
+            for kmer in canonical_kmers(contaminant.sequence.as_bytes(), k) {
+                let indices: &mut Vec<usize> = kmers.entry(kmer).or_default();
+                if indices.last() != Some(&i) {
+                    indices.push(i);
+                }
+            }
+        }
+        Self {
+            k,
+            kmers,
+            names: contaminants.iter().map(|c| c.name.clone()).collect(),
+        }
+    }
+
+    /// Whether there are no contaminants to screen against
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Look for contaminant k-mers in the soft clipped bases of an alignment (both ends)
+    ///
+    /// # Arguments
+    /// * `record`: the alignment
+    ///
+    /// # Returns
+    /// * the name of the contaminant sharing the most k-mers with the clips (the first one
+    ///   in the FASTA file on ties), None if no k-mer is shared
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::adapters::parse_fasta;
+    /// use filter_clipped::screen::ContaminantIndex;
+    /// use rust_htslib::bam::record::{CigarString, Record};
+    /// let contaminants = parse_fasta(">phiX\nGAGTTTTATCGCTTCCATGAC\n").unwrap();
+    /// let index = ContaminantIndex::new(&contaminants, 8);
+    /// let mut record = Record::new();
+    /// let cigar = CigarString::try_from("10M12S").unwrap();
+    /// record.set(b"read", Some(&cigar), b"ACGTACGTACTTTATCGCTTCC", &[30; 22]);
+    /// assert_eq!(index.find(&record), Some("phiX"));
+    /// ```
+    pub fn find(&self, record: &Record) -> Option<&str> {
+        if self.is_empty() {
+            return None;
+        }
+        let seq: Vec<u8> = record.seq().as_bytes();
+        let cigar: CigarStringView = record.cigar();
+        let leading: usize = (cigar.leading_softclips() as usize).min(seq.len());
+        let trailing: usize = (cigar.trailing_softclips() as usize).min(seq.len() - leading);
+        let mut hits: Vec<usize> = vec![0; self.names.len()];
+        for clip in [&seq[..leading], &seq[seq.len() - trailing..]] {
+            for kmer in canonical_kmers(clip, self.k) {
+                for i in self.kmers.get(&kmer).into_iter().flatten() {
+                    hits[*i] += 1;
+                }
+            }
+        }
+        let (best, count) =
+            hits.iter()
+                .enumerate()
+                .fold((0, 0), |best, (i, count)| match *count > best.1 {
+                    true => (i, *count),
+                    _ => best,
+                });
+        match count {
+            0 => None,
+            _ => Some(self.names[best].as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::parse_fasta;
+    use rstest::rstest;
+    use rust_htslib::bam::record::CigarString;
+
+    #[rstest]
+    #[case("10M12S", "ACGTACGTACTTTATCGCTTCC", Some("phiX"))]
+    #[case("12S10M", "GGAAGCGATAAAACGTACGTAC", Some("phiX"))]
+    #[case("22M", "ACGTACGTACTTTATCGCTTCC", None)]
+    #[case("10M12S", "ACGTACGTACGACGGCCAGTAA", Some("M13"))]
+    #[case("10M12S", "ACGTACGTACAGATCGGAAGAG", None)]
+    fn test_find(#[case] cigar: &str, #[case] seq: &str, #[case] expected: Option<&str>) {
+        let contaminants =
+            parse_fasta(">phiX\nGAGTTTTATCGCTTCCATGAC\n>M13 forward primer\nGTAAAACGACGGCCAGT\n")
+                .unwrap();
+        let index = ContaminantIndex::new(&contaminants, 8);
+        let cigar = CigarString::try_from(cigar).unwrap();
+        let mut record = Record::new();
+        record.set(b"read", Some(&cigar), seq.as_bytes(), &vec![30; seq.len()]);
+        assert_eq!(index.find(&record), expected);
+    }
+}
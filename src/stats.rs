@@ -0,0 +1,713 @@
+use crate::consensus::ClipConsensus;
+use crate::error::FilterClippedError;
+use crate::gc::GcProfile;
+use crate::histogram::ClipHistogram;
+use crate::kmers::KmerCounts;
+use crate::profile::ClipProfile;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::Duration;
+
+/// Why an alignment failed the filter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailReason {
+    /// too many clipped bases in total
+    TotalClip,
+    /// too many clipped bases on the 5' end
+    LeftClip,
+    /// too many clipped bases on the 3' end
+    RightClip,
+    /// soft clipped bases are an adapter, with [crate::adapters::AdapterPolicy::Fail]
+    Adapter,
+    /// soft clipped bases share a k-mer with a contaminant sequence, with
+    /// [crate::config::FilterConfig::screen]
+    Contaminant,
+    /// weighted score of the clip fractions above the cutoff
+    /// (see [crate::config::FilterConfig::score])
+    Score,
+    /// mapping quality below the minimum
+    Mapq,
+    /// too many inserted and deleted bases
+    /// (see [crate::config::FilterConfig::max_indel_fraction])
+    Indel,
+    /// too many mismatched bases (see [crate::config::FilterConfig::max_mismatch_fraction])
+    Mismatch,
+    /// too small a fraction of the read aligned
+    /// (see [crate::config::FilterConfig::min_query_coverage])
+    QueryCoverage,
+    /// no contiguous aligned block long enough
+    /// (see [crate::config::FilterConfig::min_aligned_block])
+    AlignedBlock,
+    /// too small a fraction of a split read covered by all its segments
+    /// (see [crate::config::FilterConfig::min_split_coverage])
+    SplitCoverage,
+    /// insert size (absolute TLEN) out of bounds
+    /// (see [crate::config::FilterConfig::min_insert_size])
+    InsertSize,
+    /// the filter expression is false on it (see [crate::config::FilterConfig::expression])
+    Expression,
+    /// vetoed by the extra filter (see [crate::config::FilterConfig::with_extra_filter])
+    ExtraFilter,
+    /// removed because of its flags (see [crate::config::FlagPolicy::Drop])
+    Flag,
+    /// without the proper-pair flag (see [crate::config::FilterConfig::require_proper_pair])
+    ProperPair,
+    /// removed because its cell barcode is missing or not in the whitelist
+    /// (see [crate::config::FilterConfig::cb_whitelist])
+    CellBarcode,
+    /// removed because its read name is listed to be dropped
+    /// (see [crate::config::FilterConfig::drop_names])
+    NameList,
+    /// removed because it is unmapped in the input
+    /// (see [crate::config::UnmappedPolicy::Drop])
+    Unmapped,
+    /// passed itself, but another alignment of the same read failed
+    /// (its mate, or its primary alignment in cascade mode)
+    Linked,
+}
+
+impl FailReason {
+    /// Name of the reason, as in the JSON summary
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::FailReason;
+    /// assert_eq!(FailReason::TotalClip.as_str(), "total_clip");
+    /// ```
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailReason::TotalClip => "total_clip",
+            FailReason::LeftClip => "left_clip",
+            FailReason::RightClip => "right_clip",
+            FailReason::Adapter => "adapter",
+            FailReason::Contaminant => "contaminant",
+            FailReason::Score => "score",
+            FailReason::Mapq => "mapq",
+            FailReason::Indel => "indel",
+            FailReason::Mismatch => "mismatch",
+            FailReason::QueryCoverage => "query_coverage",
+            FailReason::AlignedBlock => "aligned_block",
+            FailReason::SplitCoverage => "split_coverage",
+            FailReason::InsertSize => "insert_size",
+            FailReason::Expression => "expression",
+            FailReason::ExtraFilter => "extra_filter",
+            FailReason::Flag => "flag",
+            FailReason::ProperPair => "proper_pair",
+            FailReason::CellBarcode => "cell_barcode",
+            FailReason::NameList => "name_list",
+            FailReason::Unmapped => "unmapped",
+            FailReason::Linked => "linked",
+        }
+    }
+}
+
+/// Pass/fail counts and clip fractions of the alignments of a cell barcode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct CellStats {
+    /// number of evaluated alignments passing the filter
+    pub passed: u64,
+    /// number of evaluated alignments failing the filter
+    pub failed: u64,
+    /// sum of the 5' clip fractions
+    pub left_fraction_sum: f64,
+    /// sum of the 3' clip fractions
+    pub right_fraction_sum: f64,
+    /// sum of the total clip fractions
+    pub total_fraction_sum: f64,
+}
+
+impl CellStats {
+    /// Count an evaluated alignment
+    ///
+    /// # Arguments
+    /// - `passed`: whether the alignment passed the filter
+    /// - `fractions`: its 5', 3' and total clip fractions
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::CellStats;
+    /// let mut cell = CellStats::default();
+    /// cell.add(true, (0.0, 0.1, 0.1));
+    /// cell.add(false, (0.4, 0.0, 0.4));
+    /// assert_eq!(cell.passed + cell.failed, 2);
+    /// assert_eq!(cell.mean_fractions(), (0.2, 0.05, 0.25));
+    /// ```
+    pub fn add(&mut self, passed: bool, fractions: (f64, f64, f64)) {
+        match passed {
+            true => self.passed += 1,
+            _ => self.failed += 1,
+        }
+        self.left_fraction_sum += fractions.0;
+        self.right_fraction_sum += fractions.1;
+        self.total_fraction_sum += fractions.2;
+    }
+
+    /// Add the counts and sums of other alignments of the cell barcode
+    pub fn merge(&mut self, other: &CellStats) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.left_fraction_sum += other.left_fraction_sum;
+        self.right_fraction_sum += other.right_fraction_sum;
+        self.total_fraction_sum += other.total_fraction_sum;
+    }
+
+    /// Mean 5', 3' and total clip fractions of the alignments
+    pub fn mean_fractions(&self) -> (f64, f64, f64) {
+        let count: f64 = (self.passed + self.failed).max(1) as f64;
+        (
+            self.left_fraction_sum / count,
+            self.right_fraction_sum / count,
+            self.total_fraction_sum / count,
+        )
+    }
+}
+
+/// What happened to a set of alignments, e.g. the ones on a reference sequence
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FateCounts {
+    /// number of alignments read from the input
+    pub read: u64,
+    /// number of alignments written to the output (including the unaligned ones)
+    pub written: u64,
+    /// number of alignments made unmapped (and written)
+    pub unaligned: u64,
+    /// number of alignments not written
+    pub removed: u64,
+}
+
+impl FateCounts {
+    /// Count an alignment
+    ///
+    /// # Arguments
+    /// * `written`: whether the alignment is written out
+    /// * `unaligned`: whether the alignment is made unmapped (and written)
+    pub fn add(&mut self, written: bool, unaligned: bool) {
+        self.read += 1;
+        match written {
+            true => self.written += 1,
+            _ => self.removed += 1,
+        }
+        if unaligned {
+            self.unaligned += 1;
+        }
+    }
+
+    /// Add the counts of other alignments
+    pub fn merge(&mut self, other: &FateCounts) {
+        self.read += other.read;
+        self.written += other.written;
+        self.unaligned += other.unaligned;
+        self.removed += other.removed;
+    }
+
+    /// Percentage of the alignments read that were removed or made unmapped
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::FateCounts;
+    /// let stats = FateCounts { read: 8, written: 7, unaligned: 1, removed: 1 };
+    /// assert_eq!(stats.filtered_percent(), 25.0);
+    /// ```
+    pub fn filtered_percent(&self) -> f64 {
+        100.0 * (self.removed + self.unaligned) as f64 / self.read.max(1) as f64
+    }
+}
+
+/// What happened to the alignments by the categories of their flags, for a summary table
+/// like `samtools flagstat`; an alignment can be in more than one category
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FlagCategories {
+    /// all the alignments
+    pub total: FateCounts,
+    /// alignments neither secondary nor supplementary
+    pub primary: FateCounts,
+    /// secondary alignments
+    pub secondary: FateCounts,
+    /// supplementary alignments
+    pub supplementary: FateCounts,
+    /// alignments flagged as PCR or optical duplicates
+    pub duplicate: FateCounts,
+    /// alignments of paired reads
+    pub paired: FateCounts,
+    /// alignments of the first mates (read 1) of paired reads
+    pub read1: FateCounts,
+    /// alignments of the second mates (read 2) of paired reads
+    pub read2: FateCounts,
+    /// alignments of paired reads flagged as properly paired
+    pub proper_pair: FateCounts,
+}
+
+impl FlagCategories {
+    /// Count an alignment in its categories
+    ///
+    /// # Arguments
+    /// * `flags`: flags of the alignment (before it is made unmapped)
+    /// * `written`: whether the alignment is written out
+    /// * `unaligned`: whether the alignment is made unmapped (and written)
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::FlagCategories;
+    /// let mut categories = FlagCategories::default();
+    /// categories.add(0x1 | 0x800, false, false);
+    /// assert_eq!(categories.total.removed, 1);
+    /// assert_eq!(categories.supplementary.removed, 1);
+    /// assert_eq!(categories.primary.read, 0);
+    /// ```
+    pub fn add(&mut self, flags: u16, written: bool, unaligned: bool) {
+        self.total.add(written, unaligned);
+        if flags & (0x100 | 0x800) == 0 {
+            self.primary.add(written, unaligned);
+        }
+        if flags & 0x100 != 0 {
+            self.secondary.add(written, unaligned);
+        }
+        if flags & 0x800 != 0 {
+            self.supplementary.add(written, unaligned);
+        }
+        if flags & 0x400 != 0 {
+            self.duplicate.add(written, unaligned);
+        }
+        if flags & 0x1 != 0 {
+            self.paired.add(written, unaligned);
+            if flags & 0x40 != 0 {
+                self.read1.add(written, unaligned);
+            }
+            if flags & 0x80 != 0 {
+                self.read2.add(written, unaligned);
+            }
+            if flags & 0x2 != 0 {
+                self.proper_pair.add(written, unaligned);
+            }
+        }
+    }
+
+    /// Add the counts of other alignments
+    pub fn merge(&mut self, other: &FlagCategories) {
+        self.total.merge(&other.total);
+        self.primary.merge(&other.primary);
+        self.secondary.merge(&other.secondary);
+        self.supplementary.merge(&other.supplementary);
+        self.duplicate.merge(&other.duplicate);
+        self.paired.merge(&other.paired);
+        self.read1.merge(&other.read1);
+        self.read2.merge(&other.read2);
+        self.proper_pair.merge(&other.proper_pair);
+    }
+
+    /// The categories with their names, in the order of the tables
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::FlagCategories;
+    /// let categories = FlagCategories::default();
+    /// assert_eq!(categories.rows()[0].0, "total");
+    /// assert_eq!(categories.rows().len(), 9);
+    /// ```
+    pub fn rows(&self) -> [(&'static str, &FateCounts); 9] {
+        [
+            ("total", &self.total),
+            ("primary", &self.primary),
+            ("secondary", &self.secondary),
+            ("supplementary", &self.supplementary),
+            ("duplicate", &self.duplicate),
+            ("paired", &self.paired),
+            ("read1", &self.read1),
+            ("read2", &self.read2),
+            ("proper_pair", &self.proper_pair),
+        ]
+    }
+
+    /// Write the counts as a table, one line per category
+    ///
+    /// # Arguments
+    /// * `writer`: where to write the table
+    pub fn write_table<W: Write>(&self, writer: &mut W) -> Result<(), FilterClippedError> {
+        writeln!(
+            writer,
+            "{:<14}{:>12}{:>12}{:>12}{:>12}{:>12}",
+            "category", "read", "written", "unaligned", "removed", "filtered"
+        )?;
+        for (category, counts) in self.rows() {
+            writeln!(
+                writer,
+                "{:<14}{:>12}{:>12}{:>12}{:>12}{:>11.2}%",
+                category,
+                counts.read,
+                counts.written,
+                counts.unaligned,
+                counts.removed,
+                counts.filtered_percent()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the per-cell-barcode statistics as a TSV file, one cell barcode per line
+///
+/// # Arguments
+/// - `cells`: statistics by cell barcode
+/// - `path`: the TSV file
+pub fn write_cell_stats(
+    cells: &BTreeMap<String, CellStats>,
+    path: &str,
+) -> Result<(), FilterClippedError> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(
+        file,
+        "barcode\tpassed\tfailed\tmean_left_fraction\tmean_right_fraction\tmean_total_fraction"
+    )?;
+    for (barcode, cell) in cells.iter() {
+        let (left, right, total) = cell.mean_fractions();
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+            barcode, cell.passed, cell.failed, left, right, total
+        )?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Speed of a run, from its wall-clock time and the sizes of its input and output files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Throughput {
+    /// wall-clock time of the run in seconds
+    pub seconds: f64,
+    /// alignments read per second
+    pub records_per_second: f64,
+    /// size of the input files in bytes, unknown for stdin and remote inputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_bytes: Option<u64>,
+    /// input read in megabytes (10^6 bytes) per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_mb_per_second: Option<f64>,
+    /// size of the output file in bytes, unknown for stdout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_bytes: Option<u64>,
+    /// output written in megabytes (10^6 bytes) per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_mb_per_second: Option<f64>,
+}
+
+impl Throughput {
+    /// Work out the rates of a run
+    ///
+    /// # Arguments
+    /// * `elapsed`: wall-clock time of the run
+    /// * `records`: number of alignments read
+    /// * `input_bytes`: size of the input files, if known
+    /// * `output_bytes`: size of the output file, if known
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::Throughput;
+    /// use std::time::Duration;
+    /// let throughput = Throughput::new(Duration::from_secs(2), 1000, Some(4_000_000), None);
+    /// assert_eq!(throughput.records_per_second, 500.0);
+    /// assert_eq!(throughput.input_mb_per_second, Some(2.0));
+    /// assert_eq!(throughput.output_mb_per_second, None);
+    /// ```
+    pub fn new(
+        elapsed: Duration,
+        records: u64,
+        input_bytes: Option<u64>,
+        output_bytes: Option<u64>,
+    ) -> Self {
+        // no division by zero for runs too short to time
+        let seconds: f64 = elapsed.as_secs_f64().max(1e-9);
+        let mb_per_second = |bytes: u64| bytes as f64 / 1e6 / seconds;
+        Self {
+            seconds: elapsed.as_secs_f64(),
+            records_per_second: records as f64 / seconds,
+            input_bytes,
+            input_mb_per_second: input_bytes.map(mb_per_second),
+            output_bytes,
+            output_mb_per_second: output_bytes.map(mb_per_second),
+        }
+    }
+}
+
+/// Whether a count is 0, to leave the counts of options that are off out of the summary
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+/// Whether a flag is unset, to leave it out of the summary
+fn is_false(flag: &bool) -> bool {
+    !*flag
+}
+
+/// Summary of a filtering run
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RunStats {
+    /// whether the run stopped without filtering anything, the input having been filtered
+    /// with the same settings already (see [crate::config::FilterConfig::if_already_filtered])
+    #[serde(skip_serializing_if = "is_false")]
+    pub skipped: bool,
+    /// number of alignments read from the input
+    pub read: u64,
+    /// number of alignments written to the output
+    pub written: u64,
+    /// number of alignments made unmapped (and written)
+    pub unaligned: u64,
+    /// number of alignments written without checking the clipping because of their flags
+    pub bypassed: u64,
+    /// number of alignments removed because of their flags
+    pub dropped: u64,
+    /// number of alignments already unmapped in the input (see
+    /// [crate::config::FilterConfig::unmapped])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub already_unmapped: u64,
+    /// number of secondary alignments without a sequence (see
+    /// [crate::config::FilterConfig::seqless_secondary])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub seqless_secondary: u64,
+    /// number of alignments left out of the subsample (see [crate::config::FilterConfig::subsample])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub subsampled_out: u64,
+    /// number of supplementary alignments made primary in place of a failing primary
+    /// alignment (see [crate::config::FilterConfig::promote_supplementary])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub promoted: u64,
+    /// number of alignments written without their mate, marked as having an unmapped mate
+    /// (see [crate::config::FilterConfig::fix_orphans])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub orphans: u64,
+    /// number of failed alignments written with MAPQ set to 0
+    /// (see [crate::config::FilterConfig::zero_mapq])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub zero_mapq: u64,
+    /// number of mates passing the filter while the other mate fails
+    /// (see [crate::config::FilterConfig::singletons])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub singletons: u64,
+    /// number of records that couldn't be read and were skipped (see
+    /// [crate::config::FilterConfig::tolerant])
+    #[serde(skip_serializing_if = "is_zero")]
+    pub malformed: u64,
+    /// number of alignments failing the filter, by the first reason they failed for
+    pub failed_by_reason: BTreeMap<FailReason, u64>,
+    /// number of evaluated alignments with an adapter in their soft clipped bases, by adapter name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub adapter_hits: BTreeMap<String, u64>,
+    /// number of evaluated alignments with a contaminant k-mer in their soft clipped bases,
+    /// by contaminant name (see [crate::config::FilterConfig::screen])
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub contaminant_hits: BTreeMap<String, u64>,
+    /// what happened to the alignments by reference sequence ("*" for unmapped alignments)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub contigs: BTreeMap<String, FateCounts>,
+    /// what happened to the alignments by the categories of their flags, with `flagstat`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flag_categories: Option<FlagCategories>,
+    /// pass/fail counts and clip fractions by cell barcode, with `cell_stats`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub cells: BTreeMap<String, CellStats>,
+    /// wall-clock time and rates of the run, set once it's done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throughput: Option<Throughput>,
+    /// binned clip fractions and lengths of the evaluated alignments, with `hist_out` or
+    /// `html_report`; only the 5' and 3' clip lengths go into the summary, as `clip_lengths`
+    #[serde(
+        rename = "clip_lengths",
+        skip_serializing_if = "ClipHistogram::is_empty",
+        serialize_with = "ClipHistogram::serialize_lengths"
+    )]
+    pub histogram: ClipHistogram,
+    /// soft clip boundaries of the evaluated alignments along the reference, with `clip_profile`
+    /// or `breakpoint_bed`
+    #[serde(skip)]
+    pub profile: Option<ClipProfile>,
+    /// soft clipped sequences of the evaluated alignments by clip boundary, with `clip_consensus`
+    #[serde(skip)]
+    pub consensus: ClipConsensus,
+    /// binned GC content of the soft clipped and aligned sequence of the evaluated alignments,
+    /// with `gc_out`
+    #[serde(skip)]
+    pub gc: GcProfile,
+    /// k-mers of the soft clipped and aligned sequence of the evaluated alignments, with
+    /// `kmer_out`
+    #[serde(skip)]
+    pub kmers: Option<KmerCounts>,
+}
+
+impl RunStats {
+    /// Summary of a run skipped without filtering anything
+    pub fn skipped() -> Self {
+        Self {
+            skipped: true,
+            ..Self::default()
+        }
+    }
+
+    /// Count an alignment failing the filter
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::{FailReason, RunStats};
+    /// let mut stats = RunStats::default();
+    /// stats.add_failure(FailReason::LeftClip);
+    /// stats.add_failure(FailReason::LeftClip);
+    /// stats.add_failure(FailReason::Linked);
+    /// assert_eq!(stats.failed_by_reason[&FailReason::LeftClip], 2);
+    /// assert_eq!(stats.failed(), 3);
+    /// ```
+    pub fn add_failure(&mut self, reason: FailReason) {
+        *self.failed_by_reason.entry(reason).or_insert(0) += 1;
+    }
+
+    /// Counts of a reference sequence, added on first use
+    ///
+    /// # Arguments
+    /// * `contig`: name of the reference sequence
+    pub fn contig_mut(&mut self, contig: &str) -> &mut FateCounts {
+        // look up first, so that the name is only copied for new reference sequences
+        if !self.contigs.contains_key(contig) {
+            self.contigs
+                .insert(contig.to_string(), FateCounts::default());
+        }
+        self.contigs.get_mut(contig).unwrap()
+    }
+
+    /// Total number of alignments failing the filter, for any reason
+    pub fn failed(&self) -> u64 {
+        self.failed_by_reason.values().sum()
+    }
+
+    /// Add the totals of another run, e.g. over another part of the input
+    ///
+    /// # Example
+    /// ```
+    /// use filter_clipped::stats::{FailReason, RunStats};
+    /// let mut stats = RunStats { read: 2, written: 1, ..RunStats::default() };
+    /// stats.add_failure(FailReason::LeftClip);
+    /// let mut other = RunStats { read: 3, written: 2, ..RunStats::default() };
+    /// other.add_failure(FailReason::LeftClip);
+    /// stats.merge(other);
+    /// assert_eq!((stats.read, stats.written), (5, 3));
+    /// assert_eq!(stats.failed_by_reason[&FailReason::LeftClip], 2);
+    /// ```
+    pub fn merge(&mut self, other: RunStats) {
+        self.read += other.read;
+        self.written += other.written;
+        self.unaligned += other.unaligned;
+        self.bypassed += other.bypassed;
+        self.dropped += other.dropped;
+        self.already_unmapped += other.already_unmapped;
+        self.seqless_secondary += other.seqless_secondary;
+        self.subsampled_out += other.subsampled_out;
+        self.promoted += other.promoted;
+        self.orphans += other.orphans;
+        self.zero_mapq += other.zero_mapq;
+        self.singletons += other.singletons;
+        self.malformed += other.malformed;
+        for (reason, count) in other.failed_by_reason {
+            *self.failed_by_reason.entry(reason).or_insert(0) += count;
+        }
+        for (adapter, count) in other.adapter_hits {
+            *self.adapter_hits.entry(adapter).or_insert(0) += count;
+        }
+        for (contaminant, count) in other.contaminant_hits {
+            *self.contaminant_hits.entry(contaminant).or_insert(0) += count;
+        }
+        for (contig, counts) in other.contigs.iter() {
+            self.contig_mut(contig).merge(counts);
+        }
+        if let Some(categories) = other.flag_categories.as_ref() {
+            self.flag_categories
+                .get_or_insert_with(FlagCategories::default)
+                .merge(categories);
+        }
+        for (barcode, cell) in other.cells.iter() {
+            self.cells.entry(barcode.clone()).or_default().merge(cell);
+        }
+        self.histogram.merge(&other.histogram);
+        match (self.profile.as_mut(), other.profile) {
+            (Some(profile), Some(other_profile)) => profile.merge(&other_profile),
+            (None, other_profile) => self.profile = other_profile,
+            _ => (),
+        }
+        self.consensus.merge(&other.consensus);
+        self.gc.merge(&other.gc);
+        match (self.kmers.as_mut(), other.kmers) {
+            (Some(kmers), Some(other_kmers)) => kmers.merge(&other_kmers),
+            (None, other_kmers) => self.kmers = other_kmers,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_stats_json() {
+        let mut stats = RunStats {
+            read: 10,
+            written: 7,
+            ..RunStats::default()
+        };
+        stats.add_failure(FailReason::TotalClip);
+        stats.add_failure(FailReason::Flag);
+        stats.add_failure(FailReason::TotalClip);
+        assert_eq!(
+            serde_json::to_string(&stats).unwrap(),
+            "{\"read\":10,\"written\":7,\"unaligned\":0,\"bypassed\":0,\"dropped\":0,\
+             \"failed_by_reason\":{\"total_clip\":2,\"flag\":1}}"
+        );
+    }
+
+    #[test]
+    fn test_write_table() {
+        let mut categories = FlagCategories::default();
+        categories.add(0x1 | 0x2 | 0x40, true, false);
+        categories.add(0x1 | 0x40 | 0x100, false, false);
+        categories.add(0x1 | 0x80 | 0x400, true, true);
+        let mut table: Vec<u8> = Vec::new();
+        categories.write_table(&mut table).unwrap();
+        let text: String = String::from_utf8(table).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(
+            lines[1],
+            "total                    3           2           1           1      66.67%"
+        );
+        assert_eq!(
+            lines[3],
+            "secondary                1           0           0           1     100.00%"
+        );
+        assert_eq!(
+            lines[4],
+            "supplementary            0           0           0           0       0.00%"
+        );
+        assert_eq!(
+            lines[7],
+            "read1                    2           1           0           1      50.00%"
+        );
+        assert_eq!(
+            lines[9],
+            "proper_pair              1           1           0           0       0.00%"
+        );
+    }
+
+    #[test]
+    fn test_fail_reason_as_str() {
+        for reason in [
+            FailReason::TotalClip,
+            FailReason::Adapter,
+            FailReason::CellBarcode,
+            FailReason::NameList,
+            FailReason::Linked,
+        ] {
+            assert_eq!(
+                serde_json::to_string(&reason).unwrap(),
+                format!("\"{}\"", reason.as_str())
+            );
+        }
+    }
+}
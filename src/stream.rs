@@ -0,0 +1,216 @@
+//! Async pipeline (feature `async`) for network-hosted inputs: the alignments are
+//! fetched on one blocking task while they are filtered and written on another,
+//! so the download latency is hidden behind the filtering and compression of the output
+//!
+//! The records are still read and written with rust-htslib, run on the blocking thread
+//! pool of tokio, rather than with async readers such as the ones of noodles: htslib
+//! already fetches the remote files (http, s3, gcs), and the records, tags and filters
+//! stay the ones of the synchronous workflow
+//!
+//! Only a single input read from start to end is streamed: the extra inputs and the
+//! regions read in parallel are left to [crate::run_with_config]
+
+use crate::config::FilterConfig;
+use crate::error::FilterClippedError;
+use crate::stats::RunStats;
+use crate::{
+    already_filtered, filter_records, finish_run, io, output_header, prepare_run, run_first_pass,
+    FirstPass,
+};
+use rust_htslib::bam::{self, Header, Read, Reader, Record};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Instant;
+use std::{iter, mem, panic};
+use tokio::runtime;
+use tokio::sync::mpsc;
+use tokio::task;
+
+/// Number of alignments sent from the reading task to the writing task at once
+const BATCH_SIZE: usize = 1024;
+/// Number of batches read ahead of the writing task
+const BUFFERED_BATCHES: usize = 16;
+
+type Batch = Vec<Result<Record, FilterClippedError>>;
+
+/// Run a blocking closure on the blocking thread pool of the runtime
+async fn blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(value) => value,
+        // the tasks are never cancelled, so this is a panic: keep it as it is
+        Err(err) => panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// Read the input in batches, until the input is done, an error occurs (unless
+/// `tolerant`, the writing task then decides when to stop) or the writing task is gone
+///
+/// # Arguments
+/// - `in_bam`: the opened input file
+/// - `sender`: channel to the writing task
+/// - `tolerant`: keep reading after the records that can't be read
+fn read_batches(mut in_bam: Reader, sender: mpsc::Sender<Batch>, tolerant: bool) {
+    let mut batch: Batch = Vec::with_capacity(BATCH_SIZE);
+    for r in in_bam.records() {
+        let failed: bool = r.is_err() && !tolerant;
+        batch.push(r.map_err(FilterClippedError::from));
+        if failed || batch.len() == BATCH_SIZE {
+            let full: Batch = mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if sender.blocking_send(full).is_err() || failed {
+                return;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        // nothing left to do if the writing task has already stopped
+        let _ = sender.blocking_send(batch);
+    }
+}
+
+/// Same workflow as [crate::run_with_config], with the input read concurrently
+/// to the filtering and the writing of the output
+///
+/// # Arguments
+/// - `config`: settings for the run
+///
+/// # Returns
+/// - a summary of what happened to the alignments, see [RunStats]
+pub async fn run_async(config: FilterConfig) -> Result<RunStats, FilterClippedError> {
+    if !config.extra_inputs.is_empty() || config.region_threads > 1 {
+        return Err(FilterClippedError::InvalidConfig(String::from(
+            "extra inputs and region threads aren't supported by the async pipeline, use run_with_config",
+        )));
+    }
+    let started: Instant = Instant::now();
+    // picking the preset and deriving the thresholds read the input, waiting on the network
+    let config: FilterConfig = blocking(move || prepare_run(&config).map(Cow::into_owned)).await?;
+    let config: Arc<FilterConfig> = Arc::new(config);
+
+    // opening a remote file and parsing its header waits on the network too
+    let reader_config = Arc::clone(&config);
+    let opened: Option<(Reader, Header)> = blocking(move || {
+        let in_bam: Reader = io::open_reader(&reader_config)?;
+        if already_filtered(&reader_config, in_bam.header())? {
+            return Ok(None);
+        }
+        let header: Header = output_header(&reader_config, in_bam.header())?;
+        Ok::<_, FilterClippedError>(Some((in_bam, header)))
+    })
+    .await?;
+    let (in_bam, header): (Reader, Header) = match opened {
+        Some(opened) => opened,
+        None => return Ok(RunStats::skipped()),
+    };
+
+    // only once the run isn't skipped, the first pass reading the whole input
+    let first_pass: Option<FirstPass> = match config.two_pass {
+        true => {
+            let config = Arc::clone(&config);
+            Some(blocking(move || run_first_pass(&config)).await?)
+        }
+        _ => None,
+    };
+
+    let tolerant: bool = config.tolerant;
+    let (sender, mut receiver) = mpsc::channel::<Batch>(BUFFERED_BATCHES);
+    let reading = task::spawn_blocking(move || read_batches(in_bam, sender, tolerant));
+    let result: Result<RunStats, FilterClippedError> = blocking(move || {
+        let mut out_bam: bam::Writer = io::open_writer(&config, &header)?;
+        let records = iter::from_fn(|| receiver.blocking_recv()).flatten();
+        let stats: RunStats = filter_records(records, &mut out_bam, &config, first_pass.as_ref())?;
+        finish_run(&config, out_bam, stats, started)
+    })
+    .await;
+    if let Err(err) = reading.await {
+        panic::resume_unwind(err.into_panic());
+    }
+    result
+}
+
+/// Run [run_async] to completion from synchronous code
+///
+/// # Arguments
+/// - `config`: settings for the run
+///
+/// # Returns
+/// - a summary of what happened to the alignments, see [RunStats]
+pub fn run_blocking(config: FilterConfig) -> Result<RunStats, FilterClippedError> {
+    // the work happens on the blocking thread pool, a single scheduler thread is enough
+    let runtime = runtime::Builder::new_current_thread().build()?;
+    runtime.block_on(run_async(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_with_config;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(false, false)]
+    #[case(true, false)]
+    #[case(false, true)]
+    fn test_run_blocking(#[case] inverse: bool, #[case] two_pass: bool) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .both(0.1)
+            .left(0.1)
+            .right(0.1)
+            .inverse(inverse)
+            .two_pass(two_pass);
+        let case: String = format!("{}_{}", inverse, two_pass);
+        let mut expected: RunStats = run_with_config(
+            &config
+                .clone()
+                .out_bam(format!("test/data/out_sync_{}.bam", case)),
+        )
+        .unwrap();
+        let mut stats: RunStats =
+            run_blocking(config.out_bam(format!("test/data/out_async_{}.bam", case))).unwrap();
+        // the timings differ from run to run
+        assert!(stats.throughput.take().is_some());
+        expected.throughput = None;
+        assert_eq!(stats, expected);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_run_blocking_auto_preset(#[case] auto_preset: bool) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/lane2.sam")
+            .left(0.5)
+            .auto_preset(auto_preset);
+        let mut expected: RunStats = run_with_config(
+            &config
+                .clone()
+                .out_bam(format!("test/data/out_sync_preset_{}.bam", auto_preset)),
+        )
+        .unwrap();
+        let mut stats: RunStats =
+            run_blocking(config.out_bam(format!("test/data/out_async_preset_{}.bam", auto_preset)))
+                .unwrap();
+        assert!(stats.throughput.take().is_some());
+        expected.throughput = None;
+        assert_eq!(stats, expected);
+    }
+
+    #[rstest]
+    #[case(vec![String::from("test/data/test.sam")], 1)]
+    #[case(vec![], 2)]
+    fn test_run_blocking_unsupported(#[case] extra_inputs: Vec<String>, #[case] threads: usize) {
+        let config = FilterConfig::new()
+            .in_bam("test/data/test.sam")
+            .out_bam("test/data/out_async_unsupported.bam")
+            .extra_inputs(extra_inputs)
+            .region_threads(threads, 1_000_000);
+        assert!(matches!(
+            run_blocking(config),
+            Err(FilterClippedError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+//! Deterministic subsampling by read name, so that all the alignments of a read (and
+//! both mates of a pair) are kept or left out together, like `samtools view -s`
+
+/// Offset basis of the 64-bit FNV-1a hash
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+/// Prime of the 64-bit FNV-1a hash
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// 64-bit FNV-1a hash of a sequence of bytes
+///
+/// # Arguments
+/// * `bytes`: the bytes to hash
+pub(crate) fn fnv1a<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u64 {
+    bytes.into_iter().fold(FNV_OFFSET, |h, byte| {
+        (h ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Finalizer of splitmix64, spreading the bits of the FNV hash over the whole word
+fn mix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// Position of a read name in [0, 1), the same for every run with the same seed (and on
+/// every platform), but unrelated between seeds
+///
+/// # Arguments
+/// * `qname`: the read name
+/// * `seed`: seed of the subsampling
+///
+/// # Example
+/// ```
+/// use filter_clipped::subsample::name_fraction;
+/// let fraction = name_fraction(b"read1", 42);
+/// assert!((0.0..1.0).contains(&fraction));
+/// assert_eq!(fraction, name_fraction(b"read1", 42));
+/// assert_ne!(fraction, name_fraction(b"read1", 43));
+/// ```
+pub fn name_fraction(qname: &[u8], seed: u64) -> f64 {
+    let h = fnv1a(seed.to_le_bytes().iter().chain(qname));
+    // the top 53 bits, as many as an f64 holds exactly
+    (mix(h) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Whether the alignments of a read name are in a subsample
+///
+/// # Arguments
+/// * `qname`: the read name
+/// * `seed`: seed of the subsampling
+/// * `fraction`: fraction of the read names to keep, between 0 and 1
+pub fn is_sampled(qname: &[u8], seed: u64, fraction: f64) -> bool {
+    name_fraction(qname, seed) < fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0.1, 0)]
+    #[case(0.5, 42)]
+    #[case(0.9, 7)]
+    fn test_is_sampled(#[case] fraction: f64, #[case] seed: u64) {
+        let sampled: usize = (0..10000)
+            .filter(|i| is_sampled(format!("read{}", i).as_bytes(), seed, fraction))
+            .count();
+        assert!((sampled as f64 / 10000.0 - fraction).abs() < 0.02);
+    }
+
+    #[rstest]
+    #[case(0.0, false)]
+    #[case(1.0, true)]
+    fn test_is_sampled_bounds(#[case] fraction: f64, #[case] expected: bool) {
+        assert_eq!(is_sampled(b"read", 42, fraction), expected);
+    }
+}
@@ -0,0 +1,56 @@
+use rust_htslib::bam::record::{Aux, Record};
+
+/// Read an integer aux tag (e.g. NM), whatever integer type it is stored as
+///
+/// # Arguments
+/// - `record`: the alignment
+/// - `tag`: two-letter name of the tag
+///
+/// # Returns
+/// - the value, or None if the tag is missing or isn't an integer
+///
+/// # Example
+/// ```
+/// use filter_clipped::tags::integer_tag;
+/// use rust_htslib::bam::record::{Aux, Record};
+/// let mut record = Record::new();
+/// assert_eq!(integer_tag(&record, b"NM"), None);
+/// record.push_aux(b"NM", Aux::U8(3)).unwrap();
+/// assert_eq!(integer_tag(&record, b"NM"), Some(3));
+/// ```
+pub fn integer_tag(record: &Record, tag: &[u8]) -> Option<i64> {
+    match record.aux(tag).ok()? {
+        Aux::I8(value) => Some(value as i64),
+        Aux::U8(value) => Some(value as i64),
+        Aux::I16(value) => Some(value as i64),
+        Aux::U16(value) => Some(value as i64),
+        Aux::I32(value) => Some(value as i64),
+        Aux::U32(value) => Some(value as i64),
+        _ => None,
+    }
+}
+
+/// Read a string aux tag (e.g. CB)
+///
+/// # Arguments
+/// - `record`: the alignment
+/// - `tag`: two-letter name of the tag
+///
+/// # Returns
+/// - the value, or None if the tag is missing or isn't a string
+///
+/// # Example
+/// ```
+/// use filter_clipped::tags::string_tag;
+/// use rust_htslib::bam::record::{Aux, Record};
+/// let mut record = Record::new();
+/// assert_eq!(string_tag(&record, b"CB"), None);
+/// record.push_aux(b"CB", Aux::String("ACGT-1")).unwrap();
+/// assert_eq!(string_tag(&record, b"CB").as_deref(), Some("ACGT-1"));
+/// ```
+pub fn string_tag(record: &Record, tag: &[u8]) -> Option<String> {
+    match record.aux(tag).ok()? {
+        Aux::String(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
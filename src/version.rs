@@ -0,0 +1,60 @@
+//! Detailed version information for `--version`: the htslib linked in and what it was built
+//! with (e.g. libcurl and S3 support for remote inputs), and the cargo features enabled
+
+use rust_htslib::htslib;
+use std::ffi::CStr;
+
+/// Version of rust-htslib the binary was built with, from Cargo.lock (see build.rs)
+pub const RUST_HTSLIB_VERSION: &str = env!("RUST_HTSLIB_VERSION");
+
+/// Version of the htslib linked in, e.g. "1.16"
+pub fn htslib_version() -> String {
+    // SAFETY: htslib returns a pointer to a static null-terminated string
+    unsafe { CStr::from_ptr(htslib::hts_version()) }
+        .to_string_lossy()
+        .to_string()
+}
+
+/// What the linked htslib was built with, e.g. "build=configure libcurl=yes S3=yes ..."
+pub fn htslib_features() -> String {
+    // SAFETY: htslib returns a pointer to a static null-terminated string
+    unsafe { CStr::from_ptr(htslib::hts_feature_string()) }
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Cargo features of filter-clipped the binary was built with
+pub fn cargo_features() -> Vec<&'static str> {
+    let mut features: Vec<&'static str> = Vec::new();
+    if cfg!(feature = "async") {
+        features.push("async");
+    }
+    if cfg!(feature = "parquet") {
+        features.push("parquet");
+    }
+    features
+}
+
+/// Text printed after the program name by `--version`
+///
+/// # Example
+/// ```
+/// use filter_clipped::version::long_version;
+/// let version = long_version();
+/// assert!(version.starts_with(env!("CARGO_PKG_VERSION")));
+/// assert!(version.contains("\nhtslib "));
+/// ```
+pub fn long_version() -> String {
+    let features: Vec<&str> = cargo_features();
+    format!(
+        "{}\nrust-htslib {}\nhtslib {}\nhtslib features: {}\nfeatures: {}",
+        env!("CARGO_PKG_VERSION"),
+        RUST_HTSLIB_VERSION,
+        htslib_version(),
+        htslib_features(),
+        match features.is_empty() {
+            true => "none".to_string(),
+            false => features.join(", "),
+        }
+    )
+}